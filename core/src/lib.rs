@@ -0,0 +1,10 @@
+//! Control algorithm of fan-controller without any dependency on `std` or an allocator.
+//!
+//! The Linux daemon and embedded fan controllers share this core. On `embedded-hal` targets,
+//! feed temperatures to [`stepping::Stepping::decide`] and pass the resulting
+//! [`units::Duty::percent`] to `SetDutyCycle::set_duty_cycle_percent`.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod stepping;
+pub mod units;
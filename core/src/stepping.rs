@@ -0,0 +1,218 @@
+use crate::units::{Celsius, Duty};
+use core::time::Duration;
+
+/// Dead zone used unless configured otherwise, half a degree either side of the target
+pub const DEADZONE: Celsius = Celsius::new(0, 500);
+
+/// Settings of the stepping algorithm, which nudges duty up or down until the target is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stepping {
+    /// Target temperature to maintain
+    pub target: Celsius,
+    /// Temperature at which the fan goes straight to `pwm_max`
+    pub temperature_max: Celsius,
+    pub pwm_min: Duty,
+    pub pwm_max: Duty,
+    pub increment: u8,
+    pub decrement: u8,
+    /// Distance from the target within which the duty is left alone
+    pub deadzone: Celsius,
+    /// Larger increments the faster the temperature rises, if set
+    pub ramp: Option<Ramp>,
+}
+
+/// Scaling of the increment with the rate of rise, so fast rises such as a compile job starting
+/// are caught quickly while slow drift still gets gentle steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ramp {
+    /// Percent added to the increment per °C/s of rise
+    pub gain: u8,
+    /// Time between the readings compared
+    pub interval: Duration,
+}
+
+impl Stepping {
+    /// Determines required duty to get closer to the target temperature.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - Latest temperature reading
+    /// * `previous` - Reading before the latest one
+    /// * `duty` - Duty currently applied
+    pub fn required(&self, current: Celsius, previous: Celsius, duty: Duty) -> Duty {
+        if current >= self.temperature_max {
+            return self.pwm_max;
+        }
+
+        if current > self.target && previous <= current {
+            return duty.raise(self.rising_increment(current, previous));
+        }
+
+        if current > self.target && previous > current {
+            return duty.lower(self.decrement);
+        }
+
+        if current < self.target {
+            return duty.lower(self.decrement);
+        }
+
+        duty
+    }
+
+    /// Returns the increment for a rise from `previous` to `current`, scaled up by the ramp.
+    fn rising_increment(&self, current: Celsius, previous: Celsius) -> u8 {
+        let Some(ramp) = self.ramp else {
+            return self.increment;
+        };
+        let interval = ramp.interval.as_millis() as i64;
+        if interval == 0 {
+            return self.increment;
+        }
+
+        // Millidegrees per millisecond are degrees per second
+        let rise = i64::from((current - previous).millidegrees());
+        let extra = (i64::from(ramp.gain) * rise + interval / 2) / interval;
+        (i64::from(self.increment) + extra).min(100) as u8
+    }
+
+    /// Checks and fixes provided duty to be within the limits.
+    pub fn clamp(&self, duty: Duty) -> Duty {
+        if duty > self.pwm_max {
+            return self.pwm_max;
+        }
+
+        if duty < self.pwm_min {
+            return self.pwm_min;
+        }
+
+        duty
+    }
+
+    /// Returns the duty for a temperature regardless of the duty applied, rising linearly from
+    /// `pwm_min` at the target to `pwm_max` at `temperature_max`.
+    ///
+    /// For callers keeping no state between readings, such as a single run from a timer.
+    pub fn curve(&self, current: Celsius) -> Duty {
+        let span = i64::from(self.temperature_max.millidegrees() - self.target.millidegrees());
+        if span <= 0 {
+            return if current >= self.temperature_max {
+                self.pwm_max
+            } else {
+                self.pwm_min
+            };
+        }
+
+        let offset = i64::from(current.millidegrees() - self.target.millidegrees()).clamp(0, span);
+        let (min, max) = (
+            i64::from(self.pwm_min.percent()),
+            i64::from(self.pwm_max.percent()),
+        );
+        let percent = min + ((max - min) * offset + span / 2) / span;
+        self.clamp(Duty::new(percent as u8).unwrap_or(self.pwm_max))
+    }
+
+    /// Returns the duty to apply after a new reading.
+    ///
+    /// Keeps the current duty while the temperature is within the dead zone around the target, to
+    /// avoid needless changes.
+    pub fn decide(&self, current: Celsius, previous: Celsius, duty: Duty) -> Duty {
+        if (current - self.target).abs() <= self.deadzone {
+            return duty;
+        }
+
+        self.clamp(self.required(current, previous, duty))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ramp, Stepping, DEADZONE};
+    use crate::units::{Celsius, Duty};
+    use core::time::Duration;
+
+    fn stepping() -> Stepping {
+        Stepping {
+            target: Celsius::new(40, 0),
+            temperature_max: Celsius::new(70, 0),
+            pwm_min: Duty::new(30).unwrap(),
+            pwm_max: Duty::FULL,
+            increment: 2,
+            decrement: 1,
+            deadzone: DEADZONE,
+            ramp: None,
+        }
+    }
+
+    #[test]
+    fn ramp_scales_increment_with_rate_of_rise() {
+        let stepping = Stepping {
+            ramp: Some(Ramp {
+                gain: 10,
+                interval: Duration::from_secs(5),
+            }),
+            ..stepping()
+        };
+        let duty = Duty::new(50).unwrap();
+        let raised = |from, to| {
+            stepping
+                .required(Celsius::new(to, 0), Celsius::new(from, 0), duty)
+                .percent()
+        };
+
+        // 2°C/s adds 20% to the increment of 2
+        assert_eq!(72, raised(45, 55));
+        // Slow drift keeps the plain increment
+        assert_eq!(52, raised(45, 45));
+        assert_eq!(
+            53,
+            stepping
+                .required(Celsius::new(45, 400), Celsius::new(45, 0), duty)
+                .percent()
+        );
+    }
+
+    #[test]
+    fn decide_keeps_duty_near_target() {
+        let duty = Duty::new(50).unwrap();
+        let value = stepping().decide(Celsius::new(40, 400), Celsius::new(45, 0), duty);
+        assert_eq!(duty, value);
+    }
+
+    #[test]
+    fn deadzone_is_configurable() {
+        let duty = Duty::new(50).unwrap();
+        let stepping = Stepping {
+            target: Celsius::new(40, 500),
+            deadzone: Celsius::new(1, 500),
+            ..stepping()
+        };
+        let decide = |degrees, millis| {
+            stepping
+                .decide(Celsius::new(degrees, millis), Celsius::new(45, 0), duty)
+                .percent()
+        };
+
+        assert_eq!(50, decide(39, 0));
+        assert_eq!(50, decide(42, 0));
+        assert_eq!(49, decide(42, 100));
+        assert_eq!(49, decide(38, 900));
+    }
+
+    #[test]
+    fn curve_rises_linearly_between_target_and_maximum() {
+        let curve = |degrees| stepping().curve(Celsius::new(degrees, 0)).percent();
+
+        assert_eq!(30, curve(25));
+        assert_eq!(30, curve(40));
+        assert_eq!(65, curve(55));
+        assert_eq!(100, curve(70));
+        assert_eq!(100, curve(90));
+    }
+
+    #[test]
+    fn decide_clamps_to_minimum() {
+        let duty = Duty::new(30).unwrap();
+        let value = stepping().decide(Celsius::new(20, 0), Celsius::new(20, 0), duty);
+        assert_eq!(duty, value);
+    }
+}
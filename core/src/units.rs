@@ -0,0 +1,437 @@
+use core::{fmt, num::ParseIntError, ops::Sub, str::FromStr};
+
+/// Temperature in degrees Celsius.
+///
+/// Stored as fixed-point millidegrees so control math is exact and reproducible across platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Celsius(i32);
+
+impl Celsius {
+    /// Returns a temperature from whole and fractional millidegrees, e.g. `new(40, 500)` is 40.5°C.
+    pub const fn new(degrees: i32, millis: i32) -> Self {
+        Self(degrees * 1000 + millis)
+    }
+
+    /// Converts a raw sensor value in millidegrees, as used by sysfs.
+    ///
+    /// The value is kept exactly, readings are rounded to the configured [`Precision`] once
+    /// before control decisions are made on them.
+    pub fn from_millidegrees(value: i32) -> Self {
+        Self(value)
+    }
+
+    pub fn millidegrees(self) -> i32 {
+        self.0
+    }
+
+    /// Returns the distance from zero.
+    pub fn abs(self) -> Self {
+        Self(self.0.saturating_abs())
+    }
+
+    /// Rounds to the nearest whole degree.
+    pub fn round(self) -> Self {
+        Self(round_to(self.0, 1000))
+    }
+}
+
+/// Rounds to the nearest multiple of `step`, halfway values away from zero.
+fn round_to(value: i32, step: i32) -> i32 {
+    let half = if value < 0 { -step / 2 } else { step / 2 };
+    value.saturating_add(half) / step * step
+}
+
+/// How temperatures are rounded to the decimals kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// To the nearest, halfway values away from zero
+    #[default]
+    HalfUp,
+    /// To the nearest, halfway values to the even neighbour
+    HalfEven,
+    /// Down towards the colder neighbour
+    Floor,
+    /// Up towards the warmer neighbour
+    Ceil,
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rounding::HalfUp => "half-up",
+            Rounding::HalfEven => "half-even",
+            Rounding::Floor => "floor",
+            Rounding::Ceil => "ceil",
+        })
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = RoundingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-up" => Ok(Rounding::HalfUp),
+            "half-even" => Ok(Rounding::HalfEven),
+            "floor" => Ok(Rounding::Floor),
+            "ceil" => Ok(Rounding::Ceil),
+            _ => Err(RoundingError),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundingError;
+
+impl fmt::Display for RoundingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid rounding, expected half-up, half-even, floor or ceil"
+        )
+    }
+}
+
+impl core::error::Error for RoundingError {}
+
+/// Decimals temperature readings are kept to, and how they're rounded to them.
+///
+/// Applied once to each reading, so thresholds, logs and exports all see the same value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precision {
+    /// Decimals kept, at most 3
+    pub decimals: u8,
+    pub rounding: Rounding,
+}
+
+impl Default for Precision {
+    /// One decimal rounded half up, as sensors have always been read.
+    fn default() -> Self {
+        Self {
+            decimals: 1,
+            rounding: Rounding::HalfUp,
+        }
+    }
+}
+
+impl Precision {
+    /// Rounds a temperature to the decimals kept.
+    pub fn round(self, value: Celsius) -> Celsius {
+        let step = 10_i32.pow(3 - u32::from(self.decimals.min(3)));
+        let (quotient, remainder) = (value.0.div_euclid(step), value.0.rem_euclid(step));
+        let up = match self.rounding {
+            Rounding::HalfUp => return Celsius(round_to(value.0, step)),
+            Rounding::HalfEven => {
+                remainder * 2 > step || (remainder * 2 == step && quotient % 2 != 0)
+            }
+            Rounding::Floor => false,
+            Rounding::Ceil => remainder > 0,
+        };
+        Celsius((quotient + i32::from(up)).saturating_mul(step))
+    }
+}
+
+impl Sub for Celsius {
+    type Output = Celsius;
+
+    fn sub(self, other: Celsius) -> Celsius {
+        Celsius(self.0.saturating_sub(other.0))
+    }
+}
+
+/// Formats the same way regardless of the system locale, with a decimal point, or a decimal
+/// comma in the alternate form `{:#}`.
+impl fmt::Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let degrees = self.0.unsigned_abs() / 1000;
+        let millis = self.0.unsigned_abs() % 1000;
+
+        if millis == 0 {
+            return write!(f, "{}{}", sign, degrees);
+        }
+
+        // Print only significant fraction digits, e.g. 500 as "5"
+        let (mut fraction, mut width) = (millis, 3);
+        while fraction % 10 == 0 {
+            fraction /= 10;
+            width -= 1;
+        }
+        let separator = if f.alternate() { ',' } else { '.' };
+        write!(
+            f,
+            "{}{}{}{:0width$}",
+            sign,
+            degrees,
+            separator,
+            fraction,
+            width = width
+        )
+    }
+}
+
+impl FromStr for Celsius {
+    type Err = CelsiusError;
+
+    /// Parses a decimal such as `40` or `-2.125` without going through floating point.
+    ///
+    /// A decimal comma is accepted as well, so values copied from spreadsheets in any locale parse.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CelsiusError;
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (whole, fraction) = digits.split_once(['.', ',']).unwrap_or((digits, ""));
+
+        if whole.is_empty() || fraction.len() > 3 {
+            return Err(invalid());
+        }
+        if !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let degrees: i32 = whole.parse().map_err(|_| invalid())?;
+        let millis = fraction
+            .bytes()
+            .chain(core::iter::repeat(b'0'))
+            .take(3)
+            .fold(0, |millis, digit| millis * 10 + i32::from(digit - b'0'));
+        let value = degrees
+            .checked_mul(1000)
+            .and_then(|value| value.checked_add(millis))
+            .ok_or_else(invalid)?;
+
+        Ok(Self(if negative { -value } else { value }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CelsiusError;
+
+impl fmt::Display for CelsiusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid temperature, expected a number with up to three decimals"
+        )
+    }
+}
+
+impl core::error::Error for CelsiusError {}
+
+/// Fan duty cycle in percent, always within 0-100.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Duty(u8);
+
+impl Duty {
+    pub const FULL: Duty = Duty(100);
+    pub const OFF: Duty = Duty(0);
+
+    /// Returns `None` if the value is above 100%.
+    pub const fn new(percent: u8) -> Option<Self> {
+        if percent > 100 {
+            return None;
+        }
+
+        Some(Self(percent))
+    }
+
+    pub fn percent(self) -> u8 {
+        self.0
+    }
+
+    /// Raises duty by the given percentage points, saturating at 100%.
+    pub fn raise(self, step: u8) -> Self {
+        Self(self.0.saturating_add(step).min(100))
+    }
+
+    /// Lowers duty by the given percentage points, saturating at 0%.
+    pub fn lower(self, step: u8) -> Self {
+        Self(self.0.saturating_sub(step))
+    }
+}
+
+impl TryFrom<i32> for Duty {
+    type Error = DutyError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        u8::try_from(value)
+            .ok()
+            .and_then(Duty::new)
+            .ok_or(DutyError::OutOfRange(value.into()))
+    }
+}
+
+impl fmt::Display for Duty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Duty {
+    type Err = DutyError;
+
+    /// Parses a percentage such as `60` or `60%`, or a raw duty out of a range such as `153/255`,
+    /// as hwmon and Arduino `analogWrite` use, rounded to the nearest percent.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.get(s.len().saturating_sub(3)..)
+            .is_some_and(|unit| unit.eq_ignore_ascii_case("rpm"))
+        {
+            return Err(DutyError::Rpm);
+        }
+
+        if let Some((raw, range)) = s.split_once('/') {
+            let raw: i64 = raw.trim().parse().map_err(DutyError::Parse)?;
+            let range: i64 = range.trim().parse().map_err(DutyError::Parse)?;
+            if range <= 0 || !(0..=range).contains(&raw) {
+                return Err(DutyError::RawOutOfRange { raw, range });
+            }
+            // Widened, as raw * 100 overflows i64 for large ranges; with raw at most range the
+            // result is at most 100
+            let (raw, range) = (i128::from(raw), i128::from(range));
+            return Duty::try_from(((raw * 100 + range / 2) / range) as i32);
+        }
+
+        let value: i64 = s
+            .strip_suffix('%')
+            .unwrap_or(s)
+            .trim_end()
+            .parse()
+            .map_err(DutyError::Parse)?;
+        i32::try_from(value)
+            .map_err(|_| DutyError::OutOfRange(value))
+            .and_then(Duty::try_from)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DutyError {
+    Parse(ParseIntError),
+    OutOfRange(i64),
+    RawOutOfRange {
+        raw: i64,
+        range: i64,
+    },
+    /// Speeds in RPM can't be converted without a tachometer
+    Rpm,
+}
+
+impl fmt::Display for DutyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DutyError::Parse(error) => write!(f, "invalid duty: {}", error),
+            DutyError::OutOfRange(value) => write!(f, "duty {} is not within 0-100", value),
+            DutyError::RawOutOfRange { raw, range } => {
+                write!(f, "raw duty {} is not within 0-{}", raw, range)
+            }
+            DutyError::Rpm => write!(
+                f,
+                "fan speeds in RPM need a tachometer input, which isn't supported; \
+                 give a percentage or a raw duty such as 153/255"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for DutyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Celsius, Duty, Precision, Rounding};
+
+    #[test]
+    fn millidegrees_are_rounded_to_one_decimal_by_default() {
+        let round =
+            |millidegrees| Precision::default().round(Celsius::from_millidegrees(millidegrees));
+        assert_eq!(Celsius::new(45, 678), Celsius::from_millidegrees(45678));
+        assert_eq!(Celsius::new(45, 700), round(45678));
+        assert_eq!(Celsius::new(45, 700), round(45650));
+        assert_eq!(Celsius::new(-3, -200), round(-3150));
+    }
+
+    #[test]
+    fn rounds_to_precision() {
+        let round = |decimals, rounding, millidegrees| {
+            Precision { decimals, rounding }
+                .round(Celsius::from_millidegrees(millidegrees))
+                .millidegrees()
+        };
+        assert_eq!(45678, round(3, Rounding::HalfUp, 45678));
+        assert_eq!(45680, round(2, Rounding::HalfUp, 45678));
+        assert_eq!(46000, round(0, Rounding::HalfUp, 45500));
+        assert_eq!(45600, round(1, Rounding::HalfEven, 45650));
+        assert_eq!(45800, round(1, Rounding::HalfEven, 45750));
+        assert_eq!(45800, round(1, Rounding::HalfEven, 45751));
+        assert_eq!(45600, round(1, Rounding::Floor, 45699));
+        assert_eq!(-3200, round(1, Rounding::Floor, -3150));
+        assert_eq!(45700, round(1, Rounding::Ceil, 45601));
+        assert_eq!(-3100, round(1, Rounding::Ceil, -3150));
+        assert_eq!(45600, round(1, Rounding::Ceil, 45600));
+
+        assert_eq!(Ok(Rounding::HalfEven), "half-even".parse());
+        assert_eq!("ceil", Rounding::Ceil.to_string());
+        assert!("nearest".parse::<Rounding>().is_err());
+    }
+
+    #[test]
+    fn rounds_to_whole_degrees_exactly() {
+        assert_eq!(Celsius::new(40, 0), Celsius::new(40, 499).round());
+        assert_eq!(Celsius::new(41, 0), Celsius::new(40, 500).round());
+    }
+
+    #[test]
+    fn celsius_parse_and_display_round_trip() {
+        for text in ["40", "40.5", "-2.125", "0.05"] {
+            let value: Celsius = text.parse().unwrap();
+            assert_eq!(text, value.to_string());
+        }
+        assert_eq!(Celsius::new(0, -500), "-0.5".parse().unwrap());
+        assert!("40.1234".parse::<Celsius>().is_err());
+        assert!("abc".parse::<Celsius>().is_err());
+        assert!(".5".parse::<Celsius>().is_err());
+    }
+
+    #[test]
+    fn celsius_decimal_comma() {
+        assert_eq!(Celsius::new(40, 500), "40,5".parse().unwrap());
+        assert_eq!("-2,125", format!("{:#}", Celsius::new(-2, -125)));
+        assert_eq!("40", format!("{:#}", Celsius::new(40, 0)));
+        assert!("40,5.1".parse::<Celsius>().is_err());
+    }
+
+    #[test]
+    fn duty_rejects_values_above_hundred() {
+        assert_eq!(None, Duty::new(101));
+        assert!(Duty::try_from(255).is_err());
+        assert!(Duty::try_from(-1).is_err());
+        assert!("150".parse::<Duty>().is_err());
+    }
+
+    #[test]
+    fn duty_parses_percent_and_raw_values() {
+        assert_eq!(Ok(Duty::new(60).unwrap()), "60".parse());
+        assert_eq!(Ok(Duty::new(60).unwrap()), "60 %".parse());
+        assert_eq!(Ok(Duty::new(60).unwrap()), "153/255".parse());
+        assert_eq!(Ok(Duty::FULL), "1023/1023".parse::<Duty>());
+        assert_eq!(
+            Ok(Duty::FULL),
+            "92233720368547758/92233720368547758".parse::<Duty>()
+        );
+        assert!("256/255".parse::<Duty>().is_err());
+        assert!("1/0".parse::<Duty>().is_err());
+        assert!("1200rpm".parse::<Duty>().is_err());
+        assert!("1200 RPM".parse::<Duty>().is_err());
+    }
+
+    #[test]
+    fn duty_arithmetic_saturates() {
+        assert_eq!(Duty::FULL, Duty::new(99).unwrap().raise(5));
+        assert_eq!(Duty::new(0).unwrap(), Duty::new(1).unwrap().lower(5));
+    }
+}
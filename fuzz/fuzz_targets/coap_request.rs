@@ -0,0 +1,24 @@
+#![no_main]
+
+use fan_controller::{
+    coap::Resources,
+    setpoint::Setpoint,
+    status::Snapshot,
+    units::{Celsius, Duty},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let snapshot = Snapshot {
+        temperature: Some(Celsius::new(41, 500)),
+        duty: Duty::new(52),
+        ..Snapshot::default()
+    };
+    let setpoint = Setpoint::new();
+    let resources = Resources {
+        snapshot: &snapshot,
+        setpoint: &setpoint,
+        temperature_max: Celsius::new(60, 0),
+    };
+    let _ = resources.respond(data);
+});
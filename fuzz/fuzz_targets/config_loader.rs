@@ -0,0 +1,16 @@
+#![no_main]
+
+use clap::Parser;
+use fan_controller::{args::Args, config};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(content) = std::str::from_utf8(data) {
+        if let Ok(table) = content.parse::<toml::Table>() {
+            if let Ok(options) = config::to_args(&table) {
+                let argv = std::iter::once("fan-controller".to_string()).chain(options);
+                let _ = Args::try_parse_from(argv);
+            }
+        }
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use fan_controller::control::Command;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(line) = std::str::from_utf8(data) {
+        let _ = Command::parse(line);
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use fan_controller::units::{Celsius, Duty};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(value) = text.parse::<Celsius>() {
+            // Display must produce something that parses back to the same value
+            assert_eq!(Ok(value), value.to_string().parse::<Celsius>());
+        }
+        if let Ok(value) = text.parse::<Duty>() {
+            assert!(value.percent() <= 100);
+        }
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use fan_controller::{
+    snmp,
+    status::Snapshot,
+    units::{Celsius, Duty},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let snapshot = Snapshot {
+        temperature: Some(Celsius::new(41, 500)),
+        duty: Duty::new(52),
+        ..Snapshot::default()
+    };
+    let _ = snmp::respond(data, "public", &snapshot);
+});
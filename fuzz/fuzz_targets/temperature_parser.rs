@@ -0,0 +1,12 @@
+#![no_main]
+
+use fan_controller::sensor::parse_millidegrees;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(content) = std::str::from_utf8(data) {
+        if let Ok(value) = parse_millidegrees(content) {
+            let _ = value.round().to_string();
+        }
+    }
+});
@@ -0,0 +1,39 @@
+/*
+ * C bindings for the fan-controller control core.
+ *
+ * Build the shared library with:
+ *   cargo rustc --release --lib --no-default-features --features ffi --crate-type cdylib
+ *
+ * Temperatures are in millidegrees Celsius, as read from sysfs. Duties are percentages 0-100.
+ */
+
+#ifndef FAN_CONTROLLER_H
+#define FAN_CONTROLLER_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct FcController FcController;
+
+/* Creates a controller starting at pwm_max duty. Returns NULL on invalid PWM limits. */
+FcController *fc_controller_new(int32_t target_millidegrees, int32_t max_millidegrees,
+                                uint8_t pwm_min, uint8_t pwm_max,
+                                uint8_t pwm_increment, uint8_t pwm_decrement);
+
+/* Feeds a temperature reading and returns the duty to apply. */
+uint8_t fc_controller_step(FcController *controller, int32_t millidegrees);
+
+/* Changes the temperature to maintain. */
+void fc_controller_set_target(FcController *controller, int32_t millidegrees);
+
+/* Frees a controller. Passing NULL is a no-op. */
+void fc_controller_free(FcController *controller);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FAN_CONTROLLER_H */
@@ -0,0 +1,57 @@
+# Einrichtungsassistent
+
+setup-no-sensors = Keine Temperatursensoren unter /sys/class gefunden.
+setup-temperature-path = Pfad der Temperaturdatei
+setup-given-by-hand = von Hand angegeben
+setup-sensors = Temperatursensoren:
+setup-unreadable = nicht lesbar
+setup-sensor = Sensor
+setup-expected-sensor = erwartet eine Zahl von 1 bis { $count }
+setup-pin = wiringPi-Pin, der den Lüfter ansteuert
+setup-expected-pin = erwartet eine Pin-Nummer, z. B. 3
+setup-fan-types = Lüftertypen:
+setup-fan-two-pin = 2-Pin-Lüfter, über einen Transistor geschaltet
+setup-fan-four-pin = 4-Pin-PWM-Lüfter mit dem PWM-Draht am Pin
+setup-fan-type = Lüftertyp
+setup-expected-fan-type = erwartet 1 oder 2
+setup-target = Zieltemperatur in °C
+setup-spinning-full = Dreht sich der Lüfter mit voller Drehzahl?
+setup-spinning-at = Dreht sich der Lüfter bei { $duty } % noch?
+setup-yes-no = [J/n]
+setup-yes = j, ja
+setup-no = n, nein
+setup-expected-yes-no = erwartet j oder n
+setup-invalid-answer = Ungültige Antwort, { $reason }
+setup-ended = Einrichtung beendet, bevor alle Fragen beantwortet waren
+setup-not-spinning = Der Lüfter drehte sich nicht mit voller Drehzahl, prüfe seine Verkabelung und Pin { $pin }
+setup-failed = Einrichtung fehlgeschlagen: { $error }
+setup-write-failed = Konfiguration konnte nicht geschrieben werden: { $error }
+setup-done = { $config } und { $unit } geschrieben, starte den Regler mit: systemctl daemon-reload && systemctl enable --now { $service }
+setup-no-wiringpi = Ohne wiringpi-Unterstützung gebaut, der Drehtest wird übersprungen
+
+# Farbige Konsolenausgabe
+
+console-target = Ziel
+console-fan = Lüfter
+console-target-set = Zieltemperatur auf { $target }°C gesetzt
+console-override-expired = Ferngesteuertes Ziel abgelaufen, zurück auf { $target }°C
+console-modes = Boost { $boost }, Leisemodus { $quiet }
+console-on = an
+console-off = aus
+console-energy-bias = Energieanpassung { $bias }°C, Ziel { $target }°C
+console-season = Saison { $season }, Ziel { $target }°C
+console-ab-test-arm = A/B-Test mit Profil { $profile }
+console-ab-test-ended = A/B-Test beendet
+console-no-season = Außerhalb aller Saisons, Ziel { $target }°C
+console-fault = { $message }, Lüfter läuft mit maximaler Drehzahl
+console-dropped = { $count } Ereignisse verworfen
+console-reloaded = Konfiguration neu geladen
+console-reload-rejected = Neu geladene Konfiguration abgelehnt: { $message }
+console-overtemperature = Temperatur { $temperature }°C hat das Maximum { $max }°C erreicht
+console-latched = Temperatur erreichte { $temperature }°C, Lüfter bleibt bis zur Bestätigung auf maximaler Drehzahl
+console-latch-released = Übertemperatursperre bestätigt
+console-output-failed = Schreiben auf den Lüfterausgang fehlgeschlagen: { $message }, er wird neu initialisiert
+console-output-reinitialized = Lüfterausgang neu initialisiert, { $reinits } Mal seit dem Start
+console-alarm-normal = Alarmsensor { $sensor } wieder normal bei { $temperature }°C
+console-alarm-warning = Alarmsensor { $sensor } hat bei { $temperature }°C die Warnstufe erreicht
+console-alarm-critical = Alarmsensor { $sensor } hat bei { $temperature }°C die kritische Stufe erreicht
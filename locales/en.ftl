@@ -0,0 +1,58 @@
+# Setup wizard
+
+setup-no-sensors = No temperature sensors found under /sys/class.
+setup-temperature-path = Temperature file path
+setup-given-by-hand = given by hand
+setup-sensors = Temperature sensors:
+setup-unreadable = unreadable
+setup-sensor = Sensor
+setup-expected-sensor = expected a number from 1 to { $count }
+setup-pin = wiringPi pin driving the fan
+setup-expected-pin = expected a pin number, e.g. 3
+setup-fan-types = Fan types:
+setup-fan-two-pin = 2-pin fan switched through a transistor
+setup-fan-four-pin = 4-pin PWM fan with its PWM wire on the pin
+setup-fan-type = Fan type
+setup-expected-fan-type = expected 1 or 2
+setup-target = Target temperature in °C
+setup-spinning-full = Is the fan spinning at full speed?
+setup-spinning-at = Is the fan still spinning at { $duty }%?
+# Hint after yes or no questions, and the answers taken for each
+setup-yes-no = [Y/n]
+setup-yes = y, yes
+setup-no = n, no
+setup-expected-yes-no = expected y or n
+setup-invalid-answer = Invalid answer, { $reason }
+setup-ended = setup ended before all questions were answered
+setup-not-spinning = The fan didn't spin at full speed, check its wiring and pin { $pin }
+setup-failed = Setup failed: { $error }
+setup-write-failed = Failed to write the config: { $error }
+setup-done = Wrote { $config } and { $unit }, start the controller with: systemctl daemon-reload && systemctl enable --now { $service }
+setup-no-wiringpi = Built without wiringpi support, skipping the spin test
+
+# Colored console output
+
+console-target = target
+console-fan = fan
+console-target-set = Target set to { $target }°C
+console-override-expired = Remote target expired, back to { $target }°C
+console-modes = Boost { $boost }, quiet mode { $quiet }
+console-on = on
+console-off = off
+console-energy-bias = Energy bias { $bias }°C, target { $target }°C
+console-season = Season { $season }, target { $target }°C
+console-ab-test-arm = A/B test on profile { $profile }
+console-ab-test-ended = A/B test ended
+console-no-season = Outside all seasons, target { $target }°C
+console-fault = { $message }, running fan at maximum speed
+console-dropped = Dropped { $count } events
+console-reloaded = Configuration reloaded
+console-reload-rejected = Configuration reload rejected: { $message }
+console-overtemperature = Temperature { $temperature }°C reached maximum { $max }°C
+console-latched = Temperature reached { $temperature }°C, fan latched at maximum speed until acknowledged
+console-latch-released = Overtemperature latch acknowledged
+console-output-failed = Failed to write to fan output: { $message }, initializing it again
+console-output-reinitialized = Fan output initialized again, { $reinits } times since start
+console-alarm-normal = Alarm sensor { $sensor } back to normal at { $temperature }°C
+console-alarm-warning = Alarm sensor { $sensor } reached warning level at { $temperature }°C
+console-alarm-critical = Alarm sensor { $sensor } reached critical level at { $temperature }°C
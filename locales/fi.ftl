@@ -0,0 +1,57 @@
+# Asennusavustaja
+
+setup-no-sensors = Lämpötila-antureita ei löytynyt hakemistosta /sys/class.
+setup-temperature-path = Lämpötilatiedoston polku
+setup-given-by-hand = annettu käsin
+setup-sensors = Lämpötila-anturit:
+setup-unreadable = ei luettavissa
+setup-sensor = Anturi
+setup-expected-sensor = odotettiin numeroa väliltä 1–{ $count }
+setup-pin = Tuuletinta ohjaava wiringPi-pinni
+setup-expected-pin = odotettiin pinnin numeroa, esim. 3
+setup-fan-types = Tuuletintyypit:
+setup-fan-two-pin = 2-pinninen tuuletin transistorin kautta kytkettynä
+setup-fan-four-pin = 4-pinninen PWM-tuuletin, jonka PWM-johdin on pinnissä
+setup-fan-type = Tuuletintyyppi
+setup-expected-fan-type = odotettiin 1 tai 2
+setup-target = Tavoitelämpötila (°C)
+setup-spinning-full = Pyöriikö tuuletin täydellä nopeudella?
+setup-spinning-at = Pyöriikö tuuletin yhä { $duty } %:n nopeudella?
+setup-yes-no = [K/e]
+setup-yes = k, kyllä
+setup-no = e, ei
+setup-expected-yes-no = odotettiin k tai e
+setup-invalid-answer = Virheellinen vastaus, { $reason }
+setup-ended = asennus päättyi ennen kuin kaikkiin kysymyksiin vastattiin
+setup-not-spinning = Tuuletin ei pyörinyt täydellä nopeudella, tarkista sen johdotus ja pinni { $pin }
+setup-failed = Asennus epäonnistui: { $error }
+setup-write-failed = Asetusten kirjoittaminen epäonnistui: { $error }
+setup-done = Kirjoitettiin { $config } ja { $unit }, käynnistä säädin komennolla: systemctl daemon-reload && systemctl enable --now { $service }
+setup-no-wiringpi = Käännetty ilman wiringpi-tukea, pyörimistesti ohitetaan
+
+# Värillinen konsolituloste
+
+console-target = tavoite
+console-fan = tuuletin
+console-target-set = Tavoitteeksi asetettu { $target }°C
+console-override-expired = Etäältä asetettu tavoite vanheni, takaisin { $target }°C:een
+console-modes = Tehostus { $boost }, hiljainen tila { $quiet }
+console-on = päällä
+console-off = pois
+console-energy-bias = Energiasiirtymä { $bias }°C, tavoite { $target }°C
+console-season = Kausi { $season }, tavoite { $target }°C
+console-ab-test-arm = A/B-testi profiililla { $profile }
+console-ab-test-ended = A/B-testi päättyi
+console-no-season = Kausien ulkopuolella, tavoite { $target }°C
+console-fault = { $message }, tuuletin käy täydellä nopeudella
+console-dropped = { $count } tapahtumaa hylättiin
+console-reloaded = Asetukset ladattu uudelleen
+console-reload-rejected = Uudelleen ladatut asetukset hylättiin: { $message }
+console-overtemperature = Lämpötila { $temperature }°C saavutti enimmäisarvon { $max }°C
+console-latched = Lämpötila saavutti { $temperature }°C, tuuletin lukittu täydelle nopeudelle kuittaukseen asti
+console-latch-released = Ylilämpölukitus kuitattu
+console-output-failed = Tuulettimen lähtöön kirjoittaminen epäonnistui: { $message }, se alustetaan uudelleen
+console-output-reinitialized = Tuulettimen lähtö alustettu uudelleen, { $reinits } kertaa käynnistyksen jälkeen
+console-alarm-normal = Hälytysanturi { $sensor } palasi normaaliksi, { $temperature }°C
+console-alarm-warning = Hälytysanturi { $sensor } saavutti varoitustason, { $temperature }°C
+console-alarm-critical = Hälytysanturi { $sensor } saavutti kriittisen tason, { $temperature }°C
@@ -0,0 +1,66 @@
+//! A/B testing of two config profiles, alternated on a fixed period so their noise and
+//! temperatures can be compared under the same loads and weather.
+//!
+//! The profile in effect is decided by the wall clock alone, so a restart or reload carries on
+//! with the same one, and every event after a switch is tagged with it in the event log and
+//! telemetry.
+
+use std::time::Duration;
+
+/// Two profiles taking turns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbTest {
+    profiles: [String; 2],
+    period: Duration,
+}
+
+impl AbTest {
+    pub fn new(profiles: [String; 2], period: Duration) -> Self {
+        Self { profiles, period }
+    }
+
+    /// Returns the profile in effect at a Unix time in seconds, the first one in even periods
+    /// counted from the epoch.
+    pub fn arm(&self, time: u64) -> &str {
+        let period = self.period.as_secs().max(1);
+        &self.profiles[(time / period % 2) as usize]
+    }
+}
+
+/// Parses the two profile names to alternate between, e.g. `silent,balanced`.
+pub fn parse_profiles(value: &str) -> Result<[String; 2], String> {
+    match value
+        .split(',')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [a, b] if !a.is_empty() && !b.is_empty() && a != b => Ok([a.to_string(), b.to_string()]),
+        _ => Err(format!(
+            "invalid A/B test {:?}, expected two different profiles, e.g. silent,balanced",
+            value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_profiles, AbTest};
+    use std::time::Duration;
+
+    #[test]
+    fn alternates_profiles_each_period() {
+        let profiles = parse_profiles("silent, balanced").unwrap();
+        let test = AbTest::new(profiles, Duration::from_secs(3600));
+
+        assert_eq!("silent", test.arm(0));
+        assert_eq!("silent", test.arm(3599));
+        assert_eq!("balanced", test.arm(3600));
+        assert_eq!("silent", test.arm(7200));
+
+        assert!(parse_profiles("silent").is_err());
+        assert!(parse_profiles("silent,silent").is_err());
+        assert!(parse_profiles("silent,").is_err());
+        assert!(parse_profiles("a,b,c").is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Noise of the intake and exhaust fans at each duty, for sharing the airflow between them the
+//! quietest way.
+//!
+//! Sound levels in dBA add up as powers, so one fan running fast is usually louder than two fans
+//! running at half the speed, but not always: a fan may hum at one speed or be much closer to
+//! the listener than the other.
+
+use crate::{args::Args, units::Duty};
+use clap::ValueEnum;
+use std::{cmp::Ordering, fmt};
+
+/// How the airflow the controller asks for is shared between paired fans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Objective {
+    /// Run the exhaust at --exhaust-ratio of the intake duty
+    #[default]
+    Ratio,
+    /// Share the same total duty out so the weighted noise of both fans is lowest
+    Quiet,
+}
+
+/// Points of duty and the noise in dBA the fan makes at it, interpolated in between.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseProfile {
+    points: Vec<(Duty, f64)>,
+}
+
+impl NoiseProfile {
+    /// Returns the slowest duty of the table, taken as the slowest the fan runs at.
+    pub fn slowest(&self) -> Duty {
+        self.points[0].0
+    }
+
+    /// Returns the noise at a duty.
+    ///
+    /// Below the first point and above the last, their noise applies.
+    pub fn dba(&self, duty: Duty) -> f64 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if duty <= first.0 {
+            return first.1;
+        }
+        if duty >= last.0 {
+            return last.1;
+        }
+
+        let upper = self
+            .points
+            .iter()
+            .position(|(point, _)| *point > duty)
+            .unwrap_or(self.points.len() - 1);
+        let ((low, from), (high, to)) = (self.points[upper - 1], self.points[upper]);
+        let span = f64::from(high.percent()) - f64::from(low.percent());
+        let offset = f64::from(duty.percent()) - f64::from(low.percent());
+        from + (to - from) * offset / span
+    }
+
+    /// Returns the sound power at a duty, relative to the threshold of hearing. A stopped fan
+    /// is silent.
+    fn power(&self, duty: Duty) -> f64 {
+        if duty == Duty::OFF {
+            0.0
+        } else {
+            10f64.powf(self.dba(duty) / 10.0)
+        }
+    }
+}
+
+impl fmt::Display for NoiseProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|(duty, dba)| format!("{}:{}", duty.percent(), dba))
+            .collect();
+        f.write_str(&points.join(","))
+    }
+}
+
+/// Parses a table of duty:dBA points such as `20:18,50:27,100:39`.
+pub fn parse_noise_profile(value: &str) -> Result<NoiseProfile, String> {
+    let invalid = || {
+        format!(
+            "invalid noise profile {:?}, expected e.g. 20:18,50:27,100:39",
+            value
+        )
+    };
+
+    let mut points: Vec<(Duty, f64)> = Vec::new();
+    for point in value.split(',') {
+        let (duty, dba) = point.split_once(':').ok_or_else(invalid)?;
+        let duty: Duty = duty
+            .trim()
+            .parse()
+            .map_err(|error| format!("{} in noise profile {:?}", error, value))?;
+        let dba: f64 = dba.trim().parse().map_err(|_| invalid())?;
+        if !dba.is_finite() {
+            return Err(invalid());
+        }
+        if let Some((previous, _)) = points.last() {
+            if duty <= *previous {
+                return Err(format!(
+                    "noise profile {:?} duties must rise from point to point",
+                    value
+                ));
+            }
+        }
+        points.push((duty, dba));
+    }
+    Ok(NoiseProfile { points })
+}
+
+/// Noise profiles of the intake and exhaust fans, with how much each one's noise counts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Acoustics {
+    intake: NoiseProfile,
+    exhaust: NoiseProfile,
+    /// Weights of the intake and exhaust noise, e.g. higher for a fan facing the room
+    weights: [f64; 2],
+}
+
+impl Acoustics {
+    pub fn new(intake: NoiseProfile, exhaust: NoiseProfile, weights: [f64; 2]) -> Self {
+        Self {
+            intake,
+            exhaust,
+            weights,
+        }
+    }
+
+    /// Returns the profiles of the options, if the quiet objective is selected.
+    pub fn from_args(args: &Args) -> Option<Self> {
+        if args.fan_objective != Objective::Quiet {
+            return None;
+        }
+        let weights = match args.noise_weight[..] {
+            [intake, exhaust] => [intake, exhaust],
+            _ => [1.0, 1.0],
+        };
+        Some(Self::new(
+            args.intake_noise.clone()?,
+            args.exhaust_noise.clone()?,
+            weights,
+        ))
+    }
+
+    fn noise(&self, intake: Duty, exhaust: Duty) -> f64 {
+        self.weights[0] * self.intake.power(intake) + self.weights[1] * self.exhaust.power(exhaust)
+    }
+
+    /// Returns the quietest intake and exhaust duties adding up to a total in percent.
+    ///
+    /// The exhaust stays on the side of the intake the ratio puts it on, no faster than the
+    /// intake below 100% and no slower above, so the enclosure's pressure keeps its sign. Each
+    /// fan either stops or runs at least at the first duty of its table. Returns `None` if no
+    /// pair of duties fits.
+    pub fn split(&self, total: u32, ratio: u8) -> Option<(Duty, Duty)> {
+        let usable =
+            |duty: Duty, profile: &NoiseProfile| duty == Duty::OFF || duty >= profile.slowest();
+        (0..=100u32)
+            .filter_map(|intake| {
+                let exhaust = total.checked_sub(intake)?;
+                let (intake, exhaust) = (
+                    Duty::new(intake as u8)?,
+                    Duty::new(u8::try_from(exhaust).ok()?)?,
+                );
+                let side = if ratio <= 100 {
+                    exhaust <= intake
+                } else {
+                    exhaust >= intake
+                };
+                (side && usable(intake, &self.intake) && usable(exhaust, &self.exhaust))
+                    .then_some((intake, exhaust))
+            })
+            .min_by(|a, b| {
+                self.noise(a.0, a.1)
+                    .partial_cmp(&self.noise(b.0, b.1))
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+}
+
+/// Checks that the quiet objective has both profiles and a weight for each fan.
+pub fn validate(args: &Args) -> Result<(), String> {
+    if args.fan_objective == Objective::Quiet
+        && (args.intake_noise.is_none() || args.exhaust_noise.is_none())
+    {
+        return Err("--fan-objective quiet needs --intake-noise and --exhaust-noise".to_string());
+    }
+    if !args.noise_weight.is_empty() && args.noise_weight.len() != 2 {
+        return Err(format!(
+            "--noise-weight needs 2 values, for the intake and the exhaust, got {}",
+            args.noise_weight.len()
+        ));
+    }
+    if args
+        .noise_weight
+        .iter()
+        .any(|weight| weight.is_nan() || *weight < 0.0)
+    {
+        return Err("--noise-weight values must not be negative".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_noise_profile, Acoustics};
+    use crate::pwm::tests::duty;
+
+    #[test]
+    fn parses_and_interpolates_profiles() {
+        let profile = parse_noise_profile("20:18,50:27,100:39").unwrap();
+        assert_eq!("20:18,50:27,100:39", profile.to_string());
+        assert_eq!(18.0, profile.dba(duty(10)));
+        assert_eq!(21.0, profile.dba(duty(30)));
+        assert_eq!(39.0, profile.dba(duty(100)));
+
+        assert!(parse_noise_profile("50:27,20:18").is_err());
+        assert!(parse_noise_profile("20:loud").is_err());
+        assert!(parse_noise_profile("120:40").is_err());
+    }
+
+    #[test]
+    fn splits_total_for_least_weighted_noise() {
+        let quiet = parse_noise_profile("20:15,50:25,100:40").unwrap();
+        let loud = parse_noise_profile("20:25,50:35,100:50").unwrap();
+
+        // Equal fans share evenly, the exhaust no faster than the intake
+        let even = Acoustics::new(quiet.clone(), quiet.clone(), [1.0, 1.0]);
+        assert_eq!(Some((duty(60), duty(60))), even.split(120, 80));
+        assert_eq!(Some((duty(0), duty(0))), even.split(0, 80));
+
+        // A louder exhaust takes a smaller share, and stops while the intake alone is quieter
+        let loud_exhaust = Acoustics::new(quiet.clone(), loud.clone(), [1.0, 1.0]);
+        assert_eq!(Some((duty(72), duty(38))), loud_exhaust.split(110, 80));
+        assert_eq!(Some((duty(30), duty(0))), loud_exhaust.split(30, 80));
+
+        // Above a ratio of 100, the exhaust stays at least as fast as the intake
+        assert_eq!(Some((duty(55), duty(55))), loud_exhaust.split(110, 120));
+
+        // Weighting the exhaust down moves the airflow onto it, as far as the pressure allows
+        let weighted = Acoustics::new(loud, quiet, [1.0, 0.1]);
+        assert_eq!(Some((duty(50), duty(50))), weighted.split(100, 80));
+
+        // No pair of duties reaches more than both fans at full speed
+        assert_eq!(None, even.split(201, 80));
+    }
+}
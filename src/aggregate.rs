@@ -0,0 +1,249 @@
+//! Several temperature sources combined into one reading, e.g. the CPU and a case probe.
+
+use crate::{
+    curve::FanCurve,
+    sensor::{Sensor, SensorError},
+    units::{Celsius, Duty},
+};
+use clap::ValueEnum;
+
+/// How the readings of several sources are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Aggregate {
+    /// Hottest reading
+    #[default]
+    Max,
+    /// Average of the readings
+    Mean,
+    /// Average weighted by --sensor-weight
+    WeightedMean,
+    /// Each reading through its own --sensor-curve, the highest duty wins
+    HighestDuty,
+}
+
+/// Sources read together and combined into a single temperature.
+///
+/// A source that can't be read fails the whole reading, so the fan runs at maximum speed rather
+/// than following the sources left.
+pub struct Aggregator {
+    sensors: Vec<Box<dyn Sensor>>,
+    aggregate: Aggregate,
+    /// Weight of each source for a weighted mean
+    weights: Vec<f64>,
+    /// Curve of each source when taking the highest duty
+    curves: Vec<FanCurve>,
+    /// Readings of the last successful read, in source order
+    readings: Vec<Celsius>,
+}
+
+impl Aggregator {
+    /// Returns an aggregator over the sources, with a weight and curve for each as the aggregate
+    /// needs them.
+    pub fn new(
+        sensors: Vec<Box<dyn Sensor>>,
+        aggregate: Aggregate,
+        weights: Vec<f64>,
+        curves: Vec<FanCurve>,
+    ) -> Self {
+        Self {
+            sensors,
+            aggregate,
+            weights,
+            curves,
+            readings: Vec::new(),
+        }
+    }
+
+    /// Combines readings, in source order, into one temperature, or returns `None` if they
+    /// can't be: without readings, or for a weighted mean without one weight per reading whose
+    /// sum is positive.
+    ///
+    /// Taking the highest duty leaves the hottest reading as the temperature, so the maximum
+    /// temperature still applies to every source.
+    pub fn combine(&self, readings: &[Celsius]) -> Option<Celsius> {
+        let millidegrees = readings
+            .iter()
+            .map(|reading| f64::from(reading.millidegrees()));
+        let combined = match self.aggregate {
+            Aggregate::Max | Aggregate::HighestDuty => return readings.iter().copied().max(),
+            Aggregate::Mean if readings.is_empty() => return None,
+            Aggregate::Mean => millidegrees.sum::<f64>() / readings.len() as f64,
+            Aggregate::WeightedMean => {
+                let total: f64 = self.weights.iter().sum();
+                if self.weights.len() != readings.len() || total.is_nan() || total <= 0.0 {
+                    return None;
+                }
+                let weighted: f64 = millidegrees
+                    .zip(&self.weights)
+                    .map(|(reading, weight)| reading * weight)
+                    .sum();
+                weighted / total
+            }
+        };
+        Some(Celsius::from_millidegrees(combined.round() as i32))
+    }
+}
+
+impl Sensor for Aggregator {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        let readings = self
+            .sensors
+            .iter_mut()
+            .map(|sensor| sensor.read())
+            .collect::<Result<Vec<_>, _>>()?;
+        let combined = self.combine(&readings).ok_or_else(|| {
+            SensorError::Parse(format!(
+                "cannot combine {} readings by {:?}",
+                readings.len(),
+                self.aggregate
+            ))
+        })?;
+        self.readings = readings;
+        Ok(combined)
+    }
+
+    fn demand(&self) -> Option<Duty> {
+        if self.aggregate != Aggregate::HighestDuty {
+            return None;
+        }
+        self.curves
+            .iter()
+            .zip(&self.readings)
+            .map(|(curve, reading)| curve.duty(*reading))
+            .max()
+    }
+}
+
+/// Checks that the options give a weight or curve for every source where the aggregate needs
+/// them.
+pub fn validate(
+    sources: usize,
+    aggregate: Aggregate,
+    weights: &[f64],
+    curves: &[FanCurve],
+) -> Result<(), String> {
+    match aggregate {
+        Aggregate::WeightedMean if weights.len() != sources => Err(format!(
+            "--aggregate weighted-mean needs {} --sensor-weight values, one per sensor, got {}",
+            sources,
+            weights.len()
+        )),
+        Aggregate::WeightedMean
+            if weights
+                .iter()
+                .any(|weight| weight.is_nan() || *weight < 0.0) =>
+        {
+            Err("--sensor-weight values must not be negative".to_string())
+        }
+        Aggregate::WeightedMean if weights.iter().sum::<f64>() <= 0.0 => {
+            Err("--sensor-weight values must not all be zero".to_string())
+        }
+        Aggregate::HighestDuty if curves.len() != sources => Err(format!(
+            "--aggregate highest-duty needs {} --sensor-curve values, one per sensor, got {}",
+            sources,
+            curves.len()
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, Aggregate, Aggregator};
+    use crate::{
+        curve::parse_curve,
+        pwm::tests::duty,
+        sensor::{Sensor, SensorError},
+        units::Celsius,
+    };
+
+    /// Sensor always reading the same, or failing for `None`.
+    struct Fixed(Option<Celsius>);
+
+    impl Sensor for Fixed {
+        fn read(&mut self) -> Result<Celsius, SensorError> {
+            self.0
+                .ok_or_else(|| SensorError::Parse("unplugged".to_string()))
+        }
+    }
+
+    fn aggregator(aggregate: Aggregate, readings: &[Option<Celsius>]) -> Aggregator {
+        let sensors = readings
+            .iter()
+            .map(|reading| Box::new(Fixed(*reading)) as Box<dyn Sensor>)
+            .collect();
+        Aggregator::new(
+            sensors,
+            aggregate,
+            vec![3.0, 1.0],
+            vec![
+                parse_curve("40:20,60:100").unwrap(),
+                parse_curve("30:20,40:60").unwrap(),
+            ],
+        )
+    }
+
+    #[test]
+    fn combines_readings() {
+        let readings = [Some(Celsius::new(50, 0)), Some(Celsius::new(38, 0))];
+
+        let mut max = aggregator(Aggregate::Max, &readings);
+        assert_eq!(Celsius::new(50, 0), max.read().unwrap());
+        assert_eq!(None, max.demand());
+
+        let mut mean = aggregator(Aggregate::Mean, &readings);
+        assert_eq!(Celsius::new(44, 0), mean.read().unwrap());
+
+        let mut weighted = aggregator(Aggregate::WeightedMean, &readings);
+        assert_eq!(Celsius::new(47, 0), weighted.read().unwrap());
+    }
+
+    #[test]
+    fn highest_duty_of_per_sensor_curves() {
+        let mut aggregator = aggregator(
+            Aggregate::HighestDuty,
+            &[Some(Celsius::new(50, 0)), Some(Celsius::new(38, 0))],
+        );
+        assert_eq!(None, aggregator.demand());
+
+        assert_eq!(Celsius::new(50, 0), aggregator.read().unwrap());
+        assert_eq!(Some(duty(60)), aggregator.demand());
+    }
+
+    #[test]
+    fn any_unreadable_source_fails_the_reading() {
+        let mut aggregator = aggregator(Aggregate::Max, &[Some(Celsius::new(50, 0)), None]);
+        assert!(aggregator.read().is_err());
+    }
+
+    #[test]
+    fn weights_that_cannot_average_fail_the_reading() {
+        let readings = [Celsius::new(45, 0), Celsius::new(50, 0)];
+        let sensors = || {
+            readings
+                .iter()
+                .map(|reading| Box::new(Fixed(Some(*reading))) as Box<dyn Sensor>)
+                .collect()
+        };
+
+        let mut zero = Aggregator::new(sensors(), Aggregate::WeightedMean, vec![0.0, 0.0], vec![]);
+        assert_eq!(None, zero.combine(&readings));
+        let missing = Aggregator::new(sensors(), Aggregate::WeightedMean, vec![1.0], vec![]);
+        assert_eq!(None, missing.combine(&readings));
+
+        // Failing the reading runs the fan at maximum rather than at the duty for 0°C
+        assert!(zero.read().is_err());
+    }
+
+    #[test]
+    fn validates_weights_and_curves() {
+        let curve = parse_curve("40:20,60:100").unwrap();
+        assert!(validate(2, Aggregate::Max, &[], &[]).is_ok());
+        assert!(validate(2, Aggregate::WeightedMean, &[1.0], &[]).is_err());
+        assert!(validate(2, Aggregate::WeightedMean, &[1.0, -1.0], &[]).is_err());
+        assert!(validate(2, Aggregate::WeightedMean, &[0.0, 0.0], &[]).is_err());
+        assert!(validate(2, Aggregate::WeightedMean, &[2.0, 1.0], &[]).is_ok());
+        assert!(validate(2, Aggregate::HighestDuty, &[], std::slice::from_ref(&curve)).is_err());
+        assert!(validate(2, Aggregate::HighestDuty, &[], &[curve.clone(), curve]).is_ok());
+    }
+}
@@ -0,0 +1,264 @@
+//! Warning and critical thresholds of sensors that are only monitored, e.g. a drive or the case,
+//! raising events whether or not the sensor takes part in fan control.
+
+use crate::{
+    args::Args,
+    events::Event,
+    sensor::{self, Sensor, SensorSource},
+    units::Celsius,
+};
+use std::fmt;
+
+/// Distance a reading falls below a threshold before its level clears, so a reading hovering at
+/// the threshold doesn't raise an event every poll
+const HYSTERESIS: Celsius = Celsius::new(1, 0);
+
+/// How far a monitored reading has risen, in increasing severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum AlarmLevel {
+    #[default]
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl AlarmLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlarmLevel::Normal => "normal",
+            AlarmLevel::Warning => "warning",
+            AlarmLevel::Critical => "critical",
+        }
+    }
+}
+
+impl fmt::Display for AlarmLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Sensor with its warning and critical thresholds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alarm {
+    pub warn: Celsius,
+    pub crit: Celsius,
+    /// Source as given, naming the sensor in events
+    pub name: String,
+    pub source: SensorSource,
+}
+
+impl Alarm {
+    /// Returns the level of a reading, given the level of the one before.
+    fn level(&self, previous: AlarmLevel, reading: Celsius) -> AlarmLevel {
+        let reached = |threshold: Celsius, level: AlarmLevel| {
+            reading >= threshold || (previous >= level && reading > threshold - HYSTERESIS)
+        };
+        if reached(self.crit, AlarmLevel::Critical) {
+            AlarmLevel::Critical
+        } else if reached(self.warn, AlarmLevel::Warning) {
+            AlarmLevel::Warning
+        } else {
+            AlarmLevel::Normal
+        }
+    }
+}
+
+/// Parses thresholds followed by a sensor source, e.g. `60:70:/sys/class/hwmon/hwmon2/temp1_input`
+/// or `55:65:thermal:gpu-thermal`.
+pub fn parse_alarm(value: &str) -> Result<Alarm, String> {
+    let invalid = || {
+        format!(
+            "invalid alarm {:?}, expected e.g. 60:70:/sys/class/hwmon/hwmon2/temp1_input",
+            value
+        )
+    };
+    let mut parts = value.splitn(3, ':');
+    let (Some(warn), Some(crit), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    let warn: Celsius = warn.trim().parse().map_err(|_| invalid())?;
+    let crit: Celsius = crit.trim().parse().map_err(|_| invalid())?;
+    if crit < warn {
+        return Err(format!(
+            "alarm {:?} has its critical threshold below the warning one",
+            value
+        ));
+    }
+    Ok(Alarm {
+        warn,
+        crit,
+        name: name.to_string(),
+        source: sensor::parse_source(name)?,
+    })
+}
+
+/// Monitored sensor and the level of its last reading.
+struct Watched {
+    alarm: Alarm,
+    sensor: Box<dyn Sensor>,
+    level: AlarmLevel,
+    /// Whether the last read failed, so an outage is only logged once
+    failed: bool,
+}
+
+/// Sensors watched for their alarm thresholds.
+pub struct Alarms {
+    watched: Vec<Watched>,
+}
+
+impl Alarms {
+    pub fn new(alarms: Vec<(Alarm, Box<dyn Sensor>)>) -> Self {
+        Self {
+            watched: alarms
+                .into_iter()
+                .map(|(alarm, sensor)| Watched {
+                    alarm,
+                    sensor,
+                    level: AlarmLevel::Normal,
+                    failed: false,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the alarms of the options, if any are given.
+    pub fn from_args(args: &Args) -> Option<Self> {
+        (!args.alarm.is_empty()).then(|| {
+            Self::new(
+                args.alarm
+                    .iter()
+                    .map(|alarm| (alarm.clone(), sensor::build(&alarm.source, args)))
+                    .collect(),
+            )
+        })
+    }
+
+    /// Whether these are the alarms given, so a reload can keep their levels.
+    pub fn watches(&self, alarms: &[Alarm]) -> bool {
+        self.watched
+            .iter()
+            .map(|watched| &watched.alarm)
+            .eq(alarms.iter())
+    }
+
+    /// Reads every sensor and returns an event for each whose level changed.
+    ///
+    /// A sensor that can't be read keeps its level, since it doesn't control the fan.
+    pub fn check(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        for watched in self.watched.iter_mut() {
+            let reading = match watched.sensor.read() {
+                Ok(reading) => reading,
+                Err(error) => {
+                    if !watched.failed {
+                        log::warn!(
+                            "Failed to read alarm sensor {}: {}",
+                            watched.alarm.name,
+                            error
+                        );
+                        watched.failed = true;
+                    }
+                    continue;
+                }
+            };
+            watched.failed = false;
+
+            let level = watched.alarm.level(watched.level, reading);
+            if level != watched.level {
+                watched.level = level;
+                events.push(Event::Alarm {
+                    sensor: watched.alarm.name.clone(),
+                    level,
+                    temperature: reading,
+                });
+            }
+        }
+        events
+    }
+
+    /// Returns the most severe level of the last readings.
+    pub fn level(&self) -> AlarmLevel {
+        self.watched
+            .iter()
+            .map(|watched| watched.level)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_alarm, AlarmLevel, Alarms};
+    use crate::{
+        events::Event,
+        sensor::{Sensor, SensorError, SensorSpec},
+        units::Celsius,
+    };
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    /// Sensor returning queued readings in tenths of a degree, `None` standing for a failed read.
+    struct Scripted(Rc<RefCell<VecDeque<Option<i32>>>>);
+
+    impl Sensor for Scripted {
+        fn read(&mut self) -> Result<Celsius, SensorError> {
+            match self.0.borrow_mut().pop_front().flatten() {
+                Some(tenths) => Ok(Celsius::from_millidegrees(tenths * 100)),
+                None => Err(SensorError::Parse(String::new())),
+            }
+        }
+    }
+
+    #[test]
+    fn parses_thresholds_and_source() {
+        let alarm = parse_alarm("55:65.5:thermal:gpu-thermal").unwrap();
+        assert_eq!(Celsius::new(55, 0), alarm.warn);
+        assert_eq!(Celsius::new(65, 500), alarm.crit);
+        assert_eq!("thermal:gpu-thermal", alarm.name);
+        assert_eq!(
+            SensorSpec::ThermalZone("gpu-thermal".to_string()),
+            alarm.source.spec
+        );
+
+        assert!(parse_alarm("60:/sys/x").is_err());
+        assert!(parse_alarm("70:60:/sys/x").is_err());
+        assert!(parse_alarm("warm:70:/sys/x").is_err());
+    }
+
+    #[test]
+    fn reports_level_changes_with_hysteresis() {
+        let readings = [
+            Some(500),
+            Some(605),
+            Some(615),
+            Some(705),
+            Some(695),
+            Some(685),
+            None,
+            Some(585),
+        ];
+        let sensor = Scripted(Rc::new(RefCell::new(readings.into_iter().collect())));
+        let alarm = parse_alarm("60:70:/sys/x").unwrap();
+        let mut alarms = Alarms::new(vec![(alarm, Box::new(sensor) as Box<dyn Sensor>)]);
+
+        let mut levels = Vec::new();
+        for _ in readings {
+            for event in alarms.check() {
+                let Event::Alarm { level, .. } = event else {
+                    panic!("unexpected event {:?}", event);
+                };
+                levels.push(level);
+            }
+        }
+        assert_eq!(
+            vec![
+                AlarmLevel::Warning,
+                AlarmLevel::Critical,
+                AlarmLevel::Warning,
+                AlarmLevel::Normal
+            ],
+            levels
+        );
+        assert_eq!(AlarmLevel::Normal, alarms.level());
+    }
+}
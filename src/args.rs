@@ -0,0 +1,913 @@
+use crate::{
+    ab_test,
+    acoustic::{self, NoiseProfile, Objective},
+    aggregate::{self, Aggregate},
+    alarm::{self, Alarm},
+    clock,
+    console::{ConsoleMode, DecimalSeparator},
+    curve::{self, FanCurve},
+    encoder,
+    energy::{self, SignalKind, Source},
+    events::Overflow,
+    frequency,
+    i18n::Language,
+    linearize::{self, Linearization},
+    lirc::{self, Binding},
+    logging::Filter,
+    mcp23017,
+    pipeline::{self, Pipeline},
+    plot::PlotFormat,
+    schedule::{self, Window},
+    script::{self, Script},
+    season::{self, Season},
+    sensor::{self, SensorSource, SensorSpec},
+    serial::ProtocolKind,
+    status_file::StatusFormat,
+    stepping,
+    target_schedule::{self, TargetSchedule},
+    telemetry::{self, HttpUrl},
+    temperature,
+    template::Template,
+    units::{Celsius, Duty, Rounding},
+};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_override_self = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub operation: Option<Operation>,
+
+    /// Read default options from this TOML file, options given here take precedence
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Apply the settings of this `[profiles.<name>]` section of the config file, e.g. silent.
+    /// Switched while running through the control socket, or to the next one in name order by
+    /// SIGUSR1
+    #[arg(long, requires = "config")]
+    pub profile: Option<String>,
+
+    /// Name of this controller when several run on one host, e.g. one per enclosure. Appended to
+    /// the control socket, status file, event log and spool file names and the mDNS service name,
+    /// and sent with telemetry events
+    #[arg(long, value_parser = crate::instance::parse_name)]
+    pub instance_name: Option<String>,
+
+    /// Minimum allowed fan speed in percent
+    #[arg(long, default_value_t = Duty::new(30).unwrap())]
+    pub pwm_min: Duty,
+
+    /// Maximum allowed fan speed in percent
+    #[arg(long, default_value_t = Duty::FULL)]
+    pub pwm_max: Duty,
+
+    /// Fan speed in percent never gone below except to stop the fan, defaults to --pwm-min
+    #[arg(long)]
+    pub pwm_hard_min: Option<Duty>,
+
+    /// Fan speed in percent never exceeded, defaults to --pwm-max. Emergencies such as reaching
+    /// --temperature-max-value run the fan at this speed, above --pwm-max
+    #[arg(long)]
+    pub pwm_hard_max: Option<Duty>,
+
+    #[arg(long, default_value_t = 2)]
+    pub pwm_increment: u8,
+
+    #[arg(long, default_value_t = 1)]
+    pub pwm_decrement: u8,
+
+    /// Percent added to the increment for each °C/s the temperature rose since the last poll,
+    /// to catch fast rises quickly; 0 keeps the increment fixed
+    #[arg(long, default_value_t = 0)]
+    pub ramp_gain: u8,
+
+    /// Degrees either side of the target temperature within which the fan speed is left alone
+    #[arg(long, default_value_t = stepping::DEADZONE)]
+    pub deadzone: Celsius,
+
+    /// Milliseconds to run the fan at full speed when it rises from below --kick-start-below,
+    /// for fans that won't spin up from a standstill at low duties; 0 disables kick-starts
+    #[arg(long, default_value_t = 0)]
+    pub kick_start_ms: u64,
+
+    /// Most percentage points the fan speed may change by per minute, however large the
+    /// increment, decrement or fan curve step. Overheating and faults still go to full speed at once
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    pub pwm_slew_rate: Option<u8>,
+
+    /// Seconds to keep a fan speed before changing it again, however often the temperature is
+    /// polled, so a fluctuating load doesn't keep changing the fan's sound. Reaching the max
+    /// temperature still changes it at once
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub min_dwell: Option<u64>,
+
+    /// Fan speed at full CPU load, scaled down to --pwm-min when idle. The fan runs at least as
+    /// fast as the CPU load of the last poll calls for, spinning up as a heavy job starts rather
+    /// than once the temperature has risen
+    #[arg(long)]
+    pub cpu_load_duty: Option<Duty>,
+
+    /// Fan speed to run at least at while the Raspberry Pi firmware reports the soft temperature
+    /// limit, or throttling along with under-voltage, applied at once rather than stepped to
+    #[arg(long)]
+    pub throttle_duty: Option<Duty>,
+
+    /// Map cooling percent to fan duty with cooling:duty points, e.g. 25:40,50:60,100:100, so
+    /// the controller, --pwm-min and --pwm-max work in cooling percent rather than duty
+    #[arg(long, value_parser = linearize::parse_linearization)]
+    pub linearize: Option<Linearization>,
+
+    /// Duty the fan must be below for a rise to be kick-started, 1 for only a stopped fan
+    #[arg(long, default_value_t = Duty::new(1).unwrap())]
+    pub kick_start_below: Duty,
+
+    /// Stop the fan completely below this temperature, rather than keeping it at --pwm-min
+    #[arg(long)]
+    pub fan_off_below: Option<Celsius>,
+
+    /// Start a stopped fan again above this temperature, the target temperature by default
+    #[arg(long, requires = "fan_off_below")]
+    pub fan_restart_above: Option<Celsius>,
+
+    /// Control the fan on and off only, for relay or transistor fans without PWM: switch it on at
+    /// --pwm-max above this temperature, and off again below --switch-off-below
+    #[arg(
+        long,
+        requires = "switch_off_below",
+        conflicts_with_all = ["fan_off_below", "fan_curve", "duty_script"]
+    )]
+    pub switch_on_above: Option<Celsius>,
+
+    /// Switch the fan off below this temperature in on/off control
+    #[arg(long, requires = "switch_on_above")]
+    pub switch_off_below: Option<Celsius>,
+
+    /// Set the fan speed from a curve of temperature:percent points, e.g. 40:30,50:60,65:100,
+    /// interpolated in between, instead of stepping towards the target temperature
+    #[arg(long, value_parser = curve::parse_curve)]
+    pub fan_curve: Option<FanCurve>,
+
+    /// Decide the fan speed with a Rhai script, falling back to stepping towards the target
+    /// temperature if it fails
+    #[arg(long, value_parser = script::parse_script_file, conflicts_with = "fan_curve")]
+    pub duty_script: Option<Script>,
+
+    /// Target temperature to maintain
+    #[arg(short, long, default_value_t = Celsius::new(40, 0))]
+    pub temperature_target_value: Celsius,
+
+    /// Max allowed temperature value
+    #[arg(long, default_value_t = Celsius::new(70, 0))]
+    pub temperature_max_value: Celsius,
+
+    /// Temperature at which the fan runs at --pwm-hard-max regardless of a fan stop, the minimum
+    /// dwell and anything else holding it lower
+    #[arg(long)]
+    pub temperature_hard_max: Option<Celsius>,
+
+    /// Decimals temperature readings are rounded to before control decisions, logs and exports
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=3))]
+    pub temperature_precision: u8,
+
+    /// How readings are rounded to --temperature-precision: half-up, half-even, floor or ceil
+    #[arg(long, default_value_t = Rounding::HalfUp)]
+    pub temperature_rounding: Rounding,
+
+    /// Target temperature between two dates of the year, e.g. 11-01..03-31=38 for winter; may be
+    /// repeated, the target option applies outside all seasons
+    #[arg(long, value_parser = season::parse_season)]
+    pub season: Vec<Season>,
+
+    /// Target temperature through the day as time=target points, e.g. 07:00=45,22:00=38, moving
+    /// gradually from one point to the next instead of the target option
+    #[arg(long, value_parser = target_schedule::parse_target_schedule, conflicts_with = "season")]
+    pub target_schedule: Option<TargetSchedule>,
+
+    /// Electricity price or solar surplus to bias the target temperature by, read from a file,
+    /// an http:// URL or an mqtt://host[:port]/topic
+    #[arg(long, value_parser = energy::parse_source)]
+    pub energy_input: Option<Source>,
+
+    /// What the energy input measures
+    #[arg(long, value_enum, default_value_t = SignalKind::Price)]
+    pub energy_signal: SignalKind,
+
+    /// Energy input value above which the target temperature is biased
+    #[arg(long, default_value_t = 0.0)]
+    pub energy_threshold: f64,
+
+    /// How far the target temperature moves above the threshold, up for prices and down for
+    /// surplus
+    #[arg(long, default_value_t = Celsius::new(2, 0))]
+    pub energy_bias: Celsius,
+
+    /// How often the energy input is read from a file or URL
+    #[arg(long, default_value = "5m", value_parser = clock::parse_duration)]
+    pub energy_interval: Duration,
+
+    /// Number of latest readings averaged for control decisions, to keep a noisy sensor from
+    /// making the fan hunt; raw readings are still logged
+    #[arg(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub smoothing_window: usize,
+
+    /// Smooth readings with an exponentially weighted average instead, each new reading
+    /// weighted by this factor between 0 and 1; lower values smooth more
+    #[arg(long, conflicts_with = "smoothing_window", value_parser = temperature::parse_alpha)]
+    pub smoothing_alpha: Option<f64>,
+
+    /// Smooth readings with a Kalman filter instead, tracking the temperature and its rate of
+    /// change, given the standard deviation of the sensor's noise, e.g. 0.5
+    #[arg(long, conflicts_with_all = ["smoothing_window", "smoothing_alpha"])]
+    pub kalman_measurement_noise: Option<Celsius>,
+
+    /// How quickly the rate of change may itself change in the Kalman filter, in °C/s²; higher
+    /// values follow sudden load changes sooner but smooth less
+    #[arg(long, default_value_t = 0.01, requires = "kalman_measurement_noise", value_parser = temperature::parse_process_noise)]
+    pub kalman_process_noise: f64,
+
+    /// Temperature file to read; may be repeated to read several thermal zones, e.g. the CPU, GPU
+    /// and PMIC, each poll, combined by --aggregate with the first one as the primary sensor
+    #[arg(long, default_value = "/sys/class/thermal/thermal_zone0/temp")]
+    pub temperature_file_path: Vec<String>,
+
+    /// Read temperature from a LibreHardwareMonitor WMI sensor instead of a file (Windows),
+    /// e.g. /amdcpu/0/temperature/2
+    #[arg(long, conflicts_with = "temperature_file_path")]
+    pub lhm_sensor: Option<String>,
+
+    /// Read temperature from the thermal zone of this type, e.g. cpu-thermal, instead of a file.
+    /// The zone is looked up at startup and again whenever it can't be read, so it's found after
+    /// kernel or firmware updates renumber the zones
+    #[arg(long, conflicts_with_all = ["temperature_file_path", "lhm_sensor"])]
+    pub thermal_zone: Option<String>,
+
+    /// Read temperature from an hwmon channel given as chip/channel instead of a file, e.g.
+    /// cpu_thermal/temp1 or nvme/Composite by its label. The chip is found by its name at startup
+    /// and again whenever it can't be read, so it's found after the hwmon devices are renumbered
+    #[arg(long, conflicts_with_all = ["temperature_file_path", "lhm_sensor", "thermal_zone"], value_parser = sensor::parse_hwmon)]
+    pub hwmon_sensor: Option<SensorSpec>,
+
+    /// Sensor to fall back to while the primary one can't be read, as a file path,
+    /// `lhm:<identifier>`, `mcp3008:<channel>`, `thermal:<type>`, `hwmon:<chip>/<channel>`,
+    /// `nvme:<controller>` or `smart:<device>`; may be repeated to try several in order
+    #[arg(long, value_parser = sensor::parse_source)]
+    pub fallback_sensor: Vec<SensorSource>,
+
+    /// Another sensor read along with the primary one and combined with it by --aggregate, as a
+    /// file path, `lhm:<identifier>`, `mcp3008:<channel>`, `thermal:<type>`,
+    /// `hwmon:<chip>/<channel>`, `nvme:<controller>` or `smart:<device>`; may be repeated
+    #[arg(long, value_parser = sensor::parse_source)]
+    pub extra_sensor: Vec<SensorSource>,
+
+    /// Stages the primary sensor's readings are run through in order, e.g.
+    /// offset=-1.5,outlier=10; fallback and extra sensors take theirs after an @
+    #[arg(long, value_parser = pipeline::parse_pipeline)]
+    pub sensor_pipeline: Option<Pipeline>,
+
+    /// How the readings of the primary and extra sensors, including further temperature files,
+    /// are combined
+    #[arg(long, value_enum, default_value_t = Aggregate::Max)]
+    pub aggregate: Aggregate,
+
+    /// Weights of the primary and extra sensors in order for --aggregate weighted-mean,
+    /// e.g. 2,1
+    #[arg(long, value_delimiter = ',')]
+    pub sensor_weight: Vec<f64>,
+
+    /// Fan curve of each of the primary and extra sensors in order for --aggregate highest-duty;
+    /// repeated once per sensor
+    #[arg(
+        long,
+        value_parser = curve::parse_curve,
+        conflicts_with_all = ["fan_curve", "duty_script", "switch_on_above"]
+    )]
+    pub sensor_curve: Vec<FanCurve>,
+
+    /// Sensor watched for warning and critical thresholds without taking part in fan control,
+    /// as warn:crit:source, e.g. 60:70:/sys/class/hwmon/hwmon2/temp1_input; may be repeated
+    #[arg(long, value_parser = alarm::parse_alarm)]
+    pub alarm: Vec<Alarm>,
+
+    /// Reading of `smart:` disks while they're in standby, which smartctl leaves asleep rather
+    /// than waking them to ask
+    #[arg(long, default_value_t = Celsius::new(25, 0))]
+    pub smart_standby_temperature: Celsius,
+
+    /// Read temperature from a thermistor on this MCP3008 ADC channel instead of a file
+    #[arg(long, conflicts_with_all = ["temperature_file_path", "lhm_sensor", "thermal_zone", "hwmon_sensor"], value_parser = clap::value_parser!(u8).range(0..=7))]
+    pub mcp3008_channel: Option<u8>,
+
+    /// SPI device the MCP3008 is attached to
+    #[arg(long, default_value = "/dev/spidev0.0")]
+    pub spi_device: String,
+
+    /// Resistance in ohms of the resistor in series with the thermistor
+    #[arg(long, default_value_t = 10_000.0)]
+    pub thermistor_series_resistance: f64,
+
+    /// Steinhart-Hart coefficients A,B,C of the thermistor, defaults suit a common 10k NTC
+    #[arg(
+        long,
+        default_value = "1.009249522e-3,2.378405444e-4,2.019202697e-7",
+        value_parser = crate::mcp3008::parse_coefficients
+    )]
+    pub thermistor_coefficients: crate::mcp3008::SteinhartHart,
+
+    /// Temperature polling rate
+    #[arg(short, long, default_value_t = 5)]
+    pub pollrate: u64,
+
+    /// Poll adaptively, this often while the temperature is changing or above the target,
+    /// backing off towards --pollrate-max while it is stable below the target
+    #[arg(long, requires = "pollrate_max", conflicts_with = "pollrate")]
+    pub pollrate_min: Option<u64>,
+
+    /// Longest time between polls when polling adaptively
+    #[arg(long, requires = "pollrate_min")]
+    pub pollrate_max: Option<u64>,
+
+    /// Stop controlling the fan and exit after this long, e.g. 10m or 1h30m
+    #[arg(long, value_parser = clock::parse_duration)]
+    pub run_for: Option<Duration>,
+
+    /// Read the temperature once, apply the duty for it and exit, e.g. from a systemd timer.
+    /// The duty rises linearly from --pwm-min at the target to --pwm-max at the max temperature
+    #[arg(long, conflicts_with = "run_for")]
+    pub oneshot: bool,
+
+    /// Delay suspend and shutdown until the fan output has been released, using systemd-inhibit
+    #[arg(long)]
+    pub inhibit: bool,
+
+    /// GPIO pin (wiringPi numbering) of a button to ground: a short press toggles a boost at
+    /// maximum speed, a long press toggles quiet mode
+    #[arg(long)]
+    pub gpio_button: Option<i32>,
+
+    /// How long a boost from the button lasts
+    #[arg(long, default_value = "15m", value_parser = clock::parse_duration)]
+    pub boost_duration: Duration,
+
+    /// Maximum fan speed in percent in quiet mode, below the max temperature
+    #[arg(long, default_value_t = Duty::new(50).unwrap())]
+    pub quiet_pwm_max: Duty,
+
+    /// Daily time window in which quiet mode is switched on, e.g. 22:00-07:00; may be repeated
+    #[arg(long, value_parser = schedule::parse_window)]
+    pub quiet_hours: Vec<Window>,
+
+    /// Profile of the config file to switch to during quiet hours, switching back afterwards
+    #[arg(long, requires_all = ["quiet_hours", "config"])]
+    pub quiet_profile: Option<String>,
+
+    /// Two profiles of the config file to alternate between, e.g. silent,balanced, tagging events
+    /// in the event log and telemetry with the one in effect so their outcomes can be compared
+    #[arg(long, value_parser = ab_test::parse_profiles, requires = "config", conflicts_with = "quiet_profile")]
+    pub ab_test: Option<[String; 2]>,
+
+    /// How long each profile of the A/B test runs before the other takes over
+    #[arg(long, default_value = "1h", value_parser = clock::parse_duration)]
+    pub ab_period: Duration,
+
+    /// GPIO pins A,B (wiringPi numbering) of a rotary encoder adjusting the target temperature
+    #[arg(long, value_parser = encoder::parse_pins)]
+    pub gpio_encoder: Option<(i32, i32)>,
+
+    /// Read IR remote key presses from this lircd socket, e.g. /var/run/lirc/lircd
+    #[arg(long)]
+    pub lirc_socket: Option<PathBuf>,
+
+    /// IR remote key and the command it runs, one of boost, quiet, up or down, e.g.
+    /// KEY_RED=boost; may be repeated, defaults to KEY_RED, KEY_GREEN, KEY_UP and KEY_DOWN
+    #[arg(long, value_parser = lirc::parse_binding)]
+    pub lirc_key: Vec<Binding>,
+
+    /// How far each detent of the rotary encoder or press of an IR remote key moves the target
+    /// temperature
+    #[arg(long, default_value_t = Celsius::new(0, 500))]
+    pub encoder_step: Celsius,
+
+    /// Keep the fan at maximum speed after the max temperature is reached, until acknowledged
+    /// with `ack` on the control socket
+    #[arg(long)]
+    pub latch_overtemperature: bool,
+
+    /// Keep the fan at maximum speed for this long after the max temperature was last reached,
+    /// e.g. 60s, rather than stepping down as soon as the temperature dips below it
+    #[arg(long, value_parser = clock::parse_duration)]
+    pub emergency_hold: Option<Duration>,
+
+    /// How far ahead the temperature forecast reported by the status command looks, fitted to
+    /// the readings from as long ago
+    #[arg(long, default_value = "5m", value_parser = clock::parse_duration)]
+    pub forecast_horizon: Duration,
+
+    /// Number of notable events, such as faults and overrides, reported by the status command
+    #[arg(long, default_value_t = 20)]
+    pub status_history: usize,
+
+    /// Keep the latest status in this file, for dashboards such as RPi-Monitor and Cockpit
+    #[arg(long)]
+    pub status_file: Option<PathBuf>,
+
+    /// Layout of the status file
+    #[arg(long, value_enum, default_value_t = StatusFormat::KeyValue)]
+    pub status_format: StatusFormat,
+
+    /// Answer SNMP v1/v2c requests for temperature and duty on this address, e.g. 0.0.0.0:161
+    #[arg(long)]
+    pub snmp_listen: Option<SocketAddr>,
+
+    /// SNMP community requests must give
+    #[arg(long, default_value = "public")]
+    pub snmp_community: String,
+
+    /// Serve readings and the target temperature over Modbus TCP on this address, e.g. 0.0.0.0:502
+    #[arg(long)]
+    pub modbus_listen: Option<SocketAddr>,
+
+    /// Return to the automatic target unless a target set over Modbus or CoAP is set again within
+    /// this long, e.g. 10m; remote targets stay until changed without it
+    #[arg(long, value_parser = clock::parse_duration)]
+    pub override_ttl: Option<Duration>,
+
+    /// Serve temperature, fan and target resources over CoAP on this address, e.g. [::]:5683
+    #[arg(long)]
+    pub coap_listen: Option<SocketAddr>,
+
+    /// Advertise the CoAP endpoint over mDNS as _fan-controller._udp, through Avahi
+    #[arg(long, requires = "coap_listen")]
+    pub mdns: bool,
+
+    /// Serve temperature, fan duty and target as a read-only BLE GATT service, which needs
+    /// BlueZ's bluetoothd stopped
+    #[arg(long)]
+    pub ble: bool,
+
+    /// GPIO pin controlling the fan
+    #[arg(short, long, required_unless_present_any = ["serial_port", "mcp23017_pin"])]
+    pub gpio_pwm: Option<i32>,
+
+    /// GPIO pin controlling an exhaust fan paired with the main (intake) fan
+    #[arg(long)]
+    pub exhaust_gpio_pwm: Option<i32>,
+
+    /// Exhaust fan duty as a percentage of the intake duty, below 100 keeps the enclosure at
+    /// positive pressure
+    #[arg(long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(1..=200))]
+    pub exhaust_ratio: u8,
+
+    /// How the duty is shared between the intake and exhaust fans: at --exhaust-ratio, or quiet
+    /// for the least weighted noise at the same total duty
+    #[arg(long, value_enum, default_value_t = Objective::Ratio, requires = "exhaust_gpio_pwm")]
+    pub fan_objective: Objective,
+
+    /// Noise of the intake fan as duty:dBA points, e.g. 20:18,50:27,100:39, the first at the
+    /// slowest duty it runs at
+    #[arg(long, value_parser = acoustic::parse_noise_profile)]
+    pub intake_noise: Option<NoiseProfile>,
+
+    /// Noise of the exhaust fan as duty:dBA points, e.g. 20:21,50:30,100:42
+    #[arg(long, value_parser = acoustic::parse_noise_profile, requires = "exhaust_gpio_pwm")]
+    pub exhaust_noise: Option<NoiseProfile>,
+
+    /// Weights of the intake and exhaust noise for --fan-objective quiet, e.g. 1,2 for an
+    /// exhaust facing the room
+    #[arg(long, value_delimiter = ',')]
+    pub noise_weight: Vec<f64>,
+
+    /// Drive a serial-attached fan controller instead of a GPIO pin, e.g. /dev/ttyUSB0 or COM3
+    #[arg(long, conflicts_with_all = ["gpio_pwm", "mcp23017_pin"])]
+    pub serial_port: Option<String>,
+
+    /// Serial port speed
+    #[arg(long, default_value_t = 9600)]
+    pub serial_baud: u32,
+
+    /// Protocol spoken to the serial fan controller
+    #[arg(long, value_enum, default_value_t = ProtocolKind::Text)]
+    pub serial_protocol: ProtocolKind,
+
+    /// Switch a relay on this MCP23017 expander pin instead of a GPIO pin, 0-7 for GPA0-7 and 8-15
+    /// for GPB0-7
+    #[arg(long, conflicts_with = "gpio_pwm", value_parser = clap::value_parser!(u8).range(0..=15))]
+    pub mcp23017_pin: Option<u8>,
+
+    /// I2C address of the MCP23017
+    #[arg(long, default_value = "0x20", value_parser = mcp23017::parse_address)]
+    pub mcp23017_address: u16,
+
+    /// I2C bus the MCP23017 is attached to
+    #[arg(long, default_value_t = 1)]
+    pub i2c_bus: u8,
+
+    /// Duty in percent at or above which a relay output is switched on
+    #[arg(long, default_value_t = Duty::new(50).unwrap())]
+    pub relay_on_duty: Duty,
+
+    /// Relay is switched on by driving its pin low
+    #[arg(long)]
+    pub relay_active_low: bool,
+
+    /// CPU core to pin the software PWM thread to, below the 1024 a CPU set holds
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(..1024))]
+    pub pwm_cpu: Option<usize>,
+
+    /// Realtime (SCHED_FIFO) priority for the software PWM thread, 1-99
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..=99))]
+    pub pwm_priority: Option<i32>,
+
+    /// Console output style, colored with an updating summary line when run in a terminal
+    #[arg(long, value_enum, default_value_t = ConsoleMode::Auto)]
+    pub console: ConsoleMode,
+
+    /// Decimal separator of temperatures in logs, console output and calibration results
+    #[arg(long, value_enum, default_value_t = DecimalSeparator::Point)]
+    pub decimal_separator: DecimalSeparator,
+
+    /// Language of the setup wizard and colored console output, from the locale by default
+    #[arg(long, value_enum)]
+    pub lang: Option<Language>,
+
+    /// Wording of the plain log line for fan speed changes, e.g. `{temp}°C -> {pwm}%`, with
+    /// placeholders {temp}, {target}, {from}, {pwm} and {direction}
+    #[arg(long)]
+    pub log_template: Option<Template>,
+
+    /// Log levels by module, e.g. `controller=debug,telemetry=warn,info`
+    #[arg(long, default_value = "info")]
+    pub log_filter: Filter,
+
+    /// Accept commands on a Unix socket at this path, e.g. to change the log filter while running
+    #[arg(long)]
+    pub control_socket: Option<PathBuf>,
+
+    /// Append events to this file as JSON lines
+    #[arg(long)]
+    pub event_log: Option<PathBuf>,
+
+    /// Show desktop notifications of overtemperature, the latch and sensor faults with
+    /// notify-send, for running within a desktop session
+    #[arg(long)]
+    pub desktop_notifications: bool,
+
+    /// Number of events buffered for a slow event log before they are dropped
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u64).range(1..))]
+    pub event_buffer: u64,
+
+    /// What to do with events arriving while the buffer is full
+    #[arg(long, value_enum, default_value_t = Overflow::Summarize)]
+    pub event_overflow: Overflow,
+
+    /// Post events as JSON to this plain HTTP collector, e.g. http://collector.local:8080/events
+    #[arg(long, value_parser = telemetry::parse_url)]
+    pub telemetry_url: Option<HttpUrl>,
+
+    /// Bearer token for the collector, as a file path or the name of a systemd credential
+    #[arg(long, requires = "telemetry_url")]
+    pub telemetry_token: Option<String>,
+
+    /// Keep events the collector doesn't accept in this file and replay them once it does
+    #[arg(long, requires = "telemetry_url")]
+    pub telemetry_spool: Option<PathBuf>,
+
+    /// Maximum number of spooled events, older ones are discarded first
+    #[arg(long, default_value_t = 10_000)]
+    pub telemetry_spool_limit: usize,
+
+    /// Print systemd service file content
+    #[arg(long)]
+    pub print_systemd: bool,
+
+    /// Keep the built-in defaults instead of those for the board model found in the device tree
+    #[arg(long)]
+    pub no_board_defaults: bool,
+
+    /// Default --temperature-max-value and --temperature-hard-max to 5°C below the throttling
+    /// and critical trip points of the thermal zone read from
+    #[arg(long)]
+    pub trip_point_defaults: bool,
+}
+
+impl Args {
+    /// Parses the options, rejecting those a reload would reject as unusable together.
+    pub fn try_parse_valid(argv: Vec<String>) -> Result<Self, clap::Error> {
+        let args = Self::try_parse_from(argv)?;
+        args.validate()
+            .map_err(|error| Self::command().error(ErrorKind::ArgumentConflict, error))?;
+        Ok(args)
+    }
+
+    /// Checks the options for combinations the parser can't catch on its own, at startup and
+    /// before a reload is applied.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pwm_min > self.pwm_max {
+            return Err(format!(
+                "--pwm-min {} exceeds --pwm-max {}",
+                self.pwm_min, self.pwm_max
+            ));
+        }
+        if let Some(hard_min) = self
+            .pwm_hard_min
+            .filter(|hard_min| *hard_min > self.pwm_min)
+        {
+            return Err(format!(
+                "--pwm-hard-min {} exceeds --pwm-min {}",
+                hard_min, self.pwm_min
+            ));
+        }
+        if let Some(hard_max) = self
+            .pwm_hard_max
+            .filter(|hard_max| *hard_max < self.pwm_max)
+        {
+            return Err(format!(
+                "--pwm-hard-max {} is below --pwm-max {}",
+                hard_max, self.pwm_max
+            ));
+        }
+        if self.temperature_target_value >= self.temperature_max_value {
+            return Err(format!(
+                "--temperature-target-value {} is not below --temperature-max-value {}",
+                self.temperature_target_value, self.temperature_max_value
+            ));
+        }
+        if let Some(hard_max) = self
+            .temperature_hard_max
+            .filter(|hard_max| *hard_max < self.temperature_max_value)
+        {
+            return Err(format!(
+                "--temperature-hard-max {} is below --temperature-max-value {}",
+                hard_max, self.temperature_max_value
+            ));
+        }
+        if self.energy_input.is_some()
+            && self.energy_signal == SignalKind::Price
+            && self.temperature_target_value.millidegrees() + self.energy_bias.millidegrees()
+                >= self.temperature_max_value.millidegrees()
+        {
+            return Err(format!(
+                "--temperature-target-value {} with --energy-bias {} is not below --temperature-max-value {}",
+                self.temperature_target_value, self.energy_bias, self.temperature_max_value
+            ));
+        }
+        if let (Some(min), Some(max)) = (self.pollrate_min, self.pollrate_max) {
+            if min > max {
+                return Err(format!(
+                    "--pollrate-min {} exceeds --pollrate-max {}",
+                    min, max
+                ));
+            }
+        }
+        if let (Some(off_below), Some(restart_above)) = (self.fan_off_below, self.fan_restart_above)
+        {
+            if restart_above < off_below {
+                return Err(format!(
+                    "--fan-restart-above {} is below --fan-off-below {}",
+                    restart_above, off_below
+                ));
+            }
+        }
+        if let (Some(on_above), Some(off_below)) = (self.switch_on_above, self.switch_off_below) {
+            if on_above < off_below {
+                return Err(format!(
+                    "--switch-on-above {} is below --switch-off-below {}",
+                    on_above, off_below
+                ));
+            }
+        }
+        acoustic::validate(self)?;
+        let extra = sensor::extra_sources(self);
+        if !extra.is_empty() {
+            aggregate::validate(
+                1 + extra.len(),
+                self.aggregate,
+                &self.sensor_weight,
+                &self.sensor_curve,
+            )?;
+        }
+        if let Some(season) = self
+            .season
+            .iter()
+            .find(|season| season.target >= self.temperature_max_value)
+        {
+            return Err(format!(
+                "--season {} target {} is not below --temperature-max-value {}",
+                season, season.target, self.temperature_max_value
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Operation to run instead of controlling the fan.
+#[derive(Subcommand, Debug)]
+pub enum Operation {
+    /// Measure the temperature the enclosure settles at for each fan duty, from `--pwm-max`
+    /// down to `--pwm-min`
+    Calibrate(CalibrateArgs),
+    /// Switch the fan between two duties around the target temperature, measure how the
+    /// temperature oscillates and print suggested PID gains, or with --live adjust the running
+    /// controller from a prompt
+    Tune(TuneArgs),
+    /// Hold a duty at a range of hardware PWM frequencies, noting how the fan responds to each
+    FrequencySweep(FrequencySweepArgs),
+    /// Convert another fan control tool's configuration into a config file for this one
+    #[command(subcommand)]
+    Import(ImportCommand),
+    /// Pack the config, calibration results, recent events and status into a tar archive, e.g.
+    /// for a bug report or to move the setup to a replacement SD card
+    ExportBundle(ExportBundleArgs),
+    /// Work with the fan speed over temperature the options give
+    #[command(subcommand)]
+    Curve(CurveCommand),
+    /// Pick a sensor, describe the fan and spin test it, then write a config file and systemd unit
+    Setup(SetupArgs),
+    /// Print a QR code with the endpoints and token a dashboard app needs to add this controller
+    PairingInfo(PairingInfoArgs),
+    /// Work with all controllers advertised over mDNS on the network
+    #[command(subcommand)]
+    Fleet(FleetCommand),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CalibrateArgs {
+    /// Percentage points to lower the fan duty by between steps
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub step: u8,
+
+    /// Seconds to hold each step for the temperature to settle
+    #[arg(long, default_value_t = 120, value_parser = clap::value_parser!(u64).range(1..))]
+    pub hold: u64,
+
+    /// Write results as CSV to this file instead of stdout
+    #[arg(long)]
+    pub results: Option<PathBuf>,
+
+    /// Also print a --linearize table learned from the results
+    #[arg(long)]
+    pub linearization: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TuneArgs {
+    /// Attach to the running controller through --control-socket instead and change its
+    /// settings from a prompt, e.g. `set target 45`, then undo them or commit them to --config
+    #[arg(long, conflicts_with_all = ["low", "high", "hysteresis", "cycles", "timeout", "rule"])]
+    pub live: bool,
+
+    /// Fan speed while the temperature is below the target
+    #[arg(long, default_value_t = Duty::new(30).unwrap())]
+    pub low: Duty,
+
+    /// Fan speed while the temperature is above the target
+    #[arg(long, default_value_t = Duty::FULL)]
+    pub high: Duty,
+
+    /// Distance past the target before switching, above the sensor noise
+    #[arg(long, default_value_t = Celsius::new(0, 300))]
+    pub hysteresis: Celsius,
+
+    /// Oscillations to average over, after the first one
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..=20))]
+    pub cycles: u64,
+
+    /// Give up after this long, e.g. 2h
+    #[arg(long, default_value = "2h", value_parser = clock::parse_duration)]
+    pub timeout: Duration,
+
+    /// Rule turning the measured oscillation into gains
+    #[arg(long, value_enum, default_value_t = crate::tune::Rule::Classic)]
+    pub rule: crate::tune::Rule,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct FrequencySweepArgs {
+    /// wiringPi pin wired to the hardware PWM peripheral, e.g. 1 for BCM GPIO 18
+    #[arg(long)]
+    pub pin: i32,
+
+    /// Fan speed in percent to hold at each frequency
+    #[arg(long, default_value_t = Duty::new(50).unwrap())]
+    pub duty: Duty,
+
+    /// Frequencies to try in Hz, e.g. 25,1000,25k
+    #[arg(long, default_value = "25,100,400,1k,5k,10k,25k", value_parser = frequency::parse_frequencies)]
+    pub frequencies: Vec<u32>,
+
+    /// Write notes as CSV to this file instead of stdout
+    #[arg(long)]
+    pub results: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImportCommand {
+    /// Convert an lm-sensors fancontrol configuration, as written by pwmconfig
+    Fancontrol(ImportFancontrolArgs),
+    /// Take the config file out of a bundle written by export-bundle
+    Bundle(ImportBundleArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportBundleArgs {
+    /// Bundle to take the config from
+    pub path: PathBuf,
+
+    /// Write the config to this file instead of stdout
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportBundleArgs {
+    /// Archive to write
+    #[arg(long, default_value = "fan-controller-bundle.tar")]
+    pub out: PathBuf,
+
+    /// Calibration results to include, as written by calibrate --results
+    #[arg(long)]
+    pub calibration: Option<PathBuf>,
+
+    /// Number of the latest events of --event-log to include
+    #[arg(long, default_value_t = 1000)]
+    pub events: usize,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportFancontrolArgs {
+    /// fancontrol configuration to convert
+    #[arg(default_value = "/etc/fancontrol")]
+    pub path: PathBuf,
+
+    /// Write the config to this file instead of stdout
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SetupArgs {
+    /// Config file to write
+    #[arg(long, default_value = "/etc/fan-controller/config.toml")]
+    pub out: PathBuf,
+
+    /// systemd unit to write, running the controller with the config file
+    #[arg(long, default_value = "/etc/systemd/system/fan-controller.service")]
+    pub unit: PathBuf,
+
+    /// Write the config without spinning the fan, assuming the usual minimum speed for its type
+    #[arg(long)]
+    pub skip_spin_test: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CurveCommand {
+    /// Plot the fan speed over temperature, with the operating point at the current reading
+    Render(CurveRenderArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CurveRenderArgs {
+    /// Write the plot to this file instead of stdout
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+
+    /// Format of the plot, by default from the file extension or text
+    #[arg(long, value_enum)]
+    pub format: Option<PlotFormat>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PairingInfoArgs {
+    /// Host name or address the app connects to, instead of this machine's host name
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Token to hand to the app, as a file path or the name of a systemd credential
+    #[arg(long)]
+    pub token: Option<String>,
+
+    /// Write the QR code as a PNG image to this file instead of the terminal
+    #[arg(long)]
+    pub png: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FleetCommand {
+    /// Print the temperature, duty and alerts of every controller in one table
+    Status(FleetStatusArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct FleetStatusArgs {
+    /// How long to wait for each controller to answer, e.g. 2s
+    #[arg(long, default_value = "2s", value_parser = clock::parse_duration)]
+    pub timeout: Duration,
+}
@@ -0,0 +1,513 @@
+//! Read-only BLE GATT service, so a phone app can check the enclosure when standing next to it.
+//!
+//! Serves a small GATT database straight over the L2CAP ATT channel and advertises it with
+//! `btmgmt`, so no D-Bus or Bluetooth library is needed. BlueZ's `bluetoothd` serves its own
+//! database on the same channel, so it must not be running.
+//!
+//! | Characteristic | UUID | Value |
+//! |----------------|------|-------|
+//! | Temperature | `2a6e` | Signed hundredths of a degree Celsius, `0x8000` while unknown |
+//! | Fan duty | `9b3c0002-7e4a-4b1f-9c2d-6a1e5f3b8d20` | Percent, `0xff` while unknown |
+//! | Target | `9b3c0003-7e4a-4b1f-9c2d-6a1e5f3b8d20` | Same as the temperature |
+//!
+//! All values are little-endian, as usual for GATT.
+
+use crate::{
+    status::{Snapshot, Status},
+    units::Celsius,
+};
+use std::{
+    io,
+    thread::{self, JoinHandle},
+};
+
+/// UUID the service is advertised with
+pub const SERVICE: &str = "9b3c0001-7e4a-4b1f-9c2d-6a1e5f3b8d20";
+
+/// UUIDs in the little-endian order ATT sends them in
+const SERVICE_UUID: [u8; 16] = uuid(0x0001);
+const DUTY_UUID: [u8; 16] = uuid(0x0002);
+const TARGET_UUID: [u8; 16] = uuid(0x0003);
+
+/// Bluetooth SIG Temperature characteristic
+const TEMPERATURE_UUID: u16 = 0x2a6e;
+const PRIMARY_SERVICE: u16 = 0x2800;
+const CHARACTERISTIC: u16 = 0x2803;
+/// Characteristic property allowing reads
+const READ: u8 = 0x02;
+
+const ERROR_RESPONSE: u8 = 0x01;
+const EXCHANGE_MTU_REQUEST: u8 = 0x02;
+const FIND_INFORMATION_REQUEST: u8 = 0x04;
+const FIND_BY_TYPE_VALUE_REQUEST: u8 = 0x06;
+const READ_BY_TYPE_REQUEST: u8 = 0x08;
+const READ_REQUEST: u8 = 0x0a;
+const READ_BLOB_REQUEST: u8 = 0x0c;
+const READ_BY_GROUP_TYPE_REQUEST: u8 = 0x10;
+const WRITE_REQUEST: u8 = 0x12;
+
+const INVALID_HANDLE: u8 = 0x01;
+const WRITE_NOT_PERMITTED: u8 = 0x03;
+const INVALID_PDU: u8 = 0x04;
+const REQUEST_NOT_SUPPORTED: u8 = 0x06;
+const INVALID_OFFSET: u8 = 0x07;
+const ATTRIBUTE_NOT_FOUND: u8 = 0x0a;
+const UNSUPPORTED_GROUP_TYPE: u8 = 0x10;
+
+/// Only the default MTU is used, which every client supports
+const MTU: usize = 23;
+
+/// Custom 128-bit UUID with the given 16-bit part, little-endian.
+const fn uuid(short: u16) -> [u8; 16] {
+    let [high, low] = short.to_be_bytes();
+    [
+        0x20, 0x8d, 0x3b, 0x5f, 0x1e, 0x6a, 0x2d, 0x9c, 0x1f, 0x4b, 0x4a, 0x7e, low, high, 0x3c,
+        0x9b,
+    ]
+}
+
+/// Type of an attribute, 16-bit for Bluetooth SIG types and 128-bit for custom ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Short(u16),
+    Long([u8; 16]),
+}
+
+impl Kind {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        match bytes.len() {
+            2 => Some(Kind::Short(u16::from_le_bytes([bytes[0], bytes[1]]))),
+            16 => Some(Kind::Long(bytes.try_into().unwrap())),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Kind::Short(uuid) => uuid.to_le_bytes().to_vec(),
+            Kind::Long(uuid) => uuid.to_vec(),
+        }
+    }
+}
+
+struct Attribute {
+    handle: u16,
+    kind: Kind,
+    value: Vec<u8>,
+}
+
+/// GATT database with the values of the given snapshot.
+pub struct Database {
+    attributes: Vec<Attribute>,
+}
+
+impl Database {
+    pub fn new(snapshot: &Snapshot) -> Self {
+        let characteristics = [
+            (
+                Kind::Short(TEMPERATURE_UUID),
+                centidegrees(snapshot.temperature),
+            ),
+            (
+                Kind::Long(DUTY_UUID),
+                vec![snapshot.duty.map_or(0xff, |duty| duty.percent())],
+            ),
+            (Kind::Long(TARGET_UUID), centidegrees(snapshot.target)),
+        ];
+
+        let mut attributes = vec![Attribute {
+            handle: 1,
+            kind: Kind::Short(PRIMARY_SERVICE),
+            value: SERVICE_UUID.to_vec(),
+        }];
+        for (kind, value) in characteristics {
+            let handle = attributes.len() as u16 + 1;
+            let mut declaration = vec![READ];
+            declaration.extend((handle + 1).to_le_bytes());
+            declaration.extend(kind.bytes());
+            attributes.push(Attribute {
+                handle,
+                kind: Kind::Short(CHARACTERISTIC),
+                value: declaration,
+            });
+            attributes.push(Attribute {
+                handle: handle + 1,
+                kind,
+                value,
+            });
+        }
+        Self { attributes }
+    }
+
+    /// Last handle of the service, which is the only one in the database.
+    fn group_end(&self) -> u16 {
+        self.attributes.len() as u16
+    }
+
+    fn range(&self, start: u16, end: u16) -> impl Iterator<Item = &Attribute> {
+        self.attributes
+            .iter()
+            .filter(move |attribute| (start..=end).contains(&attribute.handle))
+    }
+
+    /// Answers a request PDU, or returns `None` for commands and confirmations.
+    pub fn respond(&self, pdu: &[u8]) -> Option<Vec<u8>> {
+        let &opcode = pdu.first()?;
+        // Commands and confirmations get no response, whether supported or not
+        if opcode & 0x40 != 0 || opcode == 0x1e {
+            return None;
+        }
+        Some(
+            self.execute(opcode, &pdu[1..])
+                .unwrap_or_else(|(handle, code)| {
+                    vec![
+                        ERROR_RESPONSE,
+                        opcode,
+                        handle as u8,
+                        (handle >> 8) as u8,
+                        code,
+                    ]
+                }),
+        )
+    }
+
+    /// Returns the response, or the handle and code of the error.
+    fn execute(&self, opcode: u8, data: &[u8]) -> Result<Vec<u8>, (u16, u8)> {
+        let range = || -> Result<(u16, u16), (u16, u8)> {
+            let (start, end) = (handle(data, 0)?, handle(data, 2)?);
+            if start == 0 || start > end {
+                return Err((start, INVALID_HANDLE));
+            }
+            Ok((start, end))
+        };
+
+        match opcode {
+            EXCHANGE_MTU_REQUEST => Ok(vec![opcode + 1, MTU as u8, 0]),
+            FIND_INFORMATION_REQUEST => {
+                let (start, end) = range()?;
+                let mut found = self.range(start, end).peekable();
+                let long = match found.peek() {
+                    Some(attribute) => matches!(attribute.kind, Kind::Long(_)),
+                    None => return Err((start, ATTRIBUTE_NOT_FOUND)),
+                };
+                // Entries in one response share a format, 16-bit or 128-bit
+                let mut response = vec![opcode + 1, if long { 2 } else { 1 }];
+                for attribute in found {
+                    let kind = attribute.kind.bytes();
+                    if matches!(attribute.kind, Kind::Long(_)) != long
+                        || response.len() + 2 + kind.len() > MTU
+                    {
+                        break;
+                    }
+                    response.extend(attribute.handle.to_le_bytes());
+                    response.extend(kind);
+                }
+                Ok(response)
+            }
+            FIND_BY_TYPE_VALUE_REQUEST => {
+                let (start, end) = range()?;
+                let (kind, value) = (handle(data, 4)?, data.get(6..).unwrap_or_default());
+                let mut response = vec![opcode + 1];
+                for attribute in self.range(start, end) {
+                    if attribute.kind == Kind::Short(kind) && attribute.value == value {
+                        response.extend(attribute.handle.to_le_bytes());
+                        response.extend(self.group_end().to_le_bytes());
+                    }
+                }
+                if response.len() == 1 {
+                    return Err((start, ATTRIBUTE_NOT_FOUND));
+                }
+                Ok(response)
+            }
+            READ_BY_TYPE_REQUEST => {
+                let (start, end) = range()?;
+                let kind = Kind::parse(&data[4..]).ok_or((0, INVALID_PDU))?;
+                let found = self
+                    .range(start, end)
+                    .filter(|attribute| attribute.kind == kind);
+                self.list(opcode + 1, found, |attribute| {
+                    [&attribute.handle.to_le_bytes()[..], &attribute.value].concat()
+                })
+                .ok_or((start, ATTRIBUTE_NOT_FOUND))
+            }
+            READ_BY_GROUP_TYPE_REQUEST => {
+                let (start, end) = range()?;
+                if Kind::parse(&data[4..]) != Some(Kind::Short(PRIMARY_SERVICE)) {
+                    return Err((start, UNSUPPORTED_GROUP_TYPE));
+                }
+                let found = self
+                    .range(start, end)
+                    .filter(|attribute| attribute.kind == Kind::Short(PRIMARY_SERVICE));
+                self.list(opcode + 1, found, |attribute| {
+                    let mut entry = attribute.handle.to_le_bytes().to_vec();
+                    entry.extend(self.group_end().to_le_bytes());
+                    entry.extend(&attribute.value);
+                    entry
+                })
+                .ok_or((start, ATTRIBUTE_NOT_FOUND))
+            }
+            READ_REQUEST | READ_BLOB_REQUEST => {
+                let target = handle(data, 0)?;
+                let offset = if opcode == READ_BLOB_REQUEST {
+                    usize::from(handle(data, 2)?)
+                } else {
+                    0
+                };
+                let attribute = self
+                    .attributes
+                    .iter()
+                    .find(|attribute| attribute.handle == target)
+                    .ok_or((target, INVALID_HANDLE))?;
+                let value = attribute
+                    .value
+                    .get(offset..)
+                    .ok_or((target, INVALID_OFFSET))?;
+                let mut response = vec![opcode + 1];
+                response.extend(&value[..value.len().min(MTU - 1)]);
+                Ok(response)
+            }
+            WRITE_REQUEST => Err((handle(data, 0)?, WRITE_NOT_PERMITTED)),
+            _ => Err((0, REQUEST_NOT_SUPPORTED)),
+        }
+    }
+
+    /// Builds a list response from entries of the same length as the first, as ATT requires.
+    fn list<'a>(
+        &self,
+        opcode: u8,
+        attributes: impl Iterator<Item = &'a Attribute>,
+        entry: impl Fn(&Attribute) -> Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        let mut response = vec![opcode, 0];
+        for attribute in attributes {
+            let entry = entry(attribute);
+            if response.len() == 2 {
+                response[1] = entry.len() as u8;
+            } else if entry.len() != usize::from(response[1]) {
+                break;
+            }
+            if response.len() + entry.len() > MTU {
+                break;
+            }
+            response.extend(entry);
+        }
+        (response.len() > 2).then_some(response)
+    }
+}
+
+/// Reads the little-endian handle at the given index of the request parameters.
+fn handle(data: &[u8], index: usize) -> Result<u16, (u16, u8)> {
+    data.get(index..index + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or((0, INVALID_PDU))
+}
+
+fn centidegrees(value: Option<Celsius>) -> Vec<u8> {
+    let value = value.map_or(i16::MIN, |value| {
+        (value.millidegrees() / 10).clamp(-0x7fff, 0x7fff) as i16
+    });
+    value.to_le_bytes().to_vec()
+}
+
+/// Advertises the service and answers centrals from a background thread.
+#[cfg(target_os = "linux")]
+pub fn serve(status: Status) -> io::Result<JoinHandle<()>> {
+    let listener = att::listen()?;
+    advertise();
+
+    Ok(thread::spawn(move || loop {
+        let connection = match att::accept(&listener) {
+            Ok(connection) => connection,
+            Err(error) => {
+                log::warn!("Failed to accept BLE connection: {}", error);
+                continue;
+            }
+        };
+        let status = status.clone();
+        thread::spawn(move || {
+            if let Err(error) = handle_connection(connection, &status) {
+                log::warn!("BLE connection failed: {}", error);
+            }
+        });
+    }))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn serve(_status: Status) -> io::Result<JoinHandle<()>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "BLE is only supported on Linux",
+    ))
+}
+
+/// Answers requests from one central until it disconnects.
+#[cfg(target_os = "linux")]
+fn handle_connection(mut connection: std::fs::File, status: &Status) -> io::Result<()> {
+    use std::io::{Read, Write};
+
+    let mut buffer = [0u8; 512];
+    loop {
+        let length = connection.read(&mut buffer)?;
+        if length == 0 {
+            return Ok(());
+        }
+        let database = Database::new(&status.snapshot());
+        if let Some(response) = database.respond(&buffer[..length]) {
+            connection.write_all(&response)?;
+        }
+    }
+}
+
+/// Starts connectable advertising with the service UUID, so apps can find the controller.
+#[cfg(target_os = "linux")]
+fn advertise() {
+    let advertised = std::process::Command::new("btmgmt")
+        .args(["add-adv", "-c", "-g", "-n", "-u", SERVICE, "1"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .status();
+    match advertised {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::warn!("Failed to start BLE advertising: btmgmt {}", status),
+        Err(error) => log::warn!("Failed to start BLE advertising: {}", error),
+    }
+}
+
+/// L2CAP sockets on the fixed ATT channel of LE links.
+#[cfg(target_os = "linux")]
+mod att {
+    use std::{
+        fs::File,
+        io, mem,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    const BTPROTO_L2CAP: libc::c_int = 0;
+    const ATT_CID: u16 = 4;
+    const BDADDR_LE_PUBLIC: u8 = 1;
+
+    /// `struct sockaddr_l2` from BlueZ's `l2cap.h`
+    #[repr(C)]
+    struct SockaddrL2 {
+        family: libc::sa_family_t,
+        psm: u16,
+        address: [u8; 6],
+        cid: u16,
+        address_type: u8,
+    }
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// Listens for LE connections on every adapter.
+    pub fn listen() -> io::Result<OwnedFd> {
+        let fd = check(unsafe {
+            libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+                BTPROTO_L2CAP,
+            )
+        })?;
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let address = SockaddrL2 {
+            family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            psm: 0,
+            address: [0; 6],
+            cid: ATT_CID.to_le(),
+            address_type: BDADDR_LE_PUBLIC,
+        };
+        check(unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &address as *const SockaddrL2 as *const libc::sockaddr,
+                mem::size_of::<SockaddrL2>() as libc::socklen_t,
+            )
+        })?;
+        check(unsafe { libc::listen(socket.as_raw_fd(), 1) })?;
+        Ok(socket)
+    }
+
+    pub fn accept(listener: &OwnedFd) -> io::Result<File> {
+        let fd = check(unsafe {
+            libc::accept4(
+                listener.as_raw_fd(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                libc::SOCK_CLOEXEC,
+            )
+        })?;
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Database, SERVICE_UUID};
+    use crate::{pwm::tests::duty, status::Snapshot, units::Celsius};
+
+    fn database() -> Database {
+        Database::new(&Snapshot {
+            temperature: Some(Celsius::new(41, 500)),
+            duty: Some(duty(52)),
+            ..Snapshot::default()
+        })
+    }
+
+    #[test]
+    fn discovers_service_and_characteristics() {
+        let database = database();
+
+        // Primary services from handle 1 up
+        let mut expected = vec![0x11, 20, 0x01, 0x00, 0x07, 0x00];
+        expected.extend(SERVICE_UUID);
+        assert_eq!(
+            Some(expected),
+            database.respond(&[0x10, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28])
+        );
+        // Nothing after the service
+        assert_eq!(
+            Some(vec![0x01, 0x10, 0x08, 0x00, 0x0a]),
+            database.respond(&[0x10, 0x08, 0x00, 0xff, 0xff, 0x00, 0x28])
+        );
+
+        // Characteristic declarations stop at the first 128-bit UUID, which has another length
+        assert_eq!(
+            Some(vec![0x09, 7, 0x02, 0x00, 0x02, 0x03, 0x00, 0x6e, 0x2a]),
+            database.respond(&[0x08, 0x01, 0x00, 0x07, 0x00, 0x03, 0x28])
+        );
+    }
+
+    #[test]
+    fn reads_values() {
+        let database = database();
+
+        // 41.50°C
+        assert_eq!(
+            Some(vec![0x0b, 0x36, 0x10]),
+            database.respond(&[0x0a, 0x03, 0x00])
+        );
+        assert_eq!(Some(vec![0x0b, 52]), database.respond(&[0x0a, 0x05, 0x00]));
+        // Target is unknown until the first decision
+        assert_eq!(
+            Some(vec![0x0b, 0x00, 0x80]),
+            database.respond(&[0x0a, 0x07, 0x00])
+        );
+
+        assert_eq!(
+            Some(vec![0x01, 0x0a, 0x08, 0x00, 0x01]),
+            database.respond(&[0x0a, 0x08, 0x00])
+        );
+        assert_eq!(
+            Some(vec![0x01, 0x12, 0x05, 0x00, 0x03]),
+            database.respond(&[0x12, 0x05, 0x00, 100])
+        );
+        // Write commands are dropped silently
+        assert_eq!(None, database.respond(&[0x52, 0x05, 0x00, 100]));
+    }
+}
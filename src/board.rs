@@ -0,0 +1,241 @@
+//! Detection of the board model from the device tree, choosing defaults suited to it.
+//!
+//! The defaults are inserted ahead of all other options, so the config file and the command line
+//! both take precedence over them.
+
+use crate::units::Celsius;
+use std::{fs, path::Path};
+
+/// Device tree node naming the board, e.g. `Raspberry Pi 4 Model B Rev 1.4`
+pub const MODEL: &str = "/proc/device-tree/model";
+
+/// Option turning detection off, leaving the built-in defaults alone
+const DISABLE: &str = "--no-board-defaults";
+
+/// Options conflicting with a default, which is left out when any of them is given
+const REPLACED_BY: &[(&str, &[&str])] = &[
+    (
+        "--temperature-file-path",
+        &["--lhm-sensor", "--mcp3008-channel"],
+    ),
+    ("--pollrate", &["--pollrate-min"]),
+];
+
+/// Defaults for boards whose model starts with `prefix`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    pub prefix: &'static str,
+    pub temperature_file_path: &'static str,
+    /// A few degrees below where the SoC starts throttling
+    pub temperature_max: Celsius,
+    /// Seconds between polls, shorter for SoCs that heat up quickly under load
+    pub pollrate: u64,
+}
+
+/// Known boards, more specific prefixes first.
+pub const PROFILES: &[Profile] = &[
+    Profile {
+        prefix: "Raspberry Pi 5",
+        temperature_file_path: "/sys/class/thermal/thermal_zone0/temp",
+        temperature_max: Celsius::new(75, 0),
+        pollrate: 2,
+    },
+    Profile {
+        prefix: "Raspberry Pi 4",
+        temperature_file_path: "/sys/class/thermal/thermal_zone0/temp",
+        temperature_max: Celsius::new(75, 0),
+        pollrate: 5,
+    },
+    Profile {
+        prefix: "Raspberry Pi Zero",
+        temperature_file_path: "/sys/class/thermal/thermal_zone0/temp",
+        temperature_max: Celsius::new(70, 0),
+        pollrate: 10,
+    },
+    Profile {
+        prefix: "Raspberry Pi",
+        temperature_file_path: "/sys/class/thermal/thermal_zone0/temp",
+        temperature_max: Celsius::new(70, 0),
+        pollrate: 5,
+    },
+    Profile {
+        prefix: "Orange Pi 5",
+        temperature_file_path: "/sys/class/thermal/thermal_zone0/temp",
+        temperature_max: Celsius::new(80, 0),
+        pollrate: 2,
+    },
+    Profile {
+        prefix: "Orange Pi",
+        temperature_file_path: "/sys/class/thermal/thermal_zone0/temp",
+        temperature_max: Celsius::new(70, 0),
+        pollrate: 5,
+    },
+];
+
+/// Board found in the device tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Board {
+    pub model: String,
+    /// Defaults for the model, if it is a known one
+    pub profile: Option<Profile>,
+}
+
+impl Board {
+    /// Returns the board named by a device tree model, which is NUL terminated.
+    pub fn from_model(model: &str) -> Self {
+        let model = model.trim_end_matches('\0').trim().to_string();
+        let profile = PROFILES
+            .iter()
+            .find(|profile| model.starts_with(profile.prefix))
+            .copied();
+        Self { model, profile }
+    }
+
+    /// Returns the options for the board's defaults, none for an unknown board.
+    pub fn options(&self) -> Vec<String> {
+        let Some(profile) = self.profile else {
+            return Vec::new();
+        };
+        vec![
+            "--temperature-file-path".to_string(),
+            profile.temperature_file_path.to_string(),
+            "--temperature-max-value".to_string(),
+            profile.temperature_max.to_string(),
+            "--pollrate".to_string(),
+            profile.pollrate.to_string(),
+        ]
+    }
+
+    /// Describes what was detected and which defaults it selected, for the log.
+    pub fn describe(&self) -> String {
+        match self.profile {
+            Some(_) => format!(
+                "Detected {}, defaulting to {}",
+                self.model,
+                self.options().join(" ")
+            ),
+            None => format!("Detected {}, no board defaults known", self.model),
+        }
+    }
+}
+
+/// Reads the board from a device tree model file, if there is one.
+pub fn detect(path: &Path) -> Option<Board> {
+    let model = fs::read_to_string(path).ok()?;
+    Some(Board::from_model(&model))
+}
+
+/// Returns the command line with the board's defaults inserted before all other options, unless
+/// `--no-board-defaults` is given.
+pub fn args_with_board(mut argv: Vec<String>, board: Option<&Board>) -> Vec<String> {
+    let Some(board) = board else {
+        return argv;
+    };
+    if argv.iter().skip(1).any(|argument| argument == DISABLE) {
+        return argv;
+    }
+
+    let given = |option: &str| {
+        argv.iter()
+            .skip(1)
+            .any(|argument| argument == option || argument.starts_with(&format!("{}=", option)))
+    };
+    let mut options = Vec::new();
+    for pair in board.options().chunks(2) {
+        // Given options would otherwise add to repeatable ones such as --temperature-file-path
+        if given(&pair[0]) {
+            continue;
+        }
+        let replaced = REPLACED_BY
+            .iter()
+            .find(|(option, _)| *option == pair[0])
+            .is_some_and(|(_, replacements)| replacements.iter().any(|option| given(option)));
+        if !replaced {
+            options.extend_from_slice(pair);
+        }
+    }
+
+    let position = argv.len().min(1);
+    argv.splice(position..position, options);
+    argv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{args_with_board, Board};
+    use crate::args::Args;
+    use crate::units::Celsius;
+    use clap::Parser;
+
+    #[test]
+    fn matches_most_specific_model() {
+        let board = Board::from_model("Raspberry Pi 5 Model B Rev 1.0\0");
+
+        assert_eq!("Raspberry Pi 5 Model B Rev 1.0", board.model);
+        assert_eq!(2, board.profile.unwrap().pollrate);
+        assert_eq!(
+            10,
+            Board::from_model("Raspberry Pi Zero 2 W Rev 1.0\0")
+                .profile
+                .unwrap()
+                .pollrate
+        );
+        assert_eq!(None, Board::from_model("Pine64 RockPro64").profile);
+    }
+
+    #[test]
+    fn given_options_take_precedence() {
+        let board = Board::from_model("Raspberry Pi 5 Model B Rev 1.0");
+        let argv = [
+            "fan-controller",
+            "--gpio-pwm",
+            "3",
+            "--pollrate",
+            "10",
+            "--temperature-file-path",
+            "/sys/class/hwmon/hwmon2/temp1_input",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        let args = Args::parse_from(args_with_board(argv, Some(&board)));
+
+        assert_eq!(10, args.pollrate);
+        assert_eq!(
+            vec!["/sys/class/hwmon/hwmon2/temp1_input"],
+            args.temperature_file_path
+        );
+        assert_eq!(Celsius::new(75, 0), args.temperature_max_value);
+    }
+
+    #[test]
+    fn conflicting_options_replace_defaults() {
+        let board = Board::from_model("Raspberry Pi 5 Model B Rev 1.0");
+        let argv = [
+            "fan-controller",
+            "--gpio-pwm",
+            "3",
+            "--pollrate-min",
+            "1",
+            "--pollrate-max",
+            "15",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        let args = Args::parse_from(args_with_board(argv, Some(&board)));
+
+        assert_eq!(Some(1), args.pollrate_min);
+        assert_eq!(Celsius::new(75, 0), args.temperature_max_value);
+    }
+
+    #[test]
+    fn defaults_can_be_turned_off() {
+        let board = Board::from_model("Raspberry Pi 5 Model B Rev 1.0");
+        let argv = ["fan-controller", "--gpio-pwm", "3", "--no-board-defaults"]
+            .map(String::from)
+            .to_vec();
+
+        assert_eq!(argv.clone(), args_with_board(argv, Some(&board)));
+    }
+}
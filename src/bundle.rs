@@ -0,0 +1,261 @@
+//! Bundles of a controller's setup and recent history, to attach to bug reports or to move a
+//! setup to a replacement SD card.
+//!
+//! A bundle is a plain tar archive, so `tar -xf` unpacks it anywhere. It holds the effective
+//! options as a config file, and whatever else was at hand when it was exported: the config file
+//! as written, calibration results, the end of the event log, spooled telemetry and the status.
+
+use crate::reload::Value;
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+};
+
+/// Name of the config file as written in a bundle, with its profiles, includes and comments
+pub const CONFIG: &str = "config.toml";
+
+/// Name of the effective options in a bundle, for a setup given on the command line alone
+pub const EFFECTIVE: &str = "effective.toml";
+
+/// Size of tar headers and the unit content is padded to
+const BLOCK: usize = 512;
+
+/// Options not carried over, since they point at the config they were read from
+const SKIPPED: [&str; 2] = ["config", "profile"];
+
+/// Options holding secrets, left out of bundles meant for bug reports
+const REDACTED: [&str; 1] = ["snmp-community"];
+
+/// Named files of a bundle, in the order they were added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bundle {
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, replacing any of the same name.
+    pub fn add(&mut self, name: &str, content: impl Into<Vec<u8>>) {
+        self.files.retain(|(existing, _)| existing != name);
+        self.files.push((name.to_string(), content.into()));
+    }
+
+    /// Returns the content of a file, if the bundle has it.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, content)| content.as_slice())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Writes the bundle as a tar archive, with the files modified at a Unix time.
+    pub fn write(&self, writer: &mut impl Write, time: u64) -> io::Result<()> {
+        for (name, content) in &self.files {
+            writer.write_all(&header(name, content.len(), time)?)?;
+            writer.write_all(content)?;
+            writer.write_all(&vec![0; padding(content.len())])?;
+        }
+        // Two empty blocks end the archive
+        writer.write_all(&[0; 2 * BLOCK])
+    }
+
+    /// Reads a bundle from a tar archive, skipping anything but regular files.
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut bundle = Self::new();
+        let mut block = [0; BLOCK];
+        loop {
+            reader.read_exact(&mut block)?;
+            if block.iter().all(|&byte| byte == 0) {
+                return Ok(bundle);
+            }
+            if &block[257..262] != b"ustar" {
+                return Err(invalid("not a tar archive"));
+            }
+
+            let name = field(&block[..100]);
+            let size = usize::from_str_radix(field(&block[124..136]).trim(), 8)
+                .map_err(|_| invalid("invalid file size in tar header"))?;
+            let mut content = vec![0; size + padding(size)];
+            reader.read_exact(&mut content)?;
+            content.truncate(size);
+            if matches!(block[156], b'0' | 0) {
+                bundle.add(name, content);
+            }
+        }
+    }
+}
+
+/// Returns the bytes padding content of a size to a whole block.
+fn padding(size: usize) -> usize {
+    (BLOCK - size % BLOCK) % BLOCK
+}
+
+/// Returns a text field of a tar header up to its first NUL.
+fn field(bytes: &[u8]) -> &str {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or_default()
+}
+
+/// Returns the ustar header of a regular file.
+fn header(name: &str, size: usize, time: u64) -> io::Result<[u8; BLOCK]> {
+    if name.len() >= 100 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("file name {:?} too long for a tar archive", name),
+        ));
+    }
+
+    let mut header = [0; BLOCK];
+    let mut put = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    put(0, name.as_bytes());
+    put(100, b"0000644\0");
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(136, format!("{:011o}\0", time).as_bytes());
+    put(156, b"0");
+    put(257, b"ustar\0");
+    put(263, b"00");
+
+    // Summed with the checksum field itself as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    Ok(header)
+}
+
+/// Returns a TOML string of a value.
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Renders effective options as a config file standing on its own.
+pub fn to_config(options: &BTreeMap<String, Value>) -> String {
+    let mut config = String::from("# Effective options exported by fan-controller export-bundle\n");
+    for (option, value) in options {
+        if SKIPPED.contains(&option.as_str()) {
+            continue;
+        }
+        if REDACTED.contains(&option.as_str()) {
+            config.push_str(&format!("# {} left out, set it again\n", option));
+            continue;
+        }
+        match value {
+            Value::Flag(false) => {}
+            Value::Flag(true) => config.push_str(&format!("{} = true\n", option)),
+            Value::Values(values) => match values.as_slice() {
+                [] => {}
+                [value] => config.push_str(&format!("{} = {}\n", option, quote(value))),
+                values => {
+                    let values: Vec<String> = values.iter().map(|value| quote(value)).collect();
+                    config.push_str(&format!("{} = [{}]\n", option, values.join(", ")));
+                }
+            },
+        }
+    }
+    config
+}
+
+/// Returns the last lines of a text, all of them if there are fewer.
+pub fn tail(text: &str, lines: usize) -> &str {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    if lines == 0 {
+        return "";
+    }
+    match text.rmatch_indices('\n').nth(lines - 1) {
+        Some((index, _)) => &text[index + 1..],
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tail, to_config, Bundle, EFFECTIVE};
+    use crate::{args::Args, config::to_args, reload::Value};
+    use clap::Parser;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn round_trips_through_tar() {
+        let mut bundle = Bundle::new();
+        bundle.add(EFFECTIVE, "gpio-pwm = \"3\"\n");
+        bundle.add("events.jsonl", vec![b'x'; 1000]);
+        bundle.add("empty", "");
+
+        let mut archive = Vec::new();
+        bundle.write(&mut archive, 1_700_000_000).unwrap();
+        assert_eq!(0, archive.len() % 512);
+        assert_eq!(bundle, Bundle::read(&mut archive.as_slice()).unwrap());
+
+        assert!(Bundle::read(&mut [1; 1024].as_slice()).is_err());
+        assert!(Bundle::read(&mut &archive[..600]).is_err());
+    }
+
+    #[test]
+    fn renders_options_as_config() {
+        let options = BTreeMap::from([
+            (
+                "config".to_string(),
+                Value::Values(vec!["/etc/fc.toml".into()]),
+            ),
+            ("gpio-pwm".to_string(), Value::Values(vec!["3".into()])),
+            ("inhibit".to_string(), Value::Flag(true)),
+            ("relay-active-low".to_string(), Value::Flag(false)),
+            (
+                "quiet-hours".to_string(),
+                Value::Values(vec!["22:00-07:00".into(), "12:00-13:00".into()]),
+            ),
+            (
+                "snmp-community".to_string(),
+                Value::Values(vec!["s3cr\"t".into()]),
+            ),
+        ]);
+        let config = to_config(&options);
+        assert_eq!(
+            "# Effective options exported by fan-controller export-bundle\n\
+             gpio-pwm = \"3\"\n\
+             inhibit = true\n\
+             quiet-hours = [\"22:00-07:00\", \"12:00-13:00\"]\n\
+             # snmp-community left out, set it again\n",
+            config
+        );
+
+        let mut argv = vec!["fan-controller".to_string()];
+        argv.extend(to_args(&toml::from_str(&config).unwrap()).unwrap());
+        let args = Args::parse_from(argv);
+        assert_eq!(Some(3), args.gpio_pwm);
+        assert_eq!(2, args.quiet_hours.len());
+    }
+
+    #[test]
+    fn takes_last_lines() {
+        assert_eq!("b\nc", tail("a\nb\nc\n", 2));
+        assert_eq!("a\nb\nc", tail("a\nb\nc", 5));
+        assert_eq!("c", tail("a\nb\nc", 1));
+        assert_eq!("", tail("a\nb\nc", 0));
+    }
+}
@@ -0,0 +1,251 @@
+//! Push button on a GPIO pin for local control on enclosures without network access.
+//!
+//! A short press toggles a timed boost at maximum speed, a long press toggles quiet mode, which
+//! caps the fan speed until the maximum temperature is reached.
+
+use crate::units::Duty;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Time the level must hold before a change counts, to ride out contact bounce
+pub const SETTLE: Duration = Duration::from_millis(30);
+
+/// Time the button must be held for a long press
+pub const HOLD: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Short,
+    Long,
+}
+
+/// Turns sampled button levels into presses.
+///
+/// Long presses are reported as soon as the button has been held long enough, short ones on
+/// release.
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    level: bool,
+    /// When the level last changed
+    since: Instant,
+    /// When the current press started, once it has settled
+    pressed: Option<Instant>,
+    /// Whether the current press was already reported as long
+    held: bool,
+}
+
+impl Debouncer {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            level: false,
+            since: now,
+            pressed: None,
+            held: false,
+        }
+    }
+
+    /// Records the level sampled at `now`, `true` while pressed, and returns a completed press.
+    pub fn update(&mut self, now: Instant, pressed: bool) -> Option<Press> {
+        if pressed != self.level {
+            self.level = pressed;
+            self.since = now;
+        }
+        let settled = now.duration_since(self.since) >= SETTLE;
+
+        match self.pressed {
+            None if settled && self.level => {
+                self.pressed = Some(self.since);
+                None
+            }
+            Some(_) if settled && !self.level => {
+                self.pressed = None;
+                let held = std::mem::take(&mut self.held);
+                (!held).then_some(Press::Short)
+            }
+            Some(start) if self.level && !self.held && now.duration_since(start) >= HOLD => {
+                self.held = true;
+                Some(Press::Long)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Boost and quiet mode, shared between the button and the controller.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct Modes {
+    boost: Duration,
+    quiet_max: Duty,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    boost_until: Option<Instant>,
+    quiet: bool,
+}
+
+impl Modes {
+    /// Returns modes boosting for `boost` at a time and capping the speed at `quiet_max` while
+    /// quiet.
+    pub fn new(boost: Duration, quiet_max: Duty) -> Self {
+        Self {
+            boost,
+            quiet_max,
+            state: Arc::default(),
+        }
+    }
+
+    /// Applies a press, short ones toggling the boost and long ones quiet mode.
+    pub fn press(&self, press: Press, now: Instant) {
+        match press {
+            Press::Short => self.toggle_boost(now),
+            Press::Long => self.toggle_quiet(),
+        }
+    }
+
+    /// Starts a boost, or ends the one in effect early.
+    pub fn toggle_boost(&self, now: Instant) {
+        let boosting = self.boosting(now);
+        let mut state = self.state.lock().unwrap();
+        state.boost_until = (!boosting).then(|| now + self.boost);
+    }
+
+    pub fn toggle_quiet(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.quiet = !state.quiet;
+    }
+
+    pub fn set_quiet(&self, quiet: bool) {
+        self.state.lock().unwrap().quiet = quiet;
+    }
+
+    pub fn boosting(&self, now: Instant) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .boost_until
+            .is_some_and(|until| now < until)
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.state.lock().unwrap().quiet
+    }
+
+    /// Highest duty while quiet
+    pub fn quiet_max(&self) -> Duty {
+        self.quiet_max
+    }
+}
+
+/// Watches a button wiring the pin to ground, with the internal pull-up enabled.
+#[cfg(feature = "wiringpi")]
+pub fn watch(pin: i32, modes: Modes) {
+    use libc::c_int;
+
+    #[link(name = "wiringPi")]
+    extern "C" {
+        fn wiringPiSetup() -> c_int;
+        fn pinMode(pin: c_int, mode: c_int);
+        fn pullUpDnControl(pin: c_int, pud: c_int);
+        fn digitalRead(pin: c_int) -> c_int;
+    }
+    const INPUT: c_int = 0;
+    const PUD_UP: c_int = 2;
+    const LOW: c_int = 0;
+
+    unsafe {
+        wiringPiSetup();
+        pinMode(pin, INPUT);
+        pullUpDnControl(pin, PUD_UP);
+    }
+    std::thread::spawn(move || {
+        let mut debouncer = Debouncer::new(Instant::now());
+        loop {
+            let now = Instant::now();
+            let pressed = unsafe { digitalRead(pin) } == LOW;
+            if let Some(press) = debouncer.update(now, pressed) {
+                modes.press(press, now);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debouncer, Modes, Press};
+    use crate::units::Duty;
+    use std::time::{Duration, Instant};
+
+    /// Feeds levels sampled every 10 ms, returning the presses reported
+    fn presses(levels: &[(bool, u64)]) -> Vec<Press> {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(start);
+        let mut elapsed = 0;
+        let mut presses = Vec::new();
+        for &(level, millis) in levels {
+            for _ in 0..millis / 10 {
+                let now = start + Duration::from_millis(elapsed);
+                presses.extend(debouncer.update(now, level));
+                elapsed += 10;
+            }
+        }
+        presses
+    }
+
+    #[test]
+    fn reports_short_and_long_presses() {
+        assert_eq!(
+            vec![Press::Short],
+            presses(&[(false, 100), (true, 200), (false, 100)])
+        );
+        assert_eq!(
+            vec![Press::Long],
+            presses(&[(false, 100), (true, 1500), (false, 100)])
+        );
+    }
+
+    #[test]
+    fn ignores_bounce() {
+        assert_eq!(
+            vec![Press::Short],
+            presses(&[
+                (true, 10),
+                (false, 10),
+                (true, 10),
+                (false, 10),
+                (true, 200),
+                (false, 10),
+                (true, 10),
+                (false, 100),
+            ])
+        );
+        assert!(presses(&[(true, 20), (false, 100)]).is_empty());
+    }
+
+    #[test]
+    fn boost_times_out_or_toggles_off() {
+        let modes = Modes::new(Duration::from_secs(60), Duty::new(50).unwrap());
+        let start = Instant::now();
+
+        modes.press(Press::Short, start);
+        assert!(modes.boosting(start + Duration::from_secs(59)));
+        assert!(!modes.boosting(start + Duration::from_secs(60)));
+
+        let later = start + Duration::from_secs(100);
+        modes.press(Press::Short, later);
+        assert!(modes.boosting(later));
+        modes.press(Press::Short, later + Duration::from_secs(1));
+        assert!(!modes.boosting(later + Duration::from_secs(2)));
+
+        modes.press(Press::Long, start);
+        assert!(modes.is_quiet());
+        modes.press(Press::Long, start);
+        assert!(!modes.is_quiet());
+    }
+}
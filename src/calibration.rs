@@ -0,0 +1,353 @@
+//! Sweep measuring the temperature the enclosure settles at for each fan duty.
+//!
+//! The results show how much cooling each extra percent of fan speed buys, which helps pick
+//! `--pwm-min` and the target temperature.
+
+use crate::{
+    clock::Clock,
+    console::DecimalSeparator,
+    events::{Event, EventBus, Progress},
+    pwm::Output,
+    sensor::Sensor,
+    units::{Celsius, Duty},
+};
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Name of the operation in progress reports
+const OPERATION: &str = "calibration";
+
+/// Temperature the sensor settled at while the fan ran at a duty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    pub duty: Duty,
+    pub temperature: Celsius,
+}
+
+/// Duties to hold the fan at, and for how long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Calibration {
+    pub steps: Vec<Duty>,
+    /// Time given to the temperature to settle at each step
+    pub hold: Duration,
+    /// Interval of temperature checks and progress reports within a step
+    pub interval: Duration,
+    /// Temperature at which the sweep is abandoned and the fan returned to full speed
+    pub temperature_max: Celsius,
+}
+
+impl Calibration {
+    /// Returns a sweep from `from` down to `to` in steps of `step` percent.
+    ///
+    /// Starting fast keeps the enclosure cool while the slower, hotter steps are reached.
+    pub fn sweep(from: Duty, to: Duty, step: u8) -> Vec<Duty> {
+        let mut steps = vec![from];
+        let mut duty = from;
+        while duty > to {
+            duty = duty.lower(step.max(1)).max(to);
+            steps.push(duty);
+        }
+        steps
+    }
+
+    /// Returns the estimated time left once the given number of steps is done and the
+    /// current one has been held for `held`.
+    fn remaining(&self, done: usize, held: Duration) -> Duration {
+        let left = (self.steps.len() - done) as u32;
+        (self.hold * left).saturating_sub(held)
+    }
+
+    /// Runs the sweep, reporting progress as events, and returns the points measured.
+    ///
+    /// Stops early if the temperature reaches the maximum or `abort` is set, which is checked
+    /// once per interval. However the sweep ends, the fan is returned to the full speed it started
+    /// at rather than left at the step being tested.
+    pub fn run(
+        &self,
+        output: &mut dyn Output,
+        sensor: &mut dyn Sensor,
+        clock: &mut dyn Clock,
+        events: &mut EventBus,
+        abort: &AtomicBool,
+    ) -> Vec<Point> {
+        let points = self.sweep_steps(output, sensor, clock, events, abort);
+        output.write(Duty::FULL);
+        points
+    }
+
+    fn sweep_steps(
+        &self,
+        output: &mut dyn Output,
+        sensor: &mut dyn Sensor,
+        clock: &mut dyn Clock,
+        events: &mut EventBus,
+        abort: &AtomicBool,
+    ) -> Vec<Point> {
+        let mut points = Vec::new();
+
+        for (done, &duty) in self.steps.iter().enumerate() {
+            output.write(duty);
+
+            let mut held = Duration::ZERO;
+            let mut settled = None;
+            while held < self.hold {
+                events.publish(Event::Progress(Progress {
+                    operation: OPERATION,
+                    done,
+                    total: self.steps.len(),
+                    duty,
+                    remaining: self.remaining(done, held),
+                }));
+
+                let interval = self.interval.min(self.hold - held);
+                clock.sleep(interval);
+                held += interval;
+
+                if abort.load(Ordering::SeqCst) {
+                    log::warn!(
+                        "Calibration aborted after {} of {} steps",
+                        done,
+                        self.steps.len()
+                    );
+                    return points;
+                }
+
+                match sensor.read() {
+                    Ok(temperature) => {
+                        events.publish(Event::Sample {
+                            temperature,
+                            raw: sensor.raw(),
+                        });
+                        if temperature >= self.temperature_max {
+                            events.publish(Event::Fault {
+                                message: format!(
+                                    "Calibration stopped at {}%, temperature reached {}°C",
+                                    duty, temperature
+                                ),
+                            });
+                            return points;
+                        }
+                        settled = Some(temperature);
+                    }
+                    Err(error) => events.publish(Event::Fault {
+                        message: error.to_string(),
+                    }),
+                }
+            }
+
+            if let Some(temperature) = settled {
+                points.push(Point { duty, temperature });
+            }
+        }
+
+        events.publish(Event::Progress(Progress {
+            operation: OPERATION,
+            done: self.steps.len(),
+            total: self.steps.len(),
+            duty: self.steps.last().copied().unwrap_or(Duty::FULL),
+            remaining: Duration::ZERO,
+        }));
+        points
+    }
+}
+
+/// Writes points as CSV with a header line.
+///
+/// With a decimal comma, fields are separated by semicolons as spreadsheets in those locales
+/// expect.
+pub fn write_csv(
+    points: &[Point],
+    writer: &mut dyn Write,
+    decimal: DecimalSeparator,
+) -> io::Result<()> {
+    let delimiter = match decimal {
+        DecimalSeparator::Point => ',',
+        DecimalSeparator::Comma => ';',
+    };
+
+    writeln!(writer, "duty{}temperature", delimiter)?;
+    for point in points {
+        writeln!(
+            writer,
+            "{}{}{}",
+            point.duty,
+            delimiter,
+            decimal.celsius(point.temperature)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_csv, Calibration, Point};
+    use crate::{
+        console::DecimalSeparator,
+        events::{Event, EventBus},
+        mock::{MockClock, MockOutput, MockSink},
+        pwm::tests::duty,
+        sensor::{Sensor, SensorError},
+        units::{Celsius, Duty},
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    /// Enclosure settling instantly at 30°C plus a degree for every percent below full speed.
+    struct Enclosure {
+        output: MockOutput,
+        /// Interrupt raised once this many readings have been taken
+        interrupt_after: Option<(usize, Arc<AtomicBool>)>,
+    }
+
+    impl Enclosure {
+        fn new(output: &MockOutput) -> Self {
+            Self {
+                output: output.clone(),
+                interrupt_after: None,
+            }
+        }
+    }
+
+    impl Sensor for Enclosure {
+        fn read(&mut self) -> Result<Celsius, SensorError> {
+            if let Some((readings, interrupt)) = &mut self.interrupt_after {
+                *readings = readings.saturating_sub(1);
+                if *readings == 0 {
+                    interrupt.store(true, Ordering::SeqCst);
+                }
+            }
+
+            let duty = self.output.writes().last().copied().unwrap_or(Duty::FULL);
+            Ok(Celsius::new(130 - i32::from(duty.percent()), 0))
+        }
+    }
+
+    fn calibration(steps: Vec<Duty>) -> Calibration {
+        Calibration {
+            steps,
+            hold: Duration::from_secs(60),
+            interval: Duration::from_secs(20),
+            temperature_max: Celsius::new(70, 0),
+        }
+    }
+
+    #[test]
+    fn sweeps_down_to_minimum() {
+        assert_eq!(
+            vec![duty(100), duty(75), duty(50), duty(30)],
+            Calibration::sweep(duty(100), duty(30), 25)
+        );
+    }
+
+    #[test]
+    fn measures_each_step_and_reports_progress() {
+        let output = MockOutput::new();
+        let clock = MockClock::new();
+        let sink = MockSink::new();
+        let mut events = EventBus::new();
+        events.subscribe(Box::new(sink.clone()));
+
+        let points = calibration(vec![duty(100), duty(80)]).run(
+            &mut output.clone(),
+            &mut Enclosure::new(&output),
+            &mut clock.clone(),
+            &mut events,
+            &AtomicBool::new(false),
+        );
+
+        assert_eq!(
+            vec![
+                Point {
+                    duty: duty(100),
+                    temperature: Celsius::new(30, 0)
+                },
+                Point {
+                    duty: duty(80),
+                    temperature: Celsius::new(50, 0)
+                },
+            ],
+            points
+        );
+        assert_eq!(Duration::from_secs(120), clock.elapsed());
+
+        let remaining: Vec<u64> = sink
+            .events()
+            .iter()
+            .filter_map(|event| match event {
+                Event::Progress(progress) => Some(progress.remaining.as_secs()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![120, 100, 80, 60, 40, 20, 0], remaining);
+    }
+
+    #[test]
+    fn stops_at_maximum_temperature() {
+        let output = MockOutput::new();
+        let mut events = EventBus::new();
+
+        let points = calibration(vec![duty(100), duty(50), duty(30)]).run(
+            &mut output.clone(),
+            &mut Enclosure::new(&output),
+            &mut MockClock::new(),
+            &mut events,
+            &AtomicBool::new(false),
+        );
+
+        assert_eq!(1, points.len());
+        assert_eq!(vec![duty(100), duty(50), duty(100)], output.writes());
+    }
+
+    #[test]
+    fn interrupt_keeps_finished_steps_and_restores_full_speed() {
+        let output = MockOutput::new();
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut enclosure = Enclosure::new(&output);
+        // Three readings per step, so this lands midway through the second step
+        enclosure.interrupt_after = Some((5, Arc::clone(&interrupt)));
+
+        let points = calibration(vec![duty(100), duty(90), duty(80)]).run(
+            &mut output.clone(),
+            &mut enclosure,
+            &mut MockClock::new(),
+            &mut EventBus::new(),
+            &interrupt,
+        );
+
+        assert_eq!(
+            vec![duty(100)],
+            points.iter().map(|p| p.duty).collect::<Vec<_>>()
+        );
+        assert_eq!(vec![duty(100), duty(90), duty(100)], output.writes());
+    }
+
+    #[test]
+    fn writes_csv() {
+        let mut csv = Vec::new();
+        let points = [Point {
+            duty: duty(80),
+            temperature: Celsius::new(45, 500),
+        }];
+
+        write_csv(&points, &mut csv, DecimalSeparator::Point).unwrap();
+        assert_eq!(
+            "duty,temperature\n80,45.5\n",
+            String::from_utf8(csv).unwrap()
+        );
+
+        let mut csv = Vec::new();
+        write_csv(&points, &mut csv, DecimalSeparator::Comma).unwrap();
+        assert_eq!(
+            "duty;temperature\n80;45,5\n",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{schedule::TimeOfDay, season::MonthDay};
+use std::{
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Source of time for the control loop.
+pub trait Clock {
+    /// Returns the current moment.
+    fn now(&self) -> Instant;
+
+    /// Returns the wall-clock time, which A/B tests switch arms by.
+    fn wall(&self) -> SystemTime;
+
+    /// Returns the time of day quiet hours and target schedules follow.
+    fn time_of_day(&self) -> TimeOfDay;
+
+    /// Returns the date seasons follow.
+    fn today(&self) -> MonthDay;
+
+    /// Blocks for the given duration.
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// Clock following real time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn time_of_day(&self) -> TimeOfDay {
+        TimeOfDay::now()
+    }
+
+    fn today(&self) -> MonthDay {
+        MonthDay::today()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Parses a duration such as `90s`, `10m`, `2h` or `1d`, or a combination like `1h30m`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration {:?}, expected e.g. 90s, 10m or 1h30m",
+            value
+        )
+    };
+
+    let mut total = Duration::ZERO;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let amount: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit = match rest[digits..].chars().next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let seconds = amount.checked_mul(unit).ok_or_else(invalid)?;
+        total = total
+            .checked_add(Duration::from_secs(seconds))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_duration;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90s"));
+        assert_eq!(Ok(Duration::from_secs(600)), parse_duration("10m"));
+        assert_eq!(Ok(Duration::from_secs(5400)), parse_duration("1h30m"));
+        assert_eq!(Ok(Duration::from_secs(86400)), parse_duration("1d"));
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("").is_err());
+    }
+}
@@ -0,0 +1,432 @@
+//! CoAP endpoint for constrained IoT deployments standardized on it instead of HTTP or MQTT.
+//!
+//! Serves plain text resources over UDP, with discovery through `/.well-known/core`:
+//!
+//! - `/temperature` is the latest reading in degrees Celsius
+//! - `/fan` is the fan duty in percent
+//! - `/target` is the target temperature, which `PUT` changes
+//! - `/alerts` lists what needs attention, one per line, and is empty when all is well
+//! - `/status` is the same JSON object as the `status` control command
+
+use crate::{
+    setpoint::Setpoint,
+    status::{Snapshot, Status},
+    units::Celsius,
+};
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::atomic::{AtomicU16, Ordering},
+    thread::{self, JoinHandle},
+};
+
+const VERSION: u8 = 1;
+
+const CONFIRMABLE: u8 = 0;
+const NON_CONFIRMABLE: u8 = 1;
+const ACKNOWLEDGEMENT: u8 = 2;
+
+const GET: u8 = 0x01;
+const PUT: u8 = 0x03;
+
+const CHANGED: u8 = 0x44;
+const CONTENT: u8 = 0x45;
+const BAD_REQUEST: u8 = 0x80;
+const NOT_FOUND: u8 = 0x84;
+const METHOD_NOT_ALLOWED: u8 = 0x85;
+const SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+const URI_PATH: u16 = 11;
+const CONTENT_FORMAT: u16 = 12;
+
+const TEXT_PLAIN: u8 = 0;
+const LINK_FORMAT: u8 = 40;
+const JSON: u8 = 50;
+
+/// Resource list returned for discovery
+const CORE_LINKS: &str = "</temperature>;rt=\"temperature\";ct=0,</fan>;ct=0,</target>;ct=0,</alerts>;ct=0,</status>;ct=50";
+
+/// Resources served from the latest status, and where target changes go.
+pub struct Resources<'a> {
+    pub snapshot: &'a Snapshot,
+    pub setpoint: &'a Setpoint,
+    /// Targets at or above this are refused, as the controller would never reach them
+    pub temperature_max: Celsius,
+}
+
+/// Response code, content format and payload.
+type Reply = (u8, Option<u8>, String);
+
+impl Resources<'_> {
+    fn reply(&self, method: u8, path: &str, payload: &[u8]) -> Reply {
+        let value = |value: Option<String>| match value {
+            Some(value) => (CONTENT, Some(TEXT_PLAIN), value),
+            None => (SERVICE_UNAVAILABLE, None, String::new()),
+        };
+
+        match (method, path) {
+            (GET, ".well-known/core") => (CONTENT, Some(LINK_FORMAT), CORE_LINKS.to_string()),
+            (GET, "temperature") => value(self.snapshot.temperature.map(|t| t.to_string())),
+            (GET, "fan") => value(self.snapshot.duty.map(|duty| duty.to_string())),
+            (GET, "target") => value(self.snapshot.target.map(|t| t.to_string())),
+            (GET, "alerts") => (CONTENT, Some(TEXT_PLAIN), alerts(self.snapshot).join("\n")),
+            (GET, "status") => (CONTENT, Some(JSON), self.snapshot.to_json()),
+            (PUT, "target") => {
+                let target = std::str::from_utf8(payload)
+                    .ok()
+                    .and_then(|text| text.trim().parse::<Celsius>().ok())
+                    .filter(|target| *target < self.temperature_max);
+                match target {
+                    Some(target) => {
+                        log::info!("Target temperature {}°C requested over CoAP", target);
+                        self.setpoint.request_remote(target);
+                        (CHANGED, None, String::new())
+                    }
+                    None => (BAD_REQUEST, None, String::new()),
+                }
+            }
+            (_, "temperature" | "fan" | "target" | "alerts" | "status" | ".well-known/core") => {
+                (METHOD_NOT_ALLOWED, None, String::new())
+            }
+            _ => (NOT_FOUND, None, String::new()),
+        }
+    }
+
+    /// Answers a request datagram, or returns `None` for anything that isn't a request.
+    pub fn respond(&self, request: &[u8]) -> Option<Vec<u8>> {
+        let message = Message::parse(request)?;
+        // Only requests are answered, responses and empty messages are ignored
+        if message.code == 0 || message.code >> 5 != 0 {
+            return None;
+        }
+        let kind = match message.kind {
+            CONFIRMABLE => ACKNOWLEDGEMENT,
+            NON_CONFIRMABLE => NON_CONFIRMABLE,
+            _ => return None,
+        };
+
+        let (code, format, payload) = self.reply(message.code, &message.path, message.payload);
+        let mut response = vec![VERSION << 6 | kind << 4 | message.token.len() as u8, code];
+        response.extend(message.id.to_be_bytes());
+        response.extend(message.token);
+        if let Some(format) = format {
+            // Zero is sent as an empty value
+            let value: &[u8] = if format == 0 { &[] } else { &[format] };
+            encode_option(&mut response, CONTENT_FORMAT, value);
+        }
+        if !payload.is_empty() {
+            response.push(0xff);
+            response.extend(payload.as_bytes());
+        }
+        Some(response)
+    }
+}
+
+/// Parts of a request the resources need.
+struct Message<'a> {
+    kind: u8,
+    code: u8,
+    id: u16,
+    token: &'a [u8],
+    /// Uri-Path segments joined by slashes
+    path: String,
+    payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let header = data.get(..4)?;
+        if header[0] >> 6 != VERSION {
+            return None;
+        }
+        let token_length = usize::from(header[0] & 0x0f);
+        if token_length > 8 {
+            return None;
+        }
+        let token = data.get(4..4 + token_length)?;
+
+        let mut rest = &data[4 + token_length..];
+        let mut number = 0u16;
+        let mut segments = Vec::new();
+        let mut payload: &[u8] = &[];
+        while let Some((&byte, after)) = rest.split_first() {
+            if byte == 0xff {
+                payload = after;
+                break;
+            }
+            rest = after;
+            let delta = extended(byte >> 4, &mut rest)?;
+            let length = usize::from(extended(byte & 0x0f, &mut rest)?);
+            number = number.checked_add(delta)?;
+            let value = rest.get(..length)?;
+            rest = &rest[length..];
+            if number == URI_PATH {
+                segments.push(std::str::from_utf8(value).ok()?);
+            }
+        }
+
+        Some(Self {
+            kind: (header[0] >> 4) & 0x03,
+            code: header[1],
+            id: u16::from_be_bytes([header[2], header[3]]),
+            token,
+            path: segments.join("/"),
+            payload,
+        })
+    }
+}
+
+/// Reads an option delta or length nibble along with its extended bytes.
+fn extended(nibble: u8, rest: &mut &[u8]) -> Option<u16> {
+    match nibble {
+        0..=12 => Some(u16::from(nibble)),
+        13 => {
+            let (&byte, after) = rest.split_first()?;
+            *rest = after;
+            Some(u16::from(byte) + 13)
+        }
+        14 => {
+            let bytes = rest.get(..2)?;
+            *rest = &rest[2..];
+            u16::from_be_bytes([bytes[0], bytes[1]]).checked_add(269)
+        }
+        _ => None,
+    }
+}
+
+/// Appends an option given by its delta from the previous one.
+fn encode_option(message: &mut Vec<u8>, delta: u16, value: &[u8]) {
+    // Deltas used here are below 13, so only the length may need an extended byte
+    if value.len() < 13 {
+        message.push((delta as u8) << 4 | value.len() as u8);
+    } else {
+        message.push((delta as u8) << 4 | 13);
+        message.push((value.len() - 13) as u8);
+    }
+    message.extend(value);
+}
+
+/// Describes what needs attention, such as a latched fan or a failing sensor.
+fn alerts(snapshot: &Snapshot) -> Vec<String> {
+    let mut alerts = Vec::new();
+    if snapshot.latched {
+        alerts.push("latched at maximum".to_string());
+    }
+    if let Some(fault) = &snapshot.fault {
+        alerts.push(format!("sensor fault: {}", fault.replace('\n', " ")));
+    }
+    if let Some(error) = &snapshot.reload_error {
+        alerts.push(format!("reload rejected: {}", error.replace('\n', " ")));
+    }
+    alerts
+}
+
+/// Fetches a resource from a controller, or `None` while it has no value.
+///
+/// The socket's read timeout bounds the wait for the response.
+pub fn get(socket: &UdpSocket, address: SocketAddr, path: &str) -> io::Result<Option<String>> {
+    static MESSAGE_ID: AtomicU16 = AtomicU16::new(0);
+    let id = MESSAGE_ID.fetch_add(1, Ordering::Relaxed);
+
+    // Message ID doubles as the token
+    let mut request = vec![VERSION << 6 | CONFIRMABLE << 4 | 2, GET];
+    request.extend(id.to_be_bytes());
+    request.extend(id.to_be_bytes());
+    let mut delta = URI_PATH;
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        encode_option(&mut request, delta, segment.as_bytes());
+        delta = 0;
+    }
+    socket.send_to(&request, address)?;
+
+    let mut buffer = [0u8; 1152];
+    loop {
+        let (length, peer) = socket.recv_from(&mut buffer)?;
+        let Some(response) = Message::parse(&buffer[..length]) else {
+            continue;
+        };
+        // Late answers to earlier requests are skipped
+        if peer != address || response.token != id.to_be_bytes() {
+            continue;
+        }
+        return match response.code {
+            CONTENT => Ok(Some(String::from_utf8_lossy(response.payload).into_owned())),
+            SERVICE_UNAVAILABLE => Ok(None),
+            code => Err(io::Error::other(format!(
+                "{} answered {}.{:02}",
+                path,
+                code >> 5,
+                code & 0x1f
+            ))),
+        };
+    }
+}
+
+/// Answers requests on the given address from a background thread.
+pub fn serve(
+    address: SocketAddr,
+    status: Status,
+    setpoint: Setpoint,
+    temperature_max: Celsius,
+) -> io::Result<JoinHandle<()>> {
+    let socket = UdpSocket::bind(address)?;
+
+    Ok(thread::spawn(move || {
+        let mut buffer = [0u8; 1152];
+        loop {
+            let (length, peer) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) => {
+                    log::warn!("Failed to receive CoAP request: {}", error);
+                    continue;
+                }
+            };
+            let snapshot = status.snapshot();
+            let resources = Resources {
+                snapshot: &snapshot,
+                setpoint: &setpoint,
+                temperature_max,
+            };
+            let Some(response) = resources.respond(&buffer[..length]) else {
+                continue;
+            };
+            if let Err(error) = socket.send_to(&response, peer) {
+                log::warn!("Failed to answer CoAP request from {}: {}", peer, error);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get, Resources};
+    use crate::{pwm::tests::duty, setpoint::Setpoint, status::Snapshot, units::Celsius};
+    use std::{net::UdpSocket, thread, time::Duration};
+
+    /// Confirmable request with message ID 0x1234 and token 0xab.
+    fn request(method: u8, path: &[&str], payload: &str) -> Vec<u8> {
+        let mut message = vec![0x41, method, 0x12, 0x34, 0xab];
+        let mut previous = 0;
+        for segment in path {
+            message.push((11 - previous) << 4 | segment.len() as u8);
+            message.extend(segment.as_bytes());
+            previous = 11;
+        }
+        if !payload.is_empty() {
+            message.push(0xff);
+            message.extend(payload.as_bytes());
+        }
+        message
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            temperature: Some(Celsius::new(41, 500)),
+            duty: Some(duty(52)),
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn gets_resources() {
+        let snapshot = snapshot();
+        let setpoint = Setpoint::new();
+        let resources = Resources {
+            snapshot: &snapshot,
+            setpoint: &setpoint,
+            temperature_max: Celsius::new(70, 0),
+        };
+
+        // Acknowledgement carrying 2.05 Content as text/plain
+        assert_eq!(
+            b"\x61\x45\x12\x34\xab\xc0\xff41.5".to_vec(),
+            resources
+                .respond(&request(0x01, &["temperature"], ""))
+                .unwrap()
+        );
+        // Target is unknown until the first decision
+        assert_eq!(
+            0xa3,
+            resources.respond(&request(0x01, &["target"], "")).unwrap()[1]
+        );
+        assert_eq!(
+            0x84,
+            resources.respond(&request(0x01, &["rpm"], "")).unwrap()[1]
+        );
+
+        let discovery = resources
+            .respond(&request(0x01, &[".well-known", "core"], ""))
+            .unwrap();
+        assert!(String::from_utf8_lossy(&discovery).contains("</fan>;ct=0"));
+    }
+
+    #[test]
+    fn client_gets_resources() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        let answering = thread::spawn(move || {
+            let snapshot = Snapshot {
+                latched: true,
+                ..snapshot()
+            };
+            let setpoint = Setpoint::new();
+            let resources = Resources {
+                snapshot: &snapshot,
+                setpoint: &setpoint,
+                temperature_max: Celsius::new(70, 0),
+            };
+            let mut buffer = [0u8; 1152];
+            for _ in 0..3 {
+                let (length, peer) = server.recv_from(&mut buffer).unwrap();
+                let response = resources.respond(&buffer[..length]).unwrap();
+                server.send_to(&response, peer).unwrap();
+            }
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(
+            Some("41.5".to_string()),
+            get(&client, address, "/temperature").unwrap()
+        );
+        assert_eq!(None, get(&client, address, "/target").unwrap());
+        assert_eq!(
+            Some("latched at maximum".to_string()),
+            get(&client, address, "/alerts").unwrap()
+        );
+        answering.join().unwrap();
+    }
+
+    #[test]
+    fn puts_target() {
+        let snapshot = snapshot();
+        let setpoint = Setpoint::new();
+        let resources = Resources {
+            snapshot: &snapshot,
+            setpoint: &setpoint,
+            temperature_max: Celsius::new(70, 0),
+        };
+
+        assert_eq!(
+            0x44,
+            resources
+                .respond(&request(0x03, &["target"], "45.5"))
+                .unwrap()[1]
+        );
+        assert_eq!(Some(Celsius::new(45, 500)), setpoint.take());
+
+        assert_eq!(
+            0x80,
+            resources
+                .respond(&request(0x03, &["target"], "75"))
+                .unwrap()[1]
+        );
+        assert_eq!(
+            0x85,
+            resources.respond(&request(0x03, &["fan"], "80")).unwrap()[1]
+        );
+        assert_eq!(None, setpoint.take());
+    }
+}
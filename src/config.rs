@@ -0,0 +1,475 @@
+//! Configuration files holding default options.
+//!
+//! Keys are long option names, e.g. `gpio-pwm = 3`, and options given on the command line take
+//! precedence over them. A file can pull in others with `include = ["conf.d/*.toml"]` and override
+//! keys on a single machine in a `[host.<hostname>]` section, so one shared base config can be
+//! deployed across differently wired boards.
+//!
+//! Named profiles, e.g. `[profiles.silent]`, hold settings applied on top of everything else when
+//! selected by `profile = "silent"` or `--profile silent`.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+/// Nesting depth at which includes are assumed to loop
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Value(String),
+    IncludeDepth(PathBuf),
+    UnknownProfile(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, error) => {
+                write!(f, "Failed to read config {:?}: {}", path, error)
+            }
+            ConfigError::Parse(path, error) => {
+                write!(f, "Failed to parse config {:?}: {}", path, error)
+            }
+            ConfigError::Value(key) => {
+                write!(f, "Unsupported value for config key {:?}", key)
+            }
+            ConfigError::IncludeDepth(path) => {
+                write!(f, "Config includes nested too deeply at {:?}", path)
+            }
+            ConfigError::UnknownProfile(name) => {
+                write!(f, "No [profiles.{}] section in the config", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Returns the command line with options from the `--config` file, if one is given, inserted
+/// before the user's own options.
+pub fn args_with_config(mut argv: Vec<String>) -> Result<Vec<String>, ConfigError> {
+    let profile = option_value(&argv, "--profile");
+    let path = match option_value(&argv, "--config") {
+        Some(path) => path,
+        None => return profile.map_or(Ok(argv), |name| Err(ConfigError::UnknownProfile(name))),
+    };
+
+    let mut table = load(Path::new(&path), &hostname())?;
+    select_profile(&mut table, profile)?;
+    let options = to_args(&table)?;
+    let position = argv.len().min(1);
+    argv.splice(position..position, options);
+    Ok(argv)
+}
+
+/// Returns the names of the profiles in the `--config` file, in name order.
+pub fn profiles(argv: &[String]) -> Result<Vec<String>, ConfigError> {
+    let Some(path) = option_value(argv, "--config") else {
+        return Ok(Vec::new());
+    };
+    let table = load(Path::new(&path), &hostname())?;
+    Ok(table
+        .get("profiles")
+        .and_then(Value::as_table)
+        .map(|profiles| profiles.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+/// Replaces the profile sections with the settings of the selected one, if any, given on the
+/// command line or else by the `profile` key.
+fn select_profile(table: &mut Table, selected: Option<String>) -> Result<(), ConfigError> {
+    let profiles = table.remove("profiles");
+    let selected = match selected {
+        Some(name) => name,
+        None => match table.get("profile") {
+            Some(Value::String(name)) => name.clone(),
+            Some(_) => return Err(ConfigError::Value("profile".to_string())),
+            None => return Ok(()),
+        },
+    };
+
+    let section = profiles
+        .as_ref()
+        .and_then(|profiles| profiles.get(&selected))
+        .ok_or_else(|| ConfigError::UnknownProfile(selected.clone()))?
+        .as_table()
+        .ok_or_else(|| ConfigError::Value(format!("profiles.{}", selected)))?;
+    table.extend(section.clone());
+    table.insert("profile".to_string(), Value::String(selected));
+    Ok(())
+}
+
+/// Finds the value of an option on the command line, the last one if it's given more than once.
+fn option_value(argv: &[String], option: &str) -> Option<String> {
+    let mut value = None;
+    let mut arguments = argv.iter().skip(1);
+    while let Some(argument) = arguments.next() {
+        if argument == option {
+            value = arguments.next().cloned();
+        } else if let Some(rest) = argument
+            .strip_prefix(option)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            value = Some(rest.to_string());
+        }
+    }
+    value
+}
+
+/// Loads a config file together with its includes and the section of the given host.
+pub fn load(path: &Path, hostname: &str) -> Result<Table, ConfigError> {
+    load_nested(path, hostname, 0)
+}
+
+fn load_nested(path: &Path, hostname: &str, depth: usize) -> Result<Table, ConfigError> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(ConfigError::IncludeDepth(path.to_path_buf()));
+    }
+
+    let content =
+        fs::read_to_string(path).map_err(|error| ConfigError::Read(path.to_path_buf(), error))?;
+    let mut table: Table =
+        toml::from_str(&content).map_err(|error| ConfigError::Parse(path.to_path_buf(), error))?;
+
+    let includes = table.remove("include");
+    let hosts = table.remove("host");
+
+    // Later files override earlier ones, and the host section overrides them all
+    if let Some(includes) = includes {
+        let patterns = includes
+            .as_array()
+            .ok_or_else(|| ConfigError::Value("include".to_string()))?;
+        for pattern in patterns {
+            let pattern = pattern
+                .as_str()
+                .ok_or_else(|| ConfigError::Value("include".to_string()))?;
+            let base = path.parent().unwrap_or_else(|| Path::new(""));
+            for included in expand(&base.join(pattern))? {
+                merge(&mut table, load_nested(&included, hostname, depth + 1)?);
+            }
+        }
+    }
+
+    if let Some(section) = hosts.as_ref().and_then(|hosts| hosts.get(hostname)) {
+        let section = section
+            .as_table()
+            .ok_or_else(|| ConfigError::Value(format!("host.{}", hostname)))?;
+        merge(&mut table, section.clone());
+    }
+
+    Ok(table)
+}
+
+/// Overrides the keys of a table with those of another, merging their profiles one by one.
+fn merge(table: &mut Table, mut other: Table) {
+    if let Some(Value::Table(profiles)) = other.remove("profiles") {
+        match table.get_mut("profiles") {
+            Some(Value::Table(existing)) => existing.extend(profiles),
+            _ => {
+                table.insert("profiles".to_string(), Value::Table(profiles));
+            }
+        }
+    }
+    table.extend(other);
+}
+
+/// Returns the files matching an include pattern in sorted order.
+///
+/// Wildcards are only supported in the file name, a pattern without them must name an
+/// existing file.
+fn expand(pattern: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+    let name = pattern
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return Ok(vec![pattern.to_path_buf()]);
+    }
+
+    let directory = pattern.parent().unwrap_or_else(|| Path::new(""));
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        // Nothing to include yet, e.g. an empty conf.d that was never created
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(ConfigError::Read(directory.to_path_buf(), error)),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| wildcard_match(&name, &entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Matches a name against a pattern where `*` matches any run of characters and `?` any one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Converts config keys to command line options.
+///
+/// Arrays repeat the option once for each of their values.
+pub fn to_args(table: &Table) -> Result<Vec<String>, ConfigError> {
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let option = format!("--{}", key.replace('_', "-"));
+        match value {
+            Value::Boolean(true) => args.push(option),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                for value in values {
+                    let value = scalar(value).ok_or_else(|| ConfigError::Value(key.clone()))?;
+                    args.extend([option.clone(), value]);
+                }
+            }
+            value => {
+                let value = scalar(value).ok_or_else(|| ConfigError::Value(key.clone()))?;
+                args.extend([option, value]);
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Returns an option value given as a string or number.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns config file content with a top-level key set to a value, keeping comments, layout and
+/// all other keys as they were.
+///
+/// The key's line is replaced if it has one outside all sections, and otherwise added before the
+/// first section, where it isn't taken for a key of that section.
+pub fn set_option(content: &str, key: &str, value: &str) -> String {
+    let assignment = format!("{} = {}", key, value);
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let first_section = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+
+    let existing = lines[..first_section].iter().position(|line| {
+        line.split_once('=')
+            .is_some_and(|(name, _)| name.trim().replace('_', "-") == key)
+    });
+    match existing {
+        Some(index) => lines[index] = assignment,
+        None => {
+            // Keep a blank line between the top-level keys and the first section
+            let at = match first_section.checked_sub(1) {
+                Some(before) if lines[before].trim().is_empty() => before,
+                _ => first_section,
+            };
+            lines.insert(at, assignment);
+        }
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+/// Returns the name of this machine, matched against `[host.<hostname>]` sections.
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return String::new();
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{args_with_config, load, profiles, set_option, to_args, wildcard_match};
+    use crate::{args::Args, mock::TempPath};
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn converts_keys_to_options() {
+        let table = toml::from_str(
+            "gpio-pwm = 3\nrelay_active_low = true\nprint-systemd = false\nspi-device = \"/dev/spidev0.1\"",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                "--gpio-pwm",
+                "3",
+                "--relay-active-low",
+                "--spi-device",
+                "/dev/spidev0.1"
+            ],
+            to_args(&table).unwrap()
+        );
+    }
+
+    #[test]
+    fn sets_top_level_option() {
+        let content = "# Tuned\ngpio-pwm = 3\ntemperature_target_value = 45 # warm\n\n\
+                       [profiles.silent]\ntemperature-target-value = 50\n";
+        assert_eq!(
+            "# Tuned\ngpio-pwm = 3\ntemperature-target-value = 42.5\n\n\
+             [profiles.silent]\ntemperature-target-value = 50\n",
+            set_option(content, "temperature-target-value", "42.5")
+        );
+        assert_eq!(
+            "gpio-pwm = 3\npollrate = 2\n\n[host.garage]\npollrate = 10\n",
+            set_option(
+                "gpio-pwm = 3\n\n[host.garage]\npollrate = 10\n",
+                "pollrate",
+                "2"
+            )
+        );
+        assert_eq!("pollrate = 2\n", set_option("", "pollrate", "2"));
+    }
+
+    #[test]
+    fn repeats_options_for_arrays() {
+        let table = toml::from_str("season = [\"11-01..03-31=38\", \"06-01..08-31=45\"]").unwrap();
+
+        assert_eq!(
+            vec!["--season", "11-01..03-31=38", "--season", "06-01..08-31=45"],
+            to_args(&table).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_nested_values() {
+        let table = toml::from_str("gpio-pwm = [[3, 4]]").unwrap();
+        assert!(to_args(&table).is_err());
+        let table = toml::from_str("[gpio-pwm]\npin = 3").unwrap();
+        assert!(to_args(&table).is_err());
+    }
+
+    #[test]
+    fn includes_and_host_sections_override_in_order() {
+        let directory = TempPath::dir("includes");
+        fs::create_dir(directory.join("conf.d")).unwrap();
+        fs::write(
+            directory.join("base.toml"),
+            "include = [\"conf.d/*.toml\"]\ngpio-pwm = 3\npollrate = 5\n\n[host.garage]\npollrate = 10\n",
+        )
+        .unwrap();
+        fs::write(directory.join("conf.d/10-fan.toml"), "gpio-pwm = 4\n").unwrap();
+        fs::write(directory.join("conf.d/20-fan.toml"), "gpio-pwm = 5\n").unwrap();
+        fs::write(directory.join("conf.d/notes.txt"), "gpio-pwm = 6\n").unwrap();
+
+        let garage = load(&directory.join("base.toml"), "garage").unwrap();
+        assert_eq!(5, garage["gpio-pwm"].as_integer().unwrap());
+        assert_eq!(10, garage["pollrate"].as_integer().unwrap());
+
+        let attic = load(&directory.join("base.toml"), "attic").unwrap();
+        assert_eq!(5, attic["pollrate"].as_integer().unwrap());
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let directory = TempPath::dir("precedence");
+        let path = directory.join("config.toml");
+        fs::write(&path, "gpio-pwm = 3\npollrate = 10\n").unwrap();
+
+        let argv = [
+            "fan-controller",
+            "--config",
+            path.to_str().unwrap(),
+            "--pollrate",
+            "2",
+        ];
+        let args = Args::try_parse_from(args_with_config(argv.map(String::from).to_vec()).unwrap())
+            .unwrap();
+
+        assert_eq!(Some(3), args.gpio_pwm);
+        assert_eq!(2, args.pollrate);
+    }
+
+    #[test]
+    fn selected_profile_overrides_config() {
+        let directory = TempPath::dir("profiles");
+        let path = directory.join("config.toml");
+        fs::write(
+            &path,
+            "gpio-pwm = 3\npwm-max = 100\nprofile = \"balanced\"\n\n\
+             [profiles.silent]\npwm-max = 50\n\n[profiles.balanced]\npwm-max = 80\n",
+        )
+        .unwrap();
+        let argv = |extra: &[&str]| {
+            let mut argv = vec!["fan-controller", "--config", path.to_str().unwrap()];
+            argv.extend(extra);
+            Args::try_parse_from(args_with_config(
+                argv.iter().map(|s| s.to_string()).collect(),
+            )?)
+            .map_err(|error| super::ConfigError::Value(error.to_string()))
+        };
+
+        let args = argv(&[]).unwrap();
+        assert_eq!("80", args.pwm_max.to_string());
+        assert_eq!(Some("balanced".to_string()), args.profile);
+
+        let args = argv(&["--profile", "silent"]).unwrap();
+        assert_eq!("50", args.pwm_max.to_string());
+        assert_eq!(Some("silent".to_string()), args.profile);
+
+        assert!(argv(&["--profile", "turbo"]).is_err());
+        assert_eq!(
+            vec!["balanced", "silent"],
+            profiles(&["fan-controller", "--config", path.to_str().unwrap()].map(String::from))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("*.toml", "10-fan.toml"));
+        assert!(wildcard_match("fan-?.toml", "fan-1.toml"));
+        assert!(!wildcard_match("*.toml", "notes.txt"));
+        assert!(!wildcard_match("fan-?.toml", "fan-10.toml"));
+    }
+}
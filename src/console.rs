@@ -0,0 +1,379 @@
+//! Colored, columnar console output for watching the controller in a terminal.
+
+use crate::{
+    alarm::AlarmLevel,
+    events::{Event, LogSink, Sink},
+    i18n::text,
+    template::Template,
+    units::{Celsius, Duty},
+};
+use clap::ValueEnum;
+use std::io::{self, IsTerminal, Write};
+
+const RESET: &str = "\x1b[0m";
+const RED: &str = "\x1b[31m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+const DIM: &str = "\x1b[2m";
+/// Returns the cursor to the start of the line and erases it
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// How events are shown on the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConsoleMode {
+    /// Colored output in a terminal, plain log lines when piped or run by systemd
+    Auto,
+    /// Plain log lines
+    Plain,
+    /// Colored output with a continuously updated summary line
+    Color,
+}
+
+impl ConsoleMode {
+    /// Returns whether to use colored output.
+    pub fn interactive(self) -> bool {
+        match self {
+            ConsoleMode::Auto => {
+                io::stdout().is_terminal()
+                    // systemd connects output to the journal, which has no use for escape codes
+                    && std::env::var_os("JOURNAL_STREAM").is_none()
+                    && std::env::var_os("NO_COLOR").is_none()
+            }
+            ConsoleMode::Plain => false,
+            ConsoleMode::Color => true,
+        }
+    }
+}
+
+/// Decimal separator of temperatures shown to people.
+///
+/// Machine-readable output such as JSON always uses a point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum DecimalSeparator {
+    #[default]
+    Point,
+    /// For spreadsheets in locales writing 40,5
+    Comma,
+}
+
+impl DecimalSeparator {
+    pub fn celsius(self, value: Celsius) -> String {
+        match self {
+            DecimalSeparator::Point => value.to_string(),
+            DecimalSeparator::Comma => format!("{:#}", value),
+        }
+    }
+}
+
+/// Returns the sink showing events on the console in the given mode.
+///
+/// The template only words plain log lines, colored output keeps its columns.
+pub fn sink(
+    mode: ConsoleMode,
+    decimal: DecimalSeparator,
+    template: Option<Template>,
+) -> Box<dyn Sink> {
+    if mode.interactive() {
+        Box::new(InteractiveSink::new(io::stdout(), decimal))
+    } else {
+        Box::new(LogSink { decimal, template })
+    }
+}
+
+/// Shows fan speed changes as colored columns below a summary line that updates in place.
+pub struct InteractiveSink<W: Write> {
+    writer: W,
+    decimal: DecimalSeparator,
+    temperature: Option<Celsius>,
+    target: Option<Celsius>,
+    duty: Option<Duty>,
+}
+
+impl<W: Write> InteractiveSink<W> {
+    pub fn new(writer: W, decimal: DecimalSeparator) -> Self {
+        Self {
+            writer,
+            decimal,
+            temperature: None,
+            target: None,
+            duty: None,
+        }
+    }
+
+    /// Prints a line above the summary, which is then redrawn below it.
+    fn line(&mut self, color: &str, text: &str) -> io::Result<()> {
+        writeln!(self.writer, "{}{}{}{}", CLEAR_LINE, color, text, RESET)?;
+        self.summary()
+    }
+
+    fn summary(&mut self) -> io::Result<()> {
+        let Some(temperature) = self.temperature else {
+            return Ok(());
+        };
+        let cell = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+        write!(
+            self.writer,
+            "{}{:>8}  {}{} {:>8}{}  {} {:>4}",
+            CLEAR_LINE,
+            format!("{}°C", self.decimal.celsius(temperature)),
+            DIM,
+            text("console-target", &[]),
+            cell(
+                self.target
+                    .map(|target| format!("{}°C", self.decimal.celsius(target)))
+            ),
+            RESET,
+            text("console-fan", &[]),
+            cell(self.duty.map(|duty| format!("{}%", duty))),
+        )?;
+        self.writer.flush()
+    }
+
+    fn render(&mut self, event: &Event) -> io::Result<()> {
+        match event {
+            Event::Sample { temperature, .. } => {
+                self.temperature = Some(*temperature);
+                self.summary()
+            }
+            Event::Decision {
+                temperature,
+                target,
+                from,
+                to,
+            } => {
+                self.target = Some(*target);
+                self.duty = Some(*to);
+                let (color, arrow) = if to > from {
+                    (RED, "▲")
+                } else {
+                    (CYAN, "▼")
+                };
+                let text = format!(
+                    "{:>8}  {} {:>8}  {} {:>4} {} {:>4}",
+                    format!("{}°C", self.decimal.celsius(*temperature)),
+                    text("console-target", &[]),
+                    format!("{}°C", self.decimal.celsius(*target)),
+                    text("console-fan", &[]),
+                    format!("{}%", from),
+                    arrow,
+                    format!("{}%", to)
+                );
+                self.line(color, &text)
+            }
+            Event::Override { target } => {
+                self.target = Some(*target);
+                let text = text(
+                    "console-target-set",
+                    &[("target", &self.decimal.celsius(*target))],
+                );
+                self.line(YELLOW, &text)
+            }
+            Event::OverrideExpired { target } => {
+                self.target = Some(*target);
+                let text = text(
+                    "console-override-expired",
+                    &[("target", &self.decimal.celsius(*target))],
+                );
+                self.line(YELLOW, &text)
+            }
+            Event::ModeChanged { boost, quiet } => {
+                let state = |on| text(if on { "console-on" } else { "console-off" }, &[]);
+                let text = text(
+                    "console-modes",
+                    &[("boost", &state(*boost)), ("quiet", &state(*quiet))],
+                );
+                self.line(YELLOW, &text)
+            }
+            Event::EnergyBias { bias, target } => {
+                self.target = Some(*target);
+                let text = text(
+                    "console-energy-bias",
+                    &[
+                        ("bias", &self.decimal.celsius(*bias)),
+                        ("target", &self.decimal.celsius(*target)),
+                    ],
+                );
+                self.line(YELLOW, &text)
+            }
+            Event::ScheduledTarget { target } => {
+                // Moves a little at a time, so only the summary shows it
+                self.target = Some(*target);
+                self.summary()
+            }
+            Event::AbTestArm {
+                profile: Some(profile),
+            } => self.line(
+                YELLOW,
+                &text("console-ab-test-arm", &[("profile", profile)]),
+            ),
+            Event::AbTestArm { profile: None } => {
+                self.line(YELLOW, &text("console-ab-test-ended", &[]))
+            }
+            Event::SeasonChanged { season, target } => {
+                self.target = Some(*target);
+                let target = self.decimal.celsius(*target);
+                let text = match season {
+                    Some(season) => {
+                        text("console-season", &[("season", season), ("target", &target)])
+                    }
+                    None => text("console-no-season", &[("target", &target)]),
+                };
+                self.line(YELLOW, &text)
+            }
+            Event::Fault { message } => {
+                self.line(BOLD_RED, &text("console-fault", &[("message", message)]))
+            }
+            Event::Dropped { count } => {
+                self.line(YELLOW, &text("console-dropped", &[("count", count)]))
+            }
+            Event::Reloaded { changes } => {
+                let mut text = text("console-reloaded", &[]);
+                for change in changes {
+                    text += &format!("\n  {}", change);
+                }
+                self.line(YELLOW, &text)
+            }
+            Event::Overtemperature { temperature, max } => self.line(
+                BOLD_RED,
+                &text(
+                    "console-overtemperature",
+                    &[
+                        ("temperature", &self.decimal.celsius(*temperature)),
+                        ("max", &self.decimal.celsius(*max)),
+                    ],
+                ),
+            ),
+            Event::Latched { temperature } => self.line(
+                BOLD_RED,
+                &text(
+                    "console-latched",
+                    &[("temperature", &self.decimal.celsius(*temperature))],
+                ),
+            ),
+            Event::LatchReleased => self.line(YELLOW, &text("console-latch-released", &[])),
+            Event::OutputFailed { message } => self.line(
+                BOLD_RED,
+                &text("console-output-failed", &[("message", message)]),
+            ),
+            Event::OutputReinitialized { reinits } => self.line(
+                YELLOW,
+                &text("console-output-reinitialized", &[("reinits", reinits)]),
+            ),
+            Event::Alarm {
+                sensor,
+                level,
+                temperature,
+            } => {
+                let (color, id) = match level {
+                    AlarmLevel::Normal => (YELLOW, "console-alarm-normal"),
+                    AlarmLevel::Warning => (RED, "console-alarm-warning"),
+                    AlarmLevel::Critical => (BOLD_RED, "console-alarm-critical"),
+                };
+                self.line(
+                    color,
+                    &text(
+                        id,
+                        &[
+                            ("sensor", sensor),
+                            ("temperature", &self.decimal.celsius(*temperature)),
+                        ],
+                    ),
+                )
+            }
+            Event::ReloadRejected { message } => self.line(
+                BOLD_RED,
+                &text("console-reload-rejected", &[("message", message)]),
+            ),
+            Event::Progress(progress) => {
+                self.duty = Some(progress.duty);
+                write!(self.writer, "{}{}{}{}", CLEAR_LINE, YELLOW, progress, RESET)?;
+                if progress.done == progress.total {
+                    writeln!(self.writer)?;
+                }
+                self.writer.flush()
+            }
+        }
+    }
+}
+
+impl<W: Write> Sink for InteractiveSink<W> {
+    fn handle(&mut self, event: &Event) {
+        // Nothing sensible to do when the terminal went away
+        let _ = self.render(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecimalSeparator, InteractiveSink};
+    use crate::{events::Event, events::Sink, pwm::tests::duty, units::Celsius};
+
+    /// Returns what the sink wrote with escape codes removed.
+    fn plain(output: &[u8]) -> String {
+        let text = String::from_utf8(output.to_vec()).unwrap();
+        let mut plain = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => {
+                    // Skip up to and including the command letter
+                    for c in chars.by_ref() {
+                        if c.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                }
+                '\r' => plain.push('|'),
+                c => plain.push(c),
+            }
+        }
+        plain
+    }
+
+    #[test]
+    fn decisions_scroll_above_summary() {
+        let mut sink = InteractiveSink::new(Vec::new(), DecimalSeparator::Point);
+
+        sink.handle(&Event::Sample {
+            temperature: Celsius::new(41, 500),
+            raw: None,
+        });
+        sink.handle(&Event::Decision {
+            temperature: Celsius::new(41, 500),
+            target: Celsius::new(40, 0),
+            from: duty(50),
+            to: duty(52),
+        });
+
+        assert_eq!(
+            "|  41.5°C  target        -  fan    -\
+             |  41.5°C  target     40°C  fan  50% ▲  52%\n\
+             |  41.5°C  target     40°C  fan  52%",
+            plain(&sink.writer)
+        );
+    }
+
+    #[test]
+    fn summary_waits_for_first_sample() {
+        let mut sink = InteractiveSink::new(Vec::new(), DecimalSeparator::Point);
+
+        sink.handle(&Event::Override {
+            target: Celsius::new(45, 0),
+        });
+
+        assert_eq!("|Target set to 45°C\n", plain(&sink.writer));
+    }
+
+    #[test]
+    fn decimal_comma() {
+        let mut sink = InteractiveSink::new(Vec::new(), DecimalSeparator::Comma);
+
+        sink.handle(&Event::Override {
+            target: Celsius::new(42, 500),
+        });
+
+        assert_eq!("|Target set to 42,5°C\n", plain(&sink.writer));
+    }
+}
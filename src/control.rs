@@ -0,0 +1,217 @@
+//! Unix socket accepting line-based commands while the controller runs.
+//!
+//! Each line is one command and gets a one-line reply, either the requested value, `ok`, or
+//! `error: ` followed by the reason, e.g.
+//!
+//! ```sh
+//! echo "log-filter controller=debug,info" | socat - UNIX-CONNECT:/run/fan-controller.sock
+//! ```
+
+use crate::{
+    latch::Latch,
+    logging::{self, Filter},
+    reload,
+    setpoint::Setpoint,
+    status::Status,
+    units::Celsius,
+};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    thread::{self, JoinHandle},
+};
+
+/// Command sent over the control socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Reports the latest state as JSON
+    Status,
+    /// Reports the log filter in effect
+    LogFilter,
+    /// Replaces the log filter
+    SetLogFilter(Filter),
+    /// Releases the overtemperature latch
+    Acknowledge,
+    /// Switches to a named profile of the config file
+    Profile(String),
+    /// Reloads the options like SIGHUP, if forced even those reducing cooling during an
+    /// overtemperature emergency
+    Reload { force: bool },
+    /// Reports the target temperature in effect
+    Target,
+    /// Changes the target temperature until something else does
+    SetTarget(Celsius),
+}
+
+/// State commands report on and act upon, shared with the controller.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub status: Status,
+    pub latch: Latch,
+    pub setpoint: Setpoint,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let (name, argument) = match line.trim().split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim())),
+            None => (line.trim(), None),
+        };
+
+        match (name, argument) {
+            ("status", None) => Ok(Command::Status),
+            ("ack", None) => Ok(Command::Acknowledge),
+            ("log-filter", None) => Ok(Command::LogFilter),
+            ("log-filter", Some(filter)) => Ok(Command::SetLogFilter(filter.parse()?)),
+            ("profile", Some(name)) => Ok(Command::Profile(name.to_string())),
+            ("reload", None) => Ok(Command::Reload { force: false }),
+            ("reload", Some("--force")) => Ok(Command::Reload { force: true }),
+            ("target", None) => Ok(Command::Target),
+            ("target", Some(target)) => {
+                Ok(Command::SetTarget(target.parse().map_err(|_| {
+                    format!("invalid target temperature {:?}", target)
+                })?))
+            }
+            _ => Err(format!("unknown command {:?}", name)),
+        }
+    }
+}
+
+/// Executes a command line and returns the reply.
+pub fn respond(line: &str, context: &Context) -> String {
+    match Command::parse(line) {
+        Ok(Command::Status) => context.status.snapshot().to_json(),
+        Ok(Command::LogFilter) => logging::filter().to_string(),
+        Ok(Command::SetLogFilter(filter)) => {
+            log::info!("Log filter changed to {}", filter);
+            logging::set_filter(filter);
+            "ok".to_string()
+        }
+        Ok(Command::Acknowledge) if context.latch.acknowledge() => "ok".to_string(),
+        Ok(Command::Acknowledge) => "error: latch is not tripped".to_string(),
+        // Applied by the controller's next poll, which reports it as a reload event
+        Ok(Command::Profile(name)) => {
+            reload::switch_profile(&name);
+            "ok".to_string()
+        }
+        Ok(Command::Reload { force }) => {
+            if force {
+                log::warn!("Forced reload requested");
+            }
+            reload::request(force);
+            "ok".to_string()
+        }
+        Ok(Command::Target) => match context.status.snapshot().target {
+            Some(target) => target.to_string(),
+            None => "error: no target decided yet".to_string(),
+        },
+        // Applied by the controller's next poll like a target from Modbus or the encoder
+        Ok(Command::SetTarget(target)) => {
+            log::info!("Target temperature {}°C requested", target);
+            context.setpoint.request(target);
+            "ok".to_string()
+        }
+        Err(error) => format!("error: {}", error),
+    }
+}
+
+/// Listens for commands on a socket at the given path, replacing any stale socket left there.
+pub fn serve(path: &Path, context: Context) -> io::Result<JoinHandle<()>> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(error) = handle(stream, &context) {
+                        log::warn!("Control connection failed: {}", error);
+                    }
+                }
+                Err(error) => log::warn!("Failed to accept control connection: {}", error),
+            }
+        }
+    }))
+}
+
+fn handle(stream: UnixStream, context: &Context) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        writeln!(writer, "{}", respond(&line, context))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{serve, Command, Context};
+    use crate::{mock::TempPath, units::Celsius};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+    };
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Ok(Command::Status), Command::parse("status"));
+        assert_eq!(Ok(Command::Acknowledge), Command::parse("ack"));
+        assert_eq!(Ok(Command::LogFilter), Command::parse("log-filter\n"));
+        assert_eq!(
+            Ok(Command::SetLogFilter(
+                "controller=debug,info".parse().unwrap()
+            )),
+            Command::parse("log-filter controller=debug,info")
+        );
+        assert!(Command::parse("log-filter controller=loud").is_err());
+        assert_eq!(
+            Ok(Command::Profile("silent".to_string())),
+            Command::parse("profile silent")
+        );
+        assert_eq!(
+            Ok(Command::Reload { force: true }),
+            Command::parse("reload --force")
+        );
+        assert!(Command::parse("reload now").is_err());
+        assert_eq!(Ok(Command::Target), Command::parse("target"));
+        assert_eq!(
+            Ok(Command::SetTarget(Celsius::new(42, 500))),
+            Command::parse("target 42.5")
+        );
+        assert!(Command::parse("target warm").is_err());
+        assert!(Command::parse("reboot").is_err());
+    }
+
+    #[test]
+    fn changes_log_filter_over_socket() {
+        let path = TempPath::new("control");
+        let context = Context::default();
+        serve(&path, context.clone()).unwrap();
+
+        context.latch.trip();
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .write_all(b"log-filter controller=debug,warn\nlog-filter\nbogus\nstatus\nack\nack\n")
+            .unwrap();
+        let replies: Vec<String> = BufReader::new(stream)
+            .lines()
+            .take(6)
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!("ok", replies[0]);
+        assert_eq!("controller=debug,warn", replies[1]);
+        assert!(replies[2].starts_with("error: "));
+        assert!(replies[3].starts_with("{\"temperature\":null,"));
+        assert_eq!("ok", replies[4]);
+        assert_eq!("error: latch is not tripped", replies[5]);
+    }
+}
@@ -0,0 +1,2629 @@
+use crate::{
+    ab_test::AbTest,
+    alarm::{AlarmLevel, Alarms},
+    args::Args,
+    button::Modes,
+    clock::{Clock, SystemClock},
+    console,
+    cpu_load::CpuLoad,
+    curve::FanCurve,
+    energy::{Policy, Signal},
+    events::{Event, EventBus, LogSink, Sink},
+    inhibit::{Inhibitor, Transition},
+    latch::Latch,
+    observer::{Iteration, Observer, Verdict},
+    pwm::{FanStop, Health, NullOutput, Output, Pwm, Recovery, Slew},
+    reload::{self, Reloader},
+    schedule::{QuietHours, TimeOfDay},
+    script::{Inputs, Script},
+    season::{Calendar, MonthDay},
+    sensor::{self, FileSensor, SensorError},
+    setpoint::Setpoint,
+    stepping::{Ramp, Stepping, DEADZONE},
+    target_schedule::TargetSchedule,
+    temperature::{self, Smoothing, Temperature},
+    throttle::{Flags, Throttle},
+    units::{Celsius, Duty, Precision},
+};
+use std::time::{self, UNIX_EPOCH};
+
+pub struct Controller {
+    pub(crate) pollrate: time::Duration,
+    pub(crate) temperature: Temperature,
+    pub(crate) pwm: Pwm,
+    pub(crate) clock: Box<dyn Clock>,
+    pub(crate) observers: Vec<Box<dyn Observer>>,
+    pub(crate) events: EventBus,
+    pub(crate) reloader: Option<Reloader>,
+    pub(crate) inhibitor: Option<Inhibitor>,
+    /// Whether the output was released ahead of suspend or shutdown
+    pub(crate) parked: bool,
+    pub(crate) latch: Option<Latch>,
+    pub(crate) setpoint: Option<Setpoint>,
+    pub(crate) seasons: Option<Calendar>,
+    pub(crate) target_schedule: Option<TargetSchedule>,
+    /// Target the schedule gave last, `None` before the first poll
+    pub(crate) scheduled: Option<Celsius>,
+    pub(crate) fan_curve: Option<FanCurve>,
+    pub(crate) script: Option<Script>,
+    pub(crate) energy: Option<(Signal, Policy)>,
+    /// Offset to the target from the energy signal currently in effect
+    pub(crate) bias: Celsius,
+    pub(crate) modes: Option<Modes>,
+    /// Whether a boost was in effect at the last poll
+    pub(crate) boosting: bool,
+    /// Whether quiet mode was in effect at the last poll
+    pub(crate) quiet: bool,
+    pub(crate) quiet_hours: Option<QuietHours>,
+    pub(crate) ab_test: Option<AbTest>,
+    /// Profile of the A/B test switched to last
+    pub(crate) arm: Option<String>,
+    /// Profile selected by the options in effect
+    pub(crate) profile: Option<String>,
+    /// Profile to switch back to when quiet hours end, `Some(None)` for the one the config selects
+    pub(crate) resume_profile: Option<Option<String>>,
+    /// Percent added to the increment per °C/s of rise, 0 for a fixed increment
+    pub(crate) ramp_gain: u8,
+    /// Distance from the target within which the duty is left alone
+    pub(crate) deadzone: Celsius,
+    pub(crate) fan_stop: Option<FanStop>,
+    /// Whether a running fan is driven at maximum duty only, switched by the fan stop thresholds
+    pub(crate) on_off: bool,
+    /// Whether the fan was stopped at the last decision
+    pub(crate) stopped: bool,
+    /// Shortest and longest time between polls when polling adaptively
+    pub(crate) adaptive: Option<(time::Duration, time::Duration)>,
+    /// Raised target requested during an emergency, applied once it's over
+    pub(crate) deferred_target: Option<Celsius>,
+    /// How long a remote target lasts unless requested again
+    pub(crate) override_ttl: Option<time::Duration>,
+    /// When the remote target in effect lapses, and the automatic target it replaced
+    pub(crate) override_expiry: Option<(time::Instant, Celsius)>,
+    /// How long the fan stays at maximum after the maximum temperature was last reached
+    pub(crate) emergency_hold: Option<time::Duration>,
+    /// When the emergency hold in effect ends
+    pub(crate) held_until: Option<time::Instant>,
+    /// Shortest time between changes of the duty, below the maximum temperature
+    pub(crate) min_dwell: Option<time::Duration>,
+    /// When the duty last changed
+    pub(crate) changed_at: Option<time::Instant>,
+    /// CPU utilization sampler and the duty at full load
+    pub(crate) cpu_load: Option<(CpuLoad, Duty)>,
+    /// CPU utilization in percent over the last poll, if sampled
+    pub(crate) load: Option<u8>,
+    /// Source of the firmware's throttling flags and the duty to run at least at while throttled
+    pub(crate) throttle: Option<(Throttle, Duty)>,
+    /// Whether the firmware reported throttling more cooling helps with at the last poll
+    pub(crate) throttling: bool,
+    /// Sensors watched for alarm thresholds alone
+    pub(crate) alarms: Option<Alarms>,
+    /// Temperature at which the fan runs at the hard duty limit whatever else holds it lower
+    pub(crate) temperature_hard_max: Option<Celsius>,
+}
+
+/// Returns the quiet hours of the options, if any are given.
+fn quiet_hours(args: &Args) -> Option<QuietHours> {
+    (!args.quiet_hours.is_empty())
+        .then(|| QuietHours::new(args.quiet_hours.clone(), args.quiet_profile.clone()))
+}
+
+/// Returns the A/B test of the options, if one is given.
+fn ab_test(args: &Args) -> Option<AbTest> {
+    args.ab_test
+        .clone()
+        .map(|profiles| AbTest::new(profiles, args.ab_period))
+}
+
+/// Returns the adaptive polling bounds of the options, if given.
+fn adaptive(args: &Args) -> Option<(time::Duration, time::Duration)> {
+    args.pollrate_min.zip(args.pollrate_max).map(|(min, max)| {
+        (
+            time::Duration::from_secs(min),
+            time::Duration::from_secs(max),
+        )
+    })
+}
+
+/// Returns the seasons of the options, if any are given.
+fn calendar(args: &Args) -> Option<Calendar> {
+    (!args.season.is_empty())
+        .then(|| Calendar::new(args.season.clone(), args.temperature_target_value))
+}
+
+impl Controller {
+    /// Returns a controller to be used within the application.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Application options arguments
+    /// * `output` - Hardware the fan duty is written to
+    pub fn new(args: &Args, output: Box<dyn Output>) -> Self {
+        let mut controller = Self::from_parts(
+            time::Duration::from_secs(args.pollrate_min.unwrap_or(args.pollrate)),
+            Temperature::new(args),
+            Pwm::new(args, output),
+        )
+        .with_sink(console::sink(
+            args.console,
+            args.decimal_separator,
+            args.log_template.clone(),
+        ));
+        controller.seasons = calendar(args);
+        controller.target_schedule = args.target_schedule.clone();
+        controller.quiet_hours = quiet_hours(args);
+        controller.ab_test = ab_test(args);
+        controller.profile = args.profile.clone();
+        controller.fan_curve = args.fan_curve.clone();
+        controller.script = args.duty_script.clone();
+        controller.ramp_gain = args.ramp_gain;
+        controller.deadzone = args.deadzone;
+        controller.fan_stop = FanStop::new(args);
+        controller.on_off = args.switch_on_above.is_some();
+        controller.adaptive = adaptive(args);
+        controller.override_ttl = args.override_ttl;
+        controller.emergency_hold = args.emergency_hold;
+        controller.min_dwell = args.min_dwell.map(time::Duration::from_secs);
+        controller.cpu_load = args.cpu_load_duty.map(|duty| (CpuLoad::new(), duty));
+        controller.throttle = args.throttle_duty.map(|duty| (Throttle::new(), duty));
+        controller.alarms = Alarms::from_args(args);
+        controller.temperature_hard_max = args.temperature_hard_max;
+        controller
+    }
+
+    /// Returns a controller with defaults for everything but its core parts.
+    ///
+    /// No sinks are subscribed to its events.
+    pub(crate) fn from_parts(pollrate: time::Duration, temperature: Temperature, pwm: Pwm) -> Self {
+        Self {
+            pollrate,
+            temperature,
+            pwm,
+            clock: Box::new(SystemClock),
+            observers: Vec::new(),
+            events: EventBus::new(),
+            reloader: None,
+            inhibitor: None,
+            parked: false,
+            latch: None,
+            setpoint: None,
+            seasons: None,
+            target_schedule: None,
+            scheduled: None,
+            fan_curve: None,
+            script: None,
+            energy: None,
+            bias: Celsius::default(),
+            modes: None,
+            boosting: false,
+            quiet: false,
+            quiet_hours: None,
+            ab_test: None,
+            arm: None,
+            profile: None,
+            resume_profile: None,
+            ramp_gain: 0,
+            deadzone: DEADZONE,
+            fan_stop: None,
+            on_off: false,
+            stopped: false,
+            adaptive: None,
+            deferred_target: None,
+            override_ttl: None,
+            override_expiry: None,
+            emergency_hold: None,
+            held_until: None,
+            min_dwell: None,
+            changed_at: None,
+            cpu_load: None,
+            load: None,
+            throttle: None,
+            throttling: false,
+            alarms: None,
+            temperature_hard_max: None,
+        }
+    }
+
+    /// Returns a controller fed through `step` instead of reading a sensor, starting at `pwm_max`.
+    ///
+    /// Decisions are not written anywhere, the caller applies the returned duty itself.
+    pub fn detached(
+        target: Celsius,
+        temperature_max: Celsius,
+        pwm_min: Duty,
+        pwm_max: Duty,
+        pwm_increment: u8,
+        pwm_decrement: u8,
+    ) -> Self {
+        Self::from_parts(
+            time::Duration::ZERO,
+            Temperature {
+                current: Celsius::default(),
+                previous: Celsius::default(),
+                max: temperature_max,
+                target,
+                sensor: Box::new(FileSensor::new("")),
+                raw: Celsius::default(),
+                smoothing: Smoothing::default(),
+                precision: Precision::default(),
+            },
+            Pwm {
+                current: pwm_max,
+                previous: pwm_max,
+                increment: pwm_increment,
+                decrement: pwm_decrement,
+                min: pwm_min,
+                max: pwm_max,
+                hard_min: pwm_min,
+                hard_max: pwm_max,
+                output: Box::new(NullOutput),
+                written: None,
+                kick: None,
+                slew: None,
+                linearize: None,
+                recovery: Recovery::default(),
+            },
+        )
+        .with_sink(Box::new(LogSink::default()))
+    }
+
+    /// Replaces the clock driving the control loop, e.g. with virtual time in tests.
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Adds a hook invoked with every iteration's inputs and decision.
+    ///
+    /// Any observer returning `Verdict::Veto` keeps the fan at its current duty for that iteration.
+    pub fn with_observer(mut self, observer: Box<dyn Observer>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Subscribes a sink to the controller's events.
+    pub fn with_sink(mut self, sink: Box<dyn Sink>) -> Self {
+        self.events.subscribe(sink);
+        self
+    }
+
+    /// Makes the control loop reload its options on SIGHUP.
+    pub fn with_reloader(mut self, reloader: Reloader) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+
+    /// Makes the control loop park the fan before suspend and shutdown, delaying them until it has.
+    pub fn with_inhibitor(mut self, inhibitor: Inhibitor) -> Self {
+        self.inhibitor = Some(inhibitor);
+        self
+    }
+
+    /// Keeps the fan at maximum speed after the maximum temperature is reached, until the latch
+    /// is acknowledged.
+    pub fn with_latch(mut self, latch: Latch) -> Self {
+        self.latch = Some(latch);
+        self
+    }
+
+    /// Applies targets requested through the setpoint at the next poll.
+    pub fn with_setpoint(mut self, setpoint: Setpoint) -> Self {
+        self.setpoint = Some(setpoint);
+        self
+    }
+
+    /// Switches the target to that of the season each day falls in, checked at every poll.
+    ///
+    /// A target set by other means stays in effect until the next season starts.
+    pub fn with_seasons(mut self, seasons: Calendar) -> Self {
+        self.seasons = Some(seasons);
+        self
+    }
+
+    /// Sets the fan speed from the curve instead of stepping towards the target temperature.
+    pub fn with_fan_curve(mut self, curve: FanCurve) -> Self {
+        self.fan_curve = Some(curve);
+        self
+    }
+
+    /// Moves the target temperature by the policy's bias while the energy signal calls for it.
+    pub fn with_energy(mut self, signal: Signal, policy: Policy) -> Self {
+        self.energy = Some((signal, policy));
+        self
+    }
+
+    /// Runs the fan at maximum speed while boosting, and caps it below the maximum temperature
+    /// while quiet.
+    pub fn with_modes(mut self, modes: Modes) -> Self {
+        self.modes = Some(modes);
+        self
+    }
+
+    /// Switches quiet mode on during the quiet hours and off outside them, checked at every poll.
+    ///
+    /// Quiet mode toggled by other means stays that way until quiet hours next start or end.
+    pub fn with_quiet_hours(mut self, quiet_hours: QuietHours) -> Self {
+        self.quiet_hours = Some(quiet_hours);
+        self
+    }
+
+    /// Reloads the options and applies them if they're valid, publishing the outcome.
+    ///
+    /// The new sensor must give a reading before anything changes. Options selecting the fan
+    /// output only take effect on restart. During an emergency, options reducing cooling are
+    /// rejected.
+    pub fn reload(&mut self) {
+        self.reload_with(false);
+    }
+
+    /// Reloads the options, applying those reducing cooling during an emergency too if forced.
+    fn reload_with(&mut self, force: bool) {
+        let Some(mut reloader) = self.reloader.take() else {
+            return;
+        };
+
+        match reloader
+            .load()
+            .and_then(|loaded| {
+                if !force {
+                    self.interlock(&loaded.args)?;
+                }
+                Ok(loaded)
+            })
+            .and_then(|loaded| self.reconfigure(&loaded.args).map(|()| loaded))
+        {
+            Ok(loaded) => {
+                let changes = reloader.apply(loaded);
+                self.events.publish(Event::Reloaded { changes });
+            }
+            Err(message) => self.events.publish(Event::ReloadRejected { message }),
+        }
+        self.reloader = Some(reloader);
+    }
+
+    /// Whether the temperature is at its maximum, the emergency hold lasts or the overtemperature
+    /// latch is tripped.
+    fn emergency(&self) -> bool {
+        self.temperature.current >= self.temperature.max
+            || self.held_until.is_some()
+            || self.latch.as_ref().is_some_and(Latch::is_tripped)
+    }
+
+    /// Checks that the options don't reduce cooling during an emergency, so a mistaken remote
+    /// change can't slow the fan while it's most needed.
+    fn interlock(&self, args: &Args) -> Result<(), String> {
+        if !self.emergency() {
+            return Ok(());
+        }
+
+        let mut changes = Vec::new();
+        if args.pwm_max < self.pwm.max {
+            changes.push(format!("--pwm-max {} → {}", self.pwm.max, args.pwm_max));
+        }
+        let hard_max = args.pwm_hard_max.unwrap_or(args.pwm_max);
+        if hard_max < self.pwm.hard_max {
+            changes.push(format!(
+                "--pwm-hard-max {} → {}",
+                self.pwm.hard_max, hard_max
+            ));
+        }
+        if args.temperature_target_value > self.temperature.target {
+            changes.push(format!(
+                "--temperature-target-value {} → {}",
+                self.temperature.target, args.temperature_target_value
+            ));
+        }
+        if args.temperature_max_value > self.temperature.max {
+            changes.push(format!(
+                "--temperature-max-value {} → {}",
+                self.temperature.max, args.temperature_max_value
+            ));
+        }
+        if changes.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "{} would reduce cooling during an overtemperature emergency; send `reload --force` \
+             to the control socket to apply anyway",
+            changes.join(", ")
+        ))
+    }
+
+    /// Swaps in the control settings and sensor of the given options.
+    fn reconfigure(&mut self, args: &Args) -> Result<(), String> {
+        let mut sensor = sensor::from_args(args);
+        sensor
+            .read()
+            .map_err(|error| format!("new temperature sensor is unreadable: {}", error))?;
+
+        self.pollrate = time::Duration::from_secs(args.pollrate_min.unwrap_or(args.pollrate));
+        self.adaptive = adaptive(args);
+        self.temperature.sensor = sensor;
+        self.temperature.target = args.temperature_target_value;
+        self.override_ttl = args.override_ttl;
+        self.override_expiry = None;
+        self.emergency_hold = args.emergency_hold;
+        if self.emergency_hold.is_none() {
+            self.held_until = None;
+        }
+        self.min_dwell = args.min_dwell.map(time::Duration::from_secs);
+        // The sampler keeps its last times, so the next poll still has a load to go by
+        match (&mut self.cpu_load, args.cpu_load_duty) {
+            (Some((_, duty)), Some(reloaded)) => *duty = reloaded,
+            (_, reloaded) => {
+                self.cpu_load = reloaded.map(|duty| (CpuLoad::new(), duty));
+                self.load = None;
+            }
+        }
+        match (&mut self.throttle, args.throttle_duty) {
+            (Some((_, duty)), Some(reloaded)) => *duty = reloaded,
+            (_, reloaded) => {
+                self.throttle = reloaded.map(|duty| (Throttle::new(), duty));
+                self.throttling = false;
+            }
+        }
+        match &self.alarms {
+            Some(alarms) if alarms.watches(&args.alarm) => {}
+            _ => self.alarms = Alarms::from_args(args),
+        }
+        // Applied again from the next poll on, now that the reloaded target may have replaced it
+        self.seasons = calendar(args);
+        self.target_schedule = args.target_schedule.clone();
+        self.scheduled = None;
+        match (&self.quiet_hours, quiet_hours(args)) {
+            (Some(current), Some(reloaded)) if current.same_schedule(&reloaded) => {}
+            (_, reloaded) => self.quiet_hours = reloaded,
+        }
+        match (&self.ab_test, ab_test(args)) {
+            (Some(current), Some(reloaded)) if *current == reloaded => {}
+            (_, reloaded) => {
+                self.ab_test = reloaded;
+                // Switched to the profile of the new test, if any, from the next poll on
+                if self.arm.take().is_some() {
+                    self.events.publish(Event::AbTestArm { profile: None });
+                }
+            }
+        }
+        self.profile = args.profile.clone();
+        self.temperature.max = args.temperature_max_value;
+        let smoothing = Smoothing::new(args);
+        if !self.temperature.smoothing.same_filter(&smoothing) {
+            self.temperature.smoothing = smoothing;
+        }
+        self.temperature.precision = temperature::precision(args);
+        self.fan_curve = args.fan_curve.clone();
+        self.script = args.duty_script.clone();
+        if let Some((_, policy)) = &mut self.energy {
+            *policy = Policy::new(args);
+        }
+        self.pwm.increment = args.pwm_increment;
+        self.ramp_gain = args.ramp_gain;
+        self.deadzone = args.deadzone;
+        self.fan_stop = FanStop::new(args);
+        self.on_off = args.switch_on_above.is_some();
+        self.pwm.decrement = args.pwm_decrement;
+        self.pwm.min = args.pwm_min;
+        self.pwm.max = args.pwm_max;
+        self.pwm.hard_min = args.pwm_hard_min.unwrap_or(args.pwm_min);
+        self.pwm.hard_max = args.pwm_hard_max.unwrap_or(args.pwm_max);
+        self.temperature_hard_max = args.temperature_hard_max;
+        self.pwm.slew = Slew::new(args);
+        if self.pwm.linearize != args.linearize {
+            // The same cooling percent now needs a different duty
+            self.pwm.linearize = args.linearize.clone();
+            self.pwm.written = None;
+        }
+        let duty = if self.emergency() {
+            self.pwm.fix_pwm_value(self.pwm.current)
+        } else {
+            self.pwm.soft_clamp(self.pwm.current)
+        };
+        if duty != self.pwm.current && self.pwm.current != Duty::OFF {
+            self.pwm.write(duty);
+        }
+        Ok(())
+    }
+
+    /// Returns the target temperature in effect, including the energy bias.
+    fn target(&self) -> Celsius {
+        Celsius::from_millidegrees(
+            self.temperature.target.millidegrees() + self.bias.millidegrees(),
+        )
+    }
+
+    /// Returns the stepping algorithm settings currently in effect.
+    ///
+    /// At the maximum temperature the hard duty limit replaces the soft one and the quiet cap.
+    fn stepping(&self) -> Stepping {
+        let pwm_max = match &self.modes {
+            _ if self.temperature.current >= self.temperature.max => self.pwm.hard_max,
+            Some(modes) if self.quiet => self.pwm.max.min(modes.quiet_max()),
+            _ => self.pwm.max,
+        };
+        Stepping {
+            target: self.target(),
+            temperature_max: self.temperature.max,
+            pwm_min: self.pwm.min,
+            pwm_max,
+            increment: self.pwm.increment,
+            decrement: self.pwm.decrement,
+            deadzone: self.deadzone,
+            ramp: (self.ramp_gain > 0).then_some(Ramp {
+                gain: self.ramp_gain,
+                interval: self.pollrate,
+            }),
+        }
+    }
+
+    /// Returns the duty for a temperature regardless of the duty applied, from the fan curve if
+    /// there is one. Reaching the maximum temperature always gives the hard duty limit.
+    fn curve(&self, current: Celsius) -> Duty {
+        match &self.fan_curve {
+            _ if current >= self.temperature.max => self.pwm.hard_max,
+            Some(curve) => self.stepping().clamp(curve.duty(current)),
+            None => self.stepping().curve(current),
+        }
+    }
+
+    /// Returns the least duty the CPU load of the last poll calls for, scaled from the minimum
+    /// duty when idle to the one given for full load, within the duty limits.
+    fn load_floor(&self) -> Option<Duty> {
+        let (_, full) = self.cpu_load.as_ref()?;
+        let load = u16::from(self.load?);
+        let (min, full) = (u16::from(self.pwm.min.percent()), u16::from(full.percent()));
+        let percent = min + full.saturating_sub(min) * load / 100;
+        Some(self.stepping().clamp(Duty::new(percent as u8)?))
+    }
+
+    /// Returns the least duty while the firmware throttles, within the duty limits but above the
+    /// quiet mode cap, the maximum in on/off control.
+    fn throttle_floor(&self) -> Option<Duty> {
+        let (_, duty) = self.throttle.as_ref()?;
+        if !self.throttling {
+            return None;
+        }
+        if self.on_off {
+            return Some(self.pwm.max);
+        }
+        Some(self.pwm.soft_clamp(*duty))
+    }
+
+    /// Returns the duty the sensors' own curves call for, within the duty limits, if they have
+    /// any.
+    fn demanded(&self) -> Option<Duty> {
+        let demand = self.temperature.sensor.demand()?;
+        if self.temperature.current >= self.temperature.max {
+            return Some(self.pwm.hard_max);
+        }
+        Some(self.stepping().clamp(demand))
+    }
+
+    /// Returns the duty the script decides on, within the duty limits, or `None` without a
+    /// script or when it fails.
+    ///
+    /// At the maximum temperature the fan runs at maximum speed whatever the script says.
+    fn scripted(&self, duty: Duty) -> Option<Duty> {
+        let script = self.script.as_ref()?;
+        if self.temperature.current >= self.temperature.max {
+            return Some(self.pwm.hard_max);
+        }
+
+        let degrees = |celsius: Celsius| f64::from(celsius.millidegrees()) / 1000.0;
+        let inputs = Inputs {
+            temperature: degrees(self.temperature.current),
+            previous: degrees(self.temperature.previous),
+            target: degrees(self.target()),
+            max: degrees(self.temperature.max),
+            duty: f64::from(duty.percent()),
+            pwm_min: f64::from(self.pwm.min.percent()),
+            pwm_max: f64::from(self.pwm.max.percent()),
+        };
+        match script.run(&inputs) {
+            Ok(percent) => Some(self.stepping().clamp(Duty::new(percent)?)),
+            Err(error) => {
+                log::warn!("Duty script failed, stepping instead: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Picks the time until the next poll when polling adaptively: the shortest while the
+    /// temperature moves by more than the dead zone or is above the target, otherwise doubling
+    /// up to the longest.
+    fn adapt_pollrate(&mut self) {
+        let Some((min, max)) = self.adaptive else {
+            return;
+        };
+        let change = (self.temperature.current - self.temperature.previous).abs();
+        self.pollrate = if change > self.deadzone || self.temperature.current > self.target() {
+            min
+        } else {
+            (self.pollrate * 2).clamp(min, max)
+        };
+    }
+
+    /// Notes whether the fan should be stopped at the latest reading, and returns it.
+    fn follow_fan_stop(&mut self) -> bool {
+        self.stopped = self
+            .fan_stop
+            .is_some_and(|stop| stop.stopped(self.stopped, self.temperature.current));
+        self.stopped
+    }
+
+    /// Returns the duty a single run applies at a temperature, including a fan stop.
+    pub fn duty_at(&self, current: Celsius) -> Duty {
+        match self.fan_stop {
+            Some(stop) if stop.stopped(false, current) => Duty::OFF,
+            _ => self.running_duty(current),
+        }
+    }
+
+    /// Returns the duty of a running fan in a single run, the maximum in on/off control.
+    fn running_duty(&self, current: Celsius) -> Duty {
+        if self.on_off {
+            self.switched_on(current)
+        } else {
+            self.curve(current)
+        }
+    }
+
+    /// Returns the duty of a fan switched on in on/off control, the hard limit at the maximum
+    /// temperature.
+    fn switched_on(&self, current: Celsius) -> Duty {
+        if current >= self.temperature.max {
+            self.pwm.hard_max
+        } else {
+            self.pwm.max
+        }
+    }
+
+    /// Makes a control decision based on the latest temperature reading.
+    fn adjust(&mut self) {
+        // A stopped fan starts again from the minimum
+        let duty = self.pwm.current.max(self.pwm.min);
+        let new_pwm = if self.follow_fan_stop() {
+            Duty::OFF
+        } else if self.on_off {
+            self.switched_on(self.temperature.current)
+        } else if let Some(new_pwm) = self.demanded() {
+            new_pwm
+        } else if self.fan_curve.is_some() {
+            self.curve(self.temperature.current)
+        } else if let Some(new_pwm) = self.scripted(duty) {
+            new_pwm
+        } else {
+            self.stepping()
+                .decide(self.temperature.current, self.temperature.previous, duty)
+        };
+        let new_pwm = match self.load_floor() {
+            Some(floor) if !self.on_off && new_pwm != Duty::OFF && floor > new_pwm => {
+                log::debug!("CPU load raises {}% to {}%", new_pwm, floor);
+                floor
+            }
+            _ => new_pwm,
+        };
+        // Throttling starts a stopped fan too, and the duty applies at once
+        let (new_pwm, forced) = match self.throttle_floor() {
+            Some(floor) if floor > new_pwm => {
+                log::debug!("Throttling raises {}% to {}%", new_pwm, floor);
+                self.stopped = false;
+                (floor, true)
+            }
+            _ => (new_pwm, false),
+        };
+
+        let iteration = Iteration {
+            temperature: self.temperature.current,
+            previous_temperature: self.temperature.previous,
+            target: self.target(),
+            duty: self.pwm.current,
+            decision: new_pwm,
+        };
+        let mut vetoed = false;
+        for observer in self.observers.iter_mut() {
+            // Every observer sees the iteration, even after an earlier veto
+            vetoed |= observer.on_iteration(&iteration) == Verdict::Veto;
+        }
+        if vetoed {
+            return;
+        }
+
+        // Only make changes if new PWM value actually differs from previous
+        if new_pwm != self.pwm.current {
+            if self.dwelling() && !forced {
+                log::debug!(
+                    "Keeping {}% for the minimum dwell instead of {}%",
+                    self.pwm.current,
+                    new_pwm
+                );
+                return;
+            }
+            if self.stopped {
+                self.pwm.stop();
+            } else if forced || self.on_off || self.temperature.current >= self.temperature.max {
+                self.pwm.jump(new_pwm);
+            } else {
+                self.pwm.write(new_pwm);
+            }
+            if self.pwm.current == self.pwm.previous {
+                return;
+            }
+            self.events.publish(Event::Decision {
+                temperature: self.temperature.current,
+                target: self.target(),
+                from: self.pwm.previous,
+                to: self.pwm.current,
+            });
+        }
+    }
+
+    /// Feeds a temperature reading to the controller and returns the duty it decides on.
+    ///
+    /// For embedders driving the loop themselves; the decision is not flushed to the output.
+    pub fn step(&mut self, reading: Celsius) -> Duty {
+        self.temperature.update(reading);
+        self.adjust();
+        self.pwm.current
+    }
+
+    /// Applies the target of the season the date falls in, if it's not the one applied last.
+    pub(crate) fn follow_seasons(&mut self, date: MonthDay) {
+        let Some(seasons) = &mut self.seasons else {
+            return;
+        };
+
+        if let Some((season, target)) = seasons.change(date) {
+            self.temperature.target = target;
+            self.override_expiry = None;
+            self.events.publish(Event::SeasonChanged {
+                season: season.map(|season| season.to_string()),
+                target,
+            });
+        }
+    }
+
+    /// Applies the target the schedule gives for the time, if it moved since it was applied last.
+    ///
+    /// A target set meanwhile by other means is kept until the schedule moves on.
+    pub(crate) fn follow_target_schedule(&mut self, time: TimeOfDay) {
+        let Some(schedule) = &self.target_schedule else {
+            return;
+        };
+
+        let target = schedule.target(time);
+        if self.scheduled != Some(target) {
+            self.scheduled = Some(target);
+            self.temperature.target = target;
+            self.override_expiry = None;
+            self.events.publish(Event::ScheduledTarget { target });
+        }
+    }
+
+    /// Switches quiet mode, and the quiet profile if any, as quiet hours start or end.
+    ///
+    /// The profile is switched by a reload at the next poll, so it needs a reloader.
+    pub(crate) fn follow_quiet_hours(&mut self, time: TimeOfDay) {
+        let Some(quiet_hours) = &mut self.quiet_hours else {
+            return;
+        };
+        let Some(quiet) = quiet_hours.change(time) else {
+            return;
+        };
+
+        if let Some(modes) = &self.modes {
+            modes.set_quiet(quiet);
+        }
+        if self.reloader.is_none() {
+            return;
+        }
+        match (quiet, &quiet_hours.profile) {
+            (true, Some(profile)) if self.profile.as_ref() != Some(profile) => {
+                self.resume_profile = Some(self.profile.clone());
+                reload::switch_profile(profile);
+            }
+            (false, _) => match self.resume_profile.take() {
+                Some(Some(profile)) => reload::switch_profile(&profile),
+                Some(None) => reload::reset_profile(),
+                None => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Switches to the profile of the A/B test a Unix time falls in, if it's not the one switched
+    /// to last, and tags the events from then on with it.
+    ///
+    /// The profile is switched by a reload at the next poll, so it needs a reloader.
+    pub(crate) fn follow_ab_test(&mut self, time: u64) {
+        let Some(ab_test) = &self.ab_test else {
+            return;
+        };
+        if self.reloader.is_none() {
+            return;
+        }
+
+        let arm = ab_test.arm(time).to_string();
+        if self.arm.as_ref() == Some(&arm) {
+            return;
+        }
+        if self.profile.as_ref() != Some(&arm) {
+            reload::switch_profile(&arm);
+        }
+        self.arm = Some(arm.clone());
+        self.events.publish(Event::AbTestArm { profile: Some(arm) });
+    }
+
+    /// Applies the bias the latest energy signal calls for, if it's not the one in effect.
+    fn follow_energy(&mut self) {
+        let Some((signal, policy)) = &self.energy else {
+            return;
+        };
+
+        let bias = policy.bias(signal.latest());
+        if bias != self.bias {
+            self.bias = bias;
+            self.events.publish(Event::EnergyBias {
+                bias,
+                target: self.target(),
+            });
+        }
+    }
+
+    /// Notes boosts starting or ending and quiet mode toggling, publishing the modes on changes.
+    fn follow_modes(&mut self) {
+        let Some(modes) = &self.modes else {
+            return;
+        };
+
+        let boosting = modes.boosting(self.clock.now());
+        let quiet = modes.is_quiet();
+        if (boosting, quiet) != (self.boosting, self.quiet) {
+            self.boosting = boosting;
+            self.quiet = quiet;
+            self.events.publish(Event::ModeChanged {
+                boost: boosting,
+                quiet,
+            });
+        }
+    }
+
+    /// Changes the temperature to maintain.
+    pub fn set_target(&mut self, target: Celsius) {
+        self.temperature.target = target;
+        self.events.publish(Event::Override { target });
+    }
+
+    /// Reads the sensors watched for alarms, publishing the levels that changed.
+    fn follow_alarms(&mut self) {
+        let Some(alarms) = &mut self.alarms else {
+            return;
+        };
+        for event in alarms.check() {
+            self.events.publish(event);
+        }
+    }
+
+    /// Whether the fan output failed and hasn't recovered since.
+    pub fn output_failed(&self) -> bool {
+        self.pwm.failed()
+    }
+
+    /// Returns the most severe level of the sensors watched for alarms at their last reading.
+    pub fn alarm_level(&self) -> AlarmLevel {
+        self.alarms.as_ref().map(Alarms::level).unwrap_or_default()
+    }
+
+    /// Applies the target last requested through the setpoint, or one deferred earlier once the
+    /// emergency is over.
+    fn follow_setpoint(&mut self) {
+        if let Some(request) = self.setpoint.as_ref().and_then(Setpoint::take_request) {
+            self.override_expiry = match (request.remote, self.override_ttl) {
+                (true, Some(ttl)) => {
+                    // Repeated requests keep the target that was automatic before the first one
+                    let automatic = self
+                        .override_expiry
+                        .map_or(self.temperature.target, |(_, automatic)| automatic);
+                    Some((self.clock.now() + ttl, automatic))
+                }
+                _ => None,
+            };
+            self.request_target(request.target);
+        }
+        if !self.emergency() {
+            if let Some(target) = self.deferred_target.take() {
+                self.set_target(target);
+            }
+        }
+        self.expire_override();
+    }
+
+    /// Returns to the automatic target once a remote one wasn't requested again in time.
+    ///
+    /// A return raising the target waits until an emergency is over.
+    fn expire_override(&mut self) {
+        let Some((deadline, target)) = self.override_expiry else {
+            return;
+        };
+        if self.clock.now() < deadline || (self.emergency() && target > self.temperature.target) {
+            return;
+        }
+
+        self.override_expiry = None;
+        self.deferred_target = None;
+        self.temperature.target = target;
+        self.events.publish(Event::OverrideExpired { target });
+    }
+
+    /// Changes the target as requested remotely, deferring a raise until an emergency is over.
+    ///
+    /// A later request replaces a deferred one.
+    fn request_target(&mut self, target: Celsius) {
+        if self.emergency() && target > self.temperature.target {
+            log::warn!(
+                "Target {}°C deferred until the overtemperature emergency is over",
+                target
+            );
+            self.deferred_target = Some(target);
+        } else {
+            self.deferred_target = None;
+            self.set_target(target);
+        }
+    }
+
+    /// Starts the controller
+    pub fn start(&mut self) {
+        self.pwm.init();
+
+        loop {
+            self.reload_if_requested();
+            self.follow_power_transitions();
+            if self.parked {
+                self.clock.sleep(self.pollrate);
+                continue;
+            }
+            self.poll();
+        }
+    }
+
+    /// Runs the controller for the given time, then shuts the output down.
+    ///
+    /// The last poll that fits within the time is the final one, so the controller never runs
+    /// over.
+    pub fn run_for_duration(&mut self, duration: time::Duration) {
+        self.pwm.init();
+        let deadline = self.clock.now() + duration;
+
+        loop {
+            self.reload_if_requested();
+            self.follow_power_transitions();
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            if remaining.is_zero() || remaining < self.pollrate {
+                self.clock.sleep(remaining);
+                break;
+            }
+            if self.parked {
+                self.clock.sleep(self.pollrate);
+                continue;
+            }
+            self.poll();
+        }
+
+        if !self.parked {
+            self.pwm.shutdown();
+        }
+    }
+
+    /// Runs the controller for the given number of polls, then shuts the output down.
+    pub fn run_for(&mut self, iterations: usize) {
+        self.pwm.init();
+
+        for _ in 0..iterations {
+            self.poll();
+        }
+
+        self.pwm.shutdown();
+    }
+
+    /// Reads the temperature once, applies the duty the curve gives for it and releases the output.
+    ///
+    /// The duty is written even if it matches what the output starts at, since the fan may have
+    /// been left at any speed by an earlier run. Without a reading the fan is set to maximum.
+    pub fn oneshot(&mut self) -> Result<Duty, SensorError> {
+        self.pwm.init();
+        self.pwm.written = None;
+        self.follow_seasons(self.clock.today());
+        self.follow_target_schedule(self.clock.time_of_day());
+        self.follow_alarms();
+
+        let result = self.temperature.read();
+        match &result {
+            Ok(()) => {
+                let temperature = self.temperature.current;
+                self.events.publish(Event::Sample {
+                    temperature,
+                    raw: self.temperature.sensor.raw(),
+                });
+                if self.follow_fan_stop() {
+                    self.pwm.stop();
+                } else {
+                    self.pwm.write(self.running_duty(temperature));
+                }
+                self.events.publish(Event::Decision {
+                    temperature,
+                    target: self.target(),
+                    from: self.pwm.previous,
+                    to: self.pwm.current,
+                });
+            }
+            Err(error) => {
+                self.events.publish(Event::Fault {
+                    message: error.to_string(),
+                });
+                self.pwm.write(self.pwm.hard_max);
+            }
+        }
+        self.flush();
+        self.pwm.shutdown();
+
+        result.map(|()| self.pwm.current)
+    }
+
+    /// Releases the output as on exit when the system is about to suspend or shut down, then lets
+    /// it proceed. Control resumes with the output initialized again after waking up.
+    fn follow_power_transitions(&mut self) {
+        let Some(inhibitor) = &mut self.inhibitor else {
+            return;
+        };
+
+        while let Some(transition) = inhibitor.pending() {
+            match transition {
+                Transition::Sleep | Transition::Shutdown if !self.parked => {
+                    let reason = match transition {
+                        Transition::Sleep => "suspend",
+                        _ => "shutdown",
+                    };
+                    log::info!("Parking fan before {}", reason);
+                    self.pwm.shutdown();
+                    self.parked = true;
+                    inhibitor.release();
+                }
+                Transition::Resume if self.parked => {
+                    log::info!("Resuming fan control");
+                    self.pwm.init();
+                    self.parked = false;
+                    if let Err(error) = inhibitor.acquire() {
+                        log::warn!("Failed to take inhibitor lock again: {}", error);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn reload_if_requested(&mut self) {
+        if self.reloader.is_some() && reload::requested() {
+            self.reload_with(reload::forced());
+        }
+    }
+
+    /// Trips the latch if the latest reading reached the maximum, and returns whether it's tripped.
+    fn latched(&mut self) -> bool {
+        let Some(latch) = &self.latch else {
+            return false;
+        };
+
+        if latch.take_released() {
+            self.events.publish(Event::LatchReleased);
+        }
+        if !latch.is_tripped() && self.temperature.current >= self.temperature.max {
+            latch.trip();
+            self.events.publish(Event::Latched {
+                temperature: self.temperature.current,
+            });
+        }
+        latch.is_tripped()
+    }
+
+    /// Whether the duty changed less than the minimum dwell ago and the temperature is below the
+    /// maximum, so it should be kept.
+    fn dwelling(&self) -> bool {
+        match (self.min_dwell, self.changed_at) {
+            (Some(dwell), Some(changed_at)) => {
+                self.temperature.current < self.temperature.max
+                    && self.clock.now().saturating_duration_since(changed_at) < dwell
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the latest reading reached the hard temperature limit, logging when it first does.
+    fn beyond_hard_limit(&self) -> bool {
+        let Some(hard_max) = self.temperature_hard_max else {
+            return false;
+        };
+        if self.temperature.current >= hard_max && self.temperature.previous < hard_max {
+            log::error!(
+                "Temperature {}°C reached hard limit {}°C, running fan at {}% whatever holds it lower",
+                self.temperature.current,
+                hard_max,
+                self.pwm.hard_max
+            );
+        }
+        self.temperature.current >= hard_max
+    }
+
+    /// Starts or extends the emergency hold if the latest reading reached the maximum, and returns
+    /// whether it's in effect.
+    fn held(&mut self) -> bool {
+        let Some(hold) = self.emergency_hold else {
+            return false;
+        };
+
+        let now = self.clock.now();
+        if self.temperature.current >= self.temperature.max {
+            self.held_until = Some(now + hold);
+        }
+        match self.held_until {
+            Some(until) if now < until => true,
+            Some(_) => {
+                log::info!("Emergency hold over, resuming control");
+                self.held_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Sends the duty decided on to the output, publishing when it fails or recovers.
+    fn flush(&mut self) {
+        match self.pwm.flush(self.clock.as_mut()) {
+            Some(Health::Failed(message)) => self.events.publish(Event::OutputFailed { message }),
+            Some(Health::Reinitialized(reinits)) => {
+                self.events.publish(Event::OutputReinitialized { reinits })
+            }
+            None => {}
+        }
+    }
+
+    /// Waits for the next poll, then reads the temperature and adjusts the fan.
+    fn poll(&mut self) {
+        self.clock.sleep(self.pollrate);
+        self.pwm.elapse(self.pollrate);
+        self.temperature.elapse(self.pollrate);
+
+        let time_of_day = self.clock.time_of_day();
+        self.follow_seasons(self.clock.today());
+        self.follow_target_schedule(time_of_day);
+        self.follow_quiet_hours(time_of_day);
+        self.follow_ab_test(
+            self.clock
+                .wall()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+        self.follow_energy();
+        self.follow_modes();
+        self.follow_setpoint();
+        self.follow_alarms();
+
+        if let Some((cpu_load, _)) = &mut self.cpu_load {
+            self.load = cpu_load.sample();
+        }
+        if let Some((throttle, _)) = &mut self.throttle {
+            let throttling = throttle.sample().is_some_and(Flags::calls_for_cooling);
+            if throttling != self.throttling {
+                if throttling {
+                    log::warn!("Firmware reports throttling, raising fan speed");
+                } else {
+                    log::info!("Firmware no longer reports throttling");
+                }
+                self.throttling = throttling;
+            }
+        }
+
+        match self.temperature.read() {
+            Ok(()) => {
+                self.events.publish(Event::Sample {
+                    temperature: self.temperature.raw,
+                    raw: self.temperature.sensor.raw(),
+                });
+                if self.temperature.current >= self.temperature.max
+                    && self.temperature.previous < self.temperature.max
+                {
+                    self.events.publish(Event::Overtemperature {
+                        temperature: self.temperature.current,
+                        max: self.temperature.max,
+                    });
+                }
+                // Evaluated first so a hold also starts while latched or boosting
+                let held = self.held();
+                let duty = self.pwm.current;
+                if self.beyond_hard_limit() {
+                    self.stopped = false;
+                    self.pwm.jump(self.pwm.hard_max);
+                } else if self.latched() || held {
+                    self.pwm.jump(self.pwm.hard_max);
+                } else if self.boosting {
+                    self.pwm.jump(self.pwm.max);
+                } else {
+                    self.adjust();
+                }
+                if self.pwm.current != duty {
+                    self.changed_at = Some(self.clock.now());
+                }
+                self.adapt_pollrate();
+            }
+            Err(error) => {
+                // Fail safe: without a reading we can't know how hot it is
+                self.events.publish(Event::Fault {
+                    message: error.to_string(),
+                });
+                self.pwm.jump(self.pwm.hard_max);
+                if let Some((min, _)) = self.adaptive {
+                    self.pollrate = min;
+                }
+            }
+        }
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Controller;
+    use crate::alarm::AlarmLevel;
+    use crate::args::Args;
+    use crate::button::{Modes, Press};
+    use crate::config;
+    use crate::cpu_load::CpuLoad;
+    use crate::energy::{Policy, Signal, SignalKind};
+    use crate::events::Event;
+    use crate::latch::Latch;
+    use crate::mock::{Call, MockClock, MockOutput, MockSink, TempPath};
+    use crate::observer::{Iteration, Observer, Verdict};
+    use crate::pwm::tests::{duty, recording_pwm};
+    use crate::pwm::{FanStop, Pwm, Recovery};
+    use crate::reload::Reloader;
+    use crate::schedule::{parse_window, QuietHours, TimeOfDay};
+    use crate::script::parse_script;
+    use crate::season::MonthDay;
+    use crate::sensor::{FileSensor, Raw};
+    use crate::setpoint::Setpoint;
+    use crate::temperature::{Smoothing, Temperature};
+    use crate::throttle::Throttle;
+    use crate::units::{Celsius, Duty, Precision};
+    use clap::Parser;
+    use std::{cell::RefCell, fmt::Write, fs, rc::Rc, time};
+
+    /// Returns the duty the stepping algorithm requires for the controller's latest reading
+    fn required(controller: &Controller) -> Duty {
+        controller.stepping().required(
+            controller.temperature.current,
+            controller.temperature.previous,
+            controller.pwm.current,
+        )
+    }
+
+    #[test]
+    fn temperature_over_high_limit() {
+        let controller = Controller::from_parts(
+            time::Duration::from_secs(5),
+            Temperature {
+                max: Celsius::new(70, 0),
+                current: Celsius::new(80, 0), // Higher than max
+                previous: Celsius::new(0, 0),
+                target: Celsius::new(40, 0),
+                sensor: Box::new(FileSensor::new("")),
+                raw: Celsius::default(),
+                smoothing: Smoothing::default(),
+                precision: Precision::default(),
+            },
+            Pwm {
+                current: duty(0),
+                previous: duty(0),
+                decrement: 1,
+                increment: 2,
+                min: duty(0),
+                max: duty(100),
+                hard_min: duty(0),
+                hard_max: duty(100),
+                output: Box::new(MockOutput::new()),
+                written: None,
+                kick: None,
+                slew: None,
+                linearize: None,
+                recovery: Recovery::default(),
+            },
+        );
+
+        let value = required(&controller);
+        assert_eq!(controller.pwm.max, value);
+    }
+
+    #[test]
+    fn temperature_same_as_target() {
+        let controller = Controller::from_parts(
+            time::Duration::from_secs(5),
+            Temperature {
+                target: Celsius::new(40, 0),
+                current: Celsius::new(40, 0), // Same as target
+                previous: Celsius::new(0, 0),
+                max: Celsius::new(70, 0),
+                sensor: Box::new(FileSensor::new("")),
+                raw: Celsius::default(),
+                smoothing: Smoothing::default(),
+                precision: Precision::default(),
+            },
+            Pwm {
+                current: duty(50),
+                previous: duty(0),
+                decrement: 1,
+                increment: 2,
+                min: duty(0),
+                max: duty(100),
+                hard_min: duty(0),
+                hard_max: duty(100),
+                output: Box::new(MockOutput::new()),
+                written: None,
+                kick: None,
+                slew: None,
+                linearize: None,
+                recovery: Recovery::default(),
+            },
+        );
+
+        let value = required(&controller);
+        assert_eq!(controller.pwm.current, value);
+    }
+
+    #[test]
+    fn temperature_over_target_and_rising() {
+        let controller = Controller::from_parts(
+            time::Duration::from_secs(5),
+            Temperature {
+                target: Celsius::new(40, 0),
+                current: Celsius::new(55, 0), // Higher than target and previous
+                previous: Celsius::new(50, 0), // Lower than current
+                max: Celsius::new(70, 0),
+                sensor: Box::new(FileSensor::new("")),
+                raw: Celsius::default(),
+                smoothing: Smoothing::default(),
+                precision: Precision::default(),
+            },
+            Pwm {
+                current: duty(50),
+                previous: duty(0),
+                decrement: 1,
+                increment: 2,
+                min: duty(0),
+                max: duty(100),
+                hard_min: duty(0),
+                hard_max: duty(100),
+                output: Box::new(MockOutput::new()),
+                written: None,
+                kick: None,
+                slew: None,
+                linearize: None,
+                recovery: Recovery::default(),
+            },
+        );
+
+        let value = required(&controller);
+        assert_eq!(
+            controller.pwm.current.raise(controller.pwm.increment),
+            value
+        );
+    }
+
+    #[test]
+    fn temperature_over_target_and_lowering() {
+        let controller = Controller::from_parts(
+            time::Duration::from_secs(5),
+            Temperature {
+                target: Celsius::new(40, 0),
+                current: Celsius::new(50, 0), // Higher than target, but lower than previous
+                previous: Celsius::new(55, 0), // Higher than current
+                max: Celsius::new(70, 0),
+                sensor: Box::new(FileSensor::new("")),
+                raw: Celsius::default(),
+                smoothing: Smoothing::default(),
+                precision: Precision::default(),
+            },
+            Pwm {
+                current: duty(50),
+                previous: duty(0),
+                decrement: 1,
+                increment: 2,
+                min: duty(0),
+                max: duty(100),
+                hard_min: duty(0),
+                hard_max: duty(100),
+                output: Box::new(MockOutput::new()),
+                written: None,
+                kick: None,
+                slew: None,
+                linearize: None,
+                recovery: Recovery::default(),
+            },
+        );
+
+        let value = required(&controller);
+        assert_eq!(
+            controller.pwm.current.lower(controller.pwm.decrement),
+            value
+        );
+    }
+
+    #[test]
+    fn temperature_below_target() {
+        let controller = Controller::from_parts(
+            time::Duration::from_secs(5),
+            Temperature {
+                target: Celsius::new(40, 0),
+                current: Celsius::new(30, 0), // Lower than target
+                previous: Celsius::new(0, 0),
+                max: Celsius::new(70, 0),
+                sensor: Box::new(FileSensor::new("")),
+                raw: Celsius::default(),
+                smoothing: Smoothing::default(),
+                precision: Precision::default(),
+            },
+            Pwm {
+                current: duty(50),
+                previous: duty(0),
+                decrement: 1,
+                increment: 2,
+                min: duty(0),
+                max: duty(100),
+                hard_min: duty(0),
+                hard_max: duty(100),
+                output: Box::new(MockOutput::new()),
+                written: None,
+                kick: None,
+                slew: None,
+                linearize: None,
+                recovery: Recovery::default(),
+            },
+        );
+
+        let value = required(&controller);
+        assert_eq!(
+            controller.pwm.current.lower(controller.pwm.decrement),
+            value
+        );
+    }
+
+    /// Runs the controller over a temperature trace given in millidegrees and renders
+    /// the resulting PWM trajectory, one poll per line.
+    fn trajectory(trace: &[i32]) -> String {
+        let output = MockOutput::new();
+        let mut controller = Controller::from_parts(
+            time::Duration::from_secs(5),
+            Temperature {
+                target: Celsius::new(40, 0),
+                current: Celsius::default(),
+                previous: Celsius::default(),
+                max: Celsius::new(70, 0),
+                sensor: Box::new(FileSensor::new("")),
+                raw: Celsius::default(),
+                smoothing: Smoothing::default(),
+                precision: Precision::default(),
+            },
+            recording_pwm(&output),
+        );
+        controller.pwm.min = duty(30);
+
+        let mut rendered = String::new();
+        for &millidegrees in trace {
+            controller
+                .temperature
+                .update(Celsius::from_millidegrees(millidegrees));
+            controller.adjust();
+            controller.pwm.flush(controller.clock.as_mut());
+            writeln!(
+                rendered,
+                "{:>5}°C -> {:>3}%",
+                controller.temperature.current.to_string(),
+                controller.pwm.current
+            )
+            .unwrap();
+        }
+        writeln!(rendered, "writes: {}", output.writes().len()).unwrap();
+
+        rendered
+    }
+
+    #[test]
+    fn trajectory_steady_rise() {
+        let trace: Vec<i32> = (0..12).map(|i| 38_000 + i * 1_500).collect();
+        insta::assert_snapshot!(trajectory(&trace));
+    }
+
+    #[test]
+    fn trajectory_overshoot_and_recover() {
+        let trace = [
+            41_000, 44_000, 47_500, 49_000, 48_200, 46_100, 44_000, 42_300, 40_900, 39_600, 38_100,
+            37_000,
+        ];
+        insta::assert_snapshot!(trajectory(&trace));
+    }
+
+    #[test]
+    fn trajectory_spike_over_max() {
+        let trace = [
+            42_000, 55_000, 71_300, 69_800, 60_000, 52_000, 45_000, 40_200, 39_000,
+        ];
+        insta::assert_snapshot!(trajectory(&trace));
+    }
+
+    #[test]
+    fn trajectory_cool_idle() {
+        let trace = [
+            35_000, 34_800, 35_200, 34_900, 35_100, 35_000, 34_700, 35_300,
+        ];
+        insta::assert_snapshot!(trajectory(&trace));
+    }
+
+    /// Runs the controller end to end against a sensor file, returning the recorded output calls.
+    fn run_with_sensor(name: &str, millidegrees: &str, iterations: usize) -> Vec<Call> {
+        let args = ["fan-controller", "--gpio-pwm", "0", "--pollrate", "0"];
+        let output = MockOutput::new();
+        run_with_sensor_output(name, millidegrees, iterations, &args, output.clone(), None);
+
+        output
+            .calls()
+            .iter()
+            .map(|recorded| recorded.call)
+            .collect()
+    }
+
+    fn run_with_sensor_output(
+        name: &str,
+        millidegrees: &str,
+        iterations: usize,
+        args: &[&str],
+        output: MockOutput,
+        clock: Option<MockClock>,
+    ) -> MockSink {
+        let sensor = TempPath::file(name, millidegrees);
+
+        let mut args = args.to_vec();
+        args.extend(["--temperature-file-path", sensor.arg()]);
+        let sink = MockSink::new();
+        let mut controller = Controller::new(&Args::parse_from(args), Box::new(output))
+            .with_sink(Box::new(sink.clone()));
+        if let Some(clock) = clock {
+            controller = controller.with_clock(Box::new(clock));
+        }
+        controller.run_for(iterations);
+
+        sink
+    }
+
+    #[test]
+    fn run_lowers_speed_while_below_target() {
+        let calls = run_with_sensor("below-target", "30000\n", 3);
+
+        assert_eq!(
+            vec![
+                Call::Init,
+                Call::Write(duty(99)),
+                Call::Write(duty(98)),
+                Call::Write(duty(97)),
+                Call::Shutdown,
+            ],
+            calls
+        );
+    }
+
+    #[test]
+    fn run_goes_to_maximum_without_reading() {
+        let calls = run_with_sensor("unreadable", "not a number", 2);
+
+        // Output starts at full speed, so nothing needs to be written
+        assert_eq!(vec![Call::Init, Call::Shutdown], calls);
+    }
+
+    #[test]
+    fn run_publishes_samples_decisions_and_faults() {
+        let args = ["fan-controller", "--gpio-pwm", "0", "--pollrate", "0"];
+        let events =
+            run_with_sensor_output("events", "30000", 1, &args, MockOutput::new(), None).events();
+        assert_eq!(
+            vec![
+                Event::Sample {
+                    temperature: Celsius::new(30, 0),
+                    raw: Some(Raw::Millidegrees(30000)),
+                },
+                Event::Decision {
+                    temperature: Celsius::new(30, 0),
+                    target: Celsius::new(40, 0),
+                    from: duty(100),
+                    to: duty(99),
+                },
+            ],
+            events
+        );
+
+        let events =
+            run_with_sensor_output("events-fault", "", 1, &args, MockOutput::new(), None).events();
+        assert!(matches!(events[..], [Event::Fault { .. }]));
+    }
+
+    #[test]
+    fn ab_test_publishes_profile_in_effect() {
+        let config = TempPath::file(
+            "ab-test.toml",
+            "gpio-pwm = 0\nab-test = \"silent,balanced\"\n\n\
+             [profiles.silent]\npwm-max = 50\n\n[profiles.balanced]\npwm-max = 80\n",
+        );
+        // Starting on the profile of the first period, so no switch is requested
+        let argv: Vec<String> = ["fan-controller", "--config", config.arg()]
+            .into_iter()
+            .chain(["--profile", "silent"])
+            .map(String::from)
+            .collect();
+        let args = Args::parse_from(config::args_with_config(argv.clone()).unwrap());
+        let sink = MockSink::new();
+        let mut controller =
+            Controller::new(&args, Box::new(MockOutput::new())).with_sink(Box::new(sink.clone()));
+
+        // Without a reloader the profile can't be switched, so nothing is tagged
+        controller.follow_ab_test(0);
+        assert!(sink.events().is_empty());
+
+        controller = controller.with_reloader(Reloader::new(argv));
+        controller.follow_ab_test(0);
+        controller.follow_ab_test(3599);
+        assert_eq!(
+            vec![Event::AbTestArm {
+                profile: Some("silent".to_string())
+            }],
+            sink.events()
+        );
+    }
+
+    #[test]
+    fn reload_applies_only_valid_config() {
+        let sensor = TempPath::file("reload-sensor", "30000");
+        let config = TempPath::new("reload.toml");
+        let write_config = |extra: &str| {
+            config.write(&format!(
+                "gpio-pwm = 0\ntemperature-file-path = {:?}\n{}",
+                sensor.arg(),
+                extra
+            ))
+        };
+        write_config("");
+
+        let argv = vec![
+            "fan-controller".to_string(),
+            "--config".to_string(),
+            config.arg().to_string(),
+        ];
+        let args = Args::parse_from(config::args_with_config(argv.clone()).unwrap());
+        let sink = MockSink::new();
+        let mut controller = Controller::new(&args, Box::new(MockOutput::new()))
+            .with_sink(Box::new(sink.clone()))
+            .with_reloader(Reloader::new(argv));
+
+        write_config("temperature-target-value = \"45\"\npwm-max = 80\n");
+        controller.reload();
+        assert_eq!(Celsius::new(45, 0), controller.temperature.target);
+        assert_eq!(duty(80), controller.pwm.current);
+
+        write_config("pwm-min = 90\npwm-max = 50\n");
+        controller.reload();
+        fs::remove_file(&sensor).unwrap();
+        write_config("temperature-target-value = \"50\"\n");
+        controller.reload();
+        assert_eq!(Celsius::new(45, 0), controller.temperature.target);
+        assert_eq!(duty(80), controller.pwm.max);
+
+        let events = sink.events();
+        assert!(
+            matches!(&events[0], Event::Reloaded { changes } if changes.len() == 2),
+            "{:?}",
+            events[0]
+        );
+        assert!(matches!(
+            events[1..],
+            [Event::ReloadRejected { .. }, Event::ReloadRejected { .. }]
+        ));
+    }
+
+    #[test]
+    fn emergency_holds_off_changes_reducing_cooling() {
+        let sensor = TempPath::file("interlock-sensor", "75000");
+        let config = TempPath::new("interlock.toml");
+        let write_config = |extra: &str| {
+            config.write(&format!(
+                "gpio-pwm = 0\ntemperature-max-value = \"70\"\ntemperature-file-path = {:?}\n{}",
+                sensor.arg(),
+                extra
+            ))
+        };
+        write_config("");
+
+        let argv = vec![
+            "fan-controller".to_string(),
+            "--config".to_string(),
+            config.arg().to_string(),
+        ];
+        let args = Args::parse_from(config::args_with_config(argv.clone()).unwrap());
+        let setpoint = Setpoint::new();
+        let mut controller = Controller::new(&args, Box::new(MockOutput::new()))
+            .with_setpoint(setpoint.clone())
+            .with_reloader(Reloader::new(argv));
+        controller.temperature.update(Celsius::new(75, 0));
+
+        write_config("pwm-max = 60\n");
+        controller.reload();
+        assert_eq!(Duty::FULL, controller.pwm.max);
+        controller.reload_with(true);
+        assert_eq!(duty(60), controller.pwm.max);
+
+        let target = controller.temperature.target;
+        setpoint.request(Celsius::new(50, 0));
+        controller.follow_setpoint();
+        assert_eq!(target, controller.temperature.target);
+        controller.temperature.update(Celsius::new(45, 0));
+        controller.follow_setpoint();
+        assert_eq!(Celsius::new(50, 0), controller.temperature.target);
+    }
+
+    #[test]
+    fn script_decides_duty_within_limits() {
+        let mut controller = Controller::detached(
+            Celsius::new(40, 0),
+            Celsius::new(70, 0),
+            duty(20),
+            duty(90),
+            5,
+            5,
+        );
+        controller.script = Some(
+            parse_script("let error = temperature - target; if error > 0.0 { 50.0 + error * 10.0 } else { 0 }")
+                .unwrap(),
+        );
+
+        assert_eq!(duty(70), controller.step(Celsius::new(42, 0)));
+        assert_eq!(duty(90), controller.step(Celsius::new(50, 0)));
+        assert_eq!(duty(20), controller.step(Celsius::new(35, 0)));
+
+        // Falls back to stepping when the script fails
+        controller.script = Some(parse_script("duty / 0").unwrap());
+        assert_eq!(duty(25), controller.step(Celsius::new(50, 0)));
+    }
+
+    #[test]
+    fn remote_target_lapses_unless_refreshed() {
+        let clock = MockClock::new();
+        let sink = MockSink::new();
+        let setpoint = Setpoint::new();
+        let mut controller = Controller::detached(
+            Celsius::new(40, 0),
+            Celsius::new(70, 0),
+            duty(0),
+            duty(100),
+            5,
+            5,
+        )
+        .with_clock(Box::new(clock.clone()))
+        .with_sink(Box::new(sink.clone()))
+        .with_setpoint(setpoint.clone());
+        controller.override_ttl = Some(time::Duration::from_secs(600));
+
+        setpoint.request_remote(Celsius::new(55, 0));
+        controller.follow_setpoint();
+        clock.advance(time::Duration::from_secs(500));
+        setpoint.request_remote(Celsius::new(50, 0));
+        controller.follow_setpoint();
+        clock.advance(time::Duration::from_secs(500));
+        controller.follow_setpoint();
+        assert_eq!(Celsius::new(50, 0), controller.temperature.target);
+
+        clock.advance(time::Duration::from_secs(100));
+        controller.follow_setpoint();
+        assert_eq!(Celsius::new(40, 0), controller.temperature.target);
+        assert_eq!(
+            Some(&Event::OverrideExpired {
+                target: Celsius::new(40, 0)
+            }),
+            sink.events().last()
+        );
+
+        // Set locally, e.g. with the knob, a target stays
+        setpoint.request(Celsius::new(45, 0));
+        controller.follow_setpoint();
+        clock.advance(time::Duration::from_secs(3600));
+        controller.follow_setpoint();
+        assert_eq!(Celsius::new(45, 0), controller.temperature.target);
+    }
+
+    #[test]
+    fn run_advances_virtual_time_per_poll() {
+        let clock = MockClock::new();
+        let output = MockOutput::with_clock(&clock);
+        let args = ["fan-controller", "--gpio-pwm", "0", "--pollrate", "60"];
+        run_with_sensor_output(
+            "virtual-time",
+            "30000",
+            120,
+            &args,
+            output.clone(),
+            Some(clock.clone()),
+        );
+
+        let calls = output.calls();
+        assert_eq!(time::Duration::from_secs(2 * 60 * 60), clock.elapsed());
+        assert_eq!(
+            time::Duration::from_secs(60),
+            calls[2].at.duration_since(calls[1].at)
+        );
+    }
+
+    #[test]
+    fn adaptive_pollrate_backs_off_while_stable_below_target() {
+        let clock = MockClock::new();
+        let output = MockOutput::with_clock(&clock);
+        let sensor = TempPath::file("adaptive-pollrate", "30000");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate-min",
+            "1",
+            "--pollrate-max",
+            "15",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let mut controller =
+            Controller::new(&args, Box::new(output)).with_clock(Box::new(clock.clone()));
+
+        // The first reading is a change, after that the interval doubles up to the maximum
+        for _ in 0..6 {
+            controller.poll();
+        }
+        assert_eq!(
+            time::Duration::from_secs(1 + 1 + 2 + 4 + 8 + 15),
+            clock.elapsed()
+        );
+        assert_eq!(time::Duration::from_secs(15), controller.pollrate);
+
+        sensor.write("60000");
+        controller.poll();
+        assert_eq!(time::Duration::from_secs(1), controller.pollrate);
+    }
+
+    #[test]
+    fn run_for_duration_stops_at_deadline() {
+        let clock = MockClock::new();
+        let output = MockOutput::with_clock(&clock);
+        let sensor = TempPath::file("run-for-duration", "30000");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "60",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+
+        Controller::new(&args, Box::new(output.clone()))
+            .with_clock(Box::new(clock.clone()))
+            .run_for_duration(time::Duration::from_secs(150));
+
+        // Two polls fit, the rest of the time is waited out before shutting down
+        assert_eq!(time::Duration::from_secs(150), clock.elapsed());
+        let calls: Vec<Call> = output
+            .calls()
+            .iter()
+            .map(|recorded| recorded.call)
+            .collect();
+        assert_eq!(
+            vec![
+                Call::Init,
+                Call::Write(duty(99)),
+                Call::Write(duty(98)),
+                Call::Shutdown
+            ],
+            calls
+        );
+    }
+
+    #[test]
+    fn quiet_hours_switch_quiet_mode_at_their_edges() {
+        let modes = Modes::new(time::Duration::from_secs(60), duty(50));
+        let time = |hour| TimeOfDay::new(hour, 0).unwrap();
+        let mut controller = Controller::detached(
+            Celsius::new(40, 0),
+            Celsius::new(70, 0),
+            duty(30),
+            Duty::FULL,
+            2,
+            1,
+        )
+        .with_modes(modes.clone())
+        .with_quiet_hours(QuietHours::new(
+            vec![parse_window("22:00-07:00").unwrap()],
+            None,
+        ));
+
+        controller.follow_quiet_hours(time(23));
+        controller.follow_modes();
+        assert_eq!(duty(50), controller.step(Celsius::new(60, 0)));
+
+        // Toggled off by the button, which holds until quiet hours end and start again
+        modes.toggle_quiet();
+        controller.follow_quiet_hours(time(1));
+        assert!(!modes.is_quiet());
+        controller.follow_quiet_hours(time(7));
+        controller.follow_quiet_hours(time(22));
+        assert!(modes.is_quiet());
+        controller.follow_quiet_hours(time(7));
+        assert!(!modes.is_quiet());
+    }
+
+    #[test]
+    fn poll_follows_quiet_hours_in_virtual_time() {
+        // 21:58 UTC, two polls before quiet hours start
+        let clock = MockClock::at(21 * 60 * 60 + 58 * 60);
+        let modes = Modes::new(time::Duration::from_secs(60), duty(50));
+        let sensor = TempPath::new("quiet-hours-poll");
+        sensor.write("60000");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "60",
+            "--quiet-hours",
+            "22:00-07:00",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let mut controller = Controller::new(&args, Box::new(MockOutput::new()))
+            .with_clock(Box::new(clock.clone()))
+            .with_modes(modes.clone());
+
+        controller.poll();
+        assert!(!modes.is_quiet());
+        assert_eq!(Duty::FULL, controller.pwm.current);
+        controller.poll();
+        assert!(modes.is_quiet());
+        assert_eq!(duty(50), controller.pwm.current);
+
+        clock.advance(time::Duration::from_secs(9 * 60 * 60));
+        controller.poll();
+        assert!(!modes.is_quiet());
+    }
+
+    #[test]
+    fn quiet_mode_caps_duty_below_maximum_temperature() {
+        let modes = Modes::new(time::Duration::from_secs(60), duty(50));
+        let sink = MockSink::new();
+        let mut controller = Controller::detached(
+            Celsius::new(40, 0),
+            Celsius::new(70, 0),
+            duty(30),
+            Duty::FULL,
+            2,
+            1,
+        )
+        .with_sink(Box::new(sink.clone()))
+        .with_modes(modes.clone());
+
+        modes.press(Press::Long, controller.clock.now());
+        controller.follow_modes();
+        assert_eq!(duty(50), controller.step(Celsius::new(60, 0)));
+        assert_eq!(Duty::FULL, controller.step(Celsius::new(70, 0)));
+
+        modes.press(Press::Short, controller.clock.now());
+        controller.follow_modes();
+        assert!(controller.boosting);
+        assert_eq!(
+            vec![
+                Event::ModeChanged {
+                    boost: false,
+                    quiet: true,
+                },
+                Event::ModeChanged {
+                    boost: true,
+                    quiet: true,
+                },
+            ],
+            sink.events()
+                .into_iter()
+                .filter(|event| matches!(event, Event::ModeChanged { .. }))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn fan_stops_below_threshold_and_restarts_above_another() {
+        let mut controller = Controller::detached(
+            Celsius::new(40, 0),
+            Celsius::new(70, 0),
+            duty(30),
+            Duty::FULL,
+            2,
+            1,
+        );
+        controller.fan_stop = Some(FanStop {
+            off_below: Celsius::new(35, 0),
+            restart_above: Celsius::new(38, 0),
+        });
+
+        assert_eq!(duty(99), controller.step(Celsius::new(36, 0)));
+        assert_eq!(Duty::OFF, controller.step(Celsius::new(34, 0)));
+        assert_eq!(Duty::OFF, controller.step(Celsius::new(38, 0)));
+        assert_eq!(duty(30), controller.step(Celsius::new(38, 500)));
+        assert_eq!(duty(32), controller.step(Celsius::new(41, 0)));
+    }
+
+    #[test]
+    fn on_off_control_switches_between_thresholds() {
+        let mut controller = Controller::detached(
+            Celsius::new(40, 0),
+            Celsius::new(70, 0),
+            duty(30),
+            duty(90),
+            2,
+            1,
+        );
+        controller.on_off = true;
+        controller.fan_stop = Some(FanStop {
+            off_below: Celsius::new(40, 0),
+            restart_above: Celsius::new(45, 0),
+        });
+
+        assert_eq!(duty(90), controller.step(Celsius::new(42, 0)));
+        assert_eq!(Duty::OFF, controller.step(Celsius::new(39, 500)));
+        assert_eq!(Duty::OFF, controller.step(Celsius::new(45, 0)));
+        assert_eq!(duty(90), controller.step(Celsius::new(45, 500)));
+        assert_eq!(duty(90), controller.step(Celsius::new(40, 0)));
+    }
+
+    #[test]
+    fn energy_signal_biases_target() {
+        let signal = Signal::default();
+        let sink = MockSink::new();
+        let mut controller = Controller::detached(
+            Celsius::new(40, 0),
+            Celsius::new(70, 0),
+            Duty::new(30).unwrap(),
+            Duty::FULL,
+            2,
+            1,
+        )
+        .with_sink(Box::new(sink.clone()))
+        .with_energy(
+            signal.clone(),
+            Policy {
+                kind: SignalKind::Price,
+                threshold: 0.3,
+                bias: Celsius::new(2, 0),
+            },
+        );
+
+        signal.set(Some(0.42));
+        controller.follow_energy();
+        // 41°C is above the target only without the bias
+        assert_eq!(duty(99), controller.step(Celsius::new(41, 0)));
+
+        signal.set(None);
+        controller.follow_energy();
+        controller.follow_energy();
+
+        assert_eq!(
+            vec![
+                Event::EnergyBias {
+                    bias: Celsius::new(2, 0),
+                    target: Celsius::new(42, 0),
+                },
+                Event::Decision {
+                    temperature: Celsius::new(41, 0),
+                    target: Celsius::new(42, 0),
+                    from: Duty::FULL,
+                    to: duty(99),
+                },
+                Event::EnergyBias {
+                    bias: Celsius::default(),
+                    target: Celsius::new(40, 0),
+                },
+            ],
+            sink.events()
+        );
+    }
+
+    #[test]
+    fn fan_curve_replaces_stepping() {
+        let args = [
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "0",
+            "--pwm-min",
+            "35",
+            "--fan-curve",
+            "40:30,50:60,65:100",
+        ];
+        let output = MockOutput::new();
+        run_with_sensor_output("curve", "45000\n", 2, &args, output.clone(), None);
+        run_with_sensor_output("curve-cool", "20000\n", 1, &args, output.clone(), None);
+        run_with_sensor_output("curve-max", "75000\n", 1, &args, output.clone(), None);
+
+        let calls: Vec<Call> = output
+            .calls()
+            .iter()
+            .map(|recorded| recorded.call)
+            .collect();
+        assert_eq!(
+            vec![
+                Call::Init,
+                Call::Write(duty(45)),
+                Call::Shutdown,
+                Call::Init,
+                // The curve's 30% is below the minimum
+                Call::Write(duty(35)),
+                Call::Shutdown,
+                Call::Init,
+                Call::Shutdown,
+            ],
+            calls
+        );
+    }
+
+    #[test]
+    fn oneshot_reports_alarm_of_monitored_sensor() {
+        let sensor = TempPath::new("alarm-temp");
+        let drive = TempPath::new("alarm-drive");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--temperature-file-path",
+            sensor.arg(),
+            "--alarm",
+            &format!("50:60:{}", drive.arg()),
+        ]);
+        let sink = MockSink::new();
+        let mut controller =
+            Controller::new(&args, Box::new(MockOutput::new())).with_sink(Box::new(sink.clone()));
+
+        sensor.write("40000");
+        drive.write("55000");
+        controller.oneshot().unwrap();
+        assert_eq!(AlarmLevel::Warning, controller.alarm_level());
+        assert!(sink.events().contains(&Event::Alarm {
+            sensor: drive.arg().to_string(),
+            level: AlarmLevel::Warning,
+            temperature: Celsius::new(55, 0),
+        }));
+
+        drive.write("61000");
+        controller.oneshot().unwrap();
+        assert_eq!(AlarmLevel::Critical, controller.alarm_level());
+    }
+
+    #[test]
+    fn oneshot_applies_curve_duty() {
+        let sensor = TempPath::new("oneshot");
+        let output = MockOutput::new();
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let mut controller = Controller::new(&args, Box::new(output.clone()));
+
+        sensor.write("100000");
+        assert_eq!(Duty::FULL, controller.oneshot().unwrap());
+        sensor.write("55000");
+        assert_eq!(duty(65), controller.oneshot().unwrap());
+        fs::remove_file(&sensor).unwrap();
+        assert!(controller.oneshot().is_err());
+
+        let calls: Vec<Call> = output
+            .calls()
+            .iter()
+            .map(|recorded| recorded.call)
+            .collect();
+        assert_eq!(
+            vec![
+                Call::Init,
+                Call::Write(Duty::FULL),
+                Call::Shutdown,
+                Call::Init,
+                Call::Write(duty(65)),
+                Call::Shutdown,
+                Call::Init,
+                Call::Write(Duty::FULL),
+                Call::Shutdown,
+            ],
+            calls
+        );
+    }
+
+    #[test]
+    fn latch_holds_maximum_until_acknowledged() {
+        let sensor = TempPath::new("latch");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "0",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let latch = Latch::new();
+        let sink = MockSink::new();
+        let mut controller = Controller::new(&args, Box::new(MockOutput::new()))
+            .with_sink(Box::new(sink.clone()))
+            .with_latch(latch.clone());
+
+        sensor.write("30000");
+        controller.run_for(2);
+        assert_eq!(duty(98), controller.pwm.current);
+
+        sensor.write("75000");
+        controller.run_for(1);
+        sensor.write("30000");
+        controller.run_for(3);
+        assert_eq!(Duty::FULL, controller.pwm.current);
+        assert!(latch.is_tripped());
+
+        assert!(latch.acknowledge());
+        controller.run_for(1);
+        assert_eq!(duty(99), controller.pwm.current);
+
+        let events = sink.events();
+        assert_eq!(
+            1,
+            events
+                .iter()
+                .filter(|event| matches!(event, Event::Latched { .. }))
+                .count()
+        );
+        assert_eq!(Some(&Event::LatchReleased), events.iter().rev().nth(1));
+    }
+
+    #[test]
+    fn emergency_hold_keeps_maximum_after_a_dip() {
+        let sensor = TempPath::new("hold");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "10",
+            "--emergency-hold",
+            "60s",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let mut controller = Controller::new(&args, Box::new(MockOutput::new()))
+            .with_clock(Box::new(MockClock::new()));
+
+        sensor.write("30000");
+        controller.run_for(2);
+        assert_eq!(duty(98), controller.pwm.current);
+
+        sensor.write("75000");
+        controller.run_for(1);
+        sensor.write("30000");
+        controller.run_for(5);
+        assert_eq!(Duty::FULL, controller.pwm.current);
+        assert!(controller.emergency());
+
+        controller.run_for(1);
+        assert_eq!(duty(99), controller.pwm.current);
+        assert!(!controller.emergency());
+    }
+
+    #[test]
+    fn min_dwell_spaces_changes_below_maximum() {
+        let sensor = TempPath::new("dwell");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "5",
+            "--min-dwell",
+            "20",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let mut controller = Controller::new(&args, Box::new(MockOutput::new()))
+            .with_clock(Box::new(MockClock::new()));
+
+        sensor.write("30000");
+        controller.run_for(1);
+        assert_eq!(duty(99), controller.pwm.current);
+        controller.run_for(3);
+        assert_eq!(duty(99), controller.pwm.current);
+        controller.run_for(1);
+        assert_eq!(duty(98), controller.pwm.current);
+
+        sensor.write("75000");
+        controller.run_for(1);
+        assert_eq!(Duty::FULL, controller.pwm.current);
+    }
+
+    #[test]
+    fn cpu_load_raises_duty_ahead_of_temperature() {
+        let sensor = TempPath::new("load-temp");
+        let stat = TempPath::new("load-stat");
+        let busy =
+            |user: u64, idle: u64| stat.write(&format!("cpu  {} 0 0 {} 0 0 0 0 0 0\n", user, idle));
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "0",
+            "--fan-curve",
+            "40:30,60:100",
+            "--cpu-load-duty",
+            "80",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let mut controller = Controller::new(&args, Box::new(MockOutput::new()));
+        controller.cpu_load = Some((CpuLoad::with_path(&stat), duty(80)));
+
+        sensor.write("30000");
+        busy(0, 1000);
+        controller.run_for(1);
+        assert_eq!(duty(30), controller.pwm.current);
+
+        busy(750, 1250);
+        controller.run_for(1);
+        assert_eq!(duty(67), controller.pwm.current);
+
+        busy(750, 2250);
+        controller.run_for(1);
+        assert_eq!(duty(30), controller.pwm.current);
+    }
+
+    #[test]
+    fn throttling_forces_duty_on_stopped_fan() {
+        let sensor = TempPath::new("throttle-temp");
+        let flags = TempPath::new("throttle-flags");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "0",
+            "--fan-curve",
+            "40:30,60:100",
+            "--fan-off-below",
+            "35",
+            "--throttle-duty",
+            "60",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let mut controller = Controller::new(&args, Box::new(MockOutput::new()));
+        controller.throttle = Some((Throttle::with_path(&flags), duty(60)));
+
+        sensor.write("30000");
+        flags.write("0");
+        controller.run_for(1);
+        assert_eq!(Duty::OFF, controller.pwm.current);
+
+        flags.write("8");
+        controller.run_for(1);
+        assert!(controller.throttling);
+        assert_eq!(duty(60), controller.pwm.current);
+
+        // Past under-voltage alone doesn't call for cooling
+        flags.write("50000");
+        controller.run_for(1);
+        assert!(!controller.throttling);
+        assert_eq!(Duty::OFF, controller.pwm.current);
+    }
+
+    #[test]
+    fn applies_requested_setpoint() {
+        let setpoint = Setpoint::new();
+        let args = ["fan-controller", "--gpio-pwm", "0", "--pollrate", "0"];
+        let sensor = TempPath::new("setpoint");
+        sensor.write("42000");
+        let mut args = args.to_vec();
+        args.extend(["--temperature-file-path", sensor.arg()]);
+        let mut controller = Controller::new(&Args::parse_from(args), Box::new(MockOutput::new()))
+            .with_setpoint(setpoint.clone());
+
+        setpoint.request(Celsius::new(45, 0));
+        controller.run_for(1);
+
+        assert_eq!(Celsius::new(45, 0), controller.temperature.target);
+        // 42°C is below the new target
+        assert_eq!(duty(99), controller.pwm.current);
+    }
+
+    #[test]
+    fn target_schedule_ramps_the_target() {
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--target-schedule",
+            "08:00=40,10:00=44",
+        ]);
+        let sink = MockSink::new();
+        let mut controller =
+            Controller::new(&args, Box::new(MockOutput::new())).with_sink(Box::new(sink.clone()));
+        let time = |hour, minute| TimeOfDay::new(hour, minute).unwrap();
+
+        controller.follow_target_schedule(time(9, 0));
+        assert_eq!(Celsius::new(42, 0), controller.temperature.target);
+
+        controller.set_target(Celsius::new(36, 0));
+        controller.follow_target_schedule(time(9, 0));
+        assert_eq!(Celsius::new(36, 0), controller.temperature.target);
+        controller.follow_target_schedule(time(9, 3));
+        assert_eq!(Celsius::new(42, 100), controller.temperature.target);
+
+        assert_eq!(
+            Some(&Event::ScheduledTarget {
+                target: Celsius::new(42, 100)
+            }),
+            sink.events().last()
+        );
+    }
+
+    #[test]
+    fn seasons_switch_target_and_keep_overrides_until_next() {
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--season",
+            "11-01..03-31=38",
+        ]);
+        let sink = MockSink::new();
+        let mut controller =
+            Controller::new(&args, Box::new(MockOutput::new())).with_sink(Box::new(sink.clone()));
+
+        controller.follow_seasons(MonthDay::new(12, 1).unwrap());
+        assert_eq!(Celsius::new(38, 0), controller.temperature.target);
+
+        controller.set_target(Celsius::new(36, 0));
+        controller.follow_seasons(MonthDay::new(12, 2).unwrap());
+        assert_eq!(Celsius::new(36, 0), controller.temperature.target);
+
+        controller.follow_seasons(MonthDay::new(4, 1).unwrap());
+        assert_eq!(Celsius::new(40, 0), controller.temperature.target);
+
+        assert_eq!(
+            vec![
+                Event::SeasonChanged {
+                    season: Some("11-01..03-31".to_string()),
+                    target: Celsius::new(38, 0),
+                },
+                Event::Override {
+                    target: Celsius::new(36, 0),
+                },
+                Event::SeasonChanged {
+                    season: None,
+                    target: Celsius::new(40, 0),
+                },
+            ],
+            sink.events()
+        );
+    }
+
+    /// Observer remembering every decision and vetoing those above a limit
+    struct Limiter {
+        limit: Duty,
+        seen: Rc<RefCell<Vec<Iteration>>>,
+    }
+
+    impl Observer for Limiter {
+        fn on_iteration(&mut self, iteration: &Iteration) -> Verdict {
+            self.seen.borrow_mut().push(*iteration);
+            if iteration.decision > self.limit {
+                return Verdict::Veto;
+            }
+            Verdict::Accept
+        }
+    }
+
+    #[test]
+    fn observer_sees_and_vetoes_decisions() {
+        let output = MockOutput::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut controller = Controller::from_parts(
+            time::Duration::from_secs(5),
+            Temperature {
+                target: Celsius::new(40, 0),
+                current: Celsius::default(),
+                previous: Celsius::default(),
+                max: Celsius::new(70, 0),
+                sensor: Box::new(FileSensor::new("")),
+                raw: Celsius::default(),
+                smoothing: Smoothing::default(),
+                precision: Precision::default(),
+            },
+            recording_pwm(&output),
+        )
+        .with_observer(Box::new(Limiter {
+            limit: duty(52),
+            seen: Rc::clone(&seen),
+        }));
+
+        for millidegrees in [45_000, 46_000, 47_000] {
+            controller
+                .temperature
+                .update(Celsius::from_millidegrees(millidegrees));
+            controller.adjust();
+        }
+
+        let decisions: Vec<Duty> = seen.borrow().iter().map(|i| i.decision).collect();
+        assert_eq!(vec![duty(52), duty(54), duty(54)], decisions);
+        assert_eq!(duty(52), controller.pwm.current);
+    }
+
+    #[test]
+    fn enforces_soft_then_hard_limits() {
+        let sensor = TempPath::new("limits");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--pollrate",
+            "0",
+            "--fan-curve",
+            "40:30,60:100",
+            "--pwm-max",
+            "60",
+            "--pwm-hard-max",
+            "90",
+            "--temperature-hard-max",
+            "80",
+            "--temperature-file-path",
+            sensor.arg(),
+        ]);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut controller =
+            Controller::new(&args, Box::new(MockOutput::new())).with_observer(Box::new(Limiter {
+                limit: duty(85),
+                seen: Rc::clone(&seen),
+            }));
+
+        // 1. The curve's 100% is capped by the soft limit
+        sensor.write("65000");
+        controller.run_for(1);
+        assert_eq!(duty(60), controller.pwm.current);
+
+        // 2. At the soft temperature limit the hard duty limit applies, observers may still veto
+        sensor.write("72000");
+        controller.run_for(1);
+        assert_eq!(duty(90), seen.borrow().last().unwrap().decision);
+        assert_eq!(duty(60), controller.pwm.current);
+
+        // 3. At the hard temperature limit nothing holds the fan lower
+        sensor.write("81000");
+        controller.run_for(1);
+        assert_eq!(duty(90), controller.pwm.current);
+
+        // 4. Nothing set exceeds the hard duty limit
+        controller.pwm.jump(Duty::FULL);
+        assert_eq!(duty(90), controller.pwm.current);
+
+        sensor.write("50000");
+        controller.run_for(1);
+        assert_eq!(duty(60), controller.pwm.current);
+    }
+}
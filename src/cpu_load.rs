@@ -0,0 +1,109 @@
+//! CPU utilization from `/proc/stat`, fed forward so the fan spins up as a heavy job starts
+//! instead of once the temperature has risen.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// File the kernel reports CPU time in
+pub const PROC_STAT: &str = "/proc/stat";
+
+/// Time all CPUs spent idle and in total, in clock ticks since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Times {
+    idle: u64,
+    total: u64,
+}
+
+/// Returns the times of the aggregate `cpu` line.
+fn parse(content: &str) -> Option<Times> {
+    let line = content.lines().find(|line| line.starts_with("cpu "))?;
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    // user nice system idle iowait irq softirq steal; guest time is already part of user
+    let counted = fields.get(..8).or(fields.get(..4))?;
+    Some(Times {
+        idle: counted[3] + counted.get(4).copied().unwrap_or(0),
+        total: counted.iter().sum(),
+    })
+}
+
+/// Utilization sampled between successive reads.
+pub struct CpuLoad {
+    path: PathBuf,
+    previous: Option<Times>,
+}
+
+impl CpuLoad {
+    pub fn new() -> Self {
+        Self::with_path(Path::new(PROC_STAT))
+    }
+
+    /// Returns a sampler reading another file, e.g. in tests.
+    pub fn with_path(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            previous: None,
+        }
+    }
+
+    /// Returns the percentage of time the CPUs were busy since the last sample, or `None` on
+    /// the first sample or if the times can't be read.
+    pub fn sample(&mut self) -> Option<u8> {
+        let times = fs::read_to_string(&self.path)
+            .ok()
+            .as_deref()
+            .and_then(parse);
+        let Some(times) = times else {
+            log::debug!("Failed to read CPU times from {}", self.path.display());
+            return None;
+        };
+
+        let previous = self.previous.replace(times)?;
+        let total = times.total.checked_sub(previous.total)?;
+        let idle = times.idle.checked_sub(previous.idle)?;
+        if total == 0 {
+            return Some(0);
+        }
+        Some((total.saturating_sub(idle) * 100 / total) as u8)
+    }
+}
+
+impl Default for CpuLoad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CpuLoad;
+    use crate::mock::TempPath;
+    use std::fs;
+
+    #[test]
+    fn samples_busy_share_between_reads() {
+        let path = TempPath::new("stat");
+        let stat = |user: u64, idle: u64| {
+            path.write(&format!(
+                "cpu  {} 0 100 {} 50 0 0 0 0 0\ncpu0 1 2 3 4 5 6 7 8 9 10\nintr 1\n",
+                user, idle
+            ))
+        };
+        let mut load = CpuLoad::with_path(&path);
+
+        stat(1000, 5000);
+        assert_eq!(None, load.sample());
+        stat(1750, 5250);
+        assert_eq!(Some(75), load.sample());
+        stat(1750, 5250);
+        assert_eq!(Some(0), load.sample());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(None, load.sample());
+    }
+}
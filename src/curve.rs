@@ -0,0 +1,124 @@
+//! Fan curve mapping temperatures straight to duties, instead of stepping towards a target.
+
+use crate::units::{Celsius, Duty};
+use std::fmt;
+
+/// Points of the curve, in rising temperature order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FanCurve {
+    points: Vec<(Celsius, Duty)>,
+}
+
+impl FanCurve {
+    pub fn points(&self) -> &[(Celsius, Duty)] {
+        &self.points
+    }
+
+    /// Returns the duty for a temperature, interpolated linearly between the nearest points.
+    ///
+    /// Below the first point the first duty applies, above the last point the last one.
+    pub fn duty(&self, current: Celsius) -> Duty {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if current <= first.0 {
+            return first.1;
+        }
+        if current >= last.0 {
+            return last.1;
+        }
+
+        let upper = self
+            .points
+            .iter()
+            .position(|(temperature, _)| *temperature > current)
+            .unwrap_or(self.points.len() - 1);
+        let ((low, from), (high, to)) = (self.points[upper - 1], self.points[upper]);
+        let span = i64::from(high.millidegrees() - low.millidegrees());
+        let offset = i64::from(current.millidegrees() - low.millidegrees());
+        let (from, to) = (i64::from(from.percent()), i64::from(to.percent()));
+        let percent = (from * span + (to - from) * offset + span / 2) / span;
+        Duty::new(percent as u8).unwrap_or(Duty::FULL)
+    }
+}
+
+impl fmt::Display for FanCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|(temperature, duty)| format!("{}:{}", temperature, duty))
+            .collect();
+        write!(f, "{}", points.join(","))
+    }
+}
+
+/// Parses a curve such as `40:30,50:60,65:100`, each point a temperature and the duty for it.
+pub fn parse_curve(value: &str) -> Result<FanCurve, String> {
+    let invalid = || {
+        format!(
+            "invalid fan curve {:?}, expected e.g. 40:30,50:60,65:100",
+            value
+        )
+    };
+
+    let mut points: Vec<(Celsius, Duty)> = Vec::new();
+    for point in value.split(',') {
+        let (temperature, duty) = point.split_once(':').ok_or_else(invalid)?;
+        let temperature: Celsius = temperature.trim().parse().map_err(|_| invalid())?;
+        let duty: Duty = duty
+            .parse()
+            .map_err(|error| format!("{} in fan curve {:?}", error, value))?;
+        if let Some((previous, _)) = points.last() {
+            if temperature <= *previous {
+                return Err(format!(
+                    "fan curve {:?} temperatures must rise from point to point",
+                    value
+                ));
+            }
+        }
+        points.push((temperature, duty));
+    }
+    Ok(FanCurve { points })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_curve;
+    use crate::units::Celsius;
+
+    #[test]
+    fn interpolates_between_points() {
+        let curve = parse_curve("40:30,50:60,65:100").unwrap();
+        let duty = |degrees, millis| curve.duty(Celsius::new(degrees, millis)).percent();
+
+        assert_eq!(30, duty(20, 0));
+        assert_eq!(30, duty(40, 0));
+        assert_eq!(45, duty(45, 0));
+        assert_eq!(60, duty(50, 0));
+        assert_eq!(80, duty(57, 500));
+        assert_eq!(100, duty(65, 0));
+        assert_eq!(100, duty(90, 0));
+    }
+
+    #[test]
+    fn curves_may_fall() {
+        let curve = parse_curve("0:50,10:25").unwrap();
+        assert_eq!(38, curve.duty(Celsius::new(5, 0)).percent());
+    }
+
+    #[test]
+    fn rejects_invalid_curves() {
+        assert!(parse_curve("").is_err());
+        assert!(parse_curve("40:30,40:60").is_err());
+        assert!(parse_curve("50:30,40:60").is_err());
+        assert!(parse_curve("40:130").is_err());
+        assert!(parse_curve("40=30").is_err());
+        assert_eq!(
+            "40:30,65.5:100",
+            parse_curve("40:30%, 65.5:100").unwrap().to_string()
+        );
+        assert_eq!(
+            "40:30,65:100",
+            parse_curve("40:77/255,65:255/255").unwrap().to_string()
+        );
+    }
+}
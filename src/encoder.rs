@@ -0,0 +1,192 @@
+//! Rotary encoder on two GPIO pins for adjusting the target temperature by hand.
+//!
+//! Each detent moves the target by a fixed step, requested through the setpoint like a target
+//! written over the network, so the change is logged and shown wherever targets are.
+
+use crate::{setpoint::Setpoint, status::Status, units::Celsius};
+
+/// Quadrature transitions in one detent of a full-step encoder
+const TRANSITIONS_PER_DETENT: i8 = 4;
+
+/// Parses the two pins of an encoder, given as `A,B`.
+pub fn parse_pins(value: &str) -> Result<(i32, i32), String> {
+    let invalid = || format!("invalid encoder pins {:?}, expected e.g. 5,6", value);
+    let (a, b) = value.split_once(',').ok_or_else(invalid)?;
+    let a = a.trim().parse().map_err(|_| invalid())?;
+    let b = b.trim().parse().map_err(|_| invalid())?;
+    if a == b {
+        return Err(invalid());
+    }
+    Ok((a, b))
+}
+
+/// Turns sampled levels of the encoder's two pins into detents.
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+    /// Levels of A and B at the last sample, A in the high bit
+    state: u8,
+    /// Transitions counted towards the next detent, positive clockwise
+    count: i8,
+}
+
+impl Decoder {
+    pub fn new(a: bool, b: bool) -> Self {
+        Self {
+            state: levels(a, b),
+            count: 0,
+        }
+    }
+
+    /// Records the levels of A and B, returning 1 for a clockwise detent and -1 for a
+    /// counterclockwise one.
+    ///
+    /// Invalid transitions, skipping a state, are ignored as contact bounce.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<i32> {
+        // Gray code order clockwise: 00, 01, 11, 10
+        const DIRECTION: [i8; 16] = [0, 1, -1, 0, -1, 0, 0, 1, 1, 0, 0, -1, 0, -1, 1, 0];
+        let state = levels(a, b);
+        self.count += DIRECTION[usize::from(self.state << 2 | state)];
+        self.state = state;
+
+        if self.count.abs() < TRANSITIONS_PER_DETENT {
+            return None;
+        }
+        let detent = i32::from(self.count.signum());
+        self.count = 0;
+        Some(detent)
+    }
+}
+
+fn levels(a: bool, b: bool) -> u8 {
+    u8::from(a) << 1 | u8::from(b)
+}
+
+/// Moves the target temperature by a step for each detent.
+#[derive(Debug, Clone)]
+pub struct Knob {
+    pub setpoint: Setpoint,
+    pub status: Status,
+    pub step: Celsius,
+    /// Target before the controller has reported one
+    pub target: Celsius,
+    pub temperature_max: Celsius,
+}
+
+impl Knob {
+    /// Requests the target moved by the given number of detents, starting from the latest
+    /// request if it hasn't been applied yet. Stays a step below the maximum temperature.
+    pub fn turn(&self, detents: i32) {
+        let current = self
+            .setpoint
+            .pending()
+            .or(self.status.snapshot().target)
+            .unwrap_or(self.target);
+        let highest = self.temperature_max.millidegrees() - self.step.millidegrees();
+        let millidegrees =
+            (current.millidegrees() + detents * self.step.millidegrees()).clamp(0, highest);
+        self.setpoint.request(Celsius::new(0, millidegrees));
+    }
+}
+
+/// Watches an encoder with its common pin to ground, with the internal pull-ups enabled.
+#[cfg(feature = "wiringpi")]
+pub fn watch(pins: (i32, i32), knob: Knob) {
+    use libc::c_int;
+
+    #[link(name = "wiringPi")]
+    extern "C" {
+        fn wiringPiSetup() -> c_int;
+        fn pinMode(pin: c_int, mode: c_int);
+        fn pullUpDnControl(pin: c_int, pud: c_int);
+        fn digitalRead(pin: c_int) -> c_int;
+    }
+    const INPUT: c_int = 0;
+    const PUD_UP: c_int = 2;
+    const LOW: c_int = 0;
+
+    let (a, b) = pins;
+    unsafe {
+        wiringPiSetup();
+        for pin in [a, b] {
+            pinMode(pin, INPUT);
+            pullUpDnControl(pin, PUD_UP);
+        }
+    }
+    let read = move || unsafe { (digitalRead(a) == LOW, digitalRead(b) == LOW) };
+    std::thread::spawn(move || {
+        let (level_a, level_b) = read();
+        let mut decoder = Decoder::new(level_a, level_b);
+        loop {
+            let (level_a, level_b) = read();
+            if let Some(detent) = decoder.update(level_a, level_b) {
+                knob.turn(detent);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_pins, Decoder, Knob};
+    use crate::{setpoint::Setpoint, status::Status, units::Celsius};
+
+    const CLOCKWISE: [(bool, bool); 4] =
+        [(false, true), (true, true), (true, false), (false, false)];
+
+    #[test]
+    fn decodes_detents_in_both_directions() {
+        let mut decoder = Decoder::new(false, false);
+        let detents: Vec<i32> = CLOCKWISE
+            .iter()
+            .chain(CLOCKWISE.iter().rev().skip(1))
+            .chain(&[(false, false)])
+            .filter_map(|&(a, b)| decoder.update(a, b))
+            .collect();
+        assert_eq!(vec![1, -1], detents);
+    }
+
+    #[test]
+    fn ignores_bounce() {
+        let mut decoder = Decoder::new(false, false);
+        let levels = [
+            (false, true),
+            (false, false),
+            (false, true),
+            (true, true),
+            (true, false),
+            (false, false),
+        ];
+        let detents: Vec<i32> = levels
+            .iter()
+            .filter_map(|&(a, b)| decoder.update(a, b))
+            .collect();
+        assert_eq!(vec![1], detents);
+    }
+
+    #[test]
+    fn turns_from_latest_request_below_maximum() {
+        let knob = Knob {
+            setpoint: Setpoint::new(),
+            status: Status::new(),
+            step: Celsius::new(0, 500),
+            target: Celsius::new(40, 0),
+            temperature_max: Celsius::new(42, 0),
+        };
+
+        knob.turn(1);
+        knob.turn(1);
+        assert_eq!(Some(Celsius::new(41, 0)), knob.setpoint.pending());
+        knob.turn(5);
+        assert_eq!(Some(Celsius::new(41, 500)), knob.setpoint.take());
+        knob.turn(-3);
+        assert_eq!(Some(Celsius::new(38, 500)), knob.setpoint.take());
+    }
+
+    #[test]
+    fn parses_pins() {
+        assert_eq!(Ok((5, 6)), parse_pins("5, 6"));
+        assert!(parse_pins("5").is_err());
+        assert!(parse_pins("5,5").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! Target temperature bias from electricity prices or solar surplus, for off-grid enclosures
+//! where every watt counts.
+//!
+//! The signal is read in the background, so a slow source never holds up the control loop. While
+//! it can't be read, no bias applies.
+
+use crate::{
+    args::Args,
+    telemetry::{self, HttpUrl},
+    units::Celsius,
+};
+use clap::ValueEnum;
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Time allowed for connecting and for each read or write
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the signal is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// File holding the latest value
+    File(PathBuf),
+    /// URL answering GET requests with the latest value
+    Http(HttpUrl),
+    /// MQTT topic the value is published to, subscribed to through `mosquitto_sub`
+    Mqtt {
+        host: String,
+        port: u16,
+        topic: String,
+    },
+}
+
+/// Parses a source given as `http://host[:port]/path`, `mqtt://host[:port]/topic` or a file path.
+pub fn parse_source(value: &str) -> Result<Source, String> {
+    if value.starts_with("http://") {
+        return telemetry::parse_url(value).map(Source::Http);
+    }
+    let Some(rest) = value.strip_prefix("mqtt://") else {
+        return Ok(Source::File(PathBuf::from(value)));
+    };
+
+    let (authority, topic) = rest
+        .split_once('/')
+        .filter(|(_, topic)| !topic.is_empty())
+        .ok_or_else(|| format!("missing topic in {:?}", value))?;
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port {:?}", port))?,
+        ),
+        None => (authority, 1883),
+    };
+    if host.is_empty() {
+        return Err("missing host".to_string());
+    }
+    Ok(Source::Mqtt {
+        host: host.to_string(),
+        port,
+        topic: topic.to_string(),
+    })
+}
+
+/// What the signal measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SignalKind {
+    /// Electricity price, the fan runs less while it's above the threshold
+    Price,
+    /// Solar power left over, the fan runs more while it's above the threshold
+    Surplus,
+}
+
+/// How the signal moves the target temperature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    pub kind: SignalKind,
+    pub threshold: f64,
+    /// How far the target moves while the signal is above the threshold
+    pub bias: Celsius,
+}
+
+impl Policy {
+    pub fn new(args: &Args) -> Self {
+        Self {
+            kind: args.energy_signal,
+            threshold: args.energy_threshold,
+            bias: args.energy_bias,
+        }
+    }
+
+    /// Returns the offset to the target temperature for the latest value of the signal.
+    pub fn bias(&self, value: Option<f64>) -> Celsius {
+        match value {
+            Some(value) if value > self.threshold => match self.kind {
+                SignalKind::Price => self.bias,
+                SignalKind::Surplus => Celsius::from_millidegrees(-self.bias.millidegrees()),
+            },
+            _ => Celsius::default(),
+        }
+    }
+}
+
+/// Latest value of the signal, shared with the thread reading it.
+#[derive(Debug, Clone, Default)]
+pub struct Signal(Arc<Mutex<Option<f64>>>);
+
+impl Signal {
+    /// Returns the latest value, `None` while the source can't be read.
+    pub fn latest(&self) -> Option<f64> {
+        *self.0.lock().unwrap()
+    }
+
+    pub(crate) fn set(&self, value: Option<f64>) {
+        *self.0.lock().unwrap() = value;
+    }
+}
+
+/// Starts reading the source in the background, files and URLs every `interval`.
+///
+/// MQTT messages are applied as they arrive, with the subscription retried every `interval` if
+/// it ends.
+pub fn watch(source: Source, interval: Duration) -> Signal {
+    let signal = Signal::default();
+    let shared = signal.clone();
+    thread::spawn(move || loop {
+        let result = match &source {
+            Source::File(path) => fs::read_to_string(path).and_then(|text| parse_value(&text)),
+            Source::Http(url) => get(url).and_then(|body| parse_value(&body)),
+            Source::Mqtt { host, port, topic } => subscribe(host, *port, topic, &shared),
+        };
+        match result {
+            Ok(value) => shared.set(Some(value)),
+            Err(error) => {
+                log::warn!("Failed to read energy signal: {}", error);
+                shared.set(None);
+            }
+        }
+        thread::sleep(interval);
+    });
+    signal
+}
+
+/// Parses a value such as `0.42` or `1250`, ignoring surrounding whitespace.
+pub fn parse_value(text: &str) -> io::Result<f64> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is not a number", text.trim()),
+            )
+        })
+}
+
+fn get(url: &HttpUrl) -> io::Result<String> {
+    let address = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        url.path, url.host
+    )?;
+
+    let mut response = String::new();
+    stream.take(4096).read_to_string(&mut response)?;
+    let status = response.split(' ').nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(io::Error::other(format!("server responded {:?}", status)));
+    }
+    let (_, body) = response.split_once("\r\n\r\n").unwrap_or_default();
+    Ok(body.to_string())
+}
+
+/// Applies each message on the topic until the subscription ends, returning the last value.
+fn subscribe(host: &str, port: u16, topic: &str, signal: &Signal) -> io::Result<f64> {
+    let mut subscriber = Command::new("mosquitto_sub")
+        .args(["-h", host, "-p", &port.to_string(), "-t", topic])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = subscriber.stdout.take().unwrap();
+    for line in BufReader::new(stdout).lines() {
+        match parse_value(&line?) {
+            Ok(value) => signal.set(Some(value)),
+            Err(error) => log::warn!("Ignoring energy signal message: {}", error),
+        }
+    }
+    let status = subscriber.wait()?;
+    Err(io::Error::other(format!(
+        "mosquitto_sub exited with {}",
+        status
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_source, parse_value, Policy, SignalKind, Source};
+    use crate::units::Celsius;
+    use std::path::PathBuf;
+
+    #[test]
+    fn parses_sources() {
+        assert_eq!(
+            Ok(Source::Mqtt {
+                host: "broker".to_string(),
+                port: 1883,
+                topic: "solar/surplus".to_string(),
+            }),
+            parse_source("mqtt://broker/solar/surplus")
+        );
+        assert!(matches!(
+            parse_source("http://prices:8080/now"),
+            Ok(Source::Http(url)) if url.port == 8080 && url.path == "/now"
+        ));
+        assert_eq!(
+            Ok(Source::File(PathBuf::from("/run/price"))),
+            parse_source("/run/price")
+        );
+        assert!(parse_source("mqtt://broker").is_err());
+        assert!(parse_source("mqtt://:1883/topic").is_err());
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(0.42, parse_value(" 0.42\n").unwrap());
+        assert!(parse_value("cheap").is_err());
+        assert!(parse_value("NaN").is_err());
+    }
+
+    #[test]
+    fn biases_above_threshold_only() {
+        let price = Policy {
+            kind: SignalKind::Price,
+            threshold: 0.3,
+            bias: Celsius::new(2, 0),
+        };
+        assert_eq!(Celsius::new(2, 0), price.bias(Some(0.42)));
+        assert_eq!(Celsius::default(), price.bias(Some(0.3)));
+        assert_eq!(Celsius::default(), price.bias(None));
+
+        let surplus = Policy {
+            kind: SignalKind::Surplus,
+            threshold: 500.0,
+            bias: Celsius::new(2, 0),
+        };
+        assert_eq!(Celsius::new(-2, 0), surplus.bias(Some(1250.0)));
+    }
+}
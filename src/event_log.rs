@@ -0,0 +1,272 @@
+//! Events appended to a file as JSON lines, for later analysis of thermal history.
+
+use crate::{
+    events::{Event, Sink},
+    sensor::Raw,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Appends each event to a file as one JSON object per line.
+pub struct JsonLinesSink {
+    writer: BufWriter<File>,
+    arm: Arm,
+}
+
+impl JsonLinesSink {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            arm: Arm::default(),
+        })
+    }
+}
+
+impl Sink for JsonLinesSink {
+    fn handle(&mut self, event: &Event) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.arm.follow(event);
+        let line = self.arm.tag(to_json(event, time));
+
+        // A full disk must not take the controller down with it
+        if let Err(error) = writeln!(self.writer, "{}", line).and_then(|()| self.writer.flush()) {
+            log::error!("Failed to write event log: {}", error);
+        }
+    }
+}
+
+/// Profile of the A/B test in effect, followed through the events to tag JSON lines with.
+#[derive(Debug, Clone, Default)]
+pub struct Arm {
+    profile: Option<String>,
+}
+
+impl Arm {
+    /// Notes the profile an A/B test switched to, or that it ended.
+    pub fn follow(&mut self, event: &Event) {
+        if let Event::AbTestArm { profile } = event {
+            self.profile = profile.clone();
+        }
+    }
+
+    /// Returns a JSON object line with the profile in effect added first, if any.
+    pub fn tag(&self, line: String) -> String {
+        match &self.profile {
+            Some(profile) => format!("{{\"arm\":\"{}\",{}", escape(profile), &line[1..]),
+            None => line,
+        }
+    }
+}
+
+/// Renders a raw sensor value as a JSON object with its unit, or `null`.
+pub fn raw_json(raw: Option<Raw>) -> String {
+    raw.map_or_else(
+        || "null".to_string(),
+        |raw| format!("{{\"value\":{},\"unit\":\"{}\"}}", raw.value(), raw.unit()),
+    )
+}
+
+/// Renders an event as a single line JSON object stamped with Unix time in seconds.
+pub fn to_json(event: &Event, time: u64) -> String {
+    let fields = match event {
+        Event::Sample { temperature, raw } => format!(
+            "\"event\":\"sample\",\"temperature\":{},\"raw\":{}",
+            temperature,
+            raw_json(*raw)
+        ),
+        Event::Decision {
+            temperature,
+            target,
+            from,
+            to,
+        } => format!(
+            "\"event\":\"decision\",\"temperature\":{},\"target\":{},\"from\":{},\"to\":{}",
+            temperature, target, from, to
+        ),
+        Event::Override { target } => format!("\"event\":\"override\",\"target\":{}", target),
+        Event::ModeChanged { boost, quiet } => format!(
+            "\"event\":\"mode_changed\",\"boost\":{},\"quiet\":{}",
+            boost, quiet
+        ),
+        Event::OverrideExpired { target } => {
+            format!("\"event\":\"override_expired\",\"target\":{}", target)
+        }
+        Event::EnergyBias { bias, target } => format!(
+            "\"event\":\"energy_bias\",\"bias\":{},\"target\":{}",
+            bias, target
+        ),
+        Event::ScheduledTarget { target } => {
+            format!("\"event\":\"scheduled_target\",\"target\":{}", target)
+        }
+        Event::AbTestArm { profile } => format!(
+            "\"event\":\"ab_test_arm\",\"profile\":{}",
+            profile
+                .as_ref()
+                .map_or_else(|| "null".to_string(), |profile| format!("\"{}\"", escape(profile)))
+        ),
+        Event::SeasonChanged { season, target } => format!(
+            "\"event\":\"season_changed\",\"season\":{},\"target\":{}",
+            season
+                .as_ref()
+                .map_or_else(|| "null".to_string(), |season| format!("\"{}\"", escape(season))),
+            target
+        ),
+        Event::Fault { message } => {
+            format!("\"event\":\"fault\",\"message\":\"{}\"", escape(message))
+        }
+        Event::Dropped { count } => format!("\"event\":\"dropped\",\"count\":{}", count),
+        Event::Reloaded { changes } => {
+            let changes: Vec<String> = changes
+                .iter()
+                .map(|change| {
+                    let value = |value: &Option<String>| {
+                        value.as_ref().map_or_else(
+                            || "null".to_string(),
+                            |value| format!("\"{}\"", escape(value)),
+                        )
+                    };
+                    format!(
+                        "{{\"option\":\"{}\",\"from\":{},\"to\":{}}}",
+                        escape(&change.option),
+                        value(&change.from),
+                        value(&change.to)
+                    )
+                })
+                .collect();
+            format!("\"event\":\"reloaded\",\"changes\":[{}]", changes.join(","))
+        }
+        Event::Overtemperature { temperature, max } => format!(
+            "\"event\":\"overtemperature\",\"temperature\":{},\"max\":{}",
+            temperature, max
+        ),
+        Event::Latched { temperature } => {
+            format!("\"event\":\"latched\",\"temperature\":{}", temperature)
+        }
+        Event::LatchReleased => "\"event\":\"latch_released\"".to_string(),
+        Event::OutputFailed { message } => format!(
+            "\"event\":\"output_failed\",\"message\":\"{}\"",
+            escape(message)
+        ),
+        Event::OutputReinitialized { reinits } => {
+            format!("\"event\":\"output_reinitialized\",\"reinits\":{}", reinits)
+        }
+        Event::Alarm {
+            sensor,
+            level,
+            temperature,
+        } => format!(
+            "\"event\":\"alarm\",\"sensor\":\"{}\",\"level\":\"{}\",\"temperature\":{}",
+            escape(sensor),
+            level,
+            temperature
+        ),
+        Event::ReloadRejected { message } => format!(
+            "\"event\":\"reload_rejected\",\"message\":\"{}\"",
+            escape(message)
+        ),
+        Event::Progress(progress) => format!(
+            "\"event\":\"progress\",\"operation\":\"{}\",\"done\":{},\"total\":{},\"duty\":{},\"remaining\":{}",
+            progress.operation,
+            progress.done,
+            progress.total,
+            progress.duty,
+            progress.remaining.as_secs()
+        ),
+    };
+
+    format!("{{\"time\":{},{}}}", time, fields)
+}
+
+/// Escapes a string for use inside JSON quotes.
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_json, Arm};
+    use crate::{events::Event, pwm::tests::duty, reload::Change, units::Celsius};
+
+    #[test]
+    fn renders_decision() {
+        let event = Event::Decision {
+            temperature: Celsius::new(41, 500),
+            target: Celsius::new(40, 0),
+            from: duty(50),
+            to: duty(52),
+        };
+
+        assert_eq!(
+            "{\"time\":1700000000,\"event\":\"decision\",\"temperature\":41.5,\"target\":40,\"from\":50,\"to\":52}",
+            to_json(&event, 1_700_000_000)
+        );
+    }
+
+    #[test]
+    fn tags_lines_with_ab_test_arm() {
+        let sample = Event::Sample {
+            temperature: Celsius::new(40, 0),
+            raw: None,
+        };
+        let mut arm = Arm::default();
+
+        arm.follow(&sample);
+        assert!(arm.tag(to_json(&sample, 0)).starts_with("{\"time\""));
+        let switched = Event::AbTestArm {
+            profile: Some("silent".to_string()),
+        };
+        arm.follow(&switched);
+        assert_eq!(
+            "{\"arm\":\"silent\",\"time\":0,\"event\":\"ab_test_arm\",\"profile\":\"silent\"}",
+            arm.tag(to_json(&switched, 0))
+        );
+        arm.follow(&Event::AbTestArm { profile: None });
+        assert!(arm.tag(to_json(&sample, 0)).starts_with("{\"time\""));
+    }
+
+    #[test]
+    fn escapes_fault_message() {
+        let event = Event::Fault {
+            message: "Failed to parse temperature value: \"\"\n".to_string(),
+        };
+
+        assert_eq!(
+            "{\"time\":0,\"event\":\"fault\",\"message\":\"Failed to parse temperature value: \\\"\\\"\\n\"}",
+            to_json(&event, 0)
+        );
+    }
+
+    #[test]
+    fn renders_reload_changes() {
+        let event = Event::Reloaded {
+            changes: vec![Change {
+                option: "event-log".to_string(),
+                from: None,
+                to: Some("/var/log/events.jsonl".to_string()),
+            }],
+        };
+
+        assert_eq!(
+            "{\"time\":0,\"event\":\"reloaded\",\"changes\":[{\"option\":\"event-log\",\"from\":null,\"to\":\"/var/log/events.jsonl\"}]}",
+            to_json(&event, 0)
+        );
+    }
+}
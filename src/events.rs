@@ -0,0 +1,491 @@
+//! Events published by the control loop to every subscribed sink.
+//!
+//! Sinks such as the console log only see events, so adding a new one never touches the loop.
+
+use crate::{
+    alarm::AlarmLevel,
+    console::DecimalSeparator,
+    reload::Change,
+    sensor::Raw,
+    template::{Template, Values},
+    units::{Celsius, Duty},
+};
+use clap::ValueEnum;
+use std::{
+    fmt,
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Something that happened in the control loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Temperature read from the sensor, with the value it was converted from if known
+    Sample {
+        temperature: Celsius,
+        raw: Option<Raw>,
+    },
+    /// Fan duty changed in response to the latest sample
+    Decision {
+        temperature: Celsius,
+        target: Celsius,
+        from: Duty,
+        to: Duty,
+    },
+    /// Target temperature changed while running
+    Override { target: Celsius },
+    /// Target set remotely wasn't set again within the override TTL, the automatic one is back
+    OverrideExpired { target: Celsius },
+    /// Boost from the button started or ended, or quiet mode was toggled
+    ModeChanged { boost: bool, quiet: bool },
+    /// Energy signal moved the target temperature by a different bias
+    EnergyBias { bias: Celsius, target: Celsius },
+    /// Date moved into another season, or out of all of them, and its target took effect
+    SeasonChanged {
+        season: Option<String>,
+        target: Celsius,
+    },
+    /// Target temperature moved along the target schedule
+    ScheduledTarget { target: Celsius },
+    /// A/B test switched to one of its profiles, or ended
+    AbTestArm { profile: Option<String> },
+    /// Sensor could not be read, the fan runs at maximum speed until it can
+    Fault { message: String },
+    /// Events a buffered sink could not keep up with were discarded
+    Dropped { count: usize },
+    /// Long-running operation advanced
+    Progress(Progress),
+    /// Reloaded options were applied
+    Reloaded { changes: Vec<Change> },
+    /// Reloaded options were invalid, the previous ones stay in effect
+    ReloadRejected { message: String },
+    /// Temperature rose to the maximum, the fan runs at maximum speed
+    Overtemperature { temperature: Celsius, max: Celsius },
+    /// Maximum temperature was reached with the latch enabled, the fan stays at maximum speed
+    /// until acknowledged
+    Latched { temperature: Celsius },
+    /// Latch was acknowledged, normal control resumes
+    LatchReleased,
+    /// Writing to the fan output failed, it's initialized again after a backoff
+    OutputFailed { message: String },
+    /// Fan output was initialized again after failing, this many times since starting
+    OutputReinitialized { reinits: u64 },
+    /// Monitored sensor crossed one of its alarm thresholds, `Normal` once it's back below them
+    Alarm {
+        sensor: String,
+        level: AlarmLevel,
+        temperature: Celsius,
+    },
+}
+
+/// How far a long-running operation such as calibration has got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub operation: &'static str,
+    /// Steps completed
+    pub done: usize,
+    pub total: usize,
+    /// Duty the fan is running at
+    pub duty: Duty,
+    /// Estimated time until the operation finishes
+    pub remaining: Duration,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.remaining.as_secs();
+        write!(
+            f,
+            "{}: {}/{} steps done, fan at {}%, about {}m {:02}s left",
+            self.operation,
+            self.done,
+            self.total,
+            self.duty,
+            seconds / 60,
+            seconds % 60
+        )
+    }
+}
+
+/// Receiver of control loop events.
+pub trait Sink {
+    fn handle(&mut self, event: &Event);
+}
+
+/// Delivers each published event to every subscribed sink in order.
+#[derive(Default)]
+pub struct EventBus {
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&mut self, sink: Box<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn publish(&mut self, event: Event) {
+        for sink in self.sinks.iter_mut() {
+            sink.handle(&event);
+        }
+    }
+}
+
+/// Logs events through the `log` facade.
+#[derive(Debug, Clone, Default)]
+pub struct LogSink {
+    pub decimal: DecimalSeparator,
+    /// Wording of fan speed changes in place of the built-in one
+    pub template: Option<Template>,
+}
+
+impl Sink for LogSink {
+    fn handle(&mut self, event: &Event) {
+        match event {
+            Event::Decision {
+                temperature,
+                target,
+                from,
+                to,
+            } => {
+                let direction = if to > from { "rising" } else { "lowering" };
+                let (temperature, target) = (
+                    self.decimal.celsius(*temperature),
+                    self.decimal.celsius(*target),
+                );
+                match &self.template {
+                    Some(template) => log::info!(
+                        "{}",
+                        template.render(&Values {
+                            temperature: &temperature,
+                            target: &target,
+                            from: &from.to_string(),
+                            pwm: &to.to_string(),
+                            direction,
+                        })
+                    ),
+                    None => log::info!(
+                        "Current temperature {}°C (target {}°C), {} fan speed {} -> {}",
+                        temperature,
+                        target,
+                        direction,
+                        from,
+                        to
+                    ),
+                }
+            }
+            Event::Fault { message } => {
+                log::error!("{}, running fan at maximum speed", message);
+            }
+            Event::Dropped { count } => {
+                log::warn!("Dropped {} events, sink is not keeping up", count);
+            }
+            Event::Progress(progress) => log::info!("{}", progress),
+            Event::Sample {
+                temperature,
+                raw: Some(raw),
+            } => log::debug!(
+                "Read temperature {}°C from {}",
+                self.decimal.celsius(*temperature),
+                raw
+            ),
+            Event::Sample {
+                temperature,
+                raw: None,
+            } => log::debug!("Read temperature {}°C", self.decimal.celsius(*temperature)),
+            Event::Override { target } => {
+                log::info!(
+                    "Target temperature set to {}°C",
+                    self.decimal.celsius(*target)
+                )
+            }
+            Event::OverrideExpired { target } => log::warn!(
+                "Remote target not refreshed in time, back to target temperature {}°C",
+                self.decimal.celsius(*target)
+            ),
+            Event::ScheduledTarget { target } => log::debug!(
+                "Scheduled target temperature {}°C",
+                self.decimal.celsius(*target)
+            ),
+            Event::SeasonChanged {
+                season: Some(season),
+                target,
+            } => log::info!(
+                "Season {} started, target temperature {}°C",
+                season,
+                self.decimal.celsius(*target)
+            ),
+            Event::SeasonChanged {
+                season: None,
+                target,
+            } => log::info!(
+                "Outside all seasons, target temperature {}°C",
+                self.decimal.celsius(*target)
+            ),
+            Event::AbTestArm {
+                profile: Some(profile),
+            } => log::info!("A/B test switched to profile {}", profile),
+            Event::AbTestArm { profile: None } => log::info!("A/B test ended"),
+            Event::ModeChanged { boost, quiet } => log::info!(
+                "Boost {}, quiet mode {}",
+                if *boost { "on" } else { "off" },
+                if *quiet { "on" } else { "off" }
+            ),
+            Event::EnergyBias { bias, target } => log::info!(
+                "Energy signal biases target by {}°C to {}°C",
+                self.decimal.celsius(*bias),
+                self.decimal.celsius(*target)
+            ),
+            Event::Reloaded { changes } if changes.is_empty() => {
+                log::info!("Configuration reloaded, nothing changed")
+            }
+            Event::Reloaded { changes } => {
+                let changes: Vec<String> = changes.iter().map(ToString::to_string).collect();
+                log::info!("Configuration reloaded: {}", changes.join(", "))
+            }
+            Event::Overtemperature { temperature, max } => log::warn!(
+                "Temperature {}°C reached maximum {}°C, running fan at maximum speed",
+                self.decimal.celsius(*temperature),
+                self.decimal.celsius(*max)
+            ),
+            Event::Latched { temperature } => log::error!(
+                "Temperature reached {}°C, fan latched at maximum speed until acknowledged",
+                self.decimal.celsius(*temperature)
+            ),
+            Event::LatchReleased => log::warn!("Overtemperature latch acknowledged"),
+            Event::OutputFailed { message } => log::error!(
+                "Failed to write to fan output, initializing it again: {}",
+                message
+            ),
+            Event::OutputReinitialized { reinits } => {
+                log::info!("Fan output initialized again ({} since start)", reinits)
+            }
+            Event::Alarm {
+                sensor,
+                level: AlarmLevel::Normal,
+                temperature,
+            } => log::info!(
+                "Alarm sensor {} back to normal at {}°C",
+                sensor,
+                self.decimal.celsius(*temperature)
+            ),
+            Event::Alarm {
+                sensor,
+                level: AlarmLevel::Warning,
+                temperature,
+            } => log::warn!(
+                "Alarm sensor {} reached warning level at {}°C",
+                sensor,
+                self.decimal.celsius(*temperature)
+            ),
+            Event::Alarm {
+                sensor,
+                level: AlarmLevel::Critical,
+                temperature,
+            } => log::error!(
+                "Alarm sensor {} reached critical level at {}°C",
+                sensor,
+                self.decimal.celsius(*temperature)
+            ),
+            Event::ReloadRejected { message } => {
+                log::error!(
+                    "Configuration reload rejected, keeping previous settings: {}",
+                    message
+                )
+            }
+        }
+    }
+}
+
+/// What a buffered sink does with events arriving while its buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Overflow {
+    /// Discard them silently
+    Drop,
+    /// Discard them, then tell the sink how many were lost once it catches up
+    Summarize,
+}
+
+/// Hands events to a sink running on its own thread through a bounded buffer.
+///
+/// Publishing never blocks, so a hung network sink or slow disk cannot stall the control loop.
+pub struct BufferedSink {
+    sender: SyncSender<Event>,
+    worker: JoinHandle<()>,
+    overflow: Overflow,
+    /// Events discarded since the last summary
+    dropped: usize,
+}
+
+impl BufferedSink {
+    pub fn new<S: Sink + Send + 'static>(mut sink: S, capacity: usize, overflow: Overflow) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Event>(capacity);
+        let worker = thread::spawn(move || {
+            for event in receiver {
+                sink.handle(&event);
+            }
+        });
+
+        Self {
+            sender,
+            worker,
+            overflow,
+            dropped: 0,
+        }
+    }
+
+    /// Closes the buffer and waits until the sink has handled every queued event.
+    pub fn finish(self) {
+        drop(self.sender);
+        let _ = self.worker.join();
+    }
+}
+
+impl Sink for BufferedSink {
+    fn handle(&mut self, event: &Event) {
+        if self.dropped > 0 && self.overflow == Overflow::Summarize {
+            let summary = Event::Dropped {
+                count: self.dropped,
+            };
+            if self.sender.try_send(summary).is_ok() {
+                self.dropped = 0;
+            }
+        }
+
+        match self.sender.try_send(event.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            // The sink's thread panicked, there is nobody left to deliver to
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferedSink, Event, EventBus, Overflow, Sink};
+    use crate::{mock::MockSink, units::Celsius};
+    use std::{
+        sync::mpsc::{self, Receiver, Sender},
+        thread,
+        time::Duration,
+    };
+
+    /// Sink stuck in its first event until released, like a hung network connection.
+    struct Stalled {
+        entered: Sender<()>,
+        release: Receiver<()>,
+        received: MockSink,
+    }
+
+    impl Sink for Stalled {
+        fn handle(&mut self, event: &Event) {
+            let _ = self.entered.send(());
+            let _ = self.release.recv();
+            self.received.handle(event);
+        }
+    }
+
+    fn sample(degrees: i32) -> Event {
+        Event::Sample {
+            temperature: Celsius::new(degrees, 0),
+            raw: None,
+        }
+    }
+
+    #[test]
+    fn every_sink_receives_every_event() {
+        let first = MockSink::new();
+        let second = MockSink::new();
+        let mut bus = EventBus::new();
+        bus.subscribe(Box::new(first.clone()));
+        bus.subscribe(Box::new(second.clone()));
+
+        let events = vec![
+            Event::Sample {
+                temperature: Celsius::new(41, 0),
+                raw: None,
+            },
+            Event::Override {
+                target: Celsius::new(45, 0),
+            },
+        ];
+        for event in events.iter().cloned() {
+            bus.publish(event);
+        }
+
+        assert_eq!(events, first.events());
+        assert_eq!(events, second.events());
+    }
+
+    #[test]
+    fn stalled_sink_never_blocks_publisher() {
+        let (entered, entered_receiver) = mpsc::channel();
+        let (release, release_receiver) = mpsc::channel();
+        let received = MockSink::new();
+        let mut sink = BufferedSink::new(
+            Stalled {
+                entered,
+                release: release_receiver,
+                received: received.clone(),
+            },
+            2,
+            Overflow::Summarize,
+        );
+
+        sink.handle(&sample(40));
+        entered_receiver.recv().unwrap();
+        // Two events fit in the buffer, the rest overflow
+        for degrees in 41..45 {
+            sink.handle(&sample(degrees));
+        }
+
+        drop(release);
+        while received.events().len() < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        sink.handle(&sample(45));
+        sink.finish();
+
+        assert_eq!(
+            vec![
+                sample(40),
+                sample(41),
+                sample(42),
+                Event::Dropped { count: 2 },
+                sample(45)
+            ],
+            received.events()
+        );
+    }
+
+    #[test]
+    fn drop_policy_discards_silently() {
+        let (entered, entered_receiver) = mpsc::channel();
+        let (release, release_receiver) = mpsc::channel();
+        let received = MockSink::new();
+        let mut sink = BufferedSink::new(
+            Stalled {
+                entered,
+                release: release_receiver,
+                received: received.clone(),
+            },
+            1,
+            Overflow::Drop,
+        );
+
+        sink.handle(&sample(40));
+        entered_receiver.recv().unwrap();
+        for degrees in 41..45 {
+            sink.handle(&sample(degrees));
+        }
+        drop(release);
+        sink.finish();
+
+        assert_eq!(vec![sample(40), sample(41)], received.events());
+    }
+}
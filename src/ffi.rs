@@ -0,0 +1,116 @@
+//! C ABI for the control core, so non-Rust programs can reuse the control algorithm.
+//!
+//! The caller reads temperatures and drives the fan itself; see `include/fan_controller.h`.
+
+use crate::{
+    controller::Controller,
+    units::{Celsius, Duty},
+};
+
+/// Opaque controller handle.
+pub struct FcController(Controller);
+
+/// Creates a controller starting at `pwm_max` duty.
+///
+/// Returns NULL if the PWM limits are not within 0-100 with `pwm_min <= pwm_max`.
+#[no_mangle]
+pub extern "C" fn fc_controller_new(
+    target_millidegrees: i32,
+    max_millidegrees: i32,
+    pwm_min: u8,
+    pwm_max: u8,
+    pwm_increment: u8,
+    pwm_decrement: u8,
+) -> *mut FcController {
+    let (Some(min), Some(max)) = (Duty::new(pwm_min), Duty::new(pwm_max)) else {
+        return std::ptr::null_mut();
+    };
+    if min > max {
+        return std::ptr::null_mut();
+    }
+
+    let controller = Controller::detached(
+        Celsius::new(0, target_millidegrees),
+        Celsius::new(0, max_millidegrees),
+        min,
+        max,
+        pwm_increment,
+        pwm_decrement,
+    );
+
+    Box::into_raw(Box::new(FcController(controller)))
+}
+
+/// Feeds a temperature reading in millidegrees and returns the duty to apply, in percent.
+///
+/// # Safety
+///
+/// `controller` must be a live pointer returned by `fc_controller_new`.
+#[no_mangle]
+pub unsafe extern "C" fn fc_controller_step(
+    controller: *mut FcController,
+    millidegrees: i32,
+) -> u8 {
+    let controller = &mut (*controller).0;
+    controller
+        .step(Celsius::from_millidegrees(millidegrees))
+        .percent()
+}
+
+/// Changes the temperature to maintain, in millidegrees.
+///
+/// # Safety
+///
+/// `controller` must be a live pointer returned by `fc_controller_new`.
+#[no_mangle]
+pub unsafe extern "C" fn fc_controller_set_target(
+    controller: *mut FcController,
+    millidegrees: i32,
+) {
+    (*controller).0.set_target(Celsius::new(0, millidegrees));
+}
+
+/// Frees a controller. Passing NULL is a no-op.
+///
+/// # Safety
+///
+/// `controller` must be NULL or a pointer returned by `fc_controller_new` not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn fc_controller_free(controller: *mut FcController) {
+    if !controller.is_null() {
+        drop(Box::from_raw(controller));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fc_controller_free, fc_controller_new, fc_controller_set_target, fc_controller_step,
+    };
+
+    #[test]
+    fn rejects_invalid_limits() {
+        assert!(fc_controller_new(40_000, 70_000, 60, 50, 2, 1).is_null());
+        assert!(fc_controller_new(40_000, 70_000, 30, 150, 2, 1).is_null());
+    }
+
+    #[test]
+    fn steps_through_c_abi() {
+        let controller = fc_controller_new(40_000, 70_000, 30, 100, 2, 1);
+        assert!(!controller.is_null());
+
+        unsafe {
+            assert_eq!(99, fc_controller_step(controller, 30_000));
+            assert_eq!(98, fc_controller_step(controller, 31_000));
+            assert_eq!(100, fc_controller_step(controller, 75_000));
+
+            assert_eq!(99, fc_controller_step(controller, 30_000));
+
+            // Lowering the target makes rising readings count as too hot
+            fc_controller_set_target(controller, 20_000);
+            assert_eq!(100, fc_controller_step(controller, 31_000));
+
+            fc_controller_free(controller);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Combined status of every controller advertised on the network.
+
+use crate::{coap, mdns::Instance};
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+/// Latest values of one controller, or why they couldn't be fetched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+    pub address: SocketAddr,
+    pub state: Result<Readings, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Readings {
+    pub temperature: Option<String>,
+    pub target: Option<String>,
+    pub duty: Option<String>,
+    pub alerts: Vec<String>,
+}
+
+/// Fetches the readings of each instance in parallel, waiting at most `timeout` for each answer.
+pub fn query(instances: &[Instance], timeout: Duration) -> Vec<Member> {
+    let mut members: Vec<Member> = thread::scope(|scope| {
+        let queries: Vec<_> = instances
+            .iter()
+            .map(|instance| {
+                let address = SocketAddr::new(instance.address, instance.port);
+                let name = instance.name.clone();
+                scope.spawn(move || Member {
+                    name,
+                    address,
+                    state: readings(address, timeout).map_err(describe),
+                })
+            })
+            .collect();
+        queries
+            .into_iter()
+            .map(|query| query.join().unwrap())
+            .collect()
+    });
+    members.sort_by(|a, b| a.name.cmp(&b.name).then(a.address.cmp(&b.address)));
+    members
+}
+
+fn readings(address: SocketAddr, timeout: Duration) -> io::Result<Readings> {
+    let local: SocketAddr = if address.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    Ok(Readings {
+        temperature: coap::get(&socket, address, "/temperature")?,
+        target: coap::get(&socket, address, "/target")?,
+        duty: coap::get(&socket, address, "/fan")?,
+        alerts: coap::get(&socket, address, "/alerts")?
+            .map(|alerts| alerts.lines().map(str::to_string).collect())
+            .unwrap_or_default(),
+    })
+}
+
+fn describe(error: io::Error) -> String {
+    match error.kind() {
+        // Read timeouts are reported as either, depending on the platform
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => "no answer".to_string(),
+        _ => error.to_string(),
+    }
+}
+
+/// Renders the members as a table with a header, one row each.
+pub fn table(members: &[Member]) -> String {
+    let header = ["NAME", "ADDRESS", "TEMP", "TARGET", "DUTY", "ALERTS"].map(String::from);
+    let mut rows = vec![header];
+    for member in members {
+        let value = |value: &Option<String>, unit: &str| {
+            value
+                .as_ref()
+                .map_or_else(|| "-".to_string(), |value| format!("{}{}", value, unit))
+        };
+        let row = match &member.state {
+            Ok(readings) => [
+                value(&readings.temperature, "°C"),
+                value(&readings.target, "°C"),
+                value(&readings.duty, "%"),
+                if readings.alerts.is_empty() {
+                    "-".to_string()
+                } else {
+                    readings.alerts.join(", ")
+                },
+            ],
+            Err(error) => [
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                format!("unreachable: {}", error),
+            ],
+        };
+        let [temperature, target, duty, alerts] = row;
+        rows.push([
+            member.name.clone(),
+            member.address.to_string(),
+            temperature,
+            target,
+            duty,
+            alerts,
+        ]);
+    }
+
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut text = String::new();
+    for row in &rows {
+        let mut line = String::new();
+        for (cell, width) in row.iter().zip(widths) {
+            line.push_str(cell);
+            line.push_str(&" ".repeat(width - cell.chars().count() + 2));
+        }
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{table, Member, Readings};
+
+    #[test]
+    fn renders_table() {
+        let members = [
+            Member {
+                name: "garage pi".to_string(),
+                address: "192.0.2.7:5683".parse().unwrap(),
+                state: Ok(Readings {
+                    temperature: Some("41.5".to_string()),
+                    target: Some("40".to_string()),
+                    duty: Some("52".to_string()),
+                    alerts: vec!["latched at maximum".to_string()],
+                }),
+            },
+            Member {
+                name: "rack".to_string(),
+                address: "192.0.2.8:5683".parse().unwrap(),
+                state: Err("timed out".to_string()),
+            },
+        ];
+
+        assert_eq!(
+            "\
+NAME       ADDRESS         TEMP    TARGET  DUTY  ALERTS
+garage pi  192.0.2.7:5683  41.5°C  40°C    52%   latched at maximum
+rack       192.0.2.8:5683  -       -       -     unreachable: timed out
+",
+            table(&members)
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Short-horizon temperature forecast from the recent trend, so automations can act before a
+//! limit is crossed.
+
+use crate::units::Celsius;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Expected temperature a while ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Forecast {
+    /// How far ahead the forecast looks
+    pub horizon: Duration,
+    pub temperature: Celsius,
+    /// Current trend per minute
+    pub slope: Celsius,
+    /// Time until the limit is reached at the current trend, `None` if it isn't approaching
+    pub limit_in: Option<Duration>,
+}
+
+/// Fits a line through the samples within the horizon and extends it.
+#[derive(Debug, Clone)]
+pub struct Forecaster {
+    horizon: Duration,
+    limit: Celsius,
+    samples: VecDeque<(Instant, Celsius)>,
+}
+
+impl Forecaster {
+    pub fn new(horizon: Duration, limit: Celsius) -> Self {
+        Self {
+            horizon,
+            limit,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Records a reading, forgetting those older than the horizon.
+    pub fn record(&mut self, at: Instant, temperature: Celsius) {
+        self.samples.push_back((at, temperature));
+        while let Some(&(oldest, _)) = self.samples.front() {
+            if at.duration_since(oldest) <= self.horizon {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Returns the forecast from the latest reading, if there are enough readings for a trend.
+    pub fn forecast(&self) -> Option<Forecast> {
+        let &(start, _) = self.samples.front()?;
+        let &(latest, _) = self.samples.back()?;
+        if latest.duration_since(start).is_zero() {
+            return None;
+        }
+
+        // Least squares fit of millidegrees over seconds since the first sample
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|&(at, temperature)| {
+                (
+                    at.duration_since(start).as_secs_f64(),
+                    f64::from(temperature.millidegrees()),
+                )
+            })
+            .collect();
+        let count = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+        let covariance: f64 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let slope = covariance / variance;
+
+        let now = latest.duration_since(start).as_secs_f64();
+        let current = mean_y + slope * (now - mean_x);
+        let ahead = current + slope * self.horizon.as_secs_f64();
+
+        let limit = f64::from(self.limit.millidegrees());
+        let limit_in = if current >= limit {
+            Some(Duration::ZERO)
+        } else if slope > 0.0 {
+            Some(Duration::from_secs_f64((limit - current) / slope))
+        } else {
+            None
+        };
+
+        Some(Forecast {
+            horizon: self.horizon,
+            temperature: Celsius::from_millidegrees(ahead.round() as i32),
+            slope: Celsius::from_millidegrees((slope * 60.0).round() as i32),
+            limit_in,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Forecaster;
+    use crate::units::Celsius;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn extends_recent_trend() {
+        let start = Instant::now();
+        let mut forecaster = Forecaster::new(Duration::from_secs(300), Celsius::new(70, 0));
+        assert_eq!(None, forecaster.forecast());
+
+        // Old reading outside the horizon must not flatten the trend
+        forecaster.record(start, Celsius::new(20, 0));
+        for minute in 10..=15 {
+            forecaster.record(
+                start + Duration::from_secs(minute * 60),
+                Celsius::new(40 + minute as i32 - 10, 0),
+            );
+        }
+
+        let forecast = forecaster.forecast().unwrap();
+        assert_eq!(Celsius::new(50, 0), forecast.temperature);
+        assert_eq!(Celsius::new(1, 0), forecast.slope);
+        assert_eq!(Some(Duration::from_secs(25 * 60)), forecast.limit_in);
+    }
+
+    #[test]
+    fn falling_temperature_never_reaches_limit() {
+        let start = Instant::now();
+        let mut forecaster = Forecaster::new(Duration::from_secs(300), Celsius::new(70, 0));
+        forecaster.record(start, Celsius::new(50, 0));
+        forecaster.record(start + Duration::from_secs(60), Celsius::new(49, 0));
+
+        assert_eq!(None, forecaster.forecast().unwrap().limit_in);
+    }
+}
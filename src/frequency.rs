@@ -0,0 +1,367 @@
+//! Sweep over hardware PWM frequencies at a fixed duty, noting how the fan responds to each.
+//!
+//! Fans differ in the frequency they run smoothest and quietest at. Without a tachometer the best
+//! judge is the person next to the fan, so each step waits for a note such as the speed read off
+//! a meter or how it sounds.
+
+use crate::{pwm::Output, units::Duty};
+use std::io::{self, BufRead, Write};
+
+/// Clock of the Raspberry Pi PWM peripheral as set up by wiringPi, in Hz
+pub const BASE_CLOCK: u32 = 19_200_000;
+
+/// Frequencies tried when none are given, from what cheap fans expect up to the 25 kHz of 4-pin
+/// fans
+pub const DEFAULT_FREQUENCIES: [u32; 7] = [25, 100, 400, 1_000, 5_000, 10_000, 25_000];
+
+/// Divisors of the base clock giving a PWM frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divisors {
+    /// Divisor of the base clock, 2 to 4095
+    pub clock: u32,
+    /// Clock ticks per PWM period
+    pub range: u32,
+}
+
+impl Divisors {
+    /// Returns the divisors coming closest to `hz`, or `None` if it's out of reach.
+    ///
+    /// The range is kept at 100 ticks or more where possible, so every percent of duty can be set.
+    pub fn for_frequency(hz: u32) -> Option<Self> {
+        let hz = u64::from(hz);
+        if hz == 0 {
+            return None;
+        }
+        let base = u64::from(BASE_CLOCK);
+        let clock = (base / (hz * 100)).clamp(2, 4095);
+        let range = (base + clock * hz / 2) / (clock * hz);
+        (range >= 2).then_some(Self {
+            clock: clock as u32,
+            range: range as u32,
+        })
+    }
+
+    /// Frequency the divisors give, in Hz
+    pub fn frequency(&self) -> u32 {
+        let period = self.clock * self.range;
+        (BASE_CLOCK + period / 2) / period
+    }
+
+    /// Returns the ticks per period the output is high for at a duty.
+    pub fn value(&self, duty: Duty) -> u32 {
+        (self.range * u32::from(duty.percent()) + 50) / 100
+    }
+}
+
+/// Parses frequencies such as `25,1000,25k`, in Hz.
+pub fn parse_frequencies(value: &str) -> Result<Vec<u32>, String> {
+    let invalid = || format!("invalid frequencies {:?}, expected e.g. 25,1000,25k", value);
+
+    value
+        .split(',')
+        .map(|frequency| {
+            let frequency = frequency.trim();
+            let (number, factor) = match frequency.strip_suffix('k') {
+                Some(number) => (number, 1000),
+                None => (frequency, 1),
+            };
+            number
+                .parse::<u32>()
+                .ok()
+                .and_then(|number| number.checked_mul(factor))
+                .filter(|&hz| hz > 0)
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+/// Output whose PWM frequency can be changed.
+pub trait Tunable: Output {
+    /// Switches to the frequency the divisors give, the duty to be written again afterwards.
+    fn tune(&mut self, divisors: Divisors);
+}
+
+/// Note taken at a frequency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    /// Frequency actually reached, in Hz
+    pub frequency: u32,
+    pub text: String,
+}
+
+/// Frequencies to try and the duty to hold at each.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sweep {
+    pub frequencies: Vec<u32>,
+    pub duty: Duty,
+}
+
+impl Sweep {
+    /// Runs the sweep, asking for a note on `prompt` at each frequency and reading it from
+    /// `input`, and returns the notes taken.
+    ///
+    /// Frequencies out of reach are skipped. An empty line moves on without a note, while `q` or
+    /// the end of input stops the sweep. However it ends, the fan is left at full speed.
+    pub fn run(
+        &self,
+        output: &mut dyn Tunable,
+        input: &mut dyn BufRead,
+        prompt: &mut dyn Write,
+    ) -> io::Result<Vec<Note>> {
+        let notes = self.sweep_frequencies(output, input, prompt);
+        output.write(Duty::FULL);
+        notes
+    }
+
+    fn sweep_frequencies(
+        &self,
+        output: &mut dyn Tunable,
+        input: &mut dyn BufRead,
+        prompt: &mut dyn Write,
+    ) -> io::Result<Vec<Note>> {
+        let mut notes = Vec::new();
+
+        for &hz in &self.frequencies {
+            let Some(divisors) = Divisors::for_frequency(hz) else {
+                log::warn!("Skipping {} Hz, out of reach of the PWM clock", hz);
+                continue;
+            };
+            output.tune(divisors);
+            output.write(self.duty);
+
+            let frequency = divisors.frequency();
+            write!(
+                prompt,
+                "{} Hz at {}%, note (empty to skip, q to quit): ",
+                frequency, self.duty
+            )?;
+            prompt.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+            match line.trim() {
+                "q" => break,
+                "" => {}
+                text => notes.push(Note {
+                    frequency,
+                    text: text.to_string(),
+                }),
+            }
+        }
+        Ok(notes)
+    }
+}
+
+/// Writes notes as CSV with a header line, quoting the notes.
+pub fn write_csv(notes: &[Note], writer: &mut dyn Write) -> io::Result<()> {
+    writeln!(writer, "frequency,note")?;
+    for note in notes {
+        writeln!(
+            writer,
+            "{},\"{}\"",
+            note.frequency,
+            note.text.replace('"', "\"\"")
+        )?;
+    }
+    Ok(())
+}
+
+/// wiringPi hardware PWM on one of the pins wired to the PWM peripheral, e.g. wiringPi pin 1.
+#[cfg(feature = "wiringpi")]
+pub struct HardPwm {
+    pin: i32,
+    divisors: Divisors,
+}
+
+#[cfg(feature = "wiringpi")]
+mod hardware {
+    use libc::{c_int, c_uint};
+
+    #[link(name = "wiringPi")]
+    extern "C" {
+        pub fn wiringPiSetup() -> c_int;
+        pub fn pinMode(pin: c_int, mode: c_int);
+        pub fn pwmSetMode(mode: c_int);
+        pub fn pwmSetClock(divisor: c_int);
+        pub fn pwmSetRange(range: c_uint);
+        pub fn pwmWrite(pin: c_int, value: c_int);
+    }
+    pub const INPUT: c_int = 0;
+    pub const PWM_OUTPUT: c_int = 2;
+    /// Mark-space mode, giving a steady high and low in each period
+    pub const PWM_MODE_MS: c_int = 0;
+}
+
+#[cfg(feature = "wiringpi")]
+impl HardPwm {
+    /// Returns hardware PWM on the pin, starting at the last of the default frequencies.
+    pub fn new(pin: i32) -> Self {
+        Self {
+            pin,
+            divisors: Divisors::for_frequency(DEFAULT_FREQUENCIES[DEFAULT_FREQUENCIES.len() - 1])
+                .unwrap(),
+        }
+    }
+}
+
+#[cfg(feature = "wiringpi")]
+impl Output for HardPwm {
+    fn init(&mut self) {
+        unsafe {
+            hardware::wiringPiSetup();
+            hardware::pinMode(self.pin, hardware::PWM_OUTPUT);
+            hardware::pwmSetMode(hardware::PWM_MODE_MS);
+        }
+        self.tune(self.divisors);
+        self.write(Duty::FULL);
+    }
+
+    fn write(&mut self, duty: Duty) {
+        unsafe {
+            hardware::pwmWrite(self.pin, self.divisors.value(duty) as i32);
+        }
+    }
+
+    /// Returns the pin to an input, which 4-pin fans take as full speed.
+    fn shutdown(&mut self) {
+        unsafe {
+            hardware::pinMode(self.pin, hardware::INPUT);
+        }
+    }
+}
+
+#[cfg(feature = "wiringpi")]
+impl Tunable for HardPwm {
+    fn tune(&mut self, divisors: Divisors) {
+        self.divisors = divisors;
+        unsafe {
+            hardware::pwmSetClock(divisors.clock as i32);
+            hardware::pwmSetRange(divisors.range);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_frequencies, write_csv, Divisors, Note, Sweep, Tunable};
+    use crate::{
+        mock::MockOutput,
+        pwm::{tests::duty, Output},
+        units::Duty,
+    };
+
+    /// Output recording the frequencies tuned to alongside the duties written
+    struct Tuned {
+        output: MockOutput,
+        frequencies: Vec<u32>,
+    }
+
+    impl Output for Tuned {
+        fn init(&mut self) {}
+
+        fn write(&mut self, duty: Duty) {
+            self.output.write(duty);
+        }
+
+        fn shutdown(&mut self) {}
+    }
+
+    impl Tunable for Tuned {
+        fn tune(&mut self, divisors: Divisors) {
+            self.frequencies.push(divisors.frequency());
+        }
+    }
+
+    #[test]
+    fn finds_divisors_for_frequencies() {
+        let fan = Divisors::for_frequency(25_000).unwrap();
+        assert_eq!(
+            Divisors {
+                clock: 7,
+                range: 110
+            },
+            fan
+        );
+        assert_eq!(24_935, fan.frequency());
+        assert_eq!(55, fan.value(duty(50)));
+
+        let slow = Divisors::for_frequency(25).unwrap();
+        assert_eq!(4095, slow.clock);
+        assert_eq!(25, slow.frequency());
+
+        assert_eq!(None, Divisors::for_frequency(0));
+        assert_eq!(None, Divisors::for_frequency(10_000_000));
+    }
+
+    #[test]
+    fn parses_frequencies() {
+        assert_eq!(
+            Ok(vec![25, 1000, 25_000]),
+            parse_frequencies("25, 1000,25k")
+        );
+        assert!(parse_frequencies("").is_err());
+        assert!(parse_frequencies("0").is_err());
+        assert!(parse_frequencies("fast").is_err());
+    }
+
+    #[test]
+    fn notes_each_frequency_until_quit() {
+        let output = MockOutput::new();
+        let mut tuned = Tuned {
+            output: output.clone(),
+            frequencies: Vec::new(),
+        };
+        let sweep = Sweep {
+            frequencies: vec![100, 10_000_000, 1000, 25_000, 5000],
+            duty: duty(40),
+        };
+        let mut prompt = Vec::new();
+
+        let notes = sweep
+            .run(
+                &mut tuned,
+                &mut "ticking\n\nquiet, 900 rpm\nq\n".as_bytes(),
+                &mut prompt,
+            )
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                Note {
+                    frequency: 100,
+                    text: "ticking".to_string()
+                },
+                Note {
+                    frequency: 24_935,
+                    text: "quiet, 900 rpm".to_string()
+                },
+            ],
+            notes
+        );
+        assert_eq!(vec![100, 1000, 24_935, 5003], tuned.frequencies);
+        assert_eq!(
+            vec![duty(40), duty(40), duty(40), duty(40), Duty::FULL],
+            output.writes()
+        );
+        assert!(String::from_utf8(prompt)
+            .unwrap()
+            .starts_with("100 Hz at 40%, note"));
+    }
+
+    #[test]
+    fn writes_csv() {
+        let mut csv = Vec::new();
+        let notes = [Note {
+            frequency: 24_935,
+            text: "quiet, \"smooth\"".to_string(),
+        }];
+
+        write_csv(&notes, &mut csv).unwrap();
+        assert_eq!(
+            "frequency,note\n24935,\"quiet, \"\"smooth\"\"\"\n",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+}
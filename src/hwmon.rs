@@ -0,0 +1,203 @@
+//! hwmon temperature inputs found by chip name and channel, e.g. `cpu_thermal/temp1` or
+//! `nvme/Composite`, rather than by their `hwmonN` directory.
+//!
+//! hwmon devices are numbered in probe order, which can change from one boot to the next, so a
+//! path like `hwmon2/temp1_input` can end up pointing at another chip.
+
+use crate::{
+    sensor::{FileSensor, Raw, Sensor, SensorError},
+    units::Celsius,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Directory the kernel lists hwmon devices in
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+/// Returns the number after a prefix of a file name, e.g. 10 of `hwmon10`.
+fn number(path: &Path, prefix: &str) -> Option<u32> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix(prefix)?
+        .split('_')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Returns the paths in a directory whose names are a prefix followed by a number, in numeric
+/// order.
+pub(crate) fn numbered(directory: &Path, prefix: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| number(path, prefix).is_some())
+        .collect();
+    // hwmon10 sorts before hwmon2 as text, so compare the numbers
+    paths.sort_by_key(|path| number(path, prefix));
+    paths
+}
+
+/// Returns the input file of a channel of the first chip of a name under `root`.
+///
+/// The channel is either the input's own name such as `temp1`, or the content of its label file
+/// such as `Composite`.
+pub fn find_input(root: &Path, chip: &str, channel: &str) -> Option<PathBuf> {
+    let read = |path: PathBuf| fs::read_to_string(path).map(|content| content.trim().to_string());
+
+    numbered(root, "hwmon")
+        .into_iter()
+        .filter(|device| read(device.join("name")).is_ok_and(|name| name == chip))
+        .find_map(|device| {
+            numbered(&device, "temp")
+                .into_iter()
+                .filter_map(|input| {
+                    let file = input.file_name()?.to_str()?;
+                    file.strip_suffix("_input").map(str::to_string)
+                })
+                .find(|input| {
+                    input == channel
+                        || read(device.join(format!("{}_label", input)))
+                            .is_ok_and(|label| label == channel)
+                })
+                .map(|input| device.join(format!("{}_input", input)))
+        })
+}
+
+/// hwmon channel of a chip, looked up when first read and again after it couldn't be read.
+pub struct HwmonSensor {
+    chip: String,
+    channel: String,
+    root: PathBuf,
+    /// Input file of the channel found, until reading it fails
+    file: Option<FileSensor>,
+    /// Input last read from, to tell when the lookup lands somewhere new
+    last: Option<PathBuf>,
+}
+
+impl HwmonSensor {
+    pub fn new(chip: &str, channel: &str) -> Self {
+        Self::with_root(chip, channel, Path::new(HWMON_ROOT))
+    }
+
+    /// Returns a sensor looking for chips under another directory, e.g. in tests.
+    pub fn with_root(chip: &str, channel: &str, root: &Path) -> Self {
+        Self {
+            chip: chip.to_string(),
+            channel: channel.to_string(),
+            root: root.to_path_buf(),
+            file: None,
+            last: None,
+        }
+    }
+
+    fn resolve(&mut self) -> Result<&mut FileSensor, SensorError> {
+        if self.file.is_none() {
+            let path = find_input(&self.root, &self.chip, &self.channel).ok_or_else(|| {
+                SensorError::Read(
+                    format!("hwmon channel {}/{}", self.chip, self.channel),
+                    io::Error::new(io::ErrorKind::NotFound, "no such chip or channel"),
+                )
+            })?;
+            if self.last.as_ref() != Some(&path) {
+                log::info!(
+                    "Reading hwmon channel {}/{} from {}",
+                    self.chip,
+                    self.channel,
+                    path.display()
+                );
+                self.last = Some(path.clone());
+            }
+            self.file = Some(FileSensor::new(&path.to_string_lossy()));
+        }
+        Ok(self.file.as_mut().expect("channel was just resolved"))
+    }
+}
+
+impl Sensor for HwmonSensor {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        let reading = self.resolve()?.read();
+        if reading.is_err() {
+            // Look the channel up again next time, in case the chip was renumbered
+            self.file = None;
+        }
+        reading
+    }
+
+    fn raw(&self) -> Option<Raw> {
+        self.file.as_ref().and_then(FileSensor::raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_input, HwmonSensor};
+    use crate::{mock::TempPath, sensor::Sensor, units::Celsius};
+    use std::{fs, path::Path};
+
+    fn chip(root: &Path, number: u32, name: &str, inputs: &[(u32, Option<&str>, i32)]) {
+        let device = root.join(format!("hwmon{}", number));
+        fs::create_dir_all(&device).unwrap();
+        fs::write(device.join("name"), format!("{}\n", name)).unwrap();
+        for (channel, label, millidegrees) in inputs {
+            let input = device.join(format!("temp{}_input", channel));
+            fs::write(input, format!("{}\n", millidegrees)).unwrap();
+            if let Some(label) = label {
+                fs::write(device.join(format!("temp{}_label", channel)), label).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn finds_channels_by_name_or_label() {
+        let root = TempPath::dir("hwmon-find");
+        chip(&root, 0, "cpu_thermal", &[(1, None, 45000)]);
+        chip(
+            &root,
+            10,
+            "nvme",
+            &[
+                (1, Some("Composite\n"), 38000),
+                (2, Some("Sensor 1"), 41000),
+            ],
+        );
+
+        assert_eq!(
+            Some(root.join("hwmon0").join("temp1_input")),
+            find_input(&root, "cpu_thermal", "temp1")
+        );
+        assert_eq!(
+            Some(root.join("hwmon10").join("temp1_input")),
+            find_input(&root, "nvme", "Composite")
+        );
+        assert_eq!(
+            Some(root.join("hwmon10").join("temp2_input")),
+            find_input(&root, "nvme", "temp2")
+        );
+        assert_eq!(None, find_input(&root, "nvme", "temp3"));
+        assert_eq!(None, find_input(&root, "rp1_adc", "temp1"));
+    }
+
+    #[test]
+    fn follows_a_chip_across_renumbering() {
+        let root = TempPath::dir("hwmon");
+        chip(&root, 0, "rp1_adc", &[(1, None, 30000)]);
+        chip(&root, 1, "cpu_thermal", &[(1, None, 45000)]);
+
+        let mut sensor = HwmonSensor::with_root("cpu_thermal", "temp1", &root);
+        assert_eq!(Celsius::new(45, 0), sensor.read().unwrap());
+
+        // The driver is rebound and its chip comes back under another number
+        fs::remove_dir_all(root.join("hwmon1")).unwrap();
+        chip(&root, 2, "cpu_thermal", &[(1, None, 50000)]);
+        assert!(sensor.read().is_err());
+        assert_eq!(Celsius::new(50, 0), sensor.read().unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+        assert!(sensor.read().is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! Translations of the setup wizard and colored console output.
+//!
+//! Messages live in Fluent files under `locales/`, one per language. Messages missing from a
+//! translation fall back to English. Logs stay in English, so they read the same to whoever is
+//! asked for help with them.
+
+use clap::ValueEnum;
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        OnceLock,
+    },
+};
+use unic_langid::LanguageIdentifier;
+
+/// Language messages are shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Language {
+    #[default]
+    En,
+    De,
+    Fi,
+}
+
+/// All languages, in the order of their discriminants
+const LANGUAGES: [Language; 3] = [Language::En, Language::De, Language::Fi];
+
+/// Language in effect, as its discriminant
+static LANGUAGE: AtomicU8 = AtomicU8::new(Language::En as u8);
+
+/// Messages of each language, by the discriminant, parsed on first use
+static BUNDLES: OnceLock<Vec<Bundle>> = OnceLock::new();
+
+type Bundle = FluentBundle<FluentResource>;
+
+impl Language {
+    /// Returns the language of a POSIX locale such as `de_DE.UTF-8`, if it's translated.
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let code = locale.split(['_', '.', '@']).next()?;
+        Self::from_str(code, true).ok()
+    }
+
+    /// Returns the language of the locale set in the environment, English if it isn't
+    /// translated.
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| Self::from_locale(&locale))
+            .unwrap_or_default()
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::De => "de",
+            Language::Fi => "fi",
+        }
+    }
+
+    fn resource(self) -> &'static str {
+        match self {
+            Language::En => include_str!("../locales/en.ftl"),
+            Language::De => include_str!("../locales/de.ftl"),
+            Language::Fi => include_str!("../locales/fi.ftl"),
+        }
+    }
+
+    /// Returns the messages of the language, logging any entries of its file that don't parse,
+    /// which are left out.
+    fn bundle(self) -> Bundle {
+        let resource = FluentResource::try_new(self.resource().to_string()).unwrap_or_else(
+            |(resource, errors)| {
+                for error in errors {
+                    log::error!(
+                        "Malformed message in locales/{}.ftl: {}",
+                        self.code(),
+                        error
+                    );
+                }
+                resource
+            },
+        );
+        let code: LanguageIdentifier = self.code().parse().expect("language codes are valid");
+        let mut bundle = FluentBundle::new_concurrent(vec![code]);
+        // Isolation marks around placeables show up as stray characters on terminals
+        bundle.set_use_isolating(false);
+        if let Err(errors) = bundle.add_resource(resource) {
+            for error in errors {
+                log::error!("Invalid message in locales/{}.ftl: {}", self.code(), error);
+            }
+        }
+        bundle
+    }
+}
+
+/// Replaces the language in effect.
+pub fn set_language(language: Language) {
+    LANGUAGE.store(language as u8, Ordering::Relaxed);
+}
+
+/// Returns the language in effect.
+pub fn language() -> Language {
+    LANGUAGES[usize::from(LANGUAGE.load(Ordering::Relaxed))]
+}
+
+/// Returns a message in the language in effect, with its placeables filled from `args`.
+pub fn text(id: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    translate(language(), id, args)
+}
+
+/// Returns a message in the given language, in English if it isn't translated, or its id if it
+/// doesn't exist at all.
+///
+/// Placeables without a value are left in as their variable name, e.g. `{$duty}`.
+pub fn translate(language: Language, id: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let bundles =
+        BUNDLES.get_or_init(|| LANGUAGES.iter().map(|language| language.bundle()).collect());
+    let message = [language, Language::En].iter().find_map(|language| {
+        let bundle = &bundles[*language as usize];
+        Some((bundle, bundle.get_message(id)?.value()?))
+    });
+    let Some((bundle, pattern)) = message else {
+        return id.to_string();
+    };
+
+    let mut values = FluentArgs::new();
+    for (name, value) in args {
+        values.set(*name, value.to_string());
+    }
+    let mut errors = Vec::new();
+    bundle
+        .format_pattern(pattern, Some(&values), &mut errors)
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{translate, Language, LANGUAGES};
+    use fluent_bundle::FluentResource;
+    use fluent_syntax::ast::Entry;
+    use std::{collections::BTreeSet, fs, path::Path};
+
+    /// Returns the names of a message's placeables.
+    fn placeables(message: &str) -> BTreeSet<&str> {
+        message
+            .split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}'))
+            .map(|(name, _)| name.trim())
+            .collect()
+    }
+
+    /// Returns the ids of the messages of a Fluent file, failing on entries that don't parse.
+    fn message_ids(path: &Path) -> BTreeSet<String> {
+        let content = fs::read_to_string(path).unwrap();
+        let resource = FluentResource::try_new(content)
+            .unwrap_or_else(|(_, errors)| panic!("{}: {:?}", path.display(), errors));
+        resource
+            .entries()
+            .filter_map(|entry| match entry {
+                Entry::Message(message) => Some(message.id.name.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shipped_translations_parse_and_match_english() {
+        let locales = Path::new(env!("CARGO_MANIFEST_DIR")).join("locales");
+        let english = message_ids(&locales.join("en.ftl"));
+        let mut files = 0;
+        for entry in fs::read_dir(&locales).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|extension| extension == "ftl") {
+                assert_eq!(english, message_ids(&path), "{}", path.display());
+                files += 1;
+            }
+        }
+        assert_eq!(LANGUAGES.len(), files);
+
+        // Without values, placeables are left in by name
+        for language in LANGUAGES {
+            for id in &english {
+                let message = translate(language, id, &[]);
+                assert_eq!(
+                    placeables(&translate(Language::En, id, &[])),
+                    placeables(&message),
+                    "{:?} {}",
+                    language,
+                    id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fills_placeables_and_falls_back() {
+        assert_eq!(
+            "Dreht sich der Lüfter bei 40 % noch?",
+            translate(Language::De, "setup-spinning-at", &[("duty", &40)])
+        );
+        assert_eq!(
+            "Is the fan still spinning at {$duty}%?",
+            translate(Language::En, "setup-spinning-at", &[])
+        );
+        assert_eq!(
+            "no-such-message",
+            translate(Language::Fi, "no-such-message", &[])
+        );
+    }
+
+    #[test]
+    fn language_from_locale() {
+        assert_eq!(Some(Language::De), Language::from_locale("de_DE.UTF-8"));
+        assert_eq!(Some(Language::Fi), Language::from_locale("fi"));
+        assert_eq!(None, Language::from_locale("C"));
+        assert_eq!(None, Language::from_locale("sv_SE"));
+    }
+}
@@ -0,0 +1,235 @@
+//! Conversion of lm-sensors `fancontrol` configurations, as written by `pwmconfig`, into a config
+//! file for this crate, easing migration on x86 systems.
+
+use crate::units::Celsius;
+use std::collections::HashMap;
+
+/// Directory fancontrol paths are relative to
+const HWMON: &str = "/sys/class/hwmon";
+
+/// Fan controlled by fancontrol, with speeds as raw duties out of 255.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fan {
+    /// PWM file the fan is driven through, e.g. `hwmon1/pwm1`
+    pub pwm: String,
+    /// Temperature file the fan follows
+    pub temperature: String,
+    /// Temperature below which the fan runs at `min_pwm`
+    pub min_temp: Celsius,
+    /// Temperature at and above which the fan runs at `max_pwm`
+    pub max_temp: Celsius,
+    /// Lowest duty the fan keeps spinning at, where the curve starts
+    pub min_stop: u8,
+    pub min_pwm: u8,
+    pub max_pwm: u8,
+}
+
+/// Settings of a fancontrol configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fancontrol {
+    /// Seconds between readings
+    pub interval: Option<u64>,
+    pub fans: Vec<Fan>,
+}
+
+/// Parses a fancontrol configuration such as `/etc/fancontrol`.
+pub fn parse_fancontrol(text: &str) -> Result<Fancontrol, String> {
+    let mut settings: HashMap<&str, &str> = HashMap::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("invalid line {:?}", line))?;
+        settings.insert(key.trim(), value.trim());
+    }
+
+    // Per-fan settings are lists of pwm=value pairs
+    let per_fan = |key: &str| -> Result<HashMap<&str, &str>, String> {
+        settings
+            .get(key)
+            .map(|value| {
+                value
+                    .split_whitespace()
+                    .map(|pair| {
+                        pair.split_once('=')
+                            .ok_or_else(|| format!("invalid {} entry {:?}", key, pair))
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(|| Ok(HashMap::new()))
+    };
+    let temps = per_fan("FCTEMPS")?;
+    let (min_temps, max_temps) = (per_fan("MINTEMP")?, per_fan("MAXTEMP")?);
+    let (min_stops, min_pwms, max_pwms) =
+        (per_fan("MINSTOP")?, per_fan("MINPWM")?, per_fan("MAXPWM")?);
+
+    let mut fans = Vec::new();
+    for pwm in settings
+        .get("FCTEMPS")
+        .ok_or("no FCTEMPS, no fans are controlled")?
+        .split_whitespace()
+        .filter_map(|pair| pair.split_once('=').map(|(pwm, _)| pwm))
+    {
+        let degrees = |values: &HashMap<&str, &str>, key: &str| -> Result<Celsius, String> {
+            let value = setting(values, pwm, key, None)?;
+            value
+                .parse()
+                .map_err(|_| format!("invalid {} {:?} for {}", key, value, pwm))
+        };
+        let raw = |values: &HashMap<&str, &str>, key: &str, default| -> Result<u8, String> {
+            let value = setting(values, pwm, key, default)?;
+            value
+                .parse()
+                .map_err(|_| format!("invalid {} {:?} for {}", key, value, pwm))
+        };
+
+        fans.push(Fan {
+            pwm: pwm.to_string(),
+            temperature: setting(&temps, pwm, "FCTEMPS", None)?.to_string(),
+            min_temp: degrees(&min_temps, "MINTEMP")?,
+            max_temp: degrees(&max_temps, "MAXTEMP")?,
+            min_stop: raw(&min_stops, "MINSTOP", None)?,
+            min_pwm: raw(&min_pwms, "MINPWM", Some("0"))?,
+            max_pwm: raw(&max_pwms, "MAXPWM", Some("255"))?,
+        });
+    }
+
+    let interval = match settings.get("INTERVAL") {
+        Some(value) => Some(
+            value
+                .parse()
+                .map_err(|_| format!("invalid INTERVAL {:?}", value))?,
+        ),
+        None => None,
+    };
+    Ok(Fancontrol { interval, fans })
+}
+
+/// Returns the setting for a fan, or the default if it has none.
+fn setting<'a>(
+    values: &HashMap<&str, &'a str>,
+    pwm: &str,
+    key: &str,
+    default: Option<&'a str>,
+) -> Result<&'a str, String> {
+    values
+        .get(pwm)
+        .copied()
+        .or(default)
+        .ok_or_else(|| format!("no {} for {}", key, pwm))
+}
+
+/// Returns the equivalent config file, controlling the first fan by a fan curve.
+///
+/// Settings without an equivalent, such as the hwmon PWM file, are explained in comments.
+pub fn to_config(fancontrol: &Fancontrol, source: &str) -> String {
+    let mut config = format!("# Imported from {}\n", source);
+    let Some(fan) = fancontrol.fans.first() else {
+        return config;
+    };
+
+    config.push_str(&format!(
+        "# {} has no equivalent output, set gpio-pwm, serial-port or mcp23017-pin instead\n",
+        fan.pwm
+    ));
+    if fan.min_pwm > 0 && fan.min_pwm < fan.min_stop {
+        config.push_str(&format!(
+            "# Below {}°C fancontrol ran the fan at {}/255, here it keeps running at pwm-min\n",
+            fan.min_temp, fan.min_pwm
+        ));
+    }
+    for skipped in &fancontrol.fans[1..] {
+        config.push_str(&format!(
+            "# {} is skipped, only one fan is controlled\n",
+            skipped.pwm
+        ));
+    }
+    config.push('\n');
+
+    if let Some(interval) = fancontrol.interval {
+        config.push_str(&format!("pollrate = {}\n", interval));
+    }
+    config.push_str(&format!(
+        "temperature-file-path = \"{}/{}\"\n",
+        HWMON, fan.temperature
+    ));
+    config.push_str(&format!(
+        "fan-curve = \"{}:{}/255,{}:{}/255\"\n",
+        fan.min_temp, fan.min_stop, fan.max_temp, fan.max_pwm
+    ));
+    config.push_str(&format!("pwm-min = \"{}/255\"\n", fan.min_stop));
+    config.push_str(&format!("pwm-max = \"{}/255\"\n", fan.max_pwm));
+    if fan.min_pwm == 0 {
+        // fancontrol stops the fan below MINTEMP and starts it again right above
+        config.push_str(&format!("fan-off-below = \"{}\"\n", fan.min_temp));
+        config.push_str(&format!("fan-restart-above = \"{}\"\n", fan.min_temp));
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_fancontrol, to_config};
+    use crate::units::Celsius;
+
+    const FANCONTROL: &str = "\
+# Configuration file generated by pwmconfig
+INTERVAL=10
+DEVPATH=hwmon1=devices/platform/it87.2624
+DEVNAME=hwmon1=it8721
+FCTEMPS=hwmon1/pwm1=hwmon1/temp1_input hwmon1/pwm2=hwmon1/temp2_input
+FCFANS=hwmon1/pwm1=hwmon1/fan1_input hwmon1/pwm2=hwmon1/fan2_input
+MINTEMP=hwmon1/pwm1=40 hwmon1/pwm2=35
+MAXTEMP=hwmon1/pwm1=60 hwmon1/pwm2=55
+MINSTART=hwmon1/pwm1=150 hwmon1/pwm2=120
+MINSTOP=hwmon1/pwm1=100 hwmon1/pwm2=80
+MAXPWM=hwmon1/pwm1=230
+";
+
+    #[test]
+    fn parses_fans() {
+        let fancontrol = parse_fancontrol(FANCONTROL).unwrap();
+
+        assert_eq!(Some(10), fancontrol.interval);
+        assert_eq!(2, fancontrol.fans.len());
+        let fan = &fancontrol.fans[0];
+        assert_eq!("hwmon1/pwm1", fan.pwm);
+        assert_eq!("hwmon1/temp1_input", fan.temperature);
+        assert_eq!(Celsius::new(40, 0), fan.min_temp);
+        assert_eq!(Celsius::new(60, 0), fan.max_temp);
+        assert_eq!((100, 0, 230), (fan.min_stop, fan.min_pwm, fan.max_pwm));
+        assert_eq!(255, fancontrol.fans[1].max_pwm);
+    }
+
+    #[test]
+    fn rejects_incomplete_configs() {
+        assert!(parse_fancontrol("INTERVAL=10\n").is_err());
+        assert!(parse_fancontrol("FCTEMPS=hwmon1/pwm1=hwmon1/temp1_input\n").is_err());
+        assert!(parse_fancontrol("INTERVAL\n").is_err());
+    }
+
+    #[test]
+    fn converts_first_fan_to_curve() {
+        let fancontrol = parse_fancontrol(FANCONTROL).unwrap();
+
+        assert_eq!(
+            "\
+# Imported from /etc/fancontrol
+# hwmon1/pwm1 has no equivalent output, set gpio-pwm, serial-port or mcp23017-pin instead
+# hwmon1/pwm2 is skipped, only one fan is controlled
+
+pollrate = 10
+temperature-file-path = \"/sys/class/hwmon/hwmon1/temp1_input\"
+fan-curve = \"40:100/255,60:230/255\"
+pwm-min = \"100/255\"
+pwm-max = \"230/255\"
+fan-off-below = \"40\"
+fan-restart-above = \"40\"
+",
+            to_config(&fancontrol, "/etc/fancontrol")
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Delay inhibitor keeping suspend and shutdown waiting until the fan is parked.
+//!
+//! Uses `systemd-inhibit` to hold a delay lock and `dbus-monitor` to learn from logind when the
+//! system is about to go down, so no D-Bus library is needed.
+
+use std::{
+    io::{self, BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// Power state change announced by logind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// System is about to suspend or hibernate
+    Sleep,
+    /// System is about to power off or reboot
+    Shutdown,
+    /// System resumed, or a shutdown was cancelled
+    Resume,
+}
+
+/// Turns `dbus-monitor` output into transitions.
+///
+/// Signals are printed as a header line naming the member, followed by its arguments.
+#[derive(Debug, Default)]
+pub struct MonitorParser {
+    /// Transition announced by the latest header, if its argument is true
+    pending: Option<Transition>,
+}
+
+impl MonitorParser {
+    pub fn line(&mut self, line: &str) -> Option<Transition> {
+        if line.starts_with("signal ") {
+            self.pending = if line.contains("member=PrepareForSleep") {
+                Some(Transition::Sleep)
+            } else if line.contains("member=PrepareForShutdown") {
+                Some(Transition::Shutdown)
+            } else {
+                None
+            };
+            return None;
+        }
+
+        let transition = self.pending.take()?;
+        match line.trim() {
+            "boolean true" => Some(transition),
+            "boolean false" => Some(Transition::Resume),
+            _ => None,
+        }
+    }
+}
+
+/// Delay lock on suspend and shutdown, with the logind announcements that should release it.
+pub struct Inhibitor {
+    lock: Option<Child>,
+    monitor: Child,
+    transitions: Receiver<Transition>,
+}
+
+impl Inhibitor {
+    /// Takes the lock and starts watching for transitions.
+    pub fn start() -> io::Result<Self> {
+        let mut monitor = Command::new("dbus-monitor")
+            .args([
+                "--system",
+                "type='signal',sender='org.freedesktop.login1',interface='org.freedesktop.login1.Manager'",
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdout = monitor.stdout.take().unwrap();
+        let (sender, transitions) = mpsc::channel();
+        thread::spawn(move || {
+            let mut parser = MonitorParser::default();
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if let Some(transition) = parser.line(&line) {
+                    if sender.send(transition).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut inhibitor = Self {
+            lock: None,
+            monitor,
+            transitions,
+        };
+        inhibitor.acquire()?;
+        Ok(inhibitor)
+    }
+
+    /// Returns the next transition announced since the last call, if any.
+    pub fn pending(&self) -> Option<Transition> {
+        self.transitions.try_recv().ok()
+    }
+
+    /// Takes the lock again, after resuming.
+    pub fn acquire(&mut self) -> io::Result<()> {
+        if self.lock.is_none() {
+            let lock = Command::new("systemd-inhibit")
+                .args([
+                    "--what=sleep:shutdown",
+                    "--mode=delay",
+                    "--who=fan-controller",
+                    "--why=Parking the fan",
+                    "sleep",
+                    "infinity",
+                ])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .spawn()?;
+            self.lock = Some(lock);
+        }
+        Ok(())
+    }
+
+    /// Drops the lock, letting the pending transition go ahead.
+    pub fn release(&mut self) {
+        if let Some(mut lock) = self.lock.take() {
+            let _ = lock.kill();
+            let _ = lock.wait();
+        }
+    }
+}
+
+impl Drop for Inhibitor {
+    fn drop(&mut self) {
+        self.release();
+        let _ = self.monitor.kill();
+        let _ = self.monitor.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MonitorParser, Transition};
+
+    #[test]
+    fn parses_logind_signals() {
+        let output = "\
+signal time=1700000000.1 sender=org.freedesktop.DBus -> destination=:1.42 serial=2 path=/org/freedesktop/DBus; interface=org.freedesktop.DBus; member=NameAcquired
+   string \":1.42\"
+signal time=1700000001.2 sender=:1.3 -> destination=(null destination) serial=811 path=/org/freedesktop/login1; interface=org.freedesktop.login1.Manager; member=PrepareForSleep
+   boolean true
+signal time=1700000060.3 sender=:1.3 -> destination=(null destination) serial=812 path=/org/freedesktop/login1; interface=org.freedesktop.login1.Manager; member=PrepareForSleep
+   boolean false
+signal time=1700000090.4 sender=:1.3 -> destination=(null destination) serial=813 path=/org/freedesktop/login1; interface=org.freedesktop.login1.Manager; member=PrepareForShutdown
+   boolean true
+";
+        let mut parser = MonitorParser::default();
+        let transitions: Vec<Transition> = output
+            .lines()
+            .filter_map(|line| parser.line(line))
+            .collect();
+
+        assert_eq!(
+            vec![Transition::Sleep, Transition::Resume, Transition::Shutdown],
+            transitions
+        );
+    }
+}
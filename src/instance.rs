@@ -0,0 +1,73 @@
+//! Namespacing for several controllers on one host, e.g. one per enclosure, so their sockets,
+//! files and advertisements don't collide even when they share a base config.
+
+use std::path::{Path, PathBuf};
+
+/// Parses an instance name, limited to characters safe in file names and mDNS service names.
+pub fn parse_name(value: &str) -> Result<String, String> {
+    let valid = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!(
+            "invalid instance name {:?}, expected letters, digits, - or _, e.g. rack-1",
+            value
+        ));
+    }
+    Ok(value.to_string())
+}
+
+/// Returns the path with the instance name appended to the file name, before any extension,
+/// e.g. `/run/fan-controller.sock` as `/run/fan-controller-rack-1.sock`.
+pub fn namespaced(path: &Path, instance: Option<&str>) -> PathBuf {
+    let (Some(instance), Some(stem)) = (instance, path.file_stem()) else {
+        return path.to_path_buf();
+    };
+    let mut name = stem.to_os_string();
+    name.push("-");
+    name.push(instance);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Returns the name this host's controller is advertised under, e.g. `pi-rack-1`.
+pub fn service_name(hostname: &str, instance: Option<&str>) -> String {
+    match instance {
+        Some(instance) => format!("{}-{}", hostname, instance),
+        None => hostname.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{namespaced, parse_name};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn names_are_file_name_safe() {
+        assert_eq!(Ok("rack-1".to_string()), parse_name("rack-1"));
+        assert!(parse_name("").is_err());
+        assert!(parse_name("../rack").is_err());
+        assert!(parse_name("rack 1").is_err());
+    }
+
+    #[test]
+    fn name_goes_before_extension() {
+        assert_eq!(
+            PathBuf::from("/run/fan-controller-rack-1.sock"),
+            namespaced(Path::new("/run/fan-controller.sock"), Some("rack-1"))
+        );
+        assert_eq!(
+            PathBuf::from("/var/lib/fan-controller/spool-rack-1"),
+            namespaced(Path::new("/var/lib/fan-controller/spool"), Some("rack-1"))
+        );
+        assert_eq!(
+            PathBuf::from("/run/fan-controller.sock"),
+            namespaced(Path::new("/run/fan-controller.sock"), None)
+        );
+    }
+}
@@ -0,0 +1,27 @@
+//! Ctrl-C handling for operations that must leave the fan in a safe state when interrupted.
+//!
+//! Only installed for operations such as calibration, the control loop itself keeps the default
+//! behaviour of exiting immediately.
+
+use libc::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupted(_signal: c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes Ctrl-C and SIGTERM set the interrupt flag instead of exiting.
+pub fn install() {
+    let handler = interrupted as extern "C" fn(c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Returns the flag set once an interrupt arrives.
+pub fn flag() -> &'static AtomicBool {
+    &INTERRUPTED
+}
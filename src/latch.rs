@@ -0,0 +1,47 @@
+//! Overtemperature latch keeping the fan at maximum until an operator acknowledges it.
+//!
+//! For setups where reaching the maximum temperature means a hardware fault that needs
+//! inspecting, rather than a hot day.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Shared latch state.
+///
+/// Clones share the same state, so the control socket can acknowledge what the controller trips.
+#[derive(Debug, Clone, Default)]
+pub struct Latch {
+    tripped: Arc<AtomicBool>,
+    /// Set by an acknowledgement until the controller has noticed it
+    released: Arc<AtomicBool>,
+}
+
+impl Latch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trip(&self) {
+        self.tripped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.load(Ordering::SeqCst)
+    }
+
+    /// Releases the latch, returning false if it wasn't tripped.
+    pub fn acknowledge(&self) -> bool {
+        let was_tripped = self.tripped.swap(false, Ordering::SeqCst);
+        if was_tripped {
+            self.released.store(true, Ordering::SeqCst);
+        }
+        was_tripped
+    }
+
+    /// Returns whether the latch was acknowledged since the last call.
+    pub fn take_released(&self) -> bool {
+        self.released.swap(false, Ordering::SeqCst)
+    }
+}
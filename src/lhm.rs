@@ -0,0 +1,82 @@
+//! Temperatures from LibreHardwareMonitor (or OpenHardwareMonitor) on Windows.
+//!
+//! The monitor publishes sensors through WMI while it runs. They are queried through PowerShell,
+//! which avoids binding COM and keeps this buildable on every platform.
+
+use crate::{
+    sensor::{Sensor, SensorError},
+    units::Celsius,
+};
+use std::process::Command;
+
+/// WMI namespace LibreHardwareMonitor registers its sensors in
+const NAMESPACE: &str = "root/LibreHardwareMonitor";
+
+/// Temperature sensor published by LibreHardwareMonitor over WMI.
+pub struct LhmSensor {
+    /// Sensor identifier, e.g. `/amdcpu/0/temperature/2`
+    identifier: String,
+}
+
+impl LhmSensor {
+    pub fn new(identifier: &str) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+        }
+    }
+
+    fn query(&self) -> String {
+        // Identifiers only contain path characters, but never let one break out of the quotes
+        let identifier = self.identifier.replace('\'', "");
+        format!(
+            "(Get-CimInstance -Namespace {} -ClassName Sensor -Filter \"Identifier='{}' AND SensorType='Temperature'\").Value.ToString([cultureinfo]::InvariantCulture)",
+            NAMESPACE, identifier
+        )
+    }
+}
+
+impl Sensor for LhmSensor {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        let source = format!("LibreHardwareMonitor sensor {}", self.identifier);
+        let output = Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", &self.query()])
+            .output()
+            .map_err(|error| SensorError::Read(source, error))?;
+
+        parse_degrees(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// Parses a value in degrees as printed by WMI, e.g. `45.125`.
+fn parse_degrees(content: &str) -> Result<Celsius, SensorError> {
+    let degrees: f64 = content
+        .trim()
+        .parse()
+        .map_err(|_| SensorError::Parse(content.to_string()))?;
+    if !degrees.is_finite() || degrees.abs() > 1_000_000.0 {
+        return Err(SensorError::Parse(content.to_string()));
+    }
+
+    Ok(Celsius::from_millidegrees((degrees * 1000.0).round() as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_degrees;
+    use crate::units::Celsius;
+
+    #[test]
+    fn parses_wmi_value() {
+        assert_eq!(
+            Celsius::new(45, 125),
+            parse_degrees("45.125000\r\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_missing_sensor() {
+        // Unknown identifiers print nothing
+        assert!(parse_degrees("").is_err());
+        assert!(parse_degrees("NaN").is_err());
+    }
+}
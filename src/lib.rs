@@ -0,0 +1,86 @@
+//! PWM fan controller that tries to maintain a target temperature by adjusting fan speed.
+
+pub mod ab_test;
+pub mod acoustic;
+pub mod aggregate;
+pub mod alarm;
+pub mod args;
+pub mod ble;
+pub mod board;
+pub mod bundle;
+pub mod button;
+pub mod calibration;
+pub mod clock;
+pub mod coap;
+pub mod config;
+pub mod console;
+#[cfg(unix)]
+pub mod control;
+pub mod controller;
+pub mod cpu_load;
+pub mod curve;
+pub mod encoder;
+pub mod energy;
+pub mod event_log;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fleet;
+pub mod forecast;
+pub mod frequency;
+pub mod hwmon;
+pub mod i18n;
+pub mod import;
+pub mod inhibit;
+pub mod instance;
+pub mod interrupt;
+pub mod latch;
+pub mod lhm;
+pub mod linearize;
+pub mod lirc;
+#[cfg(unix)]
+pub mod live;
+pub mod logging;
+#[cfg(unix)]
+pub mod mcp23017;
+pub mod mcp3008;
+pub mod mdns;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod modbus;
+pub mod notify;
+pub mod nvme;
+pub mod observer;
+pub mod pairing;
+pub mod pairing_info;
+pub mod pipeline;
+pub mod plot;
+pub mod pwm;
+#[cfg(feature = "python")]
+mod python;
+pub mod qr;
+pub mod reload;
+pub mod schedule;
+pub mod script;
+pub mod season;
+pub mod secret;
+pub mod sensor;
+pub mod serial;
+pub mod setpoint;
+pub mod setup;
+pub mod smart;
+pub mod snmp;
+#[cfg(feature = "wiringpi")]
+pub mod softpwm;
+pub mod status;
+pub mod status_file;
+pub mod target_schedule;
+pub mod telemetry;
+pub mod temperature;
+pub mod template;
+pub mod thermal;
+pub mod throttle;
+pub mod trip;
+pub mod tune;
+
+pub use fan_controller_core::{stepping, units};
@@ -0,0 +1,162 @@
+//! Mapping from cooling percent to the duty that delivers it.
+//!
+//! Airflow rarely grows evenly with duty: many fans do most of their cooling in the lower half
+//! of the range. With a table, the controller steps, curves and limits in cooling percent and
+//! only the value written to the fan is turned into a duty.
+
+use crate::{calibration::Point, units::Duty};
+use std::fmt;
+
+/// Points of cooling percent and the duty giving it, interpolated in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linearization {
+    points: Vec<(Duty, Duty)>,
+}
+
+impl Linearization {
+    /// Returns the duty giving a cooling percent.
+    ///
+    /// Below the first point and above the last, their duties apply. A stopped fan stays stopped.
+    pub fn duty(&self, cooling: Duty) -> Duty {
+        if cooling == Duty::OFF {
+            return Duty::OFF;
+        }
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if cooling <= first.0 {
+            return first.1;
+        }
+        if cooling >= last.0 {
+            return last.1;
+        }
+
+        let upper = self
+            .points
+            .iter()
+            .position(|(point, _)| *point > cooling)
+            .unwrap_or(self.points.len() - 1);
+        let ((low, from), (high, to)) = (self.points[upper - 1], self.points[upper]);
+        let span = i32::from(high.percent()) - i32::from(low.percent());
+        let offset = i32::from(cooling.percent()) - i32::from(low.percent());
+        let (from, to) = (i32::from(from.percent()), i32::from(to.percent()));
+        let percent = (from * span + (to - from) * offset + span / 2) / span;
+        Duty::new(percent as u8).unwrap_or(Duty::FULL)
+    }
+
+    /// Learns a table from a calibration sweep.
+    ///
+    /// The cooling of each duty is how far its temperature lies below that of the slowest duty,
+    /// as a share of the drop the fastest duty gives. Returns `None` if the sweep didn't cool
+    /// more at its fastest step than at its slowest.
+    pub fn learn(points: &[Point]) -> Option<Self> {
+        let mut points = points.to_vec();
+        points.sort_by_key(|point| point.duty);
+        let (slowest, fastest) = (points.first()?, points.last()?);
+        let drop = i64::from(slowest.temperature.millidegrees())
+            - i64::from(fastest.temperature.millidegrees());
+        if drop <= 0 {
+            return None;
+        }
+
+        let mut learned: Vec<(Duty, Duty)> = Vec::new();
+        for point in &points {
+            let cooled = i64::from(slowest.temperature.millidegrees())
+                - i64::from(point.temperature.millidegrees());
+            let percent = (cooled.clamp(0, drop) * 100 + drop / 2) / drop;
+            let cooling = Duty::new(percent as u8).unwrap_or(Duty::FULL);
+            // Readings that didn't improve on a slower duty add nothing to the table
+            match learned.last() {
+                Some((previous, _)) if cooling <= *previous => {}
+                _ => learned.push((cooling, point.duty)),
+            }
+        }
+        Some(Self { points: learned })
+    }
+}
+
+impl fmt::Display for Linearization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|(cooling, duty)| format!("{}:{}", cooling.percent(), duty.percent()))
+            .collect();
+        f.write_str(&points.join(","))
+    }
+}
+
+/// Parses a table of cooling:duty points such as `25:40,50:60,75:80,100:100`.
+pub fn parse_linearization(value: &str) -> Result<Linearization, String> {
+    let invalid = || {
+        format!(
+            "invalid linearization {:?}, expected e.g. 25:40,50:60,75:80,100:100",
+            value
+        )
+    };
+
+    let mut points: Vec<(Duty, Duty)> = Vec::new();
+    for point in value.split(',') {
+        let (cooling, duty) = point.split_once(':').ok_or_else(invalid)?;
+        let parse = |text: &str| {
+            text.trim()
+                .parse::<Duty>()
+                .map_err(|error| format!("{} in linearization {:?}", error, value))
+        };
+        let (cooling, duty) = (parse(cooling)?, parse(duty)?);
+        if let Some((previous_cooling, previous_duty)) = points.last() {
+            if cooling <= *previous_cooling || duty < *previous_duty {
+                return Err(format!(
+                    "linearization {:?} must rise in cooling and not fall in duty from point to \
+                     point",
+                    value
+                ));
+            }
+        }
+        points.push((cooling, duty));
+    }
+    Ok(Linearization { points })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_linearization, Linearization};
+    use crate::{calibration::Point, pwm::tests::duty, units::Celsius};
+
+    #[test]
+    fn parses_and_interpolates() {
+        let table = parse_linearization("25:40, 50:60,100:100").unwrap();
+        assert_eq!("25:40,50:60,100:100", table.to_string());
+
+        assert_eq!(duty(0), table.duty(duty(0)));
+        assert_eq!(duty(40), table.duty(duty(10)));
+        assert_eq!(duty(50), table.duty(duty(38)));
+        assert_eq!(duty(80), table.duty(duty(75)));
+        assert_eq!(duty(100), table.duty(duty(100)));
+
+        assert!(parse_linearization("50:60,25:70").is_err());
+        assert!(parse_linearization("25:70,50:60").is_err());
+        assert!(parse_linearization("25:101").is_err());
+        assert!(parse_linearization("25").is_err());
+    }
+
+    #[test]
+    fn learns_from_a_calibration_sweep() {
+        let point = |percent, degrees| Point {
+            duty: duty(percent),
+            temperature: Celsius::new(degrees, 0),
+        };
+        let sweep = [
+            point(100, 40),
+            point(80, 40),
+            point(60, 42),
+            point(40, 48),
+            point(20, 60),
+        ];
+        assert_eq!(
+            "0:20,60:40,90:60,100:80",
+            Linearization::learn(&sweep).unwrap().to_string()
+        );
+
+        assert_eq!(None, Linearization::learn(&[point(100, 50), point(20, 50)]));
+        assert_eq!(None, Linearization::learn(&[]));
+    }
+}
@@ -0,0 +1,193 @@
+//! IR remote control through LIRC, for media-center Pis living under the TV.
+//!
+//! Key presses decoded by `lircd` are read from its socket and mapped to the same boost, quiet
+//! mode and target adjustments as the button and the rotary encoder.
+
+use crate::{button::Modes, encoder::Knob};
+use clap::ValueEnum;
+use std::time::Instant;
+
+/// What a remote key does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Command {
+    /// Toggles a boost at maximum speed
+    Boost,
+    /// Toggles quiet mode
+    Quiet,
+    /// Raises the target temperature by a step
+    Up,
+    /// Lowers the target temperature by a step
+    Down,
+}
+
+/// Remote key mapped to a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub key: String,
+    pub command: Command,
+}
+
+/// Keys used when none are given, the colored keys found on most media-center remotes
+pub fn default_bindings() -> Vec<Binding> {
+    [
+        ("KEY_RED", Command::Boost),
+        ("KEY_GREEN", Command::Quiet),
+        ("KEY_UP", Command::Up),
+        ("KEY_DOWN", Command::Down),
+    ]
+    .into_iter()
+    .map(|(key, command)| Binding {
+        key: key.to_string(),
+        command,
+    })
+    .collect()
+}
+
+/// Parses a binding such as `KEY_RED=boost`.
+pub fn parse_binding(value: &str) -> Result<Binding, String> {
+    let invalid = || {
+        format!(
+            "invalid key binding {:?}, expected e.g. KEY_RED=boost",
+            value
+        )
+    };
+    let (key, command) = value.split_once('=').ok_or_else(invalid)?;
+    if key.trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(Binding {
+        key: key.trim().to_string(),
+        command: Command::from_str(command.trim(), true).map_err(|_| invalid())?,
+    })
+}
+
+/// Returns the repeat count and key name of a line from `lircd`, such as
+/// `0000000000f40bf0 00 KEY_UP mceusb`.
+pub fn parse_line(line: &str) -> Option<(u32, &str)> {
+    let mut fields = line.split_whitespace();
+    let _code = fields.next()?;
+    let repeat = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let key = fields.next()?;
+    fields.next()?;
+    Some((repeat, key))
+}
+
+/// Applies key presses from the remote.
+#[derive(Debug, Clone)]
+pub struct Remote {
+    pub bindings: Vec<Binding>,
+    pub modes: Modes,
+    pub knob: Knob,
+}
+
+impl Remote {
+    /// Applies the command bound to the key of a line from `lircd`.
+    ///
+    /// Held keys repeat target adjustments, while toggles only act on the first press.
+    pub fn handle(&self, line: &str, now: Instant) {
+        let Some((repeat, key)) = parse_line(line) else {
+            return;
+        };
+        let Some(binding) = self.bindings.iter().find(|binding| binding.key == key) else {
+            return;
+        };
+
+        match binding.command {
+            Command::Boost if repeat == 0 => self.modes.toggle_boost(now),
+            Command::Quiet if repeat == 0 => self.modes.toggle_quiet(),
+            Command::Up => self.knob.turn(1),
+            Command::Down => self.knob.turn(-1),
+            Command::Boost | Command::Quiet => {}
+        }
+    }
+}
+
+/// Reads key presses from the `lircd` socket in the background, reconnecting when it goes away.
+#[cfg(unix)]
+pub fn listen(path: std::path::PathBuf, remote: Remote) {
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::net::UnixStream,
+        thread,
+        time::Duration,
+    };
+
+    thread::spawn(move || loop {
+        match UnixStream::connect(&path) {
+            Ok(stream) => {
+                for line in BufReader::new(stream).lines() {
+                    match line {
+                        Ok(line) => remote.handle(&line, Instant::now()),
+                        Err(error) => {
+                            log::warn!("Lost connection to lircd: {}", error);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(error) => log::warn!("Failed to connect to lircd at {:?}: {}", path, error),
+        }
+        thread::sleep(Duration::from_secs(5));
+    });
+}
+
+#[cfg(not(unix))]
+pub fn listen(_path: std::path::PathBuf, _remote: Remote) {
+    log::warn!("LIRC is only supported on Linux, ignoring --lirc-socket");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_bindings, parse_binding, parse_line, Command, Remote};
+    use crate::{button::Modes, encoder::Knob, setpoint::Setpoint, status::Status, units::Celsius};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn parses_bindings_and_lines() {
+        assert_eq!(
+            Command::Boost,
+            parse_binding("KEY_OK=boost").unwrap().command
+        );
+        assert!(parse_binding("KEY_OK=launch").is_err());
+        assert!(parse_binding("=boost").is_err());
+
+        assert_eq!(
+            Some((0, "KEY_UP")),
+            parse_line("0000000000f40bf0 00 KEY_UP mceusb")
+        );
+        assert_eq!(
+            Some((26, "KEY_UP")),
+            parse_line("0000000000f40bf0 1a KEY_UP mceusb")
+        );
+        assert_eq!(None, parse_line("BEGIN"));
+    }
+
+    #[test]
+    fn maps_keys_to_commands() {
+        let remote = Remote {
+            bindings: default_bindings(),
+            modes: Modes::new(Duration::from_secs(60), crate::pwm::tests::duty(50)),
+            knob: Knob {
+                setpoint: Setpoint::new(),
+                status: Status::new(),
+                step: Celsius::new(1, 0),
+                target: Celsius::new(40, 0),
+                temperature_max: Celsius::new(70, 0),
+            },
+        };
+        let now = Instant::now();
+
+        remote.handle("0000000000f40bf0 00 KEY_RED mceusb", now);
+        // Holding the key doesn't toggle the boost back off
+        remote.handle("0000000000f40bf0 01 KEY_RED mceusb", now);
+        assert!(remote.modes.boosting(now));
+
+        remote.handle("0000000000f40bf0 00 KEY_GREEN mceusb", now);
+        assert!(remote.modes.is_quiet());
+
+        remote.handle("0000000000f40bf0 00 KEY_UP mceusb", now);
+        remote.handle("0000000000f40bf0 01 KEY_UP mceusb", now);
+        remote.handle("0000000000f40bf0 00 KEY_PLAY mceusb", now);
+        assert_eq!(Some(Celsius::new(42, 0)), remote.knob.setpoint.take());
+    }
+}
@@ -0,0 +1,226 @@
+//! Live tuning session attached to a running controller through its control socket.
+//!
+//! Changes take effect at the controller's next poll. They can be undone one by one, and the
+//! ones kept are written to the config file so they outlast a restart.
+
+use crate::{config, units::Celsius};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+};
+
+/// Config key the target temperature is kept under
+const TARGET_KEY: &str = "temperature-target-value";
+
+const HELP: &str = "set target <°C>   change the target temperature
+show              print the target temperature and changes not yet committed
+undo              take back the last change
+commit-to-config  write the changes to the config file
+quit              leave the changes running until the controller restarts";
+
+/// Target temperature changed during the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Change {
+    from: Celsius,
+    to: Celsius,
+}
+
+/// Connection to a running controller, with the changes made through it.
+pub struct Session {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    /// Config file changes are committed to
+    config: Option<PathBuf>,
+    /// Changes since the session started or was last committed, latest last
+    changes: Vec<Change>,
+}
+
+impl Session {
+    /// Connects to the control socket of a controller reading its options from `config`.
+    pub fn connect(socket: &Path, config: Option<&Path>) -> io::Result<Self> {
+        let writer = UnixStream::connect(socket)?;
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            config: config.map(Path::to_path_buf),
+            changes: Vec::new(),
+        })
+    }
+
+    /// Reads commands until `quit` or the end of input, replying to each.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            match line.trim() {
+                "" => {}
+                "quit" | "exit" => break,
+                line => match self.execute(line) {
+                    Ok(reply) => writeln!(output, "{}", reply)?,
+                    Err(error) => writeln!(output, "error: {}", error)?,
+                },
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+
+        if !self.changes.is_empty() {
+            writeln!(
+                output,
+                "Changes not committed to the config file stay in effect until the controller \
+                 restarts"
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Executes a command line and returns the reply.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["set", "target", value] => {
+                let to: Celsius = value
+                    .parse()
+                    .map_err(|_| format!("invalid target temperature {:?}", value))?;
+                // The controller may not have applied the previous change yet
+                let from = match self.changes.last() {
+                    Some(change) => change.to,
+                    None => self.target()?,
+                };
+                self.request(&format!("target {}", to))?;
+                self.changes.push(Change { from, to });
+                Ok(format!("target {} → {}", from, to))
+            }
+            ["set", name, ..] => Err(format!(
+                "unknown setting {:?}, only target can be set while running",
+                name
+            )),
+            ["show"] => {
+                let target = match self.changes.last() {
+                    Some(change) => change.to,
+                    None => self.target()?,
+                };
+                Ok(match self.changes.first() {
+                    Some(first) => format!(
+                        "target {} (uncommitted, was {} at the start)",
+                        target, first.from
+                    ),
+                    None => format!("target {}", target),
+                })
+            }
+            ["undo"] => {
+                let change = self.changes.pop().ok_or("nothing to undo")?;
+                if let Err(error) = self.request(&format!("target {}", change.from)) {
+                    self.changes.push(change);
+                    return Err(error);
+                }
+                Ok(format!("target {} → {}", change.to, change.from))
+            }
+            ["commit-to-config"] => {
+                let path = self.config.clone().ok_or("no --config file to commit to")?;
+                let change = self.changes.last().ok_or("nothing to commit")?;
+                let value = change.to.to_string();
+                let content = fs::read_to_string(&path)
+                    .map_err(|error| format!("failed to read {:?}: {}", path, error))?;
+                fs::write(&path, config::set_option(&content, TARGET_KEY, &value))
+                    .map_err(|error| format!("failed to write {:?}: {}", path, error))?;
+                self.changes.clear();
+                Ok(format!("{} = {} written to {:?}", TARGET_KEY, value, path))
+            }
+            ["help"] => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command {:?}, try help", line)),
+        }
+    }
+
+    /// Returns the target temperature the controller is following.
+    fn target(&mut self) -> Result<Celsius, String> {
+        let reply = self.request("target")?;
+        reply
+            .parse()
+            .map_err(|_| format!("unexpected reply {:?}", reply))
+    }
+
+    /// Sends a control command and returns its reply, or the error it reports.
+    fn request(&mut self, command: &str) -> Result<String, String> {
+        let failed = |error: io::Error| format!("lost connection to the controller: {}", error);
+        writeln!(self.writer, "{}", command).map_err(failed)?;
+        let mut reply = String::new();
+        if self.reader.read_line(&mut reply).map_err(failed)? == 0 {
+            return Err("controller closed the connection".to_string());
+        }
+        let reply = reply.trim_end();
+        match reply.strip_prefix("error: ") {
+            Some(error) => Err(error.to_string()),
+            None => Ok(reply.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use crate::{
+        control::{serve, Context},
+        events::{Event, Sink},
+        mock::TempPath,
+        units::Celsius,
+    };
+    use std::fs;
+
+    #[test]
+    fn changes_undoes_and_commits_target() {
+        let directory = TempPath::dir("live");
+        let (socket, config) = (
+            directory.join("control.sock"),
+            directory.join("config.toml"),
+        );
+        fs::write(&config, "gpio-pwm = 3\ntemperature-target-value = 45\n").unwrap();
+
+        let context = Context::default();
+        serve(&socket, context.clone()).unwrap();
+        let mut status = context.status.sink();
+        status.handle(&Event::Override {
+            target: Celsius::new(45, 0),
+        });
+
+        let mut session = Session::connect(&socket, Some(&config)).unwrap();
+        assert_eq!(Ok("target 45".to_string()), session.execute("show"));
+        assert!(session.execute("set kp 1.2").is_err());
+        assert!(session.execute("undo").is_err());
+
+        assert_eq!(
+            Ok("target 45 → 42".to_string()),
+            session.execute("set target 42")
+        );
+        assert_eq!(Some(Celsius::new(42, 0)), context.setpoint.take());
+        assert_eq!(
+            Ok("target 42 → 40.5".to_string()),
+            session.execute("set target 40.5")
+        );
+        assert_eq!(
+            Ok("target 40.5 (uncommitted, was 45 at the start)".to_string()),
+            session.execute("show")
+        );
+        assert_eq!(Ok("target 40.5 → 42".to_string()), session.execute("undo"));
+        assert_eq!(Some(Celsius::new(42, 0)), context.setpoint.take());
+
+        assert!(session.execute("commit-to-config").is_ok());
+        assert_eq!(
+            "gpio-pwm = 3\ntemperature-target-value = 42\n",
+            fs::read_to_string(&config).unwrap()
+        );
+        assert!(session.execute("commit-to-config").is_err());
+
+        let mut output = Vec::new();
+        session
+            .run("bogus\nquit\nshow\n".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(
+            "> error: unknown command \"bogus\", try help\n> ",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Log output with per-module verbosity that can be changed while running.
+//!
+//! Filters are written as `controller=debug,telemetry=warn,info`: module names with the level
+//! to log them at, and a bare level for everything else. Informational messages go to stdout and
+//! warnings and errors to stderr, so the journal keeps its priorities under systemd.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{fmt, str::FromStr, sync::RwLock};
+
+/// Prefix stripped from module paths, so filters name modules as `controller` rather than
+/// `fan_controller::controller`
+const CRATE_PREFIX: &str = "fan_controller::";
+
+/// Filter currently in effect
+static FILTER: RwLock<Option<Filter>> = RwLock::new(None);
+
+/// Levels to log at, by module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Returns whether a message from the given module at the given level is logged.
+    ///
+    /// The most specific directive naming the module or one of its parents applies.
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        let module = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+        let filter = self
+            .directives
+            .iter()
+            .filter(|(name, _)| {
+                module == name
+                    || module
+                        .strip_prefix(name.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.default, |(_, filter)| *filter);
+
+        level <= filter
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            directives: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Filter::default();
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (module, level) = match directive.split_once('=') {
+                Some((module, level)) => (Some(module.trim()), level.trim()),
+                None => (None, directive),
+            };
+            let level: LevelFilter = level
+                .parse()
+                .map_err(|_| format!("invalid log level {:?}", level))?;
+
+            match module {
+                Some(module) => filter.directives.push((module.to_string(), level)),
+                None => filter.default = level,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (module, level) in &self.directives {
+            write!(f, "{}={},", module, level.as_str().to_lowercase())?;
+        }
+        write!(f, "{}", self.default.as_str().to_lowercase())
+    }
+}
+
+/// Installs the logger with the given filter.
+///
+/// Only the first call installs it, later ones just replace the filter.
+pub fn init(filter: Filter) {
+    set_filter(filter);
+    if log::set_logger(&Logger).is_ok() {
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+/// Replaces the filter in effect.
+pub fn set_filter(filter: Filter) {
+    *FILTER.write().unwrap() = Some(filter);
+}
+
+/// Returns the filter in effect.
+pub fn filter() -> Filter {
+    FILTER.read().unwrap().clone().unwrap_or_default()
+}
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match FILTER.read().unwrap().as_ref() {
+            Some(filter) => filter.enabled(metadata.target(), metadata.level()),
+            None => metadata.level() <= Level::Info,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if record.level() <= Level::Warn {
+            eprintln!("{}", record.args());
+        } else {
+            println!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use log::Level;
+
+    #[test]
+    fn most_specific_directive_applies() {
+        let filter: Filter = "controller=debug,telemetry=error,warn".parse().unwrap();
+
+        assert!(filter.enabled("fan_controller::controller", Level::Debug));
+        assert!(!filter.enabled("fan_controller::controller", Level::Trace));
+        assert!(!filter.enabled("fan_controller::telemetry", Level::Warn));
+        assert!(filter.enabled("fan_controller::serial", Level::Warn));
+        assert!(!filter.enabled("fan_controller::serial", Level::Info));
+        // Names match whole modules only
+        assert!(!filter.enabled("fan_controller::controllers", Level::Debug));
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let filter: Filter = "events=trace, debug".parse().unwrap();
+        assert_eq!("events=trace,debug", filter.to_string());
+        assert_eq!(filter, filter.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn rejects_unknown_level() {
+        assert!("controller=loud".parse::<Filter>().is_err());
+    }
+}
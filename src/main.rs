@@ -1,456 +1,1086 @@
-use clap::Parser;
-use libc::c_int;
-use std::{fs, path::Path, thread, time};
-
-#[link(name = "wiringPi")]
-extern "C" {
-    fn wiringPiSetup() -> c_int;
-    fn pinMode(pin: c_int, mode: c_int);
-    fn softPwmCreate(pin: c_int, value: c_int, range: c_int) -> c_int;
-    fn softPwmWrite(pin: c_int, value: c_int);
-}
-
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Minimum allowed fan speed
-    #[arg(long, default_value_t = 30)]
-    pwm_min: i32,
-
-    /// Maximum allowed fan speed
-    #[arg(long, default_value_t = 100)]
-    pwm_max: i32,
-
-    #[arg(long, default_value_t = 2)]
-    pwm_increment: i32,
-
-    #[arg(long, default_value_t = 1)]
-    pwm_decrement: i32,
-
-    /// Target temperature to maintain
-    #[arg(short, long, default_value_t = 40.0)]
-    temperature_target_value: f32,
-
-    /// Max allowed temperature value
-    #[arg(long, default_value_t = 70.0)]
-    temperature_max_value: f32,
-
-    #[arg(long, default_value = "/sys/class/thermal/thermal_zone0/temp")]
-    temperature_file_path: String,
-
-    /// Temperature polling rate
-    #[arg(short, long, default_value_t = 5)]
-    pollrate: u64,
-
-    /// GPIO pin controlling the fan
-    #[arg(short, long)]
-    gpio_pwm: i32,
-
-    /// Print systemd service file content
-    #[arg(long)]
-    print_systemd: bool,
-}
-
-struct Pwm {
-    current: i32,
-    previous: i32,
-    increment: i32,
-    decrement: i32,
-    min: i32,
-    max: i32,
-    gpio_pin: i32,
-}
-
-impl Pwm {
-    fn new(args: &Args) -> Self {
-        Self {
-            current: args.pwm_max,
-            previous: args.pwm_max,
-            increment: args.pwm_increment,
-            decrement: args.pwm_decrement,
-            min: args.pwm_min,
-            max: args.pwm_max,
-            gpio_pin: args.gpio_pwm,
-        }
+use clap::{CommandFactory, ValueEnum};
+use fan_controller::{
+    acoustic::Acoustics,
+    alarm::AlarmLevel,
+    args::{
+        Args, CalibrateArgs, CurveCommand, CurveRenderArgs, ExportBundleArgs, FleetCommand,
+        FleetStatusArgs, FrequencySweepArgs, ImportBundleArgs, ImportCommand, ImportFancontrolArgs,
+        Operation, PairingInfoArgs, SetupArgs, TuneArgs,
+    },
+    ble, board,
+    bundle::{self, Bundle},
+    button::Modes,
+    calibration::{write_csv, Calibration},
+    clock::SystemClock,
+    coap, config, console,
+    controller::Controller,
+    encoder::Knob,
+    energy::{self, Policy},
+    event_log::JsonLinesSink,
+    events::{BufferedSink, EventBus, Sink},
+    fleet,
+    i18n::{self, text, Language},
+    import,
+    inhibit::Inhibitor,
+    instance, interrupt,
+    latch::Latch,
+    linearize::Linearization,
+    lirc::{self, Remote},
+    logging,
+    mdns::{self, Advertisement},
+    modbus,
+    notify::DesktopSink,
+    pairing, pairing_info,
+    plot::{Plot, PlotFormat, Series},
+    pwm::{NullOutput, Output},
+    qr::QrCode,
+    reload::{self, Reloader},
+    secret, sensor,
+    serial::SerialOutput,
+    setpoint::Setpoint,
+    setup::{self, Wizard},
+    snmp,
+    status::Status,
+    status_file::StatusFileSink,
+    telemetry::{HttpSink, Spool},
+    trip,
+    tune::Relay,
+};
+use std::{
+    fs::File,
+    io::{self, Write},
+    rc::Rc,
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Returns the options selecting the fan outputs.
+fn output_options(args: &Args) -> String {
+    let intake = intake_options(args);
+    match args.exhaust_gpio_pwm {
+        Some(pin) => format!(
+            "{} --exhaust-gpio-pwm {} --exhaust-ratio {}",
+            intake, pin, args.exhaust_ratio
+        ),
+        None => intake,
     }
+}
 
-    /// Initializes GPIO pin for PWM use
-    fn init(&self) {
-        unsafe {
-            wiringPiSetup();
-            pinMode(self.gpio_pin, 1); // 1 = output
-            softPwmCreate(self.gpio_pin, self.max, self.max); // GPIO pin, initial value, range
-        }
+/// Returns the options selecting the main fan output.
+fn intake_options(args: &Args) -> String {
+    if let Some(port) = &args.serial_port {
+        return format!(
+            "--serial-port {} --serial-baud {} --serial-protocol {}",
+            port,
+            args.serial_baud,
+            args.serial_protocol.to_possible_value().unwrap().get_name()
+        );
     }
 
-    /// Checks and fixes provided PWM value to be within the limits
-    fn fix_pwm_value(&self, value: i32) -> i32 {
-        if value > self.max {
-            return self.max;
-        }
+    if let Some(pin) = args.mcp23017_pin {
+        let active_low = if args.relay_active_low {
+            " --relay-active-low"
+        } else {
+            ""
+        };
+        return format!(
+            "--mcp23017-pin {} --mcp23017-address 0x{:02x} --i2c-bus {} --relay-on-duty {}{}",
+            pin, args.mcp23017_address, args.i2c_bus, args.relay_on_duty, active_low
+        );
+    }
 
-        if value < self.min {
-            return self.min;
-        }
+    match args.gpio_pwm {
+        Some(pin) => format!("--gpio-pwm {}", pin),
+        None => String::new(),
+    }
+}
 
-        return value;
+/// Prints systemd service file content with the given options.
+fn print_systemd(args: &Args) {
+    println!("{}", systemd_unit(&systemd_options(args)));
+}
+
+/// Returns the options the systemd service runs the controller with.
+fn systemd_options(args: &Args) -> String {
+    let output = output_options(args);
+    let pollrate = match (args.pollrate_min, args.pollrate_max) {
+        (Some(min), Some(max)) => format!("--pollrate-min {} --pollrate-max {}", min, max),
+        _ => format!("--pollrate {}", args.pollrate),
+    };
+    let mut options = format!(
+        "{} {} --temperature-target-value {}",
+        output, pollrate, args.temperature_target_value
+    );
+    if let Some(instance) = &args.instance_name {
+        options += &format!(" --instance-name {}", instance);
     }
+    options
+}
 
-    /// Writes new PWM value
-    fn write(&mut self, value: i32) {
-        self.previous = self.current;
-        self.current = self.fix_pwm_value(value);
-        unsafe {
-            softPwmWrite(self.gpio_pin, self.current);
-        }
+/// Returns systemd service file content running the controller with the given options.
+fn systemd_unit(options: &str) -> String {
+    format!(
+        "[Unit]
+Description=PWM fan controller for Orange PI systems
+
+[Service]
+Type=simple
+ExecStart=/usr/local/bin/opi-fan-controller {}
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+
+[Install]
+WantedBy=multi-user.target",
+        options
+    )
+}
+
+/// Starts watching the button on the pin for boost and quiet mode presses.
+#[cfg(feature = "wiringpi")]
+fn button(pin: i32, modes: Modes) {
+    fan_controller::button::watch(pin, modes);
+}
+
+#[cfg(not(feature = "wiringpi"))]
+fn button(_pin: i32, _modes: Modes) {
+    eprintln!("Built without wiringpi support, --gpio-button is unavailable");
+    std::process::exit(2);
+}
+
+/// Starts watching the rotary encoder on the pins for target adjustments.
+#[cfg(feature = "wiringpi")]
+fn encoder(pins: (i32, i32), knob: Knob) {
+    fan_controller::encoder::watch(pins, knob);
+}
+
+#[cfg(not(feature = "wiringpi"))]
+fn encoder(_pins: (i32, i32), _knob: Knob) {
+    eprintln!("Built without wiringpi support, --gpio-encoder is unavailable");
+    std::process::exit(2);
+}
+
+/// Returns the output selected by the options, paired with an exhaust fan when one is given.
+fn output(args: &Args) -> Box<dyn Output> {
+    let intake = intake_output(args);
+
+    #[cfg(feature = "wiringpi")]
+    if let Some(pin) = args.exhaust_gpio_pwm {
+        return Box::new(
+            fan_controller::pairing::PairedOutput::new(
+                intake,
+                Box::new(fan_controller::softpwm::SoftPwm::on_pin(pin, args)),
+                args.exhaust_ratio,
+            )
+            .with_acoustics(Acoustics::from_args(args)),
+        );
     }
+
+    #[cfg(not(feature = "wiringpi"))]
+    if args.exhaust_gpio_pwm.is_some() {
+        eprintln!("Built without wiringpi support, --exhaust-gpio-pwm is unavailable");
+        std::process::exit(2);
+    }
+
+    intake
 }
 
-struct Temperature {
-    current: f32,
-    previous: f32,
-    max: f32,
-    target: f32,
-    source_file_path: String,
+/// Returns the output driving the main fan.
+fn intake_output(args: &Args) -> Box<dyn Output> {
+    if let Some(port) = &args.serial_port {
+        return Box::new(SerialOutput::new(
+            port,
+            args.serial_baud,
+            args.serial_protocol.protocol(),
+        ));
+    }
+
+    #[cfg(unix)]
+    if let Some(pin) = args.mcp23017_pin {
+        return Box::new(fan_controller::mcp23017::Mcp23017Relay::new(
+            args.i2c_bus,
+            args.mcp23017_address,
+            pin,
+            args.relay_on_duty,
+            args.relay_active_low,
+        ));
+    }
+
+    #[cfg(feature = "wiringpi")]
+    if let (Some(pin), Some(_)) = (args.gpio_pwm, args.switch_on_above) {
+        return Box::new(fan_controller::softpwm::GpioSwitch::new(
+            pin,
+            args.relay_active_low,
+        ));
+    }
+
+    #[cfg(feature = "wiringpi")]
+    return Box::new(fan_controller::softpwm::SoftPwm::new(args));
+
+    #[cfg(not(feature = "wiringpi"))]
+    {
+        eprintln!(
+            "Built without wiringpi support, use --serial-port or --mcp23017-pin to drive the fan"
+        );
+        std::process::exit(2);
+    }
 }
 
-impl Temperature {
-    fn new(args: &Args) -> Self {
-        Self {
-            current: 0.0,
-            previous: 0.0,
-            max: args.temperature_max_value,
-            target: args.temperature_target_value,
-            source_file_path: args.temperature_file_path.to_string(),
-        }
+/// Returns the sinks events are published to besides the console.
+fn sinks(args: &Args, status: &Status) -> Vec<Box<dyn Sink>> {
+    let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(status.sink())];
+
+    let instance = args.instance_name.as_deref();
+    if let Some(path) = &args.status_file {
+        let path = &instance::namespaced(path, instance);
+        sinks.push(Box::new(BufferedSink::new(
+            StatusFileSink::new(status.clone(), path, args.status_format),
+            args.event_buffer as usize,
+            args.event_overflow,
+        )));
     }
 
-    /// Read temperature from the provided source file.
-    fn read(&mut self) {
-        let path = Path::new(&self.source_file_path);
-        let fcontext = fs::read_to_string(path).unwrap_or_else(|error| {
-            panic!(
-                "Failed to read temperature from {:?}: {:?}",
-                path.display(),
-                error
-            );
+    if let Some(path) = &args.event_log {
+        let path = &instance::namespaced(path, instance);
+        let sink = JsonLinesSink::open(path).unwrap_or_else(|error| {
+            eprintln!("Failed to open event log {:?}: {}", path, error);
+            std::process::exit(2);
         });
+        sinks.push(Box::new(BufferedSink::new(
+            sink,
+            args.event_buffer as usize,
+            args.event_overflow,
+        )));
+    }
 
-        let value: f32 = fcontext.trim().parse().unwrap_or_else(|error| {
-            panic!("Failed to parse temperature value: {:?}", error);
+    if args.desktop_notifications {
+        sinks.push(Box::new(BufferedSink::new(
+            DesktopSink::new(instance),
+            args.event_buffer as usize,
+            args.event_overflow,
+        )));
+    }
+
+    if let Some(url) = &args.telemetry_url {
+        let token = args.telemetry_token.as_deref().map(|reference| {
+            secret::load(reference).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                std::process::exit(2);
+            })
+        });
+        let spool = args.telemetry_spool.as_deref().map(|path| {
+            Spool::new(
+                &instance::namespaced(path, instance),
+                args.telemetry_spool_limit,
+            )
         });
+        sinks.push(Box::new(BufferedSink::new(
+            HttpSink::new(url.clone(), token, spool).with_instance(args.instance_name.clone()),
+            args.event_buffer as usize,
+            args.event_overflow,
+        )));
+    }
 
-        self.previous = self.current;
+    sinks
+}
 
-        // Round to one decimal point
-        self.current = ((value / 1000.0) * 10.0).round() / 10.0;
+/// Applies the duty for a single reading and exits.
+fn oneshot(args: &Args, sinks: Vec<Box<dyn Sink>>) {
+    // Software PWM stops with the process, leaving the fan at whatever the pin settles to
+    if args.gpio_pwm.is_some() || args.exhaust_gpio_pwm.is_some() {
+        eprintln!(
+            "--oneshot needs an output that keeps its setting after exit, use --serial-port or --mcp23017-pin"
+        );
+        std::process::exit(2);
     }
-}
 
-struct Controller {
-    pollrate: time::Duration,
-    temperature: Temperature,
-    pwm: Pwm,
+    let mut controller = Controller::new(args, output(args));
+    for sink in sinks {
+        controller = controller.with_sink(sink);
+    }
+    if controller.oneshot().is_err() || controller.output_failed() {
+        std::process::exit(1);
+    }
+    match controller.alarm_level() {
+        AlarmLevel::Normal => {}
+        AlarmLevel::Warning => std::process::exit(3),
+        AlarmLevel::Critical => std::process::exit(4),
+    }
 }
 
-impl Controller {
-    /// Returns a controller to be used within the application.
-    ///
-    /// # Arguments
-    ///
-    /// * `args` - Application options arguments
-    fn new(args: &Args) -> Self {
-        Self {
-            pollrate: time::Duration::from_secs(args.pollrate),
-            temperature: Temperature::new(&args),
-            pwm: Pwm::new(&args),
-        }
+/// Runs a calibration sweep and writes its results.
+fn calibrate(args: &Args, options: &CalibrateArgs, sinks: Vec<Box<dyn Sink>>) {
+    let mut events = EventBus::new();
+    events.subscribe(console::sink(
+        args.console,
+        args.decimal_separator,
+        args.log_template.clone(),
+    ));
+    for sink in sinks {
+        events.subscribe(sink);
     }
 
-    /// Determines required PWM value to get closer to the target temperature.
-    fn get_required_pwm(&self) -> i32 {
-        if self.temperature.current >= self.temperature.max {
-            return self.pwm.max;
-        }
+    let calibration = Calibration {
+        steps: Calibration::sweep(args.pwm_max, args.pwm_min, options.step),
+        hold: Duration::from_secs(options.hold),
+        interval: Duration::from_secs(args.pollrate.max(1)),
+        temperature_max: args.temperature_max_value,
+    };
 
-        if self.temperature.current > self.temperature.target
-            && self.temperature.previous <= self.temperature.current
-        {
-            return self.pwm.current + self.pwm.increment;
+    let mut output = output(args);
+    output.init();
+    interrupt::install();
+    let points = calibration.run(
+        output.as_mut(),
+        sensor::from_args(args).as_mut(),
+        &mut SystemClock,
+        &mut events,
+        interrupt::flag(),
+    );
+    output.shutdown();
+
+    let result = match &options.results {
+        Some(path) => File::create(path)
+            .and_then(|mut file| write_csv(&points, &mut file, args.decimal_separator)),
+        None => write_csv(&points, &mut io::stdout(), args.decimal_separator),
+    };
+    if let Err(error) = result {
+        eprintln!("Failed to write calibration results: {}", error);
+        std::process::exit(1);
+    }
+    if options.linearization {
+        match Linearization::learn(&points) {
+            Some(linearization) => eprintln!("--linearize {}", linearization),
+            None => eprintln!("The sweep didn't cool more at --pwm-max than at --pwm-min"),
         }
+    }
 
-        if self.temperature.current > self.temperature.target
-            && self.temperature.previous > self.temperature.current
-        {
-            return self.pwm.current - self.pwm.decrement;
+    if interrupt::flag().load(Ordering::SeqCst) {
+        // Conventional status for termination by SIGINT
+        std::process::exit(130);
+    }
+}
+
+/// Runs the relay test and prints the gains suggested by it.
+fn tune(args: &Args, options: &TuneArgs, sinks: Vec<Box<dyn Sink>>) {
+    if options.low >= options.high {
+        eprintln!(
+            "--low {} must be below --high {}",
+            options.low, options.high
+        );
+        std::process::exit(2);
+    }
+
+    let mut events = EventBus::new();
+    events.subscribe(console::sink(
+        args.console,
+        args.decimal_separator,
+        args.log_template.clone(),
+    ));
+    for sink in sinks {
+        events.subscribe(sink);
+    }
+
+    let relay = Relay {
+        low: options.low,
+        high: options.high,
+        target: args.temperature_target_value,
+        hysteresis: options.hysteresis,
+        cycles: options.cycles as usize,
+        interval: Duration::from_secs(args.pollrate.max(1)),
+        timeout: options.timeout,
+        temperature_max: args.temperature_max_value,
+    };
+
+    let mut output = output(args);
+    output.init();
+    interrupt::install();
+    let result = relay.run(
+        output.as_mut(),
+        sensor::from_args(args).as_mut(),
+        &mut SystemClock,
+        &mut events,
+        interrupt::flag(),
+    );
+    output.shutdown();
+
+    let response = result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        // Conventional status for termination by SIGINT
+        let interrupted = interrupt::flag().load(Ordering::SeqCst);
+        std::process::exit(if interrupted { 130 } else { 1 });
+    });
+    let gains = response.gains(options.rule);
+    println!(
+        "Oscillation period {}s, amplitude {:.2}°C, ultimate gain {:.2}%/°C",
+        response.period.as_secs(),
+        response.amplitude,
+        response.ultimate_gain
+    );
+    println!("kp = {:.3}", gains.kp);
+    println!("ki = {:.5}", gains.ki);
+    println!("kd = {:.3}", gains.kd);
+}
+
+/// Tunes the running controller from a prompt, through its control socket.
+#[cfg(unix)]
+fn tune_live(args: &Args) {
+    let Some(path) = &args.control_socket else {
+        eprintln!("tune --live needs the --control-socket of the running controller");
+        std::process::exit(2);
+    };
+    let path = &instance::namespaced(path, args.instance_name.as_deref());
+    let mut session = fan_controller::live::Session::connect(path, args.config.as_deref())
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to connect to control socket {:?}: {}", path, error);
+            std::process::exit(1);
+        });
+    if let Err(error) = session.run(io::stdin().lock(), io::stdout()) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn tune_live(_args: &Args) {
+    eprintln!("tune --live needs a control socket, which is only available on Unix");
+    std::process::exit(2);
+}
+
+/// Sweeps hardware PWM frequencies and writes the notes taken.
+#[cfg(feature = "wiringpi")]
+fn frequency_sweep(options: &FrequencySweepArgs) {
+    use fan_controller::frequency::{self, HardPwm, Sweep};
+
+    let sweep = Sweep {
+        frequencies: options.frequencies.clone(),
+        duty: options.duty,
+    };
+    let mut output = HardPwm::new(options.pin);
+    output.init();
+    let notes = sweep.run(&mut output, &mut io::stdin().lock(), &mut io::stderr());
+    output.shutdown();
+
+    let result = notes.and_then(|notes| match &options.results {
+        Some(path) => {
+            File::create(path).and_then(|mut file| frequency::write_csv(&notes, &mut file))
         }
+        None => frequency::write_csv(&notes, &mut io::stdout()),
+    });
+    if let Err(error) = result {
+        eprintln!("Failed to write frequency sweep notes: {}", error);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "wiringpi"))]
+fn frequency_sweep(_options: &FrequencySweepArgs) {
+    eprintln!("Built without wiringpi support, frequency-sweep is unavailable");
+    std::process::exit(2);
+}
+
+/// Publishes this instance over mDNS, with the endpoints besides CoAP in TXT records.
+fn advertise(args: &Args) -> Option<Advertisement> {
+    let mut txt = Vec::new();
+    if let Some(address) = args.modbus_listen {
+        txt.push(format!("modbus={}", address.port()));
+    }
+    if let Some(address) = args.snmp_listen {
+        txt.push(format!("snmp={}", address.port()));
+    }
+    if args.ble {
+        txt.push("ble=1".to_string());
+    }
 
-        if self.temperature.current < self.temperature.target {
-            return self.pwm.current - self.pwm.decrement;
+    // Required by the argument parser along with --mdns
+    let port = args.coap_listen.unwrap().port();
+    let name = instance::service_name(&config::hostname(), args.instance_name.as_deref());
+    match Advertisement::publish(&name, port, &txt) {
+        Ok(advertisement) => Some(advertisement),
+        Err(error) => {
+            log::warn!("Failed to advertise over mDNS: {}", error);
+            None
         }
+    }
+}
 
-        return self.pwm.current;
+/// Prints the combined status of the controllers advertised on the network.
+fn fleet_status(options: &FleetStatusArgs) {
+    let instances = mdns::browse().unwrap_or_else(|error| {
+        eprintln!(
+            "Failed to discover controllers with avahi-browse: {}",
+            error
+        );
+        std::process::exit(2);
+    });
+    if instances.is_empty() {
+        eprintln!("No controllers found, they are advertised with --coap-listen and --mdns");
+        std::process::exit(1);
     }
+    print!(
+        "{}",
+        fleet::table(&fleet::query(&instances, options.timeout))
+    );
+}
 
-    /// Starts the controller
-    fn start(&mut self) {
-        self.pwm.init();
+/// Converts a fancontrol configuration and writes the resulting config.
+fn import_fancontrol(options: &ImportFancontrolArgs) {
+    let fancontrol = std::fs::read_to_string(&options.path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| import::parse_fancontrol(&text))
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to read {:?}: {}", options.path, error);
+            std::process::exit(2);
+        });
+    let config = import::to_config(&fancontrol, &options.path.display().to_string());
+
+    let result = match &options.out {
+        Some(path) => std::fs::write(path, config),
+        None => io::stdout().write_all(config.as_bytes()),
+    };
+    if let Err(error) = result {
+        eprintln!("Failed to write config: {}", error);
+        std::process::exit(1);
+    }
+}
 
-        loop {
-            thread::sleep(self.pollrate);
+/// Writes the config file out of a bundle, or its effective options if it has none.
+fn import_bundle(options: &ImportBundleArgs) {
+    let bundle = File::open(&options.path)
+        .and_then(|mut file| Bundle::read(&mut file))
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to read bundle {:?}: {}", options.path, error);
+            std::process::exit(2);
+        });
+    let Some(config) = bundle
+        .get(bundle::CONFIG)
+        .or_else(|| bundle.get(bundle::EFFECTIVE))
+    else {
+        eprintln!("Bundle {:?} holds no config", options.path);
+        std::process::exit(2);
+    };
 
-            self.temperature.read();
+    let result = match &options.out {
+        Some(path) => std::fs::write(path, config),
+        None => io::stdout().write_all(config),
+    };
+    if let Err(error) = result {
+        eprintln!("Failed to write config: {}", error);
+        std::process::exit(1);
+    }
+}
 
-            // Avoid making unnecessary PWM changes when we are near the target temperature
-            if self.temperature.current.round() == self.temperature.target {
-                continue;
-            }
+/// Returns the status of the running controller, asked through its control socket.
+#[cfg(unix)]
+fn live_status(path: &std::path::Path) -> io::Result<String> {
+    use io::BufRead;
 
-            let new_pwm = self.pwm.fix_pwm_value(self.get_required_pwm());
-
-            // Only make changes if new PWM value actually differs from previous
-            if new_pwm > self.pwm.current {
-                self.pwm.write(new_pwm);
-                println!(
-                    "Current temperature {}°C (target {}°C), rising fan speed {} -> {}",
-                    self.temperature.current,
-                    self.temperature.target,
-                    self.pwm.previous,
-                    self.pwm.current
-                );
-            }
+    let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    stream.write_all(b"status\n")?;
+    let mut status = String::new();
+    io::BufReader::new(stream).read_line(&mut status)?;
+    Ok(status)
+}
 
-            if new_pwm < self.pwm.current {
-                self.pwm.write(new_pwm);
-                println!(
-                    "Current temperature {}°C (target {}°C), lowering fan speed {} -> {}",
-                    self.temperature.current,
-                    self.temperature.target,
-                    self.pwm.previous,
-                    self.pwm.current
-                );
+#[cfg(not(unix))]
+fn live_status(_path: &std::path::Path) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "control sockets are only available on Unix",
+    ))
+}
+
+/// Packs the setup and recent history into a bundle, leaving out whatever isn't there.
+fn export_bundle(args: &Args, options: &ExportBundleArgs) {
+    let instance = args.instance_name.as_deref();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut bundle = Bundle::new();
+    bundle.add(
+        "bundle.txt",
+        format!(
+            "fan-controller {}\nhost {}\ninstance {}\nexported {}\n",
+            env!("CARGO_PKG_VERSION"),
+            config::hostname(),
+            instance.unwrap_or("-"),
+            time
+        ),
+    );
+
+    match reload::effective(std::env::args().collect()) {
+        Ok(options) => bundle.add(bundle::EFFECTIVE, bundle::to_config(&options)),
+        Err(error) => eprintln!("Leaving out effective options: {}", error),
+    }
+    let files = [
+        (bundle::CONFIG, args.config.clone()),
+        ("calibration.csv", options.calibration.clone()),
+        (
+            "telemetry-spool.jsonl",
+            args.telemetry_spool
+                .as_ref()
+                .map(|path| instance::namespaced(path, instance)),
+        ),
+    ];
+    for (name, path) in files {
+        let Some(path) = path else {
+            continue;
+        };
+        match std::fs::read(&path) {
+            Ok(content) => bundle.add(name, content),
+            Err(error) => eprintln!("Leaving out {:?}: {}", path, error),
+        }
+    }
+
+    if let Some(path) = &args.event_log {
+        let path = instance::namespaced(path, instance);
+        match std::fs::read_to_string(&path) {
+            Ok(events) => {
+                let mut events = bundle::tail(&events, options.events).to_string();
+                events.push('\n');
+                bundle.add("events.jsonl", events);
             }
+            Err(error) => eprintln!("Leaving out {:?}: {}", path, error),
+        }
+    }
+    if let Some(path) = &args.control_socket {
+        let path = instance::namespaced(path, instance);
+        match live_status(&path) {
+            Ok(status) => bundle.add("status.json", status),
+            Err(error) => eprintln!("Leaving out status, controller not reachable: {}", error),
         }
     }
+
+    let result = File::create(&options.out).and_then(|mut file| bundle.write(&mut file, time));
+    if let Err(error) = result {
+        eprintln!("Failed to write bundle {:?}: {}", options.out, error);
+        std::process::exit(1);
+    }
+    let names: Vec<&str> = bundle.names().collect();
+    println!("Wrote {} with {}", options.out.display(), names.join(", "));
 }
 
-/// Prints systemd service file content with the given options.
-fn print_systemd(args: &Args) {
-    let options = format!(
-        "--gpio-pwm {:?} --pollrate {:?} --temperature-target-value {:?}",
-        args.gpio_pwm, args.pollrate, args.temperature_target_value
-    );
+/// Runs the guided setup, writing the config file and systemd unit it arrives at.
+fn setup(args: &Args, options: &SetupArgs) {
+    let sensors = setup::find_sensors(std::path::Path::new("/sys/class"));
+    let (mut input, mut prompt) = (io::stdin().lock(), io::stdout());
+    let mut wizard = Wizard::new(&mut input, &mut prompt);
 
+    let answers = wizard
+        .ask(&sensors, args.temperature_target_value)
+        .and_then(|mut answers| {
+            if options.skip_spin_test {
+                return Ok(answers);
+            }
+            let Some(mut output) = spin_test_output(answers.pin, args) else {
+                return Ok(answers);
+            };
+            if !wizard.spin_test(&mut answers, output.as_mut(), &mut SystemClock)? {
+                eprintln!("{}", text("setup-not-spinning", &[("pin", &answers.pin)]));
+                std::process::exit(1);
+            }
+            Ok(answers)
+        })
+        .unwrap_or_else(|error| {
+            eprintln!("{}", text("setup-failed", &[("error", &error)]));
+            std::process::exit(1);
+        });
+
+    let unit = systemd_unit(&format!("--config {}", options.out.display()));
+    let result = options
+        .out
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&options.out, setup::to_config(&answers)))
+        .and_then(|()| std::fs::write(&options.unit, unit + "\n"));
+    if let Err(error) = result {
+        eprintln!("{}", text("setup-write-failed", &[("error", &error)]));
+        std::process::exit(1);
+    }
     println!(
-        "[Unit]
-Description=PWM fan controller for Orange PI systems
+        "{}",
+        text(
+            "setup-done",
+            &[
+                ("config", &options.out.display()),
+                ("unit", &options.unit.display()),
+                (
+                    "service",
+                    &options
+                        .unit
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                ),
+            ],
+        )
+    );
+}
 
-[Service]
-Type=simple
-ExecStart=/usr/local/bin/opi-fan-controller {}
-Restart=on-failure
+/// Returns the output the setup spin test drives the fan through.
+#[cfg(feature = "wiringpi")]
+fn spin_test_output(pin: i32, args: &Args) -> Option<Box<dyn Output>> {
+    Some(Box::new(fan_controller::softpwm::SoftPwm::on_pin(
+        pin, args,
+    )))
+}
 
-[Install]
-WantedBy=multi-user.target",
-        options
-    );
+#[cfg(not(feature = "wiringpi"))]
+fn spin_test_output(_pin: i32, _args: &Args) -> Option<Box<dyn Output>> {
+    eprintln!("{}", text("setup-no-wiringpi", &[]));
+    None
 }
 
-fn main() {
-    let args = Args::parse();
+/// Plots the fan speed over temperature for the options and writes the plot.
+fn curve_render(args: &Args, options: &CurveRenderArgs) {
+    let format = options
+        .format
+        .or_else(|| {
+            let extension = options.out.as_ref()?.extension()?.to_str()?;
+            PlotFormat::from_extension(extension)
+        })
+        .unwrap_or(PlotFormat::Text);
 
-    if args.print_systemd == true {
-        print_systemd(&args);
-        return;
+    let mut temperatures = vec![args.temperature_target_value, args.temperature_max_value];
+    temperatures.extend(args.fan_off_below);
+    if let Some(curve) = &args.fan_curve {
+        temperatures.extend(curve.points().iter().map(|(temperature, _)| *temperature));
     }
+    let (from, to) = Plot::range(&temperatures);
 
-    let mut controller = Controller::new(&args);
-    controller.start();
-}
+    let controller = Rc::new(Controller::new(args, Box::new(NullOutput)));
+    let (fan, ratio) = (Rc::clone(&controller), args.exhaust_ratio);
+    let acoustics = args
+        .exhaust_gpio_pwm
+        .and_then(|_| Acoustics::from_args(args));
+    let duties = Rc::new(move |temperature| {
+        pairing::duties(fan.duty_at(temperature), ratio, acoustics.as_ref())
+    });
+    let intake = Rc::clone(&duties);
+    let mut series = vec![Series::new(
+        if args.exhaust_gpio_pwm.is_some() {
+            "intake"
+        } else {
+            "fan"
+        },
+        '*',
+        move |temperature| intake(temperature).0,
+    )];
+    if args.exhaust_gpio_pwm.is_some() {
+        series.push(Series::new("exhaust", 'o', move |temperature| {
+            duties(temperature).1
+        }));
+    }
+    let operating = match sensor::from_args(args).read() {
+        Ok(temperature) => Some((temperature, controller.duty_at(temperature))),
+        Err(error) => {
+            eprintln!("Leaving out the operating point: {}", error);
+            None
+        }
+    };
 
-#[cfg(test)]
-mod tests {
-    use super::{Controller, Pwm, Temperature};
-    use std::time;
+    let plot = Plot {
+        from,
+        to,
+        series,
+        operating,
+    }
+    .render(format);
+    let result = match &options.out {
+        Some(path) => std::fs::write(path, plot),
+        None => io::stdout().write_all(&plot),
+    };
+    if let Err(error) = result {
+        eprintln!("Failed to write plot: {}", error);
+        std::process::exit(1);
+    }
+}
 
-    #[test]
-    fn pwm_value_too_high() {
-        let pwm = Pwm {
-            current: 0,
-            previous: 0,
-            increment: 2,
-            decrement: 1,
-            min: 0,
-            max: 100,
-            gpio_pin: 0,
-        };
+/// Prints the QR code a dashboard app scans to add this controller.
+fn pairing_info(args: &Args, options: &PairingInfoArgs) {
+    let token = options.token.as_deref().map(|reference| {
+        secret::load(reference).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        })
+    });
+    let host = options.host.clone().unwrap_or_else(config::hostname);
+    let Some(uri) = pairing_info::uri(args, &host, token.as_ref()) else {
+        eprintln!("No endpoint to pair with, enable --coap-listen, --modbus-listen, --snmp-listen or --ble");
+        std::process::exit(2);
+    };
+    let code = QrCode::encode(uri.as_bytes()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    });
 
-        let pwm_value = pwm.max + 10;
-        let value = pwm.fix_pwm_value(pwm_value);
-        assert_eq!(pwm.max, value);
+    match &options.png {
+        Some(path) => {
+            if let Err(error) = std::fs::write(path, code.to_png(8)) {
+                eprintln!("Failed to write {:?}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", code.to_terminal()),
+    }
+    // Without a token the URI has nothing secret, and is handy for typing in
+    if token.is_none() {
+        println!("{}", uri);
     }
+}
 
-    #[test]
-    fn pwm_value_too_low() {
-        let pwm = Pwm {
-            current: 0,
-            previous: 0,
-            increment: 2,
-            decrement: 1,
-            min: 0,
-            max: 100,
-            gpio_pin: 0,
-        };
+fn main() {
+    let argv = config::args_with_config(std::env::args().collect()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    });
+    let trips = trip::detect(&argv);
+    let argv = trip::args_with_trip_points(argv, trips.as_ref());
+    let board = board::detect(std::path::Path::new(board::MODEL));
+    let args = Args::try_parse_valid(board::args_with_board(argv, board.as_ref()))
+        .unwrap_or_else(|error| error.exit());
+    i18n::set_language(args.lang.unwrap_or_else(Language::from_env));
 
-        let pwm_value = pwm.min - 10;
-        let value = pwm.fix_pwm_value(pwm_value);
-        assert_eq!(pwm.min, value);
+    if args.print_systemd {
+        print_systemd(&args);
+        return;
     }
 
-    #[test]
-    fn pwm_value_within_limits() {
-        let pwm = Pwm {
-            current: 0,
-            previous: 0,
-            increment: 2,
-            decrement: 1,
-            min: 0,
-            max: 100,
-            gpio_pin: 0,
-        };
+    match &args.operation {
+        Some(Operation::Tune(options)) if options.live => {
+            tune_live(&args);
+            return;
+        }
+        Some(Operation::PairingInfo(options)) => {
+            pairing_info(&args, options);
+            return;
+        }
+        Some(Operation::Fleet(FleetCommand::Status(options))) => {
+            fleet_status(options);
+            return;
+        }
+        Some(Operation::FrequencySweep(options)) => {
+            frequency_sweep(options);
+            return;
+        }
+        Some(Operation::Import(ImportCommand::Fancontrol(options))) => {
+            import_fancontrol(options);
+            return;
+        }
+        Some(Operation::Import(ImportCommand::Bundle(options))) => {
+            import_bundle(options);
+            return;
+        }
+        Some(Operation::ExportBundle(options)) => {
+            export_bundle(&args, options);
+            return;
+        }
+        Some(Operation::Curve(CurveCommand::Render(options))) => {
+            curve_render(&args, options);
+            return;
+        }
+        Some(Operation::Setup(options)) => {
+            setup(&args, options);
+            return;
+        }
+        Some(operation @ (Operation::Calibrate(_) | Operation::Tune(_)))
+            if args.gpio_pwm.is_none()
+                && args.serial_port.is_none()
+                && args.mcp23017_pin.is_none() =>
+        {
+            // Subcommands lift the required output, but calibration and tuning drive the fan
+            let name = match operation {
+                Operation::Calibrate(_) => "calibrate",
+                _ => "tune",
+            };
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    format!("{} needs --gpio-pwm, --serial-port or --mcp23017-pin", name),
+                )
+                .exit();
+        }
+        _ => {}
+    }
 
-        let pwm_value = pwm.max - 10;
-        let value = pwm.fix_pwm_value(pwm_value);
-        assert_eq!(pwm_value, value);
+    logging::init(args.log_filter.clone());
+    if let Some(board) = board.as_ref().filter(|_| !args.no_board_defaults) {
+        log::info!("{}", board.describe());
+    }
+    if let Some(trips) = &trips {
+        log::info!("{}", trips.describe());
     }
+    let status = Status::new()
+        .with_forecast(args.forecast_horizon, args.temperature_max_value)
+        .with_history(args.status_history);
+    let latch = Latch::new();
+    let setpoint = Setpoint::new();
 
-    #[test]
-    fn temperature_over_high_limit() {
-        let controller = Controller {
-            pollrate: time::Duration::from_secs(5),
-            temperature: Temperature {
-                max: 70.0,
-                current: 80.0, // Higher than max
-                previous: 0.0,
-                target: 40.0,
-                source_file_path: "".to_string(),
-            },
-            pwm: Pwm {
-                current: 0,
-                previous: 0,
-                decrement: 1,
-                increment: 2,
-                min: 0,
-                max: 100,
-                gpio_pin: 0,
-            },
+    #[cfg(unix)]
+    if let Some(path) = &args.control_socket {
+        let path = &instance::namespaced(path, args.instance_name.as_deref());
+        let context = fan_controller::control::Context {
+            status: status.clone(),
+            latch: latch.clone(),
+            setpoint: setpoint.clone(),
         };
+        if let Err(error) = fan_controller::control::serve(path, context) {
+            eprintln!("Failed to open control socket {:?}: {}", path, error);
+            std::process::exit(2);
+        }
+    }
 
-        let value = controller.get_required_pwm();
-        assert_eq!(controller.pwm.max, value);
+    if let Some(address) = args.snmp_listen {
+        if let Err(error) = snmp::serve(address, args.snmp_community.clone(), status.clone()) {
+            eprintln!("Failed to listen for SNMP on {}: {}", address, error);
+            std::process::exit(2);
+        }
     }
 
-    #[test]
-    fn temperature_same_as_target() {
-        let controller = Controller {
-            pollrate: time::Duration::from_secs(5),
-            temperature: Temperature {
-                target: 40.0,
-                current: 40.0, // Same as target
-                previous: 0.0,
-                max: 70.0,
-                source_file_path: "".to_string(),
-            },
-            pwm: Pwm {
-                current: 50,
-                previous: 0,
-                decrement: 1,
-                increment: 2,
-                min: 0,
-                max: 100,
-                gpio_pin: 0,
-            },
-        };
+    if let Some(address) = args.modbus_listen {
+        let served = modbus::serve(
+            address,
+            status.clone(),
+            setpoint.clone(),
+            args.temperature_max_value,
+        );
+        if let Err(error) = served {
+            eprintln!("Failed to listen for Modbus on {}: {}", address, error);
+            std::process::exit(2);
+        }
+    }
 
-        let value = controller.get_required_pwm();
-        assert_eq!(controller.pwm.current, value);
+    if let Some(address) = args.coap_listen {
+        let served = coap::serve(
+            address,
+            status.clone(),
+            setpoint.clone(),
+            args.temperature_max_value,
+        );
+        if let Err(error) = served {
+            eprintln!("Failed to listen for CoAP on {}: {}", address, error);
+            std::process::exit(2);
+        }
     }
 
-    #[test]
-    fn temperature_over_target_and_rising() {
-        let controller = Controller {
-            pollrate: time::Duration::from_secs(5),
-            temperature: Temperature {
-                target: 40.0,
-                current: 55.0,  // Higher than target and previous
-                previous: 50.0, // Lower than current
-                max: 70.0,
-                source_file_path: "".to_string(),
-            },
-            pwm: Pwm {
-                current: 50,
-                previous: 0,
-                decrement: 1,
-                increment: 2,
-                min: 0,
-                max: 100,
-                gpio_pin: 0,
-            },
+    let knob = Knob {
+        setpoint: setpoint.clone(),
+        status: status.clone(),
+        step: args.encoder_step,
+        target: args.temperature_target_value,
+        temperature_max: args.temperature_max_value,
+    };
+    if let Some(pins) = args.gpio_encoder {
+        encoder(pins, knob.clone());
+    }
+
+    let modes =
+        (args.gpio_button.is_some() || args.lirc_socket.is_some() || !args.quiet_hours.is_empty())
+            .then(|| Modes::new(args.boost_duration, args.quiet_pwm_max));
+    if let (Some(pin), Some(modes)) = (args.gpio_button, &modes) {
+        button(pin, modes.clone());
+    }
+    if let (Some(path), Some(modes)) = (&args.lirc_socket, &modes) {
+        let bindings = if args.lirc_key.is_empty() {
+            lirc::default_bindings()
+        } else {
+            args.lirc_key.clone()
         };
+        lirc::listen(
+            path.clone(),
+            Remote {
+                bindings,
+                modes: modes.clone(),
+                knob,
+            },
+        );
+    }
 
-        let value = controller.get_required_pwm();
-        assert_eq!(controller.pwm.current + controller.pwm.increment, value);
+    if args.ble {
+        if let Err(error) = ble::serve(status.clone()) {
+            eprintln!("Failed to serve BLE GATT service: {}", error);
+            std::process::exit(2);
+        }
     }
 
-    #[test]
-    fn temperature_over_target_and_lowering() {
-        let controller = Controller {
-            pollrate: time::Duration::from_secs(5),
-            temperature: Temperature {
-                target: 40.0,
-                current: 50.0,  // Higher than target, but lower than previous
-                previous: 55.0, // Higher than current
-                max: 70.0,
-                source_file_path: "".to_string(),
-            },
-            pwm: Pwm {
-                current: 50,
-                previous: 0,
-                decrement: 1,
-                increment: 2,
-                min: 0,
-                max: 100,
-                gpio_pin: 0,
-            },
-        };
+    // Withdrawn when dropped at exit
+    let _advertisement = if args.mdns { advertise(&args) } else { None };
 
-        let value = controller.get_required_pwm();
-        assert_eq!(controller.pwm.current - controller.pwm.decrement, value);
+    let sinks = sinks(&args, &status);
+    match &args.operation {
+        Some(Operation::Calibrate(options)) => calibrate(&args, options, sinks),
+        Some(Operation::Tune(options)) => tune(&args, options, sinks),
+        // Handled before any server starts
+        Some(
+            Operation::PairingInfo(_)
+            | Operation::Fleet(_)
+            | Operation::FrequencySweep(_)
+            | Operation::Import(_)
+            | Operation::ExportBundle(_)
+            | Operation::Curve(_)
+            | Operation::Setup(_),
+        ) => {
+            unreachable!()
+        }
+        None if args.oneshot => oneshot(&args, sinks),
+        None => {
+            let mut controller = Controller::new(&args, output(&args))
+                .with_reloader(Reloader::new(std::env::args().collect()))
+                .with_setpoint(setpoint);
+            if let Some(modes) = modes {
+                controller = controller.with_modes(modes);
+            }
+            if let Some(source) = &args.energy_input {
+                let signal = energy::watch(source.clone(), args.energy_interval);
+                controller = controller.with_energy(signal, Policy::new(&args));
+            }
+            for sink in sinks {
+                controller = controller.with_sink(sink);
+            }
+            if args.latch_overtemperature {
+                controller = controller.with_latch(latch);
+            }
+            if args.inhibit {
+                match Inhibitor::start() {
+                    Ok(inhibitor) => controller = controller.with_inhibitor(inhibitor),
+                    Err(error) => log::warn!("Failed to take inhibitor lock: {}", error),
+                }
+            }
+            #[cfg(unix)]
+            reload::install();
+            match args.run_for {
+                Some(duration) => controller.run_for_duration(duration),
+                None => controller.start(),
+            }
+        }
     }
+}
 
-    #[test]
-    fn temperature_below_target() {
-        let controller = Controller {
-            pollrate: time::Duration::from_secs(5),
-            temperature: Temperature {
-                target: 40.0,
-                current: 30.0, // Lower than target
-                previous: 0.0,
-                max: 70.0,
-                source_file_path: "".to_string(),
-            },
-            pwm: Pwm {
-                current: 50,
-                previous: 0,
-                decrement: 1,
-                increment: 2,
-                min: 0,
-                max: 100,
-                gpio_pin: 0,
-            },
-        };
+#[cfg(test)]
+mod tests {
+    use super::{systemd_options, systemd_unit};
+    use clap::Parser;
+    use fan_controller::args::Args;
 
-        let value = controller.get_required_pwm();
-        assert_eq!(controller.pwm.current - controller.pwm.decrement, value);
+    #[test]
+    fn systemd_unit_names_exhaust_fan_once() {
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "1",
+            "--exhaust-gpio-pwm",
+            "2",
+        ]);
+        let unit = systemd_unit(&systemd_options(&args));
+        let exec_start = unit
+            .lines()
+            .find(|line| line.starts_with("ExecStart="))
+            .unwrap();
+        assert_eq!(
+            "ExecStart=/usr/local/bin/opi-fan-controller --gpio-pwm 1 --exhaust-gpio-pwm 2 \
+             --exhaust-ratio 80 --pollrate 5 --temperature-target-value 40",
+            exec_start
+        );
     }
 }
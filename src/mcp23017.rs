@@ -0,0 +1,176 @@
+//! Relay outputs on an MCP23017 I2C GPIO expander, for enclosures running out of native pins.
+
+use crate::{pwm::Output, units::Duty};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::io::AsRawFd,
+};
+
+/// ioctl selecting the I2C target address of an i2c-dev file
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+
+// Register addresses with the default IOCON.BANK = 0 layout
+const IODIRA: u8 = 0x00;
+const OLATA: u8 = 0x14;
+
+/// Relay on one of the 16 expander pins, switched on when duty reaches a threshold.
+pub struct Mcp23017Relay {
+    bus: u8,
+    address: u16,
+    /// Expander pin, 0-7 for GPA0-7 and 8-15 for GPB0-7
+    pin: u8,
+    on_duty: Duty,
+    active_low: bool,
+    device: Option<File>,
+    /// First failure to switch the relay since it was last taken
+    error: Option<io::Error>,
+}
+
+impl Mcp23017Relay {
+    pub fn new(bus: u8, address: u16, pin: u8, on_duty: Duty, active_low: bool) -> Self {
+        Self {
+            bus,
+            address,
+            pin,
+            on_duty,
+            active_low,
+            device: None,
+            error: None,
+        }
+    }
+
+    /// Register of the pin's port and its bit within it.
+    fn port_bit(&self, register: u8) -> (u8, u8) {
+        (register + self.pin / 8, 1 << (self.pin % 8))
+    }
+
+    fn open(&self) -> io::Result<File> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/dev/i2c-{}", self.bus))?;
+
+        if unsafe {
+            libc::ioctl(
+                device.as_raw_fd(),
+                I2C_SLAVE,
+                libc::c_ulong::from(self.address),
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(device)
+    }
+
+    /// Sets or clears the pin's bit in a register, leaving other pins untouched.
+    fn update_bit(&mut self, register: u8, set: bool) -> io::Result<()> {
+        let (register, bit) = self.port_bit(register);
+        let device = self
+            .device
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "not initialized"))?;
+
+        device.write_all(&[register])?;
+        let mut value = [0u8];
+        device.read_exact(&mut value)?;
+
+        let value = if set { value[0] | bit } else { value[0] & !bit };
+        device.write_all(&[register, value])
+    }
+
+    /// Returns whether the output latch should be high for the given relay state.
+    fn level(&self, on: bool) -> bool {
+        on != self.active_low
+    }
+}
+
+impl Output for Mcp23017Relay {
+    fn init(&mut self) {
+        let device = self.open().unwrap_or_else(|error| {
+            panic!(
+                "Failed to open MCP23017 at 0x{:02x} on I2C bus {}: {}",
+                self.address, self.bus, error
+            )
+        });
+        self.device = Some(device);
+
+        // Start switched on, like other outputs start at full duty, before making the pin an output
+        let result = self
+            .update_bit(OLATA, self.level(true))
+            .and_then(|_| self.update_bit(IODIRA, false));
+        if let Err(error) = result {
+            panic!("Failed to configure MCP23017 pin {}: {}", self.pin, error);
+        }
+    }
+
+    fn write(&mut self, duty: Duty) {
+        let level = self.level(duty >= self.on_duty);
+        if let Err(error) = self.update_bit(OLATA, level) {
+            let error = io::Error::new(
+                error.kind(),
+                format!("failed to switch MCP23017 pin {}: {}", self.pin, error),
+            );
+            self.error.get_or_insert(error);
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.device = None;
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Opens the bus again and makes the pin an output once more, in case the expander was reset.
+    fn reinit(&mut self) -> io::Result<()> {
+        self.device = Some(self.open()?);
+        self.update_bit(OLATA, self.level(true))?;
+        self.update_bit(IODIRA, false)
+    }
+}
+
+/// Parses an I2C address given in decimal or as hex with a `0x` prefix.
+pub fn parse_address(value: &str) -> Result<u16, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+
+    match parsed {
+        Ok(address) if address <= 0x7f => Ok(address),
+        _ => Err(format!("{} is not a 7-bit I2C address", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_address, Mcp23017Relay, OLATA};
+    use crate::units::Duty;
+
+    #[test]
+    fn pins_map_to_port_registers() {
+        let relay = |pin| Mcp23017Relay::new(1, 0x20, pin, Duty::FULL, false);
+
+        assert_eq!((0x14, 0b0000_0001), relay(0).port_bit(OLATA));
+        assert_eq!((0x14, 0b1000_0000), relay(7).port_bit(OLATA));
+        assert_eq!((0x15, 0b0000_0100), relay(10).port_bit(OLATA));
+    }
+
+    #[test]
+    fn active_low_relays_invert_level() {
+        let relay = Mcp23017Relay::new(1, 0x20, 0, Duty::FULL, true);
+        assert!(!relay.level(true));
+        assert!(relay.level(false));
+    }
+
+    #[test]
+    fn parses_hex_and_decimal_addresses() {
+        assert_eq!(Ok(0x20), parse_address("0x20"));
+        assert_eq!(Ok(0x27), parse_address("39"));
+        assert!(parse_address("0x80").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! Thermistors read through an MCP3008 SPI ADC, for cheap probes placed anywhere in an enclosure.
+//!
+//! SPI is accessed through Linux spidev, reads fail with `Unsupported` elsewhere.
+
+use crate::{
+    sensor::{Raw, Sensor, SensorError},
+    units::Celsius,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io,
+};
+
+/// `SPI_IOC_MESSAGE(1)`, a single full-duplex transfer
+#[cfg(target_os = "linux")]
+const SPI_IOC_MESSAGE_1: libc::c_ulong = 0x4020_6b00;
+
+/// Highest 10-bit ADC reading
+const ADC_MAX: f64 = 1023.0;
+
+/// Transfer descriptor of the spidev ioctl interface.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct SpiIocTransfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+/// Steinhart-Hart coefficients describing a thermistor's resistance curve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SteinhartHart {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+impl SteinhartHart {
+    /// Returns the temperature of a thermistor with the given resistance in ohms.
+    pub fn temperature(&self, resistance: f64) -> f64 {
+        let ln = resistance.ln();
+        1.0 / (self.a + self.b * ln + self.c * ln.powi(3)) - 273.15
+    }
+}
+
+/// Parses coefficients given as `A,B,C`.
+pub fn parse_coefficients(value: &str) -> Result<SteinhartHart, String> {
+    let coefficients: Vec<f64> = value
+        .split(',')
+        .map(|part| part.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|error| format!("invalid coefficient: {}", error))?;
+
+    match coefficients[..] {
+        [a, b, c] => Ok(SteinhartHart { a, b, c }),
+        _ => Err("expected three coefficients A,B,C".to_string()),
+    }
+}
+
+/// NTC thermistor wired from an MCP3008 input to ground, with a series resistor to the reference voltage.
+pub struct Mcp3008Thermistor {
+    device_path: String,
+    channel: u8,
+    series_resistance: f64,
+    coefficients: SteinhartHart,
+    device: Option<File>,
+    /// Value of the last reading
+    adc: Option<u16>,
+}
+
+impl Mcp3008Thermistor {
+    pub fn new(
+        device_path: &str,
+        channel: u8,
+        series_resistance: f64,
+        coefficients: SteinhartHart,
+    ) -> Self {
+        Self {
+            device_path: device_path.to_string(),
+            channel,
+            series_resistance,
+            coefficients,
+            device: None,
+            adc: None,
+        }
+    }
+
+    /// Reads the raw 10-bit value of the channel.
+    fn read_adc(&mut self) -> io::Result<u16> {
+        if self.device.is_none() {
+            let device = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&self.device_path)?;
+            self.device = Some(device);
+        }
+        let device = self.device.as_ref().unwrap();
+
+        // Start bit, single-ended mode with channel select, then clock out the result
+        let tx = [0x01, (0x08 | self.channel) << 4, 0x00];
+        let rx = transfer(device, &tx).inspect_err(|_| {
+            // Reopen on the next read in case the device went away
+            self.device = None;
+        })?;
+
+        Ok((u16::from(rx[1] & 0x03) << 8) | u16::from(rx[2]))
+    }
+
+    /// Converts an ADC reading to temperature, or `None` for an open or shorted probe.
+    fn convert(&self, adc: u16) -> Option<Celsius> {
+        let adc = f64::from(adc);
+        if adc <= 0.0 || adc >= ADC_MAX {
+            return None;
+        }
+
+        let resistance = self.series_resistance * adc / (ADC_MAX - adc);
+        let degrees = self.coefficients.temperature(resistance);
+        if !degrees.is_finite() {
+            return None;
+        }
+
+        Some(Celsius::from_millidegrees((degrees * 1000.0).round() as i32))
+    }
+}
+
+/// Clocks the given bytes out to the device while reading as many back.
+#[cfg(target_os = "linux")]
+fn transfer(device: &File, tx: &[u8; 3]) -> io::Result<[u8; 3]> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut rx = [0u8; 3];
+    let transfer = SpiIocTransfer {
+        tx_buf: tx.as_ptr() as u64,
+        rx_buf: rx.as_mut_ptr() as u64,
+        len: tx.len() as u32,
+        speed_hz: 1_000_000,
+        bits_per_word: 8,
+        ..Default::default()
+    };
+
+    if unsafe { libc::ioctl(device.as_raw_fd(), SPI_IOC_MESSAGE_1, &transfer) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(rx)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn transfer(_device: &File, _tx: &[u8; 3]) -> io::Result<[u8; 3]> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SPI is only supported on Linux",
+    ))
+}
+
+impl Sensor for Mcp3008Thermistor {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        let source = format!("{} channel {}", self.device_path, self.channel);
+        let adc = self
+            .read_adc()
+            .map_err(|error| SensorError::Read(source, error))?;
+
+        self.adc = Some(adc);
+        self.convert(adc)
+            .ok_or_else(|| SensorError::Parse(format!("ADC value {}", adc)))
+    }
+
+    fn raw(&self) -> Option<Raw> {
+        self.adc.map(Raw::Counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_coefficients, Mcp3008Thermistor};
+    use crate::units::Celsius;
+
+    fn thermistor() -> Mcp3008Thermistor {
+        let coefficients =
+            parse_coefficients("1.009249522e-3,2.378405444e-4,2.019202697e-7").unwrap();
+        Mcp3008Thermistor::new("/dev/spidev0.0", 0, 10_000.0, coefficients)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn transfer_matches_kernel_layout() {
+        assert_eq!(32, std::mem::size_of::<super::SpiIocTransfer>());
+    }
+
+    #[test]
+    fn converts_midscale_to_room_temperature() {
+        // Equal to the 10k series resistor, which this thermistor reaches at 25°C
+        assert_eq!(
+            Some(Celsius::new(25, 0)),
+            thermistor().convert(512).map(|c| c.round())
+        );
+    }
+
+    #[test]
+    fn hotter_thermistor_reads_lower() {
+        let thermistor = thermistor();
+        assert!(thermistor.convert(300).unwrap() > thermistor.convert(600).unwrap());
+    }
+
+    #[test]
+    fn rejects_open_or_shorted_probe() {
+        assert_eq!(None, thermistor().convert(0));
+        assert_eq!(None, thermistor().convert(1023));
+    }
+
+    #[test]
+    fn requires_three_coefficients() {
+        assert!(parse_coefficients("1,2").is_err());
+        assert!(parse_coefficients("1,x,3").is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! DNS-SD advertisement over mDNS, so dashboards can find every controller on the LAN.
+//!
+//! Instances are published as `_fan-controller._udp` at the CoAP endpoint, whose `/status`
+//! resource has the full status, with the other endpoints in TXT records. Publishing and
+//! browsing go through Avahi's `avahi-publish` and `avahi-browse`, so no D-Bus library is
+//! needed.
+
+use std::{
+    io,
+    net::IpAddr,
+    process::{Child, Command, Stdio},
+};
+
+/// Service type instances are published as
+pub const SERVICE_TYPE: &str = "_fan-controller._udp";
+
+/// Published service, withdrawn when dropped.
+pub struct Advertisement {
+    publisher: Child,
+}
+
+impl Advertisement {
+    /// Publishes this machine's instance under the given name.
+    pub fn publish(name: &str, port: u16, txt: &[String]) -> io::Result<Self> {
+        let publisher = Command::new("avahi-publish")
+            .args(["--service", name, SERVICE_TYPE, &port.to_string()])
+            .args(txt)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()?;
+        Ok(Self { publisher })
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let _ = self.publisher.kill();
+        let _ = self.publisher.wait();
+    }
+}
+
+/// Controller found on the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    pub name: String,
+    pub host: String,
+    pub address: IpAddr,
+    pub port: u16,
+    pub txt: Vec<(String, String)>,
+}
+
+/// Parses a resolved entry of `avahi-browse --parsable --resolve` output.
+///
+/// Entries look like `=;eth0;IPv4;name;_fan-controller._udp;local;host.local;192.0.2.7;5683;"modbus=502"`,
+/// with separators and spaces in the name escaped as three decimal digits.
+pub fn parse_browse_line(line: &str) -> Option<Instance> {
+    let fields: Vec<&str> = line.splitn(10, ';').collect();
+    if fields.len() != 10 || fields[0] != "=" || fields[4] != SERVICE_TYPE {
+        return None;
+    }
+
+    let txt = fields[9]
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .filter_map(|record| {
+            let (key, value) = record.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect();
+    Some(Instance {
+        name: unescape(fields[3]),
+        host: fields[6].to_string(),
+        address: fields[7].parse().ok()?,
+        port: fields[8].parse().ok()?,
+        txt,
+    })
+}
+
+/// Undoes Avahi's escaping of labels, `\032` for a space and `\.` for a dot.
+fn unescape(label: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = label.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = after;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let code = rest
+            .get(..3)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| digits.parse::<u8>().ok());
+        match code {
+            Some(code) => {
+                bytes.push(code);
+                rest = &rest[3..];
+            }
+            None => {
+                if let Some((&escaped, after)) = rest.split_first() {
+                    bytes.push(escaped);
+                    rest = after;
+                }
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Returns the instances currently on the network, once per address.
+pub fn browse() -> io::Result<Vec<Instance>> {
+    let output = Command::new("avahi-browse")
+        .args(["--parsable", "--resolve", "--terminate", SERVICE_TYPE])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "avahi-browse failed with {}",
+            output.status
+        )));
+    }
+
+    let mut instances: Vec<Instance> = Vec::new();
+    for instance in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_browse_line)
+    {
+        // Instances reachable on several interfaces are listed for each
+        if !instances.contains(&instance) {
+            instances.push(instance);
+        }
+    }
+    Ok(instances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_browse_line;
+
+    #[test]
+    fn parses_resolved_instances() {
+        let output = r#"+;eth0;IPv4;garage\032pi;_fan-controller._udp;local
+=;eth0;IPv4;garage\032pi;_fan-controller._udp;local;garage-pi.local;192.0.2.7;5683;"snmp=161" "modbus=502"
+=;eth0;IPv6;rack\0463;_fan-controller._udp;local;rack.local;fe80::1;5683;
+"#;
+        let instances: Vec<_> = output.lines().filter_map(parse_browse_line).collect();
+
+        assert_eq!(2, instances.len());
+        assert_eq!("garage pi", instances[0].name);
+        assert_eq!("192.0.2.7", instances[0].address.to_string());
+        assert_eq!(5683, instances[0].port);
+        assert_eq!(
+            vec![
+                ("snmp".to_string(), "161".to_string()),
+                ("modbus".to_string(), "502".to_string())
+            ],
+            instances[0].txt
+        );
+        assert_eq!("rack.3", instances[1].name);
+        assert!(instances[1].txt.is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! Test doubles for running the controller without hardware.
+
+use crate::{
+    clock::Clock,
+    events::{Event, Sink},
+    pwm::Output,
+    schedule::TimeOfDay,
+    season::MonthDay,
+    units::Duty,
+};
+use std::{
+    fs, io,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Clock that only advances when slept on, so long-running loops finish instantly.
+///
+/// Its wall-clock time starts at the Unix epoch unless set, and is told in UTC. Clones share the
+/// same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    wall_start: SystemTime,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            wall_start: UNIX_EPOCH,
+            elapsed: Arc::default(),
+        }
+    }
+
+    /// Returns a clock whose wall-clock time starts at the given Unix time in seconds.
+    pub fn at(seconds: u64) -> Self {
+        Self {
+            wall_start: UNIX_EPOCH + Duration::from_secs(seconds),
+            ..Self::new()
+        }
+    }
+
+    fn unix_seconds(&self) -> u64 {
+        self.wall()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    /// Returns virtual time passed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+
+    /// Moves time forward without sleeping.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn wall(&self) -> SystemTime {
+        self.wall_start + self.elapsed()
+    }
+
+    fn time_of_day(&self) -> TimeOfDay {
+        TimeOfDay::utc(self.unix_seconds())
+    }
+
+    fn today(&self) -> MonthDay {
+        MonthDay::utc(self.unix_seconds())
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Call made to an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Call {
+    Init,
+    Write(Duty),
+    Shutdown,
+    Reinit,
+}
+
+/// Call together with the moment it was made.
+#[derive(Debug, Clone, Copy)]
+pub struct Recorded {
+    pub at: Instant,
+    pub call: Call,
+}
+
+/// Output that records every call made to it.
+///
+/// Clones share the same record, so a test can keep one handle while the controller owns another.
+#[derive(Debug, Clone, Default)]
+pub struct MockOutput {
+    calls: Arc<Mutex<Vec<Recorded>>>,
+    clock: Option<MockClock>,
+    /// Whether writes and initializing again fail, as if the device went away
+    failing: Arc<AtomicBool>,
+    /// Whether a write failed since the error was last taken
+    failed: Arc<AtomicBool>,
+}
+
+impl MockOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an output timestamping calls with the given virtual clock.
+    pub fn with_clock(clock: &MockClock) -> Self {
+        Self {
+            clock: Some(clock.clone()),
+            ..Self::default()
+        }
+    }
+
+    /// Makes writes and initializing again fail, or succeed again.
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+
+    /// Returns all recorded calls in order.
+    pub fn calls(&self) -> Vec<Recorded> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Returns the duties written, in order.
+    pub fn writes(&self) -> Vec<Duty> {
+        self.calls()
+            .iter()
+            .filter_map(|recorded| match recorded.call {
+                Call::Write(duty) => Some(duty),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, call: Call) {
+        let at = match &self.clock {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        };
+        self.calls.lock().unwrap().push(Recorded { at, call });
+    }
+}
+
+impl Output for MockOutput {
+    fn init(&mut self) {
+        self.record(Call::Init);
+    }
+
+    /// Failed writes aren't recorded, as they never reached the fan.
+    fn write(&mut self, duty: Duty) {
+        if self.failing.load(Ordering::SeqCst) {
+            self.failed.store(true, Ordering::SeqCst);
+        } else {
+            self.record(Call::Write(duty));
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.record(Call::Shutdown);
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.failed
+            .swap(false, Ordering::SeqCst)
+            .then(|| io::Error::new(io::ErrorKind::NotFound, "device gone"))
+    }
+
+    fn reinit(&mut self) -> io::Result<()> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "device gone"));
+        }
+        self.record(Call::Reinit);
+        Ok(())
+    }
+}
+
+/// Sink that records every event published to it.
+///
+/// Clones share the same record.
+#[derive(Debug, Clone, Default)]
+pub struct MockSink {
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl MockSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns all received events in order.
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl Sink for MockSink {
+    fn handle(&mut self, event: &Event) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// File or directory under the temporary directory standing in for e.g. a sensor, removed when
+/// dropped so a failing test leaves nothing behind.
+///
+/// Names are unique per process and guard, so tests running in parallel never share one.
+#[derive(Debug)]
+pub struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    /// Reserves a path named after `name` without creating anything.
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "fan-controller-{}-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        Self { path }
+    }
+
+    /// Creates a file holding `content`.
+    pub fn file(name: &str, content: &str) -> Self {
+        let file = Self::new(name);
+        file.write(content);
+        file
+    }
+
+    /// Creates an empty directory.
+    pub fn dir(name: &str) -> Self {
+        let dir = Self::new(name);
+        fs::create_dir_all(&dir.path).unwrap();
+        dir
+    }
+
+    /// Replaces the content of the file, e.g. to change a temperature between polls.
+    pub fn write(&self, content: &str) {
+        fs::write(&self.path, content).unwrap();
+    }
+
+    /// Returns the path as given on the command line.
+    pub fn arg(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = if self.path.is_dir() {
+            fs::remove_dir_all(&self.path)
+        } else {
+            fs::remove_file(&self.path)
+        };
+    }
+}
@@ -0,0 +1,279 @@
+//! Modbus TCP server, so PLC and SCADA setups can read the controller and change its target.
+//!
+//! Temperatures are in hundredths of a degree Celsius as signed 16-bit values, with `0x8000`
+//! while unknown.
+//!
+//! | Table | Address | Value |
+//! |-------|---------|-------|
+//! | Input register | 0 | Temperature |
+//! | Input register | 1 | Fan duty in percent |
+//! | Input register | 2 | Target temperature |
+//! | Input register | 3 | 1 while latched at maximum after an overtemperature |
+//! | Input register | 4 | 1 while the sensor can't be read |
+//! | Holding register | 0 | Target temperature, writable |
+
+use crate::{
+    setpoint::Setpoint,
+    status::{Snapshot, Status},
+    units::Celsius,
+};
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+};
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+/// Register value of an unknown temperature
+const UNKNOWN: u16 = 0x8000;
+
+/// Largest number of registers a single read may ask for
+const MAX_READ: usize = 125;
+
+/// Registers served from the latest status, and where writes go.
+pub struct Registers<'a> {
+    pub snapshot: &'a Snapshot,
+    pub setpoint: &'a Setpoint,
+    /// Targets at or above this are refused, as the controller would never reach them
+    pub temperature_max: Celsius,
+}
+
+impl Registers<'_> {
+    fn input(&self) -> [u16; 5] {
+        [
+            centidegrees(self.snapshot.temperature),
+            self.snapshot.duty.map_or(0, |duty| duty.percent().into()),
+            centidegrees(self.snapshot.target),
+            self.snapshot.latched.into(),
+            self.snapshot.fault.is_some().into(),
+        ]
+    }
+
+    fn holding(&self) -> [u16; 1] {
+        [centidegrees(self.snapshot.target)]
+    }
+
+    /// Requests the target temperature given as a holding register value.
+    fn write_target(&self, value: u16) -> Result<(), u8> {
+        let target = Celsius::from_millidegrees(i32::from(value as i16) * 10);
+        if value == UNKNOWN || target >= self.temperature_max {
+            return Err(ILLEGAL_DATA_VALUE);
+        }
+        log::info!("Target temperature {}°C requested over Modbus", target);
+        self.setpoint.request_remote(target);
+        Ok(())
+    }
+
+    /// Executes a request PDU and returns the response PDU.
+    pub fn respond(&self, pdu: &[u8]) -> Vec<u8> {
+        let Some(&function) = pdu.first() else {
+            return exception(0, ILLEGAL_FUNCTION);
+        };
+        self.execute(function, &pdu[1..])
+            .unwrap_or_else(|code| exception(function, code))
+    }
+
+    fn execute(&self, function: u8, data: &[u8]) -> Result<Vec<u8>, u8> {
+        let word = |index: usize| -> Result<u16, u8> {
+            match data.get(index * 2..index * 2 + 2) {
+                Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+                None => Err(ILLEGAL_DATA_VALUE),
+            }
+        };
+
+        match function {
+            READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                let (start, count) = (usize::from(word(0)?), usize::from(word(1)?));
+                if count == 0 || count > MAX_READ {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                let input = self.input();
+                let holding = self.holding();
+                let table: &[u16] = if function == READ_INPUT_REGISTERS {
+                    &input
+                } else {
+                    &holding
+                };
+                let values = table
+                    .get(start..start + count)
+                    .ok_or(ILLEGAL_DATA_ADDRESS)?;
+
+                let mut response = vec![function, (count * 2) as u8];
+                for value in values {
+                    response.extend(value.to_be_bytes());
+                }
+                Ok(response)
+            }
+            WRITE_SINGLE_REGISTER => {
+                if word(0)? != 0 {
+                    return Err(ILLEGAL_DATA_ADDRESS);
+                }
+                self.write_target(word(1)?)?;
+                Ok([&[function][..], &data[..4]].concat())
+            }
+            WRITE_MULTIPLE_REGISTERS => {
+                let (start, count) = (word(0)?, word(1)?);
+                if start != 0 || count != 1 {
+                    return Err(ILLEGAL_DATA_ADDRESS);
+                }
+                // Values follow a byte count
+                let value = data
+                    .get(5..7)
+                    .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                    .ok_or(ILLEGAL_DATA_VALUE)?;
+                self.write_target(value)?;
+                Ok([&[function][..], &data[..4]].concat())
+            }
+            _ => Err(ILLEGAL_FUNCTION),
+        }
+    }
+}
+
+fn centidegrees(value: Option<Celsius>) -> u16 {
+    value.map_or(UNKNOWN, |value| {
+        let centidegrees = (value.millidegrees() / 10).clamp(-0x7fff, 0x7fff);
+        centidegrees as i16 as u16
+    })
+}
+
+fn exception(function: u8, code: u8) -> Vec<u8> {
+    vec![function | 0x80, code]
+}
+
+/// Serves Modbus TCP clients on the given address, each from its own thread.
+pub fn serve(
+    address: SocketAddr,
+    status: Status,
+    setpoint: Setpoint,
+    temperature_max: Celsius,
+) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(address)?;
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let status = status.clone();
+                    let setpoint = setpoint.clone();
+                    thread::spawn(move || {
+                        if let Err(error) = handle(stream, &status, &setpoint, temperature_max) {
+                            log::warn!("Modbus connection failed: {}", error);
+                        }
+                    });
+                }
+                Err(error) => log::warn!("Failed to accept Modbus connection: {}", error),
+            }
+        }
+    }))
+}
+
+/// Answers requests from one client until it disconnects.
+fn handle(
+    mut stream: TcpStream,
+    status: &Status,
+    setpoint: &Setpoint,
+    temperature_max: Celsius,
+) -> io::Result<()> {
+    loop {
+        // Transaction and protocol identifiers, length of the rest, unit identifier
+        let mut header = [0u8; 7];
+        match stream.read_exact(&mut header) {
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        if length < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Modbus frame too short",
+            ));
+        }
+        let mut pdu = vec![0u8; length - 1];
+        stream.read_exact(&mut pdu)?;
+
+        let snapshot = status.snapshot();
+        let registers = Registers {
+            snapshot: &snapshot,
+            setpoint,
+            temperature_max,
+        };
+        let response = registers.respond(&pdu);
+
+        let mut frame = header[..4].to_vec();
+        frame.extend(((response.len() + 1) as u16).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend(response);
+        stream.write_all(&frame)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registers;
+    use crate::{pwm::tests::duty, setpoint::Setpoint, status::Snapshot, units::Celsius};
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            temperature: Some(Celsius::new(41, 500)),
+            target: Some(Celsius::new(40, 0)),
+            duty: Some(duty(52)),
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn reads_input_registers() {
+        let snapshot = snapshot();
+        let setpoint = Setpoint::new();
+        let registers = Registers {
+            snapshot: &snapshot,
+            setpoint: &setpoint,
+            temperature_max: Celsius::new(70, 0),
+        };
+
+        assert_eq!(
+            vec![0x04, 6, 0x10, 0x36, 0x00, 0x34, 0x0f, 0xa0],
+            registers.respond(&[0x04, 0x00, 0x00, 0x00, 0x03])
+        );
+        // Past the end of the map
+        assert_eq!(
+            vec![0x84, 0x02],
+            registers.respond(&[0x04, 0x00, 0x04, 0x00, 0x02])
+        );
+        assert_eq!(
+            vec![0x81, 0x01],
+            registers.respond(&[0x01, 0x00, 0x00, 0x00, 0x01])
+        );
+    }
+
+    #[test]
+    fn writes_target() {
+        let snapshot = snapshot();
+        let setpoint = Setpoint::new();
+        let registers = Registers {
+            snapshot: &snapshot,
+            setpoint: &setpoint,
+            temperature_max: Celsius::new(70, 0),
+        };
+
+        // 45.50°C
+        let request = [0x06, 0x00, 0x00, 0x11, 0xc6];
+        assert_eq!(request.to_vec(), registers.respond(&request));
+        assert_eq!(Some(Celsius::new(45, 500)), setpoint.take());
+
+        // 75°C is above the maximum
+        assert_eq!(
+            vec![0x90, 0x03],
+            registers.respond(&[0x10, 0x00, 0x00, 0x00, 0x01, 0x02, 0x1d, 0x4c])
+        );
+        assert_eq!(None, setpoint.take());
+    }
+}
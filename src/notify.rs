@@ -0,0 +1,192 @@
+//! Desktop notifications of emergencies, sensor faults and alarms, for running on a desktop such
+//! as Raspberry Pi OS.
+//!
+//! Notifications are sent with `notify-send` from libnotify, which reaches whichever
+//! notification daemon the desktop runs without binding D-Bus here.
+
+use crate::{
+    alarm::AlarmLevel,
+    events::{Event, Sink},
+};
+use std::process::Command;
+
+/// How prominently the desktop shows a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    fn as_str(self) -> &'static str {
+        match self {
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+/// Message shown on the desktop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub urgency: Urgency,
+    pub body: String,
+}
+
+/// Sink showing notable events as desktop notifications.
+///
+/// A sensor fault is only shown when it starts, not on every poll it lasts, and once it's
+/// readable again.
+pub struct DesktopSink {
+    title: String,
+    /// Whether the last reading failed
+    faulted: bool,
+    /// Whether a failure to send was logged already
+    warned: bool,
+}
+
+impl DesktopSink {
+    /// Returns a sink titling notifications with the instance name, if any.
+    pub fn new(instance: Option<&str>) -> Self {
+        Self {
+            title: match instance {
+                Some(instance) => format!("Fan controller {}", instance),
+                None => "Fan controller".to_string(),
+            },
+            faulted: false,
+            warned: false,
+        }
+    }
+
+    /// Returns the notification an event calls for, if any.
+    pub fn notification(&mut self, event: &Event) -> Option<Notification> {
+        let (urgency, body) = match event {
+            Event::Overtemperature { temperature, max } => (
+                Urgency::Critical,
+                format!(
+                    "Emergency cooling: {}°C reached the maximum of {}°C, the fan runs at full \
+                     speed",
+                    temperature, max
+                ),
+            ),
+            Event::Latched { temperature } => (
+                Urgency::Critical,
+                format!(
+                    "Fan latched at maximum speed at {}°C until acknowledged",
+                    temperature
+                ),
+            ),
+            Event::LatchReleased => (
+                Urgency::Normal,
+                "Latch acknowledged, normal control resumes".to_string(),
+            ),
+            Event::Fault { message } if !self.faulted => {
+                self.faulted = true;
+                (
+                    Urgency::Critical,
+                    format!("{}; the fan runs at maximum speed", message),
+                )
+            }
+            Event::Alarm {
+                sensor,
+                level,
+                temperature,
+            } => (
+                match level {
+                    AlarmLevel::Critical => Urgency::Critical,
+                    AlarmLevel::Normal | AlarmLevel::Warning => Urgency::Normal,
+                },
+                match level {
+                    AlarmLevel::Normal => format!("{} back to normal at {}°C", sensor, temperature),
+                    level => format!("{} reached {} level at {}°C", sensor, level, temperature),
+                },
+            ),
+            Event::Sample { .. } if self.faulted => {
+                self.faulted = false;
+                (
+                    Urgency::Normal,
+                    "Temperature sensor readable again".to_string(),
+                )
+            }
+            _ => return None,
+        };
+        Some(Notification { urgency, body })
+    }
+}
+
+impl Sink for DesktopSink {
+    fn handle(&mut self, event: &Event) {
+        let Some(notification) = self.notification(event) else {
+            return;
+        };
+
+        let result = Command::new("notify-send")
+            .arg("--app-name=fan-controller")
+            .arg(format!("--urgency={}", notification.urgency.as_str()))
+            .arg(&self.title)
+            .arg(&notification.body)
+            .status();
+        let error = match result {
+            Ok(status) if status.success() => return,
+            Ok(status) => format!("notify-send exited with {}", status),
+            Err(error) => error.to_string(),
+        };
+        if !self.warned {
+            log::warn!("Failed to show desktop notification: {}", error);
+            self.warned = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DesktopSink, Urgency};
+    use crate::{alarm::AlarmLevel, events::Event, units::Celsius};
+
+    #[test]
+    fn notifies_of_emergencies_and_fault_changes() {
+        let mut sink = DesktopSink::new(None);
+        let fault = Event::Fault {
+            message: "Failed to read temperature".to_string(),
+        };
+        let sample = Event::Sample {
+            temperature: Celsius::new(40, 0),
+            raw: None,
+        };
+
+        assert_eq!(None, sink.notification(&sample));
+        assert_eq!(
+            Urgency::Critical,
+            sink.notification(&fault).unwrap().urgency
+        );
+        assert_eq!(None, sink.notification(&fault));
+        assert_eq!(
+            "Temperature sensor readable again",
+            sink.notification(&sample).unwrap().body
+        );
+        assert_eq!(None, sink.notification(&sample));
+
+        let overtemperature = sink.notification(&Event::Overtemperature {
+            temperature: Celsius::new(71, 0),
+            max: Celsius::new(70, 0),
+        });
+        assert_eq!(
+            "Emergency cooling: 71°C reached the maximum of 70°C, the fan runs at full speed",
+            overtemperature.unwrap().body
+        );
+        assert_eq!(
+            Urgency::Normal,
+            sink.notification(&Event::LatchReleased).unwrap().urgency
+        );
+
+        let alarm = sink
+            .notification(&Event::Alarm {
+                sensor: "thermal:nvme".to_string(),
+                level: AlarmLevel::Critical,
+                temperature: Celsius::new(71, 0),
+            })
+            .unwrap();
+        assert_eq!(Urgency::Critical, alarm.urgency);
+        assert_eq!("thermal:nvme reached critical level at 71°C", alarm.body);
+    }
+}
@@ -0,0 +1,261 @@
+//! Composite temperature of an NVMe drive, for cooling an SSD rather than the SoC.
+//!
+//! The drive's hwmon entry is read where the kernel provides one. Otherwise the SMART / Health
+//! log is fetched with the NVMe admin Get Log Page command, which needs root, and only works on
+//! Linux.
+
+use crate::{
+    hwmon,
+    sensor::{FileSensor, Raw, Sensor, SensorError},
+    units::Celsius,
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Directory the kernel lists NVMe controllers in
+pub const NVME_ROOT: &str = "/sys/class/nvme";
+
+/// `NVME_IOCTL_ADMIN_CMD`, passing an admin command through to the controller
+#[cfg(target_os = "linux")]
+const NVME_IOCTL_ADMIN_CMD: libc::c_ulong = 0xc048_4e41;
+
+/// Opcode of the Get Log Page admin command
+#[cfg(target_os = "linux")]
+const GET_LOG_PAGE: u8 = 0x02;
+
+/// Identifier of the SMART / Health Information log page
+#[cfg(target_os = "linux")]
+const SMART_LOG: u32 = 0x02;
+
+/// Length of the SMART / Health Information log page
+const SMART_LOG_LEN: usize = 512;
+
+/// Admin command of the NVMe ioctl interface.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct AdminCommand {
+    opcode: u8,
+    flags: u8,
+    rsvd1: u16,
+    nsid: u32,
+    cdw2: u32,
+    cdw3: u32,
+    metadata: u64,
+    addr: u64,
+    metadata_len: u32,
+    data_len: u32,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+    timeout_ms: u32,
+    result: u32,
+}
+
+/// Parses a controller given as e.g. `nvme0` or `/dev/nvme0`.
+pub fn parse_controller(value: &str) -> Result<String, String> {
+    let name = value.strip_prefix("/dev/").unwrap_or(value);
+    match name.strip_prefix("nvme") {
+        Some(number) if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) => {
+            Ok(name.to_string())
+        }
+        _ => Err(format!(
+            "invalid NVMe controller {:?}, expected e.g. nvme0",
+            value
+        )),
+    }
+}
+
+/// Returns the hwmon temperature file of a controller under `root`, the composite temperature
+/// being its first input.
+///
+/// Recent kernels put the hwmon device under the controller, older ones under its PCI device.
+pub fn find_input(root: &Path, controller: &str) -> Option<PathBuf> {
+    let controller = root.join(controller);
+    [controller.clone(), controller.join("device").join("hwmon")]
+        .iter()
+        .flat_map(|directory| hwmon::numbered(directory, "hwmon"))
+        .map(|device| device.join("temp1_input"))
+        .find(|input| input.exists())
+}
+
+/// Returns the composite temperature of a SMART / Health log page, in kelvins at bytes 1 and 2.
+fn composite(log: &[u8; SMART_LOG_LEN]) -> Option<i32> {
+    match u16::from_le_bytes([log[1], log[2]]) {
+        // Zero when the drive doesn't report it
+        0 => None,
+        kelvins => Some(i32::from(kelvins) * 1000 - 273_150),
+    }
+}
+
+/// Fetches the SMART / Health log page of a controller device.
+#[cfg(target_os = "linux")]
+fn smart_log(device: &Path) -> io::Result<[u8; SMART_LOG_LEN]> {
+    use std::{fs::File, os::unix::io::AsRawFd};
+
+    let device = File::open(device)?;
+    let mut log = [0u8; SMART_LOG_LEN];
+    let dwords = (SMART_LOG_LEN / 4) as u32;
+    let mut command = AdminCommand {
+        opcode: GET_LOG_PAGE,
+        // The log of the controller as a whole rather than of a namespace
+        nsid: 0xffff_ffff,
+        addr: log.as_mut_ptr() as u64,
+        data_len: SMART_LOG_LEN as u32,
+        cdw10: ((dwords - 1) << 16) | SMART_LOG,
+        ..Default::default()
+    };
+
+    if unsafe { libc::ioctl(device.as_raw_fd(), NVME_IOCTL_ADMIN_CMD, &mut command) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(log)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn smart_log(_device: &Path) -> io::Result<[u8; SMART_LOG_LEN]> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "NVMe admin commands are only supported on Linux",
+    ))
+}
+
+/// Composite temperature of an NVMe controller, from its hwmon entry or else its SMART log.
+///
+/// The hwmon entry is looked up on every read until one is found, and again after it couldn't
+/// be read, e.g. once the `nvme` driver was reloaded.
+pub struct NvmeSensor {
+    controller: String,
+    root: PathBuf,
+    /// hwmon temperature file found, until reading it fails
+    hwmon: Option<FileSensor>,
+    /// File last read from, to tell when the lookup lands somewhere new
+    last: Option<PathBuf>,
+    /// Last reading of the SMART log, in millidegrees
+    raw: Option<i32>,
+}
+
+impl NvmeSensor {
+    pub fn new(controller: &str) -> Self {
+        Self::with_root(controller, Path::new(NVME_ROOT))
+    }
+
+    /// Returns a sensor looking for controllers under another directory, e.g. in tests.
+    pub fn with_root(controller: &str, root: &Path) -> Self {
+        Self {
+            controller: controller.to_string(),
+            root: root.to_path_buf(),
+            hwmon: None,
+            last: None,
+            raw: None,
+        }
+    }
+
+    /// Notes the file a reading comes from, logging it when it changes.
+    fn reading_from(&mut self, path: &Path) {
+        if self.last.as_deref() != Some(path) {
+            log::info!(
+                "Reading NVMe controller {} from {}",
+                self.controller,
+                path.display()
+            );
+            self.last = Some(path.to_path_buf());
+        }
+    }
+
+    fn read_smart_log(&mut self) -> Result<Celsius, SensorError> {
+        let device = Path::new("/dev").join(&self.controller);
+        let log = smart_log(&device)
+            .map_err(|error| SensorError::Read(device.display().to_string(), error))?;
+        self.reading_from(&device);
+        let millidegrees = composite(&log).ok_or_else(|| {
+            SensorError::Parse(format!("{} composite temperature", self.controller))
+        })?;
+        self.raw = Some(millidegrees);
+        Ok(Celsius::from_millidegrees(millidegrees))
+    }
+}
+
+impl Sensor for NvmeSensor {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        if self.hwmon.is_none() {
+            if let Some(path) = find_input(&self.root, &self.controller) {
+                self.reading_from(&path);
+                self.hwmon = Some(FileSensor::new(&path.to_string_lossy()));
+            }
+        }
+        let Some(hwmon) = self.hwmon.as_mut() else {
+            return self.read_smart_log();
+        };
+        self.raw = None;
+        let reading = hwmon.read();
+        if reading.is_err() {
+            // Look the entry up again next time, in case the driver was reloaded
+            self.hwmon = None;
+        }
+        reading
+    }
+
+    fn raw(&self) -> Option<Raw> {
+        match &self.hwmon {
+            Some(hwmon) => hwmon.raw(),
+            None => self.raw.map(Raw::Millidegrees),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{composite, find_input, parse_controller, NvmeSensor, SMART_LOG_LEN};
+    use crate::{mock::TempPath, sensor::Sensor, units::Celsius};
+    use std::fs;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn admin_command_matches_kernel_layout() {
+        assert_eq!(72, std::mem::size_of::<super::AdminCommand>());
+    }
+
+    #[test]
+    fn parses_controllers() {
+        assert_eq!(Ok("nvme0".to_string()), parse_controller("nvme0"));
+        assert_eq!(Ok("nvme1".to_string()), parse_controller("/dev/nvme1"));
+        assert!(parse_controller("nvme0n1").is_err());
+        assert!(parse_controller("sda").is_err());
+    }
+
+    #[test]
+    fn reads_composite_temperature_of_smart_log() {
+        let mut log = [0u8; SMART_LOG_LEN];
+        assert_eq!(None, composite(&log));
+        log[1..3].copy_from_slice(&318u16.to_le_bytes());
+        assert_eq!(Some(44_850), composite(&log));
+    }
+
+    #[test]
+    fn finds_hwmon_entry_of_controller() {
+        let root = TempPath::dir("nvme");
+        let old = root
+            .join("nvme0")
+            .join("device")
+            .join("hwmon")
+            .join("hwmon3");
+        let new = root.join("nvme1").join("hwmon4");
+        for (device, millidegrees) in [(&old, "38850\n"), (&new, "41850\n")] {
+            fs::create_dir_all(device).unwrap();
+            fs::write(device.join("temp1_input"), millidegrees).unwrap();
+        }
+
+        assert_eq!(Some(old.join("temp1_input")), find_input(&root, "nvme0"));
+        assert_eq!(Some(new.join("temp1_input")), find_input(&root, "nvme1"));
+        assert_eq!(None, find_input(&root, "nvme2"));
+
+        let mut sensor = NvmeSensor::with_root("nvme1", &root);
+        assert_eq!(Celsius::new(41, 850), sensor.read().unwrap());
+    }
+}
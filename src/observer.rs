@@ -0,0 +1,25 @@
+use crate::units::{Celsius, Duty};
+
+/// Inputs and decision of a single control loop iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Iteration {
+    pub temperature: Celsius,
+    pub previous_temperature: Celsius,
+    pub target: Celsius,
+    /// Duty before the decision
+    pub duty: Duty,
+    /// Duty the controller decided on
+    pub decision: Duty,
+}
+
+/// Whether a decision is allowed to reach the fan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Veto,
+}
+
+/// Hook invoked on every control loop iteration, for logging, vetoing or mirroring decisions.
+pub trait Observer {
+    fn on_iteration(&mut self, iteration: &Iteration) -> Verdict;
+}
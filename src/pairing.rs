@@ -0,0 +1,146 @@
+//! Intake and exhaust fans of one zone driven together.
+
+use crate::{acoustic::Acoustics, pwm::Output, units::Duty};
+use std::io;
+
+/// Drives an exhaust fan at a fixed ratio of the intake fan's duty.
+///
+/// Keeping the exhaust slower than the intake holds the enclosure at positive pressure, so air
+/// only enters through the filtered intake.
+pub struct PairedOutput {
+    intake: Box<dyn Output>,
+    exhaust: Box<dyn Output>,
+    /// Exhaust duty as a percentage of the intake duty
+    ratio: u8,
+    /// Noise of the fans, sharing the duty between them the quietest way instead of at the ratio
+    acoustics: Option<Acoustics>,
+}
+
+impl PairedOutput {
+    pub fn new(intake: Box<dyn Output>, exhaust: Box<dyn Output>, ratio: u8) -> Self {
+        Self {
+            intake,
+            exhaust,
+            ratio,
+            acoustics: None,
+        }
+    }
+
+    /// Shares the duty between the fans for the least noise, at the total duty the ratio gives.
+    pub fn with_acoustics(mut self, acoustics: Option<Acoustics>) -> Self {
+        self.acoustics = acoustics;
+        self
+    }
+}
+
+/// Returns the exhaust duty matching an intake duty at a ratio in percent.
+pub fn exhaust_duty(intake: Duty, ratio: u8) -> Duty {
+    let percent = u32::from(intake.percent()) * u32::from(ratio) / 100;
+    Duty::new(percent.min(100) as u8).unwrap()
+}
+
+/// Returns the intake and exhaust duties for the duty the controller asks of the intake.
+///
+/// With noise profiles, the duties add up to the same total as at the ratio but are shared out
+/// for the least noise.
+pub fn duties(intake: Duty, ratio: u8, acoustics: Option<&Acoustics>) -> (Duty, Duty) {
+    let exhaust = exhaust_duty(intake, ratio);
+    let total = u32::from(intake.percent()) + u32::from(exhaust.percent());
+    acoustics
+        .and_then(|acoustics| acoustics.split(total, ratio))
+        .unwrap_or((intake, exhaust))
+}
+
+impl Output for PairedOutput {
+    fn init(&mut self) {
+        self.intake.init();
+        self.exhaust.init();
+    }
+
+    fn write(&mut self, duty: Duty) {
+        let (intake, exhaust) = duties(duty, self.ratio, self.acoustics.as_ref());
+        self.intake.write(intake);
+        self.exhaust.write(exhaust);
+    }
+
+    fn shutdown(&mut self) {
+        self.intake.shutdown();
+        self.exhaust.shutdown();
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        let intake = self.intake.take_error();
+        let exhaust = self.exhaust.take_error();
+        intake.or(exhaust)
+    }
+
+    fn reinit(&mut self) -> io::Result<()> {
+        self.intake.reinit()?;
+        self.exhaust.reinit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PairedOutput;
+    use crate::{
+        acoustic::{parse_noise_profile, Acoustics},
+        mock::{Call, MockOutput},
+        pwm::{tests::duty, Output},
+    };
+
+    fn paired(ratio: u8) -> (PairedOutput, MockOutput, MockOutput) {
+        let intake = MockOutput::new();
+        let exhaust = MockOutput::new();
+        let output = PairedOutput::new(Box::new(intake.clone()), Box::new(exhaust.clone()), ratio);
+        (output, intake, exhaust)
+    }
+
+    #[test]
+    fn exhaust_follows_intake_at_ratio() {
+        let (mut output, intake, exhaust) = paired(80);
+
+        output.write(duty(50));
+        output.write(duty(100));
+
+        assert_eq!(vec![duty(50), duty(100)], intake.writes());
+        assert_eq!(vec![duty(40), duty(80)], exhaust.writes());
+    }
+
+    #[test]
+    fn quiet_objective_shares_duty_out() {
+        let (output, intake, exhaust) = paired(80);
+        let quiet = parse_noise_profile("20:15,50:25,100:40").unwrap();
+        let loud = parse_noise_profile("20:25,50:35,100:50").unwrap();
+        let mut output = output.with_acoustics(Some(Acoustics::new(quiet, loud, [1.0, 1.0])));
+
+        output.write(duty(50));
+        output.write(duty(0));
+
+        // The 50% and 40% of the ratio, shared out with less on the louder exhaust
+        assert_eq!(vec![duty(61), duty(0)], intake.writes());
+        assert_eq!(vec![duty(29), duty(0)], exhaust.writes());
+    }
+
+    #[test]
+    fn exhaust_duty_is_capped_at_full() {
+        let (mut output, _, exhaust) = paired(150);
+
+        output.write(duty(80));
+
+        assert_eq!(vec![duty(100)], exhaust.writes());
+    }
+
+    #[test]
+    fn both_fans_are_initialized_and_released() {
+        let (mut output, intake, exhaust) = paired(80);
+
+        output.init();
+        output.shutdown();
+
+        for fan in [intake, exhaust] {
+            let calls: Vec<Call> = fan.calls().iter().map(|recorded| recorded.call).collect();
+            assert_eq!(vec![Call::Init, Call::Shutdown], calls);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Pairing URI naming the endpoints a dashboard app can reach this controller on.
+//!
+//! Printed as a QR code by `fan-controller pairing-info`, so adding many units is a scan each:
+//!
+//! ```text
+//! fan-controller://pi.local?coap=5683&modbus=502&snmp=161&community=public&ble=1&token=...
+//! ```
+//!
+//! Only the endpoints enabled in the configuration are listed.
+
+use crate::{args::Args, secret::Secret};
+
+/// Returns the URI, or `None` when no network or Bluetooth endpoint is enabled.
+pub fn uri(args: &Args, host: &str, token: Option<&Secret>) -> Option<String> {
+    let mut parameters = Vec::new();
+    if let Some(address) = args.coap_listen {
+        parameters.push(("coap", address.port().to_string()));
+    }
+    if let Some(address) = args.modbus_listen {
+        parameters.push(("modbus", address.port().to_string()));
+    }
+    if let Some(address) = args.snmp_listen {
+        parameters.push(("snmp", address.port().to_string()));
+        parameters.push(("community", args.snmp_community.clone()));
+    }
+    if args.ble {
+        parameters.push(("ble", "1".to_string()));
+    }
+    if parameters.is_empty() {
+        return None;
+    }
+    if let Some(token) = token {
+        parameters.push(("token", token.expose().to_string()));
+    }
+
+    let query: Vec<String> = parameters
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, encode(value)))
+        .collect();
+    Some(format!(
+        "fan-controller://{}?{}",
+        encode(host),
+        query.join("&")
+    ))
+}
+
+/// Percent-encodes everything but unreserved characters.
+fn encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::uri;
+    use crate::args::Args;
+    use clap::Parser;
+
+    fn args(options: &[&str]) -> Args {
+        let mut argv = vec!["fan-controller", "--gpio-pwm", "3"];
+        argv.extend(options);
+        Args::parse_from(argv)
+    }
+
+    #[test]
+    fn lists_enabled_endpoints() {
+        let args = args(&[
+            "--modbus-listen",
+            "0.0.0.0:502",
+            "--snmp-listen",
+            "0.0.0.0:1161",
+            "--snmp-community",
+            "rack 7",
+        ]);
+
+        assert_eq!(
+            Some("fan-controller://pi.local?modbus=502&snmp=1161&community=rack%207".to_string()),
+            uri(&args, "pi.local", None)
+        );
+    }
+
+    #[test]
+    fn needs_an_endpoint() {
+        assert_eq!(None, uri(&args(&[]), "pi.local", None));
+    }
+}
@@ -0,0 +1,262 @@
+//! Processing of a single sensor's readings before they're combined or controlled on, such as
+//! correcting a probe's offset or dropping the spikes of a noisy ADC.
+//!
+//! Each source has its own stages and their own state, so filtering one sensor never affects
+//! another.
+
+use crate::{
+    sensor::{Raw, Sensor, SensorError},
+    temperature::MovingAverage,
+    units::Celsius,
+};
+use std::fmt;
+
+/// Readings in a row beyond the outlier limit taken as a real change rather than outliers
+const OUTLIER_RUN: u8 = 3;
+
+/// Processing step of a pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Multiplies the reading
+    Scale(f64),
+    /// Adds to the reading
+    Offset(Celsius),
+    /// Limits the reading to a range
+    Clamp(Celsius, Celsius),
+    /// Averages over this many readings
+    Smooth(usize),
+    /// Keeps the last reading in place of one differing from it by more than this
+    Outlier(Celsius),
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Scale(factor) => write!(f, "scale={}", factor),
+            Stage::Offset(offset) => write!(f, "offset={}", offset),
+            Stage::Clamp(low, high) => write!(f, "clamp={}:{}", low, high),
+            Stage::Smooth(window) => write!(f, "smooth={}", window),
+            Stage::Outlier(limit) => write!(f, "outlier={}", limit),
+        }
+    }
+}
+
+/// Stages applied to each reading in order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages: Vec<String> = self.stages.iter().map(ToString::to_string).collect();
+        f.write_str(&stages.join(","))
+    }
+}
+
+/// Parses stages such as `scale=1.02,offset=-1.5,clamp=-20:120,smooth=5,outlier=10`.
+pub fn parse_pipeline(value: &str) -> Result<Pipeline, String> {
+    let stages = value
+        .split(',')
+        .map(|stage| parse_stage(stage.trim()))
+        .collect::<Result<_, _>>()?;
+    Ok(Pipeline { stages })
+}
+
+fn parse_stage(value: &str) -> Result<Stage, String> {
+    let invalid = || {
+        format!(
+            "invalid pipeline stage {:?}, expected scale=, offset=, clamp=, smooth= or outlier=",
+            value
+        )
+    };
+    let celsius = |text: &str| text.parse::<Celsius>().map_err(|error| error.to_string());
+
+    let (name, argument) = value.split_once('=').ok_or_else(invalid)?;
+    match name {
+        "scale" => match argument.parse::<f64>() {
+            Ok(factor) if factor.is_finite() => Ok(Stage::Scale(factor)),
+            _ => Err(invalid()),
+        },
+        "offset" => Ok(Stage::Offset(celsius(argument)?)),
+        "clamp" => {
+            let (low, high) = argument.split_once(':').ok_or_else(invalid)?;
+            let (low, high) = (celsius(low)?, celsius(high)?);
+            if low > high {
+                return Err(format!("clamp range {:?} is empty", argument));
+            }
+            Ok(Stage::Clamp(low, high))
+        }
+        "smooth" => match argument.parse::<usize>() {
+            Ok(window) if window > 0 => Ok(Stage::Smooth(window)),
+            _ => Err(invalid()),
+        },
+        "outlier" => Ok(Stage::Outlier(celsius(argument)?.abs())),
+        _ => Err(invalid()),
+    }
+}
+
+/// State of a stage across readings.
+enum Step {
+    Scale(f64),
+    Offset(Celsius),
+    Clamp(Celsius, Celsius),
+    Smooth(MovingAverage),
+    Outlier {
+        limit: Celsius,
+        last: Option<Celsius>,
+        /// Readings beyond the limit in a row
+        rejected: u8,
+    },
+}
+
+impl Step {
+    fn new(stage: Stage) -> Self {
+        match stage {
+            Stage::Scale(factor) => Step::Scale(factor),
+            Stage::Offset(offset) => Step::Offset(offset),
+            Stage::Clamp(low, high) => Step::Clamp(low, high),
+            Stage::Smooth(window) => Step::Smooth(MovingAverage::new(window)),
+            Stage::Outlier(limit) => Step::Outlier {
+                limit,
+                last: None,
+                rejected: 0,
+            },
+        }
+    }
+
+    fn apply(&mut self, value: Celsius) -> Celsius {
+        match self {
+            Step::Scale(factor) => Celsius::from_millidegrees(
+                (f64::from(value.millidegrees()) * *factor).round() as i32,
+            ),
+            Step::Offset(offset) => {
+                Celsius::from_millidegrees(value.millidegrees() + offset.millidegrees())
+            }
+            Step::Clamp(low, high) => value.clamp(*low, *high),
+            Step::Smooth(average) => average.push(value),
+            Step::Outlier {
+                limit,
+                last,
+                rejected,
+            } => match *last {
+                Some(kept) if (value - kept).abs() > *limit && *rejected + 1 < OUTLIER_RUN => {
+                    *rejected += 1;
+                    log::debug!("Ignoring outlier {}°C, keeping {}°C", value, kept);
+                    kept
+                }
+                _ => {
+                    *last = Some(value);
+                    *rejected = 0;
+                    value
+                }
+            },
+        }
+    }
+}
+
+/// Sensor with its readings run through a pipeline.
+pub struct Processed {
+    sensor: Box<dyn Sensor>,
+    steps: Vec<Step>,
+}
+
+impl Processed {
+    pub fn new(sensor: Box<dyn Sensor>, pipeline: &Pipeline) -> Self {
+        Self {
+            sensor,
+            steps: pipeline.stages.iter().copied().map(Step::new).collect(),
+        }
+    }
+}
+
+impl Sensor for Processed {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        let reading = self.sensor.read()?;
+        Ok(self
+            .steps
+            .iter_mut()
+            .fold(reading, |value, step| step.apply(value)))
+    }
+
+    fn raw(&self) -> Option<Raw> {
+        self.sensor.raw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_pipeline, Processed};
+    use crate::{
+        sensor::{Sensor, SensorError},
+        units::Celsius,
+    };
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    /// Sensor returning queued readings in degrees.
+    struct Queued(Rc<RefCell<VecDeque<i32>>>);
+
+    impl Sensor for Queued {
+        fn read(&mut self) -> Result<Celsius, SensorError> {
+            let degrees = self.0.borrow_mut().pop_front().unwrap();
+            Ok(Celsius::new(degrees, 0))
+        }
+    }
+
+    fn processed(pipeline: &str, readings: &[i32]) -> Vec<Celsius> {
+        let queue = Rc::new(RefCell::new(readings.iter().copied().collect()));
+        let mut sensor = Processed::new(
+            Box::new(Queued(Rc::clone(&queue))),
+            &parse_pipeline(pipeline).unwrap(),
+        );
+        readings.iter().map(|_| sensor.read().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_stages() {
+        let pipeline = parse_pipeline("scale=1.5, offset=-1.5,clamp=-20:120,smooth=5,outlier=10");
+        assert_eq!(
+            "scale=1.5,offset=-1.5,clamp=-20:120,smooth=5,outlier=10",
+            pipeline.unwrap().to_string()
+        );
+
+        assert!(parse_pipeline("scale=x").is_err());
+        assert!(parse_pipeline("clamp=50:40").is_err());
+        assert!(parse_pipeline("smooth=0").is_err());
+        assert!(parse_pipeline("median=3").is_err());
+        assert!(parse_pipeline("offset").is_err());
+    }
+
+    #[test]
+    fn applies_stages_in_order() {
+        assert_eq!(
+            vec![Celsius::new(41, 0), Celsius::new(60, 0)],
+            processed("scale=2,offset=1,clamp=0:60", &[20, 40])
+        );
+        assert_eq!(
+            vec![Celsius::new(60, 0), Celsius::new(82, 0)],
+            processed("offset=1,scale=2,clamp=0:90", &[29, 40])
+        );
+        assert_eq!(
+            vec![Celsius::new(40, 0), Celsius::new(41, 0)],
+            processed("smooth=2", &[40, 42])
+        );
+    }
+
+    #[test]
+    fn outliers_keep_the_last_reading_until_they_persist() {
+        assert_eq!(
+            vec![40, 40, 41, 41, 41, 85, 85]
+                .into_iter()
+                .map(|degrees| Celsius::new(degrees, 0))
+                .collect::<Vec<_>>(),
+            processed("outlier=10", &[40, 85, 41, 85, 85, 85, 85])
+        );
+    }
+}
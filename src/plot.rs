@@ -0,0 +1,454 @@
+//! Plots of the fan speed over temperature for the configured settings, to check a config before
+//! deploying it or to illustrate documentation.
+
+use crate::{
+    qr,
+    units::{Celsius, Duty},
+};
+use clap::ValueEnum;
+
+/// Size of text plots, in characters
+const TEXT_WIDTH: usize = 60;
+const TEXT_HEIGHT: usize = 21;
+
+/// Size of images and the margins around the plot area, in pixels
+const WIDTH: i64 = 640;
+const HEIGHT: i64 = 400;
+const LEFT: i64 = 60;
+const RIGHT: i64 = 20;
+const TOP: i64 = 20;
+const BOTTOM: i64 = 40;
+
+/// Line colors of the series in SVG images
+const COLORS: [&str; 2] = ["#1f77b4", "#ff7f0e"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PlotFormat {
+    /// Characters for the terminal
+    Text,
+    Svg,
+    /// Black and white image
+    Png,
+}
+
+impl PlotFormat {
+    /// Returns the format matching a file extension, if any.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "txt" => Some(Self::Text),
+            "svg" => Some(Self::Svg),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Duty over temperature of one fan.
+pub struct Series {
+    pub name: String,
+    /// Character marking the series in text plots
+    pub mark: char,
+    duty: Box<dyn Fn(Celsius) -> Duty>,
+}
+
+impl Series {
+    pub fn new(
+        name: impl Into<String>,
+        mark: char,
+        duty: impl Fn(Celsius) -> Duty + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            mark,
+            duty: Box::new(duty),
+        }
+    }
+}
+
+/// Series plotted over a temperature range, with the operating point if known.
+pub struct Plot {
+    pub from: Celsius,
+    pub to: Celsius,
+    pub series: Vec<Series>,
+    /// Latest reading and the duty a single run applies at it
+    pub operating: Option<(Celsius, Duty)>,
+}
+
+impl Plot {
+    /// Returns the range from 5°C below the lowest to 5°C above the highest of the temperatures,
+    /// widened to whole tens.
+    pub fn range(temperatures: &[Celsius]) -> (Celsius, Celsius) {
+        let millis = temperatures
+            .iter()
+            .map(|temperature| temperature.millidegrees());
+        let low = millis.clone().min().unwrap_or(0) - 5000;
+        let high = millis.max().unwrap_or(0) + 5000;
+        (
+            Celsius::new(0, low.div_euclid(10_000) * 10_000),
+            Celsius::new(0, (high + 9_999).div_euclid(10_000) * 10_000),
+        )
+    }
+
+    /// Returns the temperature at `step` of `steps` across the range.
+    fn at(&self, step: i64, steps: i64) -> Celsius {
+        let span = i64::from(self.to.millidegrees() - self.from.millidegrees());
+        Celsius::new(0, self.from.millidegrees() + (span * step / steps) as i32)
+    }
+
+    /// Returns how far across the range a temperature is, in `steps`.
+    fn position(&self, temperature: Celsius, steps: i64) -> i64 {
+        let span = i64::from(self.to.millidegrees() - self.from.millidegrees()).max(1);
+        let offset = i64::from(temperature.millidegrees() - self.from.millidegrees());
+        (offset * steps + span / 2).div_euclid(span)
+    }
+
+    /// Temperatures of the axis ticks, every 10°C
+    fn ticks(&self) -> Vec<Celsius> {
+        let first = (self.from.millidegrees() + 9_999).div_euclid(10_000);
+        let last = self.to.millidegrees().div_euclid(10_000);
+        (first..=last)
+            .map(|tens| Celsius::new(tens * 10, 0))
+            .collect()
+    }
+
+    pub fn render(&self, format: PlotFormat) -> Vec<u8> {
+        match format {
+            PlotFormat::Text => self.to_text().into_bytes(),
+            PlotFormat::Svg => self.to_svg().into_bytes(),
+            PlotFormat::Png => self.to_png(),
+        }
+    }
+
+    /// Renders the plot with characters, each series drawn with its mark and the operating point
+    /// as `X`.
+    pub fn to_text(&self) -> String {
+        let steps = TEXT_WIDTH as i64 - 1;
+        let row = |duty: Duty| {
+            let rows = TEXT_HEIGHT - 1;
+            rows - (usize::from(duty.percent()) * rows + 50) / 100
+        };
+
+        let mut grid = vec![vec![' '; TEXT_WIDTH]; TEXT_HEIGHT];
+        for series in &self.series {
+            let rows = (0..=steps).map(|column| row((series.duty)(self.at(column, steps))));
+            for (column, row) in rows.enumerate() {
+                grid[row][column] = series.mark;
+            }
+        }
+        if let Some((temperature, duty)) = self.operating {
+            let column = self.position(temperature, steps).clamp(0, steps) as usize;
+            grid[row(duty)][column] = 'X';
+        }
+
+        let mut text = String::new();
+        for (index, line) in grid.iter().enumerate() {
+            let percent = 100 - index * 100 / (TEXT_HEIGHT - 1);
+            let label = if percent.is_multiple_of(25) {
+                format!("{:>4}%", percent)
+            } else {
+                String::new()
+            };
+            let line: String = line.iter().collect();
+            text.push_str(&format!("{:>5} |{}\n", label, line.trim_end()));
+        }
+        text.push_str(&format!("{:>5} +{}\n", "", "-".repeat(TEXT_WIDTH)));
+
+        let mut labels = vec![' '; TEXT_WIDTH + 8];
+        for tick in self.ticks() {
+            let column = self.position(tick, steps) as usize;
+            let label = format!("{}°C", tick);
+            let start = column.saturating_sub(label.chars().count() / 2);
+            if labels[start.saturating_sub(1)..]
+                .iter()
+                .take(label.chars().count() + 2)
+                .all(|&c| c == ' ')
+            {
+                for (offset, c) in label.chars().enumerate() {
+                    labels[start + offset] = c;
+                }
+            }
+        }
+        let labels: String = labels.into_iter().collect();
+        text.push_str(&format!("{:>5}  {}\n", "", labels.trim_end()));
+
+        for series in &self.series {
+            text.push_str(&format!("{:>5}  {} {}\n", "", series.mark, series.name));
+        }
+        if let Some((temperature, duty)) = self.operating {
+            text.push_str(&format!("{:>5}  X {}°C at {}%\n", "", temperature, duty));
+        }
+        text
+    }
+
+    fn x(&self, temperature: Celsius) -> i64 {
+        LEFT + self.position(temperature, WIDTH - LEFT - RIGHT)
+    }
+
+    fn y(duty: Duty) -> i64 {
+        TOP + (100 - i64::from(duty.percent())) * (HEIGHT - TOP - BOTTOM) / 100
+    }
+
+    /// Points of a series, one for every other pixel across the plot area
+    fn points(&self, series: &Series) -> Vec<(i64, i64)> {
+        let steps = WIDTH - LEFT - RIGHT;
+        (0..=steps)
+            .step_by(2)
+            .map(|step| {
+                let duty = (series.duty)(self.at(step, steps));
+                (LEFT + step, Self::y(duty))
+            })
+            .collect()
+    }
+
+    /// Renders the plot as an SVG image with labeled axes and a legend.
+    pub fn to_svg(&self) -> String {
+        let (right, bottom) = (WIDTH - RIGHT, HEIGHT - BOTTOM);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+             viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"12\">\n\
+             <rect width=\"{0}\" height=\"{1}\" fill=\"white\"/>\n",
+            WIDTH, HEIGHT
+        );
+
+        for percent in (0..=100).step_by(25) {
+            let y = Self::y(Duty::new(percent).unwrap());
+            svg.push_str(&format!(
+                "<line x1=\"{left}\" y1=\"{y}\" x2=\"{right}\" y2=\"{y}\" stroke=\"#ddd\"/>\n\
+                 <text x=\"{}\" y=\"{}\" text-anchor=\"end\">{percent}%</text>\n",
+                LEFT - 6,
+                y + 4,
+                left = LEFT,
+            ));
+        }
+        for tick in self.ticks() {
+            let x = self.x(tick);
+            svg.push_str(&format!(
+                "<line x1=\"{x}\" y1=\"{top}\" x2=\"{x}\" y2=\"{bottom}\" stroke=\"#ddd\"/>\n\
+                 <text x=\"{x}\" y=\"{}\" text-anchor=\"middle\">{tick}°C</text>\n",
+                bottom + 18,
+                top = TOP,
+            ));
+        }
+        svg.push_str(&format!(
+            "<path d=\"M{} {} V{} H{}\" stroke=\"black\" fill=\"none\"/>\n",
+            LEFT, TOP, bottom, right
+        ));
+
+        for (index, series) in self.series.iter().enumerate() {
+            let color = COLORS[index % COLORS.len()];
+            let points: Vec<String> = self
+                .points(series)
+                .iter()
+                .map(|(x, y)| format!("{},{}", x, y))
+                .collect();
+            svg.push_str(&format!(
+                "<polyline points=\"{}\" stroke=\"{}\" stroke-width=\"2\" fill=\"none\"/>\n\
+                 <text x=\"{}\" y=\"{}\" fill=\"{1}\">{}</text>\n",
+                points.join(" "),
+                color,
+                LEFT + 10,
+                TOP + 16 + 16 * index as i64,
+                series.name
+            ));
+        }
+        if let Some((temperature, duty)) = self.operating {
+            svg.push_str(&format!(
+                "<circle cx=\"{}\" cy=\"{}\" r=\"5\" fill=\"red\"/>\n\
+                 <text x=\"{}\" y=\"{}\" fill=\"red\">{}°C at {}%</text>\n",
+                self.x(temperature),
+                Self::y(duty),
+                LEFT + 10,
+                TOP + 16 + 16 * self.series.len() as i64,
+                temperature,
+                duty
+            ));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Renders the plot as a black and white PNG image, later series dotted and the operating
+    /// point as a square.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut canvas = Canvas::new(WIDTH, HEIGHT);
+        let (right, bottom) = (WIDTH - RIGHT, HEIGHT - BOTTOM);
+
+        canvas.line((LEFT, TOP), (LEFT, bottom), 1);
+        canvas.line((LEFT, bottom), (right, bottom), 1);
+        for percent in (0..=100).step_by(25) {
+            let y = Self::y(Duty::new(percent).unwrap());
+            canvas.line((LEFT - 4, y), (LEFT, y), 1);
+            let label = format!("{}%", percent);
+            canvas.text(LEFT - 8 - label.len() as i64 * 8, y - 5, &label);
+        }
+        for tick in self.ticks() {
+            let x = self.x(tick);
+            canvas.line((x, bottom), (x, bottom + 4), 1);
+            let label = tick.to_string();
+            canvas.text(x - label.len() as i64 * 4, bottom + 10, &label);
+        }
+
+        for (index, series) in self.series.iter().enumerate() {
+            let dash = if index == 0 { 1 } else { 2 };
+            for pair in self.points(series).windows(2) {
+                canvas.line(pair[0], pair[1], dash);
+            }
+        }
+        if let Some((temperature, duty)) = self.operating {
+            let (x, y) = (self.x(temperature), Self::y(duty));
+            for dy in -3..=3 {
+                canvas.line((x - 3, y + dy), (x + 3, y + dy), 1);
+            }
+        }
+        canvas.to_png()
+    }
+}
+
+/// Glyphs of 3 by 5 pixels for axis labels, rows from the top in the high bits
+const FONT: [(char, u16); 13] = [
+    ('0', 0b111_101_101_101_111),
+    ('1', 0b010_110_010_010_111),
+    ('2', 0b111_001_111_100_111),
+    ('3', 0b111_001_111_001_111),
+    ('4', 0b101_101_111_001_001),
+    ('5', 0b111_100_111_001_111),
+    ('6', 0b111_100_111_101_111),
+    ('7', 0b111_001_001_001_001),
+    ('8', 0b111_101_111_101_111),
+    ('9', 0b111_101_111_001_111),
+    ('%', 0b101_001_010_100_101),
+    ('-', 0b000_000_111_000_000),
+    ('.', 0b000_000_000_000_010),
+];
+
+/// Black and white pixels to draw on.
+struct Canvas {
+    width: i64,
+    height: i64,
+    dark: Vec<bool>,
+}
+
+impl Canvas {
+    fn new(width: i64, height: i64) -> Self {
+        Self {
+            width,
+            height,
+            dark: vec![false; (width * height) as usize],
+        }
+    }
+
+    fn set(&mut self, x: i64, y: i64) {
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            self.dark[(y * self.width + x) as usize] = true;
+        }
+    }
+
+    /// Draws a line between two points, setting every `dash`th pixel.
+    fn line(&mut self, (x0, y0): (i64, i64), (x1, y1): (i64, i64), dash: usize) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+        for pixel in 0.. {
+            if pixel % dash == 0 {
+                self.set(x, y);
+            }
+            if (x, y) == (x1, y1) {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += sx;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Draws text at twice the font size, skipping characters the font lacks.
+    fn text(&mut self, x: i64, y: i64, text: &str) {
+        for (index, c) in text.chars().enumerate() {
+            let Some((_, glyph)) = FONT.iter().find(|(glyph, _)| *glyph == c) else {
+                continue;
+            };
+            for bit in 0..15 {
+                if glyph & (1 << (14 - bit)) != 0 {
+                    let (column, row) = (bit % 3, bit / 3);
+                    let (left, top) = (x + index as i64 * 8 + column * 2, y + row * 2);
+                    for (px, py) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        self.set(left + px, top + py);
+                    }
+                }
+            }
+        }
+    }
+
+    fn to_png(&self) -> Vec<u8> {
+        qr::encode_png(self.width as usize, self.height as usize, |x, y| {
+            self.dark[y * self.width as usize + x]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plot, PlotFormat, Series};
+    use crate::{
+        curve::parse_curve,
+        units::{Celsius, Duty},
+    };
+
+    fn plot() -> Plot {
+        let curve = parse_curve("40:30,50:60,60:100").unwrap();
+        Plot {
+            from: Celsius::new(30, 0),
+            to: Celsius::new(70, 0),
+            series: vec![Series::new("intake", '*', move |t| curve.duty(t))],
+            operating: Some((Celsius::new(50, 0), Duty::new(60).unwrap())),
+        }
+    }
+
+    #[test]
+    fn widens_range_to_tens() {
+        assert_eq!(
+            (Celsius::new(30, 0), Celsius::new(80, 0)),
+            Plot::range(&[Celsius::new(40, 0), Celsius::new(70, 0)])
+        );
+        assert_eq!(
+            (Celsius::new(20, 0), Celsius::new(80, 0)),
+            Plot::range(&[Celsius::new(34, 500), Celsius::new(75, 0)])
+        );
+    }
+
+    #[test]
+    fn draws_text_plot() {
+        let text = plot().to_text();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert!(lines[0].starts_with(" 100% |"));
+        assert!(lines[0].ends_with('*'));
+        assert!(lines[20].starts_with("   0% |"));
+        // 30% is between the rows for 30% and 35%, rounded to the lower one
+        assert!(lines[14].starts_with("      |***"));
+        assert_eq!(Some(37), lines[8].find('X'));
+        assert!(lines[22].contains("30°C") && lines[22].contains("70°C"));
+        assert_eq!("       * intake", lines[23]);
+        assert_eq!("       X 50°C at 60%", lines[24]);
+    }
+
+    #[test]
+    fn draws_svg_and_png() {
+        let svg = plot().to_svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains("<polyline points=\"60,258 62,258"));
+        assert!(svg.contains("<circle cx=\"340\" cy=\"156\""));
+        assert!(svg.contains(">70°C</text>"));
+
+        let png = plot().render(PlotFormat::Png);
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+        assert_eq!(640u32.to_be_bytes(), png[16..20]);
+        assert_eq!(400u32.to_be_bytes(), png[20..24]);
+    }
+}
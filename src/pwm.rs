@@ -0,0 +1,589 @@
+use crate::{
+    args::Args,
+    clock::Clock,
+    linearize::Linearization,
+    units::{Celsius, Duty},
+};
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+/// Shortest wait before initializing a failed output again
+const REINIT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+/// Longest wait between attempts to initialize a failed output again
+const REINIT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Hardware the fan duty is written to.
+pub trait Output {
+    /// Prepares the output for use.
+    fn init(&mut self);
+
+    /// Drives the output with the given duty.
+    fn write(&mut self, duty: Duty);
+
+    /// Releases the output when the controller stops.
+    fn shutdown(&mut self);
+
+    /// Returns and clears the first error of initializing or writing since the last call, for
+    /// outputs that can fail.
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+
+    /// Initializes the output again after a write failed, e.g. reopening a device that went away
+    /// and came back. Unlike `init`, failing is expected and reported rather than fatal.
+    fn reinit(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Output discarding writes, for controllers whose caller applies decisions itself.
+pub struct NullOutput;
+
+impl Output for NullOutput {
+    fn init(&mut self) {}
+
+    fn write(&mut self, _duty: Duty) {}
+
+    fn shutdown(&mut self) {}
+}
+
+/// Fan duty and the output it's written to.
+///
+/// Limits are enforced in this order, each later one taking precedence:
+///
+/// 1. Control decisions stay within the soft limits `min` and `max`, the latter lowered further
+///    by the quiet mode cap.
+/// 2. At the soft temperature limit, `--temperature-max-value`, and in the other emergencies
+///    (a sensor fault, the latch or the emergency hold), the fan runs at `hard_max` instead.
+/// 3. At the hard temperature limit, `--temperature-hard-max`, the fan runs at `hard_max`
+///    regardless of a fan stop, the minimum dwell and observer vetoes.
+/// 4. Every duty set stays within the hard limits `hard_min` and `hard_max`. Only stopping the
+///    fan goes below `hard_min`.
+pub struct Pwm {
+    pub(crate) current: Duty,
+    pub(crate) previous: Duty,
+    pub(crate) increment: u8,
+    pub(crate) decrement: u8,
+    /// Soft limits of control decisions
+    pub(crate) min: Duty,
+    pub(crate) max: Duty,
+    /// Hard limits no duty set goes beyond
+    pub(crate) hard_min: Duty,
+    pub(crate) hard_max: Duty,
+    pub(crate) output: Box<dyn Output>,
+    /// Value last sent to the output
+    pub(crate) written: Option<Duty>,
+    pub(crate) kick: Option<Kick>,
+    pub(crate) slew: Option<Slew>,
+    /// Duty giving each cooling percent, with `current` and the limits in cooling percent
+    pub(crate) linearize: Option<Linearization>,
+    pub(crate) recovery: Recovery,
+}
+
+/// Change in the output's health noticed while flushing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    /// Writing to the output failed, it's initialized again after a backoff
+    Failed(String),
+    /// Output was initialized again after failing, this many times since starting
+    Reinitialized(u64),
+}
+
+/// Attempts to initialize an output again after its writes failed, backing off between them.
+#[derive(Debug, Clone, Default)]
+pub struct Recovery {
+    /// When to try initializing the output again, while it's failed
+    retry_at: Option<Instant>,
+    /// Wait before the next attempt
+    backoff: Duration,
+    /// Times the output was initialized again
+    reinits: u64,
+}
+
+impl Recovery {
+    /// Notes a failure, doubling the wait before the next attempt up to the longest.
+    fn fail(&mut self, now: Instant) {
+        self.backoff = (self.backoff * 2).clamp(REINIT_BACKOFF_MIN, REINIT_BACKOFF_MAX);
+        self.retry_at = Some(now + self.backoff);
+    }
+
+    fn recovered(&mut self) -> u64 {
+        self.retry_at = None;
+        self.backoff = Duration::ZERO;
+        self.reinits += 1;
+        self.reinits
+    }
+}
+
+/// Full speed pulse for fans that won't spin up from a standstill at low duties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kick {
+    /// How long full speed is held before settling at the requested duty
+    pub duration: Duration,
+    /// Duty the output must have been below for a rise to need a kick
+    pub below: Duty,
+}
+
+/// Limit on how fast the duty may change, however far the controller asks to move it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slew {
+    /// Most percentage points the duty may change by per minute
+    pub per_minute: u8,
+    /// Change allowed by the time elapsed so far, in thousandths of a percentage point
+    allowance: u32,
+}
+
+impl Slew {
+    /// Returns the limit of the options, if one is given.
+    pub fn new(args: &Args) -> Option<Self> {
+        args.pwm_slew_rate.map(|per_minute| Self {
+            per_minute,
+            allowance: 0,
+        })
+    }
+
+    fn elapse(&mut self, duration: Duration) {
+        let allowed = u128::from(self.per_minute) * duration.as_millis() / 60;
+        self.allowance = self
+            .allowance
+            .saturating_add(u32::try_from(allowed).unwrap_or(u32::MAX));
+    }
+
+    /// Returns the duty moved from `from` towards `to` by at most the allowance, using it up.
+    ///
+    /// Only a fraction of a percentage point is carried over, so time spent holding a duty
+    /// doesn't add up to a jump later.
+    fn limit(&mut self, from: Duty, to: Duty) -> Duty {
+        let step = u8::try_from(self.allowance / 1000).unwrap_or(u8::MAX);
+        let limited = if to > from {
+            to.min(from.raise(step))
+        } else {
+            to.max(from.lower(step))
+        };
+        let moved = u32::from(limited.percent().abs_diff(from.percent()));
+        self.allowance = (self.allowance - moved * 1000).min(999);
+        limited
+    }
+}
+
+impl Pwm {
+    pub fn new(args: &Args, output: Box<dyn Output>) -> Self {
+        Self {
+            current: args.pwm_max,
+            previous: args.pwm_max,
+            increment: args.pwm_increment,
+            decrement: args.pwm_decrement,
+            min: args.pwm_min,
+            max: args.pwm_max,
+            hard_min: args.pwm_hard_min.unwrap_or(args.pwm_min),
+            hard_max: args.pwm_hard_max.unwrap_or(args.pwm_max),
+            output,
+            written: None,
+            kick: (args.kick_start_ms > 0).then_some(Kick {
+                duration: Duration::from_millis(args.kick_start_ms),
+                below: args.kick_start_below,
+            }),
+            slew: Slew::new(args),
+            linearize: args.linearize.clone(),
+            recovery: Recovery::default(),
+        }
+    }
+
+    /// Initializes the output, which starts at full duty
+    ///
+    /// An output that failed to initialize is left with nothing written, so the next flush
+    /// reports the failure and initializes it again with backoff.
+    pub fn init(&mut self) {
+        self.output.init();
+        self.written = match self.output.take_error() {
+            Some(error) => {
+                log::warn!("Failed to initialize fan output: {}", error);
+                None
+            }
+            None => Some(Duty::FULL),
+        };
+    }
+
+    /// Checks and fixes provided PWM value to be within the hard limits
+    pub fn fix_pwm_value(&self, value: Duty) -> Duty {
+        if value > self.hard_max {
+            return self.hard_max;
+        }
+
+        if value < self.hard_min {
+            return self.hard_min;
+        }
+
+        value
+    }
+
+    /// Returns a duty within the soft limits.
+    pub fn soft_clamp(&self, value: Duty) -> Duty {
+        value.clamp(self.min, self.max)
+    }
+
+    /// Sets new PWM value, which reaches the output on the next `flush`.
+    ///
+    /// With a slew rate limit, the value is only approached as far as the time elapsed allows.
+    pub fn write(&mut self, value: Duty) {
+        let value = match &mut self.slew {
+            Some(slew) => slew.limit(self.current, value),
+            None => value,
+        };
+        self.jump(value);
+    }
+
+    /// Sets new PWM value regardless of the slew rate limit, e.g. to reach full speed at once
+    /// when overheating.
+    pub fn jump(&mut self, value: Duty) {
+        self.previous = self.current;
+        self.current = self.fix_pwm_value(value);
+    }
+
+    /// Notes the time since the last poll, which the slew rate limit allows changes by.
+    pub fn elapse(&mut self, duration: Duration) {
+        if let Some(slew) = &mut self.slew {
+            slew.elapse(duration);
+        }
+    }
+
+    /// Stops the fan, below the minimum the limits otherwise keep it at
+    pub fn stop(&mut self) {
+        self.previous = self.current;
+        self.current = Duty::OFF;
+    }
+
+    /// Sends the current PWM value to the output if it differs from what was last written.
+    ///
+    /// Called once per loop so that successive writes within the loop coalesce into one. Rising
+    /// from below the kick-start threshold, the fan is first driven at full speed for a moment.
+    /// With a linearization, the output gets the duty giving the current cooling percent.
+    ///
+    /// Once a write fails, nothing more is written until the output was initialized again, which
+    /// is tried on later flushes with a growing backoff.
+    pub fn flush(&mut self, clock: &mut dyn Clock) -> Option<Health> {
+        let mut health = None;
+        if let Some(retry_at) = self.recovery.retry_at {
+            if clock.now() < retry_at {
+                return None;
+            }
+            if let Err(error) = self.output.reinit() {
+                log::warn!("Failed to initialize fan output again: {}", error);
+                self.recovery.fail(clock.now());
+                return None;
+            }
+            // Whatever the output was left at, write the current duty again
+            self.written = None;
+            health = Some(Health::Reinitialized(self.recovery.recovered()));
+        }
+
+        if self.written == Some(self.current) {
+            return health;
+        }
+
+        if let (Some(kick), Some(written)) = (self.kick, self.written) {
+            if written < kick.below && self.current > written {
+                self.output.write(Duty::FULL);
+                clock.sleep(kick.duration);
+            }
+        }
+        let duty = match &self.linearize {
+            Some(linearize) => linearize.duty(self.current),
+            None => self.current,
+        };
+        self.output.write(duty);
+        if let Some(error) = self.output.take_error() {
+            self.written = None;
+            self.recovery.fail(clock.now());
+            return Some(Health::Failed(error.to_string()));
+        }
+        self.written = Some(self.current);
+        health
+    }
+
+    /// Whether the output failed and hasn't been initialized again since.
+    pub fn failed(&self) -> bool {
+        self.recovery.retry_at.is_some()
+    }
+
+    /// Releases the output
+    pub fn shutdown(&mut self) {
+        self.output.shutdown();
+    }
+}
+
+/// Temperatures to stop the fan completely below and to start it again above.
+///
+/// The gap between them keeps the fan from starting and stopping around a single threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FanStop {
+    pub off_below: Celsius,
+    pub restart_above: Celsius,
+}
+
+impl FanStop {
+    /// Returns the thresholds of the options, restarting at the target temperature unless given.
+    ///
+    /// On/off control switches at its own thresholds.
+    pub fn new(args: &Args) -> Option<Self> {
+        if let (Some(restart_above), Some(off_below)) =
+            (args.switch_on_above, args.switch_off_below)
+        {
+            return Some(Self {
+                off_below,
+                restart_above,
+            });
+        }
+        let off_below = args.fan_off_below?;
+        Some(Self {
+            off_below,
+            restart_above: args
+                .fan_restart_above
+                .unwrap_or(args.temperature_target_value)
+                .max(off_below),
+        })
+    }
+
+    /// Returns whether the fan should be stopped at a temperature, given whether it's stopped.
+    pub fn stopped(&self, stopped: bool, current: Celsius) -> bool {
+        if stopped {
+            current <= self.restart_above
+        } else {
+            current < self.off_below
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Duty, FanStop, Health, Kick, Pwm, Recovery, Slew};
+    use crate::{
+        mock::{Call, MockClock, MockOutput},
+        units::Celsius,
+    };
+    use std::time::Duration;
+
+    pub(crate) fn duty(percent: u8) -> Duty {
+        Duty::new(percent).unwrap()
+    }
+
+    pub(crate) fn recording_pwm(output: &MockOutput) -> Pwm {
+        Pwm {
+            current: duty(50),
+            previous: duty(50),
+            increment: 2,
+            decrement: 1,
+            min: duty(0),
+            max: duty(100),
+            hard_min: duty(0),
+            hard_max: duty(100),
+            output: Box::new(output.clone()),
+            written: None,
+            kick: None,
+            slew: None,
+            linearize: None,
+            recovery: Recovery::default(),
+        }
+    }
+
+    #[test]
+    fn pwm_writes_coalesce_into_single_flush() {
+        let output = MockOutput::new();
+        let mut pwm = recording_pwm(&output);
+
+        pwm.write(duty(100));
+        pwm.write(duty(80));
+        pwm.write(duty(60));
+        pwm.flush(&mut MockClock::new());
+
+        assert_eq!(vec![duty(60)], output.writes());
+    }
+
+    #[test]
+    fn pwm_flush_skips_unchanged_value() {
+        let output = MockOutput::new();
+        let mut pwm = recording_pwm(&output);
+
+        pwm.init();
+        pwm.write(duty(100)); // Same as initial value
+        pwm.flush(&mut MockClock::new());
+        pwm.write(duty(60));
+        pwm.flush(&mut MockClock::new());
+        pwm.flush(&mut MockClock::new());
+
+        assert_eq!(vec![duty(60)], output.writes());
+    }
+
+    #[test]
+    fn failed_output_is_initialized_again_with_backoff() {
+        let output = MockOutput::new();
+        let clock = MockClock::new();
+        let mut pwm = recording_pwm(&output);
+        let poll = |pwm: &mut Pwm, duty: Duty| {
+            clock.advance(Duration::from_secs(1));
+            pwm.write(duty);
+            pwm.flush(&mut clock.clone())
+        };
+
+        pwm.init();
+        output.set_failing(true);
+        assert_eq!(
+            Some(Health::Failed("device gone".to_string())),
+            poll(&mut pwm, duty(60))
+        );
+        // The retry after a second fails too, so the next waits two
+        assert_eq!(None, poll(&mut pwm, duty(61)));
+        output.set_failing(false);
+        assert_eq!(None, poll(&mut pwm, duty(62)));
+        assert_eq!(Some(Health::Reinitialized(1)), poll(&mut pwm, duty(63)));
+        assert_eq!(None, poll(&mut pwm, duty(63)));
+
+        let calls: Vec<Call> = output
+            .calls()
+            .iter()
+            .map(|recorded| recorded.call)
+            .collect();
+        assert_eq!(vec![Call::Init, Call::Reinit, Call::Write(duty(63))], calls);
+    }
+
+    #[test]
+    fn kick_starts_fan_rising_from_standstill() {
+        let output = MockOutput::new();
+        let clock = MockClock::new();
+        let mut pwm = recording_pwm(&output);
+        pwm.kick = Some(Kick {
+            duration: Duration::from_millis(500),
+            below: duty(1),
+        });
+
+        pwm.init();
+        pwm.stop();
+        pwm.flush(&mut clock.clone());
+        pwm.write(duty(30));
+        pwm.flush(&mut clock.clone());
+        pwm.write(duty(32));
+        pwm.flush(&mut clock.clone());
+
+        assert_eq!(
+            vec![duty(0), Duty::FULL, duty(30), duty(32)],
+            output.writes()
+        );
+        assert_eq!(Duration::from_millis(500), clock.elapsed());
+    }
+
+    #[test]
+    fn slew_rate_limits_change_per_minute() {
+        let output = MockOutput::new();
+        let mut pwm = recording_pwm(&output);
+        pwm.slew = Some(Slew {
+            per_minute: 12,
+            allowance: 0,
+        });
+
+        // 12 points per minute allow 1 point every 5 seconds
+        pwm.elapse(Duration::from_secs(2));
+        pwm.write(duty(80));
+        assert_eq!(duty(50), pwm.current);
+        pwm.elapse(Duration::from_secs(28));
+        pwm.write(duty(80));
+        assert_eq!(duty(56), pwm.current);
+
+        // Holding a duty doesn't save up for a jump
+        pwm.elapse(Duration::from_secs(60));
+        pwm.write(duty(56));
+        pwm.elapse(Duration::from_secs(5));
+        pwm.write(duty(20));
+        assert_eq!(duty(55), pwm.current);
+
+        pwm.jump(duty(100));
+        assert_eq!(duty(100), pwm.current);
+    }
+
+    #[test]
+    fn pwm_value_too_high() {
+        let pwm = Pwm {
+            current: duty(0),
+            previous: duty(0),
+            increment: 2,
+            decrement: 1,
+            min: duty(0),
+            max: duty(90),
+            hard_min: duty(0),
+            hard_max: duty(90),
+            output: Box::new(MockOutput::new()),
+            written: None,
+            kick: None,
+            slew: None,
+            linearize: None,
+            recovery: Recovery::default(),
+        };
+
+        let pwm_value = duty(95);
+        let value = pwm.fix_pwm_value(pwm_value);
+        assert_eq!(pwm.hard_max, value);
+    }
+
+    #[test]
+    fn pwm_value_too_low() {
+        let pwm = Pwm {
+            current: duty(0),
+            previous: duty(0),
+            increment: 2,
+            decrement: 1,
+            min: duty(10),
+            max: duty(100),
+            hard_min: duty(10),
+            hard_max: duty(100),
+            output: Box::new(MockOutput::new()),
+            written: None,
+            kick: None,
+            slew: None,
+            linearize: None,
+            recovery: Recovery::default(),
+        };
+
+        let pwm_value = duty(5);
+        let value = pwm.fix_pwm_value(pwm_value);
+        assert_eq!(pwm.hard_min, value);
+    }
+
+    #[test]
+    fn pwm_value_within_limits() {
+        let pwm = Pwm {
+            current: duty(0),
+            previous: duty(0),
+            increment: 2,
+            decrement: 1,
+            min: duty(0),
+            max: duty(100),
+            hard_min: duty(0),
+            hard_max: duty(100),
+            output: Box::new(MockOutput::new()),
+            written: None,
+            kick: None,
+            slew: None,
+            linearize: None,
+            recovery: Recovery::default(),
+        };
+
+        let pwm_value = duty(50);
+        let value = pwm.fix_pwm_value(pwm_value);
+        assert_eq!(pwm_value, value);
+    }
+
+    #[test]
+    fn fan_stop_has_hysteresis() {
+        let stop = FanStop {
+            off_below: Celsius::new(35, 0),
+            restart_above: Celsius::new(40, 0),
+        };
+
+        assert!(!stop.stopped(false, Celsius::new(35, 0)));
+        assert!(stop.stopped(false, Celsius::new(34, 900)));
+        assert!(stop.stopped(true, Celsius::new(38, 0)));
+        assert!(stop.stopped(true, Celsius::new(40, 0)));
+        assert!(!stop.stopped(true, Celsius::new(40, 100)));
+    }
+}
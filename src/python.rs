@@ -0,0 +1,88 @@
+//! Python bindings, so tuning notebooks and automation scripts can run the production control logic.
+
+use crate::{
+    controller::Controller as CoreController,
+    sensor,
+    units::{Celsius, Duty},
+};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+/// Converts degrees to fixed-point Celsius, rounding to the nearest millidegree.
+fn celsius(degrees: f64) -> Celsius {
+    Celsius::new(0, (degrees * 1000.0).round() as i32)
+}
+
+fn duty(percent: u8) -> PyResult<Duty> {
+    Duty::new(percent)
+        .ok_or_else(|| PyValueError::new_err(format!("duty {} is not within 0-100", percent)))
+}
+
+/// Controller fed with temperatures in degrees Celsius, returning duties in percent.
+#[pyclass(unsendable)]
+struct Controller(CoreController);
+
+#[pymethods]
+impl Controller {
+    #[new]
+    #[pyo3(signature = (target=40.0, temperature_max=70.0, pwm_min=30, pwm_max=100, pwm_increment=2, pwm_decrement=1))]
+    fn new(
+        target: f64,
+        temperature_max: f64,
+        pwm_min: u8,
+        pwm_max: u8,
+        pwm_increment: u8,
+        pwm_decrement: u8,
+    ) -> PyResult<Self> {
+        let (min, max) = (duty(pwm_min)?, duty(pwm_max)?);
+        if min > max {
+            return Err(PyValueError::new_err("pwm_min must not exceed pwm_max"));
+        }
+
+        Ok(Self(CoreController::detached(
+            celsius(target),
+            celsius(temperature_max),
+            min,
+            max,
+            pwm_increment,
+            pwm_decrement,
+        )))
+    }
+
+    /// Feeds a temperature reading and returns the duty to apply.
+    fn step(&mut self, temperature: f64) -> u8 {
+        // Readings go through the same one decimal rounding as sensor values
+        let millidegrees = (temperature * 1000.0).round() as i32;
+        self.0
+            .step(Celsius::from_millidegrees(millidegrees))
+            .percent()
+    }
+
+    /// Changes the temperature to maintain.
+    fn set_target(&mut self, target: f64) {
+        self.0.set_target(celsius(target));
+    }
+}
+
+/// Parses sensor file content in millidegrees, returning degrees Celsius.
+#[pyfunction]
+fn parse_sensor(content: &str) -> PyResult<f64> {
+    sensor::parse_millidegrees(content)
+        .map(|value| f64::from(value.millidegrees()) / 1000.0)
+        .map_err(|error| PyValueError::new_err(error.to_string()))
+}
+
+/// Reads a sensor file such as `/sys/class/thermal/thermal_zone0/temp`, returning degrees Celsius.
+#[pyfunction]
+fn read_sensor(path: &str) -> PyResult<f64> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| PyValueError::new_err(format!("Failed to read {:?}: {}", path, error)))?;
+    parse_sensor(&content)
+}
+
+#[pymodule]
+fn fan_controller(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Controller>()?;
+    module.add_function(wrap_pyfunction!(parse_sensor, module)?)?;
+    module.add_function(wrap_pyfunction!(read_sensor, module)?)?;
+    Ok(())
+}
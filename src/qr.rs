@@ -0,0 +1,562 @@
+//! QR code encoder for short byte strings, such as the pairing URI.
+//!
+//! Supports byte mode at error correction level M in versions 1 to 9, which holds up to 180
+//! bytes, and renders for terminals or as a PNG image.
+
+use std::fmt;
+
+/// Largest version encoded, whose byte count still fits in 8 bits
+const MAX_VERSION: usize = 9;
+
+/// Total codewords, error correction codewords per block and block count of each version at
+/// level M
+const BLOCKS: [(usize, usize, usize); MAX_VERSION] = [
+    (26, 10, 1),
+    (44, 16, 1),
+    (70, 26, 1),
+    (100, 18, 2),
+    (134, 24, 2),
+    (172, 16, 4),
+    (196, 18, 4),
+    (242, 22, 4),
+    (292, 22, 5),
+];
+
+/// Centers of the alignment patterns of each version
+const ALIGNMENT: [&[usize]; MAX_VERSION] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+];
+
+/// Light modules around the code that readers need to find it
+const QUIET_ZONE: usize = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TooLong(pub usize);
+
+impl fmt::Display for TooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes don't fit in a QR code, the most is {}",
+            self.0,
+            data_codewords(MAX_VERSION) - 2
+        )
+    }
+}
+
+impl std::error::Error for TooLong {}
+
+fn data_codewords(version: usize) -> usize {
+    let (total, ec, blocks) = BLOCKS[version - 1];
+    total - ec * blocks
+}
+
+/// Square of dark and light modules, without the quiet zone.
+#[derive(Debug)]
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    /// Modules of finder, timing and other patterns, which data and masks leave alone
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes the data in the smallest version it fits.
+    pub fn encode(data: &[u8]) -> Result<Self, TooLong> {
+        // Mode and byte count come first
+        let version = (1..=MAX_VERSION)
+            .find(|&version| data.len() + 2 <= data_codewords(version))
+            .ok_or(TooLong(data.len()))?;
+
+        let size = version * 4 + 17;
+        let mut code = Self {
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        code.draw_function_patterns(version);
+        code.draw_codewords(&codewords(data, version));
+
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                code.apply_mask(mask);
+                code.draw_format(mask);
+                let penalty = code.penalty();
+                code.apply_mask(mask);
+                penalty
+            })
+            .unwrap();
+        code.apply_mask(mask);
+        code.draw_format(mask);
+        Ok(code)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Same as [`QrCode::is_dark`], with coordinates including the quiet zone.
+    fn is_dark_framed(&self, x: usize, y: usize) -> bool {
+        let inside = QUIET_ZONE..QUIET_ZONE + self.size;
+        inside.contains(&x) && inside.contains(&y) && self.is_dark(x - QUIET_ZONE, y - QUIET_ZONE)
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set(6, i, i % 2 == 0);
+            self.set(i, 6, i % 2 == 0);
+        }
+
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            // Finder with its light separator
+            for dy in -4isize..=4 {
+                for dx in -4isize..=4 {
+                    let (Some(mx), Some(my)) = (x.checked_add_signed(dx), y.checked_add_signed(dy))
+                    else {
+                        continue;
+                    };
+                    if mx < size && my < size {
+                        let distance = dx.abs().max(dy.abs());
+                        self.set(mx, my, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+
+        let centers = ALIGNMENT[version - 1];
+        let last = centers.len().saturating_sub(1);
+        for (i, &y) in centers.iter().enumerate() {
+            for (j, &x) in centers.iter().enumerate() {
+                // Corners with finders get none
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in 0..5 {
+                    for dx in 0..5 {
+                        let distance = (dx as isize - 2).abs().max((dy as isize - 2).abs());
+                        self.set(x + dx - 2, y + dy - 2, distance != 1);
+                    }
+                }
+            }
+        }
+
+        // Reserve the format areas, drawn once the mask is chosen
+        self.draw_format(0);
+
+        if version >= 7 {
+            let mut remainder = version;
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+            }
+            let bits = version << 12 | remainder;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set(a, b, dark);
+                self.set(b, a, dark);
+            }
+        }
+    }
+
+    /// Draws both copies of the format information for level M and the given mask.
+    fn draw_format(&mut self, mask: usize) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        for i in 0..=5 {
+            self.set(8, i, bit(i));
+        }
+        self.set(8, 7, bit(6));
+        self.set(8, 8, bit(7));
+        self.set(7, 8, bit(8));
+        for i in 9..15 {
+            self.set(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set(8, size - 15 + i, bit(i));
+        }
+        // Always dark
+        self.set(8, size - 8, true);
+    }
+
+    /// Places the codewords in the zigzag order, upwards and downwards in two column strips.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut index = 0;
+        let mut right = size - 1;
+        loop {
+            // Vertical timing pattern is skipped
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..size {
+                for x in [right, right - 1] {
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward {
+                        size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+                    if !self.function[y * size + x] && index < codewords.len() * 8 {
+                        self.modules[y * size + x] =
+                            (codewords[index / 8] >> (7 - index % 8)) & 1 != 0;
+                        index += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// Flips the data modules selected by the mask, so applying it twice undoes it.
+    fn apply_mask(&mut self, mask: usize) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                if flip && !self.function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// Scores patterns that make the code harder to read, lower is better.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+
+        let lines = (0..size).flat_map(|i| {
+            [
+                (0..size).map(|j| self.is_dark(j, i)).collect::<Vec<_>>(),
+                (0..size).map(|j| self.is_dark(i, j)).collect::<Vec<_>>(),
+            ]
+        });
+        for line in lines {
+            // Runs of five or more modules of the same color
+            for run in line.chunk_by(|a, b| a == b) {
+                if run.len() >= 5 {
+                    penalty += run.len() - 2;
+                }
+            }
+            // Patterns looking like a finder
+            for window in line.windows(11) {
+                let pattern = [true, false, true, true, true, false, true];
+                if (window[..7] == pattern && window[7..].iter().all(|dark| !dark))
+                    || (window[4..] == pattern && window[..4].iter().all(|dark| !dark))
+                {
+                    penalty += 40;
+                }
+            }
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.is_dark(x, y);
+                if dark == self.is_dark(x + 1, y)
+                    && dark == self.is_dark(x, y + 1)
+                    && dark == self.is_dark(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let percent = dark * 100 / self.modules.len();
+        penalty + percent.abs_diff(50) / 5 * 10
+    }
+
+    /// Renders two rows per line with half blocks, drawing light modules for dark terminals.
+    pub fn to_terminal(&self) -> String {
+        let light = |x: usize, y: usize| !self.is_dark_framed(x, y);
+        let width = self.size + QUIET_ZONE * 2;
+
+        let mut text = String::new();
+        for y in (0..width).step_by(2) {
+            for x in 0..width {
+                let bottom = y + 1 < width && light(x, y + 1);
+                text.push(match (light(x, y), bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Renders a black and white PNG image with each module as a square of `scale` pixels.
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let width = (self.size + QUIET_ZONE * 2) * scale;
+        encode_png(width, width, |x, y| {
+            self.is_dark_framed(x / scale, y / scale)
+        })
+    }
+}
+
+/// Encodes a black and white PNG image of the given size.
+pub(crate) fn encode_png(
+    width: usize,
+    height: usize,
+    dark: impl Fn(usize, usize) -> bool,
+) -> Vec<u8> {
+    let row_length = width.div_ceil(8);
+
+    let mut pixels = Vec::with_capacity((row_length + 1) * height);
+    for y in 0..height {
+        // No filter
+        pixels.push(0);
+        let mut row = vec![0u8; row_length];
+        for x in 0..width {
+            if !dark(x, y) {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        pixels.extend(row);
+    }
+
+    let mut header = Vec::new();
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // One bit grayscale, default compression and filtering, no interlacing
+    header.extend([1, 0, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Format information for level M, with its error correction and mask applied.
+fn format_bits(mask: usize) -> usize {
+    // Level M is encoded as zero
+    let data = mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    (data << 10 | remainder) ^ 0x5412
+}
+
+/// Returns the data and error correction codewords of each block, interleaved.
+fn codewords(data: &[u8], version: usize) -> Vec<u8> {
+    let capacity = data_codewords(version);
+
+    // Byte mode, count, data and a terminator of up to four bits
+    let mut stream = vec![0x40 | (data.len() >> 4) as u8];
+    let mut carry = (data.len() as u8) << 4;
+    for &byte in data {
+        stream.push(carry | byte >> 4);
+        carry = byte << 4;
+    }
+    stream.push(carry);
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if stream.len() >= capacity {
+            break;
+        }
+        stream.push(pad);
+    }
+
+    let (total, ec, blocks) = BLOCKS[version - 1];
+    let short = total / blocks - ec;
+    let short_blocks = blocks - total % blocks;
+    let divisor = divisor(ec);
+
+    let mut start = 0;
+    let mut parts = Vec::new();
+    for block in 0..blocks {
+        let length = short + usize::from(block >= short_blocks);
+        let data = &stream[start..start + length];
+        start += length;
+        parts.push((data, remainder(data, &divisor)));
+    }
+
+    let mut result = Vec::with_capacity(total);
+    for i in 0..=short {
+        for (data, _) in &parts {
+            if let Some(&byte) = data.get(i) {
+                result.push(byte);
+            }
+        }
+    }
+    for i in 0..ec {
+        for (_, ec) in &parts {
+            result.push(ec[i]);
+        }
+    }
+    result
+}
+
+/// Product in GF(256) with the QR code polynomial.
+fn multiply(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= u16::from((y >> i) & 1) * u16::from(x);
+    }
+    z as u8
+}
+
+/// Reed-Solomon generator polynomial of the given degree, without its leading term.
+fn divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = multiply(root, 0x02);
+    }
+    result
+}
+
+/// Error correction codewords of a block.
+fn remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (value, &coefficient) in result.iter_mut().zip(divisor) {
+            *value ^= multiply(coefficient, factor);
+        }
+    }
+    result
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Wraps data in a zlib stream of uncompressed blocks, which is plenty for a small image.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        stream.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(u8::from(blocks.peek().is_none()));
+        let length = block.len() as u16;
+        stream.extend(length.to_le_bytes());
+        stream.extend((!length).to_le_bytes());
+        stream.extend(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend((b << 16 | a).to_be_bytes());
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, divisor, format_bits, remainder, QrCode, TooLong};
+
+    #[test]
+    fn computes_error_correction() {
+        // Data codewords of "HELLO WORLD" in alphanumeric mode at 1-M
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23],
+            remainder(&data, &divisor(10))
+        );
+    }
+
+    #[test]
+    fn computes_format_information() {
+        assert_eq!(0b101010000010010, format_bits(0));
+        assert_eq!(0b100000011001110, format_bits(5));
+    }
+
+    #[test]
+    fn picks_smallest_version() {
+        assert_eq!(21, QrCode::encode(&[b'a'; 14]).unwrap().size());
+        assert_eq!(25, QrCode::encode(&[b'a'; 15]).unwrap().size());
+        assert_eq!(53, QrCode::encode(&[b'a'; 180]).unwrap().size());
+        assert_eq!(TooLong(181), QrCode::encode(&[b'a'; 181]).unwrap_err());
+    }
+
+    #[test]
+    fn draws_finders_and_timing() {
+        let code = QrCode::encode(b"fan-controller://pi.local?modbus=502").unwrap();
+        let size = code.size();
+        for (x, y) in [(0, 0), (size - 7, 0), (0, size - 7)] {
+            assert!(code.is_dark(x, y) && code.is_dark(x + 6, y + 6));
+            assert!(!code.is_dark(x + 1, y + 1) && code.is_dark(x + 2, y + 2));
+        }
+        assert!((8..size - 8).all(|i| code.is_dark(i, 6) == (i % 2 == 0)));
+    }
+
+    #[test]
+    fn writes_png() {
+        let png = QrCode::encode(b"x").unwrap().to_png(2);
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+        // Version 1 with the quiet zone is 29 modules wide
+        assert_eq!(58u32.to_be_bytes(), png[16..20]);
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+}
@@ -0,0 +1,494 @@
+//! Configuration reload on SIGHUP, and profile switching on SIGUSR1 or a control command.
+//!
+//! The command line and config file are read again and checked completely before anything
+//! changes, so a typo in the config leaves the controller running on its previous settings
+//! instead of stopping the fan control.
+
+use crate::{args::Args, board, config, trip};
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Whether the reload requested may reduce cooling during an emergency
+static FORCED: AtomicBool = AtomicBool::new(false);
+/// Whether the reload requested should switch to the next profile
+static NEXT_PROFILE: AtomicBool = AtomicBool::new(false);
+/// Profile the reload requested should switch to, `Some(None)` for the one the config selects
+static PROFILE: Mutex<Option<Option<String>>> = Mutex::new(None);
+
+#[cfg(unix)]
+extern "C" fn requested_by_signal(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn next_profile_by_signal(_signal: libc::c_int) {
+    NEXT_PROFILE.store(true, Ordering::SeqCst);
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGHUP request a reload and SIGUSR1 a switch to the next profile, instead of exiting.
+#[cfg(unix)]
+pub fn install() {
+    let handler = requested_by_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    let next = next_profile_by_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGHUP, handler);
+        libc::signal(libc::SIGUSR1, next);
+    }
+}
+
+/// Requests a reload switching to the named profile.
+pub fn switch_profile(name: &str) {
+    request_profile(Some(name.to_string()));
+}
+
+/// Requests a reload switching back to the profile selected by the config file, if any.
+pub fn reset_profile() {
+    request_profile(None);
+}
+
+fn request_profile(profile: Option<String>) {
+    *PROFILE.lock().unwrap_or_else(|error| error.into_inner()) = Some(profile);
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Requests a reload, which with `force` applies options reducing cooling during an
+/// overtemperature emergency too.
+pub fn request(force: bool) {
+    FORCED.fetch_or(force, Ordering::SeqCst);
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Returns whether a reload was requested since the last call.
+pub fn requested() -> bool {
+    REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+/// Returns whether the reload requested was forced.
+pub fn forced() -> bool {
+    FORCED.swap(false, Ordering::SeqCst)
+}
+
+/// Effective value of every option, by long name, including defaults.
+pub type Settings = BTreeMap<String, String>;
+
+/// Effective value of an option, as a config file would give it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// Whether a flag is set
+    Flag(bool),
+    /// Values given or defaulted, one for each time the option is repeated
+    Values(Vec<String>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Flag(set) => write!(f, "{}", set),
+            Value::Values(values) => f.write_str(&values.join(",")),
+        }
+    }
+}
+
+/// Option whose effective value changed in a reload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Long option name without the leading dashes
+    pub option: String,
+    /// Previous value, `None` if the option was unset
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |value: &Option<String>| value.clone().unwrap_or_else(|| "unset".to_string());
+        write!(
+            f,
+            "{} {} → {}",
+            self.option,
+            value(&self.from),
+            value(&self.to)
+        )
+    }
+}
+
+/// Options read by a reload, along with their effective values for reporting what changed.
+#[derive(Debug)]
+pub struct Loaded {
+    pub args: Args,
+    settings: Settings,
+    /// Command line the options were read from, with any profile switched to
+    argv: Vec<String>,
+}
+
+/// Source of the options a reload applies: the original command line, with the config file it
+/// names read again.
+#[derive(Debug, Clone)]
+pub struct Reloader {
+    argv: Vec<String>,
+    /// Settings in effect, to compare reloaded ones against
+    applied: Settings,
+}
+
+impl Reloader {
+    /// Returns a reloader for the given command line, which is also the one in effect.
+    pub fn new(argv: Vec<String>) -> Self {
+        let applied = parse(argv.clone())
+            .map(|loaded| loaded.settings)
+            .unwrap_or_default();
+        Self { argv, applied }
+    }
+
+    /// Reads and validates the options, returning why they were rejected if they're unusable.
+    ///
+    /// A profile switch requested since the last load is taken up here, and kept by later reloads
+    /// once applied.
+    pub fn load(&self) -> Result<Loaded, String> {
+        let profile = PROFILE
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .take();
+        let profile = match profile {
+            Some(profile) => Some(profile),
+            None if NEXT_PROFILE.swap(false, Ordering::SeqCst) => Some(Some(self.next_profile()?)),
+            None => None,
+        };
+        self.load_profile(profile)
+    }
+
+    /// Reads and validates the options, switching profiles if asked to: `Some(None)` switches
+    /// back to the one the config file selects.
+    fn load_profile(&self, profile: Option<Option<String>>) -> Result<Loaded, String> {
+        let argv = match profile {
+            Some(profile) => with_profile(&self.argv, profile.as_deref()),
+            None => self.argv.clone(),
+        };
+
+        let loaded = parse(argv)?;
+        loaded.args.validate()?;
+        Ok(loaded)
+    }
+
+    /// Records the loaded options as the ones in effect, returning what changed.
+    pub fn apply(&mut self, loaded: Loaded) -> Vec<Change> {
+        let changes = diff(&self.applied, &loaded.settings);
+        self.applied = loaded.settings;
+        self.argv = loaded.argv;
+        changes
+    }
+
+    /// Returns the profile after the one in effect in name order, wrapping around to the first.
+    fn next_profile(&self) -> Result<String, String> {
+        let profiles = config::profiles(&self.argv).map_err(|error| error.to_string())?;
+        let current = self.applied.get("profile");
+        let next = match profiles.iter().position(|name| Some(name) == current) {
+            Some(index) => profiles.get(index + 1).or(profiles.first()),
+            None => profiles.first(),
+        };
+        next.cloned()
+            .ok_or_else(|| "no profiles in the config to switch to".to_string())
+    }
+}
+
+/// Returns the command line with `--profile` replaced by the given one, or left out.
+fn with_profile(argv: &[String], profile: Option<&str>) -> Vec<String> {
+    let mut replaced = Vec::with_capacity(argv.len() + 2);
+    let mut arguments = argv.iter();
+    while let Some(argument) = arguments.next() {
+        if argument == "--profile" {
+            arguments.next();
+        } else if !argument.starts_with("--profile=") {
+            replaced.push(argument.clone());
+        }
+    }
+    if let Some(profile) = profile {
+        replaced.push("--profile".to_string());
+        replaced.push(profile.to_string());
+    }
+    replaced
+}
+
+/// Returns only the message of a parse error, without clap's usage hints.
+fn message(error: clap::Error) -> String {
+    let rendered = error.to_string();
+    let first = rendered.lines().next().unwrap_or_default();
+    first.trim_start_matches("error: ").to_string()
+}
+
+/// Parses the command line with the config file it names, the trip point and the board defaults.
+fn matches(argv: Vec<String>) -> Result<clap::ArgMatches, String> {
+    let argv = config::args_with_config(argv).map_err(|error| error.to_string())?;
+    let trips = trip::detect(&argv);
+    let argv = trip::args_with_trip_points(argv, trips.as_ref());
+    let board = board::detect(Path::new(board::MODEL));
+    let argv = board::args_with_board(argv, board.as_ref());
+    Args::command().try_get_matches_from(argv).map_err(message)
+}
+
+/// Returns the effective value of every option set, and if asked defaulted, by long name.
+fn values(matches: &clap::ArgMatches, defaults: bool) -> BTreeMap<String, Value> {
+    let mut options = BTreeMap::new();
+    for arg in Args::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        let (Some(long), Ok(Some(values))) = (arg.get_long(), matches.try_get_raw(id)) else {
+            continue;
+        };
+        if !defaults && matches.value_source(id) == Some(ValueSource::DefaultValue) {
+            continue;
+        }
+        let values: Vec<String> = values
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect();
+        let value = if arg.get_action().takes_values() {
+            Value::Values(values)
+        } else {
+            Value::Flag(values.iter().any(|value| value == "true"))
+        };
+        options.insert(long.to_string(), value);
+    }
+    options
+}
+
+/// Returns the value of every option a command line, the config file it names or the board
+/// defaults set, by long name, leaving out the built-in defaults.
+pub fn effective(argv: Vec<String>) -> Result<BTreeMap<String, Value>, String> {
+    matches(argv).map(|matches| values(&matches, false))
+}
+
+fn parse(argv: Vec<String>) -> Result<Loaded, String> {
+    let given = argv.clone();
+    let matches = matches(argv)?;
+    let settings = values(&matches, true)
+        .into_iter()
+        .map(|(option, value)| (option, value.to_string()))
+        .collect();
+
+    let args = Args::from_arg_matches(&matches).map_err(message)?;
+    Ok(Loaded {
+        args,
+        settings,
+        argv: given,
+    })
+}
+
+/// Returns the options whose values differ, in option name order.
+pub fn diff(from: &Settings, to: &Settings) -> Vec<Change> {
+    let options: BTreeSet<&String> = from.keys().chain(to.keys()).collect();
+    options
+        .into_iter()
+        .filter(|option| from.get(*option) != to.get(*option))
+        .map(|option| Change {
+            option: option.clone(),
+            from: from.get(option).cloned(),
+            to: to.get(option).cloned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{effective, Change, Loaded, Reloader, Value};
+    use crate::{args::Args, config, mock::TempPath};
+    use clap::error::ErrorKind;
+
+    fn reloader(config: &TempPath) -> Reloader {
+        Reloader::new(vec![
+            "fan-controller".to_string(),
+            "--config".to_string(),
+            config.arg().to_string(),
+        ])
+    }
+
+    /// Loads a config file of the given content once.
+    fn load(name: &str, content: &str) -> Result<Loaded, String> {
+        reloader(&TempPath::file(name, content)).load()
+    }
+
+    #[test]
+    fn reports_options_set_without_defaults() {
+        let config = TempPath::file(
+            "reload-effective.toml",
+            "gpio-pwm = 3\ninhibit = true\nquiet-hours = [\"22:00-07:00\", \"12:00-13:00\"]\n",
+        );
+        let argv = ["fan-controller", "--config", config.arg()];
+        let options = effective(argv.map(String::from).to_vec()).unwrap();
+
+        assert_eq!(
+            Some(&Value::Values(vec!["3".to_string()])),
+            options.get("gpio-pwm")
+        );
+        assert_eq!(Some(&Value::Flag(true)), options.get("inhibit"));
+        assert_eq!(
+            "22:00-07:00,12:00-13:00",
+            options.get("quiet-hours").unwrap().to_string()
+        );
+        assert!(!options.contains_key("pollrate"));
+        assert!(!options.contains_key("relay-active-low"));
+    }
+
+    #[test]
+    fn loads_valid_config() {
+        let args = load(
+            "reload-valid.toml",
+            "gpio-pwm = 3\ntemperature-target-value = \"42.5\"\n",
+        )
+        .unwrap()
+        .args;
+
+        assert_eq!("42.5", args.temperature_target_value.to_string());
+    }
+
+    /// Returns why the daemon refuses to start with a config file.
+    fn startup_error(config: &TempPath) -> clap::Error {
+        let argv = config::args_with_config(reloader(config).argv).unwrap();
+        Args::try_parse_valid(argv).unwrap_err()
+    }
+
+    #[test]
+    fn startup_rejects_what_reload_rejects() {
+        let config = TempPath::file(
+            "startup-limits.toml",
+            "gpio-pwm = 3\npwm-min = 90\npwm-max = 50\n",
+        );
+        let reload = reloader(&config).load().unwrap_err();
+        let startup = startup_error(&config);
+        assert_eq!("--pwm-min 90 exceeds --pwm-max 50", reload);
+        assert_eq!(ErrorKind::ArgumentConflict, startup.kind());
+        assert!(startup.to_string().contains(&reload), "{}", startup);
+        let config = TempPath::file(
+            "startup-noise.toml",
+            "gpio-pwm = 3\nexhaust-gpio-pwm = 4\nfan-objective = \"quiet\"\n",
+        );
+        let reload = reloader(&config).load().unwrap_err();
+        let startup = startup_error(&config);
+        assert_eq!(
+            "--fan-objective quiet needs --intake-noise and --exhaust-noise",
+            reload
+        );
+        assert_eq!(ErrorKind::ArgumentConflict, startup.kind());
+        assert!(startup.to_string().contains(&reload), "{}", startup);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        assert!(load("reload-syntax.toml", "gpio-pwm = \n").is_err());
+
+        let error = load("reload-value.toml", "gpio-pwm = 3\npwm-min = \"fast\"\n").unwrap_err();
+        assert!(error.contains("--pwm-min"), "{}", error);
+
+        let error = load("reload-cpu.toml", "gpio-pwm = 3\npwm-cpu = 1024\n").unwrap_err();
+        assert!(error.contains("--pwm-cpu"), "{}", error);
+
+        let error = load(
+            "reload-limits.toml",
+            "gpio-pwm = 3\npwm-min = 90\npwm-max = 50\n",
+        )
+        .unwrap_err();
+        assert_eq!("--pwm-min 90 exceeds --pwm-max 50", error);
+
+        let error = load(
+            "reload-hard.toml",
+            "gpio-pwm = 3\npwm-max = 80\npwm-hard-max = 70\n",
+        )
+        .unwrap_err();
+        assert_eq!("--pwm-hard-max 70 is below --pwm-max 80", error);
+        let error = load(
+            "reload-hard-temperature.toml",
+            "gpio-pwm = 3\ntemperature-hard-max = 65\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            "--temperature-hard-max 65 is below --temperature-max-value 70",
+            error
+        );
+
+        let error = load(
+            "reload-noise.toml",
+            "gpio-pwm = 3\nexhaust-gpio-pwm = 4\nfan-objective = \"quiet\"\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            "--fan-objective quiet needs --intake-noise and --exhaust-noise",
+            error
+        );
+    }
+
+    #[test]
+    fn reports_changed_values_including_defaults() {
+        let config = TempPath::file("reload-diff.toml", "gpio-pwm = 3\npwm-max = 90\n");
+        let mut reloader = reloader(&config);
+
+        config.write("gpio-pwm = 3\npwm-min = 40\nevent-log = \"/tmp/events\"\n");
+        let loaded = reloader.load().unwrap();
+        let changes = reloader.apply(loaded);
+
+        assert_eq!(
+            vec![
+                Change {
+                    option: "event-log".to_string(),
+                    from: None,
+                    to: Some("/tmp/events".to_string()),
+                },
+                Change {
+                    option: "pwm-max".to_string(),
+                    from: Some("90".to_string()),
+                    to: Some("100".to_string()),
+                },
+                Change {
+                    option: "pwm-min".to_string(),
+                    from: Some("30".to_string()),
+                    to: Some("40".to_string()),
+                },
+            ],
+            changes
+        );
+        assert_eq!("pwm-min 30 → 40", changes[2].to_string());
+
+        let loaded = reloader.load().unwrap();
+        assert!(reloader.apply(loaded).is_empty());
+    }
+
+    #[test]
+    fn switches_profiles() {
+        let config = TempPath::file(
+            "reload-profiles.toml",
+            "gpio-pwm = 3\nprofile = \"balanced\"\n\n[profiles.balanced]\npwm-max = 80\n\n\
+             [profiles.performance]\npwm-max = 100\n\n[profiles.silent]\npwm-max = 50\n",
+        );
+        let mut reloader = reloader(&config);
+
+        let loaded = reloader
+            .load_profile(Some(Some("silent".to_string())))
+            .unwrap();
+        assert_eq!(Some("silent".to_string()), loaded.args.profile);
+        reloader.apply(loaded);
+        // Kept by a plain reload
+        let loaded = reloader.load().unwrap();
+        assert_eq!("50", loaded.args.pwm_max.to_string());
+        reloader.apply(loaded);
+
+        let next = reloader.next_profile().unwrap();
+        let loaded = reloader.load_profile(Some(Some(next))).unwrap();
+        assert_eq!(Some("balanced".to_string()), loaded.args.profile);
+        reloader.apply(loaded);
+
+        let error = reloader
+            .load_profile(Some(Some("turbo".to_string())))
+            .unwrap_err();
+        assert!(error.contains("turbo"), "{}", error);
+        assert_eq!("80", reloader.load().unwrap().args.pwm_max.to_string());
+
+        let loaded = reloader.load_profile(Some(None)).unwrap();
+        assert_eq!(Some("balanced".to_string()), loaded.args.profile);
+    }
+}
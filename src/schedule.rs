@@ -0,0 +1,190 @@
+//! Quiet hours, daily time windows in which quiet mode caps the fan speed, e.g. overnight for a
+//! Pi in a bedroom.
+
+use std::fmt;
+
+/// Time of day to the minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u8, minute: u8) -> Option<Self> {
+        (hour < 24 && minute < 60).then_some(Self { hour, minute })
+    }
+
+    /// Returns the time in local time.
+    #[cfg(unix)]
+    pub fn now() -> Self {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            libc::localtime_r(&now, &mut tm);
+        }
+        Self {
+            hour: tm.tm_hour as u8,
+            minute: tm.tm_min as u8,
+        }
+    }
+
+    /// Returns the time in UTC.
+    #[cfg(not(unix))]
+    pub fn now() -> Self {
+        Self::utc(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        )
+    }
+
+    /// Returns the time in UTC of a Unix time in seconds.
+    pub fn utc(seconds: u64) -> Self {
+        let seconds = seconds % 86_400;
+        Self {
+            hour: (seconds / 3600) as u8,
+            minute: (seconds % 3600 / 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+/// Daily window from its start up to, but not including, its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl Window {
+    /// Whether the time falls in the window, which may run past midnight.
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&time)
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// Parses a time such as `07:30`.
+pub fn parse_time(value: &str) -> Option<TimeOfDay> {
+    let (hour, minute) = value.split_once(':')?;
+    TimeOfDay::new(hour.parse().ok()?, minute.parse().ok()?)
+}
+
+/// Parses a window such as `22:00-07:00`, from ten at night until seven in the morning.
+pub fn parse_window(value: &str) -> Result<Window, String> {
+    let invalid = || format!("invalid time window {:?}, expected e.g. 22:00-07:00", value);
+    let time = |text: &str| parse_time(text.trim()).ok_or_else(invalid);
+
+    let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+    let window = Window {
+        start: time(start)?,
+        end: time(end)?,
+    };
+    if window.start == window.end {
+        return Err(format!("time window {:?} is empty", value));
+    }
+    Ok(window)
+}
+
+/// Windows in which quiet hours are in effect.
+#[derive(Debug, Clone)]
+pub struct QuietHours {
+    windows: Vec<Window>,
+    /// Profile switched to during quiet hours
+    pub profile: Option<String>,
+    /// Whether the time applied last was in quiet hours, `None` before the first time
+    active: Option<bool>,
+}
+
+impl QuietHours {
+    pub fn new(windows: Vec<Window>, profile: Option<String>) -> Self {
+        Self {
+            windows,
+            profile,
+            active: None,
+        }
+    }
+
+    /// Returns whether quiet hours are in effect at the time, if that changed since the last call.
+    ///
+    /// Only reports a change, so quiet mode toggled meanwhile by other means is kept until quiet
+    /// hours start or end.
+    pub fn change(&mut self, time: TimeOfDay) -> Option<bool> {
+        let quiet = self.windows.iter().any(|window| window.contains(time));
+        if self.active == Some(quiet) {
+            return None;
+        }
+        self.active = Some(quiet);
+        Some(quiet)
+    }
+
+    /// Whether both have the same windows and profile, whatever their state.
+    pub fn same_schedule(&self, other: &Self) -> bool {
+        self.windows == other.windows && self.profile == other.profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_window, QuietHours, TimeOfDay};
+
+    fn time(hour: u8, minute: u8) -> TimeOfDay {
+        TimeOfDay::new(hour, minute).unwrap()
+    }
+
+    #[test]
+    fn parses_windows() {
+        let night = parse_window("22:00-07:30").unwrap();
+        assert_eq!(time(22, 0), night.start);
+        assert_eq!(time(7, 30), night.end);
+        assert_eq!("22:00-07:30", night.to_string());
+
+        assert!(parse_window("24:00-07:00").is_err());
+        assert!(parse_window("22:00").is_err());
+        assert!(parse_window("07:00-07:00").is_err());
+    }
+
+    #[test]
+    fn windows_may_span_midnight() {
+        let night = parse_window("22:00-07:00").unwrap();
+        assert!(night.contains(time(22, 0)));
+        assert!(night.contains(time(0, 0)));
+        assert!(night.contains(time(6, 59)));
+        assert!(!night.contains(time(7, 0)));
+        assert!(!night.contains(time(21, 59)));
+        assert!(parse_window("12:00-14:00").unwrap().contains(time(13, 0)));
+    }
+
+    #[test]
+    fn reports_changes_only() {
+        let mut quiet_hours = QuietHours::new(
+            vec![
+                parse_window("22:00-07:00").unwrap(),
+                parse_window("13:00-14:00").unwrap(),
+            ],
+            None,
+        );
+
+        assert_eq!(Some(false), quiet_hours.change(time(12, 0)));
+        assert_eq!(Some(true), quiet_hours.change(time(13, 0)));
+        assert_eq!(None, quiet_hours.change(time(13, 59)));
+        assert_eq!(Some(false), quiet_hours.change(time(14, 0)));
+        assert_eq!(Some(true), quiet_hours.change(time(23, 0)));
+    }
+}
@@ -0,0 +1,214 @@
+//! Duty scripts, Rhai programs deciding the next duty in place of the stepping algorithm.
+//!
+//! The value of a script's last expression is the duty in percent. Scripts see the readings and
+//! fan state as floating point variables, and have the Rhai language and standard library along
+//! with `clamp`:
+//!
+//! ```rhai
+//! let rise = temperature - previous;
+//! if temperature > target + 5.0 { pwm_max } else { duty + rise * 20.0 }
+//! ```
+//!
+//! Number literals keep their Rhai types, so `7 / 2` is the integer 3 while `7.0 / 2` is 3.5.
+
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST, FLOAT};
+use std::{fs, sync::Arc};
+
+/// Variables a script is run with.
+pub const VARIABLES: [&str; 7] = [
+    "temperature",
+    "previous",
+    "target",
+    "max",
+    "duty",
+    "pwm_min",
+    "pwm_max",
+];
+
+/// Most operations a script may run for each duty, so a runaway loop can't stall the control
+/// loop
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Compiled script, checked to only use known variables.
+#[derive(Debug, Clone)]
+pub struct Script {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+}
+
+/// Inputs of a script run, temperatures in °C and duties in percent.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Inputs {
+    pub temperature: f64,
+    pub previous: f64,
+    pub target: f64,
+    pub max: f64,
+    pub duty: f64,
+    pub pwm_min: f64,
+    pub pwm_max: f64,
+}
+
+impl Inputs {
+    fn scope(&self) -> Scope<'static> {
+        let mut scope = Scope::new();
+        for (name, value) in VARIABLES.into_iter().zip([
+            self.temperature,
+            self.previous,
+            self.target,
+            self.max,
+            self.duty,
+            self.pwm_min,
+            self.pwm_max,
+        ]) {
+            scope.push(name, value as FLOAT);
+        }
+        scope
+    }
+}
+
+impl Script {
+    /// Runs the script and returns the duty it decided on, rounded to a whole percent and
+    /// limited to 0-100.
+    pub fn run(&self, inputs: &Inputs) -> Result<u8, String> {
+        let value: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut inputs.scope(), &self.ast)
+            .map_err(|error| error.to_string())?;
+        let duty = number(&value)
+            .ok_or_else(|| format!("script returned {} instead of a duty", value.type_name()))?;
+        if !duty.is_finite() {
+            return Err(format!("script returned {} instead of a duty", duty));
+        }
+        Ok(duty.round().clamp(0.0, 100.0) as u8)
+    }
+}
+
+/// Returns the value of an integer or floating point number.
+fn number(value: &Dynamic) -> Option<f64> {
+    value
+        .as_float()
+        .ok()
+        .or_else(|| value.as_int().ok().map(|value| value as FLOAT))
+}
+
+/// Limits a number to a range, taking integers and floating point numbers alike.
+fn clamp(value: Dynamic, min: Dynamic, max: Dynamic) -> Result<FLOAT, Box<EvalAltResult>> {
+    match (number(&value), number(&min), number(&max)) {
+        (Some(value), Some(min), Some(max)) if min <= max => Ok(value.max(min).min(max)),
+        (Some(_), Some(min), Some(max)) => {
+            Err(format!("clamp minimum {} is above its maximum {}", min, max).into())
+        }
+        _ => Err("clamp takes numbers".into()),
+    }
+}
+
+/// Returns the engine scripts are compiled and run with.
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    // Unknown variables fail compiling instead of every run
+    engine.set_strict_variables(true);
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.on_print(|text| log::info!("Duty script: {}", text));
+    engine.on_debug(|text, _, _| log::debug!("Duty script: {}", text));
+    engine.register_fn("clamp", clamp);
+    engine
+}
+
+/// Reads and parses the script in a file.
+pub fn parse_script_file(path: &str) -> Result<Script, String> {
+    let source =
+        fs::read_to_string(path).map_err(|error| format!("cannot read {}: {}", path, error))?;
+    parse_script(&source).map_err(|error| format!("{}: {}", path, error))
+}
+
+/// Compiles a script, checking that it only uses known variables.
+pub fn parse_script(source: &str) -> Result<Script, String> {
+    let engine = engine();
+    let ast = engine
+        .compile_with_scope(&Inputs::default().scope(), source)
+        .map_err(|error| error.to_string())?;
+    Ok(Script {
+        engine: Arc::new(engine),
+        ast: Arc::new(ast),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_script, Inputs};
+
+    const INPUTS: Inputs = Inputs {
+        temperature: 47.5,
+        previous: 47.0,
+        target: 45.0,
+        max: 70.0,
+        duty: 40.0,
+        pwm_min: 20.0,
+        pwm_max: 100.0,
+    };
+
+    #[test]
+    fn runs_scripts() {
+        let script = parse_script(
+            "// Faster on rises
+             let rise = temperature - previous;
+             let error = temperature - target;
+             if error > 10.0 { pwm_max } else if error < 0.0 { pwm_min } else {
+                 clamp(duty + error * 2.0 + rise * 10.0, pwm_min, pwm_max)
+             }",
+        )
+        .unwrap();
+        assert_eq!(Ok(50), script.run(&INPUTS));
+
+        let cool = Inputs {
+            temperature: 40.0,
+            ..INPUTS
+        };
+        assert_eq!(Ok(20), script.run(&cool));
+
+        assert_eq!(
+            Ok(53),
+            parse_script("1 + 2 * 3 * (4 + 4) + 4")
+                .unwrap()
+                .run(&INPUTS)
+        );
+        assert_eq!(Ok(0), parse_script("-duty").unwrap().run(&INPUTS));
+        assert_eq!(Ok(100), parse_script("duty * 10").unwrap().run(&INPUTS));
+    }
+
+    #[test]
+    fn keeps_rhai_number_types() {
+        let run = |source: &str| parse_script(source).unwrap().run(&INPUTS);
+        // Integer literals divide as integers, as in any other Rhai program
+        assert_eq!(Ok(30), run("7 / 2 * 10"));
+        assert_eq!(Ok(35), run("7.0 / 2 * 10"));
+        // The variables are floating point numbers, so dividing them keeps the fraction
+        assert_eq!(Ok(24), run("(temperature - previous) / 2 * 96"));
+
+        // Integers and floating point numbers compare by value
+        assert_eq!(Ok(100), run("if 7 / 2 == 3 { 100 } else { 0 }"));
+        assert_eq!(Ok(100), run("if duty == 40 { 100 } else { 0 }"));
+        assert_eq!(Ok(0), run("if 7.0 / 2 == 3 { 100 } else { 0 }"));
+    }
+
+    #[test]
+    fn rejects_invalid_scripts() {
+        assert!(parse_script("humidity * 2").is_err());
+        assert!(parse_script("let x = 1; y").is_err());
+        assert!(parse_script("duty duty").is_err());
+        assert!(parse_script("duty # 2").is_err());
+    }
+
+    #[test]
+    fn reports_runtime_errors() {
+        let run = |source: &str| parse_script(source).unwrap().run(&INPUTS);
+        assert!(run("duty > 50").is_err());
+        assert!(run("if duty > 50 { 100 }").is_err());
+        assert!(run("if duty { 1 } else { 2 }").is_err());
+        assert!(run("duty / 0").is_err());
+        assert!(run("min(duty)").is_err());
+        assert!(run("clamp(duty, pwm_max, pwm_min)").is_err());
+        assert!(run("loop {}").is_err());
+        assert_eq!(Ok(1), run("if duty < 50 && !(max < 0) { 1 } else { 2 }"));
+    }
+}
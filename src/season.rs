@@ -0,0 +1,206 @@
+//! Target temperatures for parts of the year, so winter and summer settings differ without edits.
+
+use crate::units::Celsius;
+use std::fmt;
+
+/// Day of the year, regardless of the year.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MonthDay {
+    pub month: u8,
+    pub day: u8,
+}
+
+impl MonthDay {
+    pub fn new(month: u8, day: u8) -> Option<Self> {
+        const DAYS: [u8; 12] = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+        let days = *DAYS.get(usize::from(month).checked_sub(1)?)?;
+        (1..=days).contains(&day).then_some(Self { month, day })
+    }
+
+    /// Returns today's date in local time.
+    #[cfg(unix)]
+    pub fn today() -> Self {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            libc::localtime_r(&now, &mut tm);
+        }
+        Self {
+            month: (tm.tm_mon + 1) as u8,
+            day: tm.tm_mday as u8,
+        }
+    }
+
+    /// Returns today's date in UTC.
+    #[cfg(not(unix))]
+    pub fn today() -> Self {
+        Self::utc(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        )
+    }
+
+    /// Returns the date in UTC of a Unix time in seconds.
+    pub fn utc(seconds: u64) -> Self {
+        let days = seconds / 86_400;
+        // Civil date from days since the epoch, with years starting in March
+        let days = days as i64 + 719_468;
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        Self {
+            month: (if month < 10 { month + 3 } else { month - 9 }) as u8,
+            day: (day_of_year - (153 * month + 2) / 5 + 1) as u8,
+        }
+    }
+}
+
+impl fmt::Display for MonthDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}-{:02}", self.month, self.day)
+    }
+}
+
+/// Date range with its own target, both ends included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Season {
+    pub start: MonthDay,
+    pub end: MonthDay,
+    pub target: Celsius,
+}
+
+impl Season {
+    /// Whether the date falls in the season, which may run over the turn of the year.
+    pub fn contains(&self, date: MonthDay) -> bool {
+        if self.start <= self.end {
+            (self.start..=self.end).contains(&date)
+        } else {
+            date >= self.start || date <= self.end
+        }
+    }
+}
+
+impl fmt::Display for Season {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Parses a season such as `11-01..03-31=38.5`, from November through March.
+pub fn parse_season(value: &str) -> Result<Season, String> {
+    let invalid = || {
+        format!(
+            "invalid season {:?}, expected e.g. 11-01..03-31=38.5",
+            value
+        )
+    };
+    let date = |text: &str| -> Result<MonthDay, String> {
+        let (month, day) = text.split_once('-').ok_or_else(invalid)?;
+        let month = month.parse().map_err(|_| invalid())?;
+        let day = day.parse().map_err(|_| invalid())?;
+        MonthDay::new(month, day).ok_or_else(invalid)
+    };
+
+    let (range, target) = value.split_once('=').ok_or_else(invalid)?;
+    let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+    Ok(Season {
+        start: date(start.trim())?,
+        end: date(end.trim())?,
+        target: target.trim().parse().map_err(|_| invalid())?,
+    })
+}
+
+/// Seasons in effect, with the target used outside all of them.
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    seasons: Vec<Season>,
+    default: Celsius,
+    /// Season applied last, `Some(None)` outside all of them and `None` before the first date
+    active: Option<Option<Season>>,
+}
+
+impl Calendar {
+    pub fn new(seasons: Vec<Season>, default: Celsius) -> Self {
+        Self {
+            seasons,
+            default,
+            active: None,
+        }
+    }
+
+    /// Returns the season the date falls in, the first given where they overlap, and its target.
+    ///
+    /// Only reports a change, so a target set meanwhile by other means is kept until the date
+    /// moves into another season.
+    pub fn change(&mut self, date: MonthDay) -> Option<(Option<Season>, Celsius)> {
+        let season = self
+            .seasons
+            .iter()
+            .find(|season| season.contains(date))
+            .copied();
+        if self.active == Some(season) {
+            return None;
+        }
+        self.active = Some(season);
+        Some((season, season.map_or(self.default, |season| season.target)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_season, Calendar, MonthDay};
+    use crate::units::Celsius;
+
+    fn date(month: u8, day: u8) -> MonthDay {
+        MonthDay::new(month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_seasons() {
+        let winter = parse_season("11-01..03-31=38.5").unwrap();
+        assert_eq!(date(11, 1), winter.start);
+        assert_eq!(date(3, 31), winter.end);
+        assert_eq!(Celsius::new(38, 500), winter.target);
+        assert_eq!("11-01..03-31", winter.to_string());
+
+        assert!(parse_season("02-30..03-31=40").is_err());
+        assert!(parse_season("11-01-03-31=40").is_err());
+        assert!(parse_season("11-01..03-31").is_err());
+    }
+
+    #[test]
+    fn seasons_may_span_new_year() {
+        let winter = parse_season("11-01..03-31=38").unwrap();
+        assert!(winter.contains(date(12, 31)));
+        assert!(winter.contains(date(1, 1)));
+        assert!(winter.contains(date(3, 31)));
+        assert!(!winter.contains(date(4, 1)));
+        assert!(!winter.contains(date(10, 31)));
+    }
+
+    #[test]
+    fn reports_changes_only() {
+        let winter = parse_season("11-01..03-31=38").unwrap();
+        let summer = parse_season("06-01..08-31=45").unwrap();
+        let mut calendar = Calendar::new(vec![winter, summer], Celsius::new(40, 0));
+
+        assert_eq!(
+            Some((Some(winter), Celsius::new(38, 0))),
+            calendar.change(date(3, 30))
+        );
+        assert_eq!(None, calendar.change(date(3, 31)));
+        assert_eq!(
+            Some((None, Celsius::new(40, 0))),
+            calendar.change(date(4, 1))
+        );
+        assert_eq!(None, calendar.change(date(5, 31)));
+        assert_eq!(
+            Some((Some(summer), Celsius::new(45, 0))),
+            calendar.change(date(6, 1))
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Credentials for network sinks, read from files instead of the command line or config.
+//!
+//! Values passed as options are visible to every user through `ps`, and configs are often world
+//! readable. Credentials are instead named by a file path, or by the name of a systemd credential
+//! (`LoadCredential=`) when running as a service.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Credential value, kept out of `Debug` output so it cannot end up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Returns the credential for use in a request.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    Read(PathBuf, io::Error),
+    /// File is readable by users other than its owner and group
+    Permissions(PathBuf),
+    Empty(PathBuf),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::Read(path, error) => {
+                write!(f, "Failed to read credential {:?}: {}", path, error)
+            }
+            SecretError::Permissions(path) => {
+                write!(f, "Credential {:?} must not be accessible to others", path)
+            }
+            SecretError::Empty(path) => write!(f, "Credential {:?} is empty", path),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+/// Loads a credential given as a file path, or as a bare name of a systemd credential.
+pub fn load(reference: &str) -> Result<Secret, SecretError> {
+    let credentials = std::env::var_os("CREDENTIALS_DIRECTORY").map(PathBuf::from);
+    read(&resolve(reference, credentials.as_deref()))
+}
+
+/// Returns the file holding a credential.
+///
+/// Bare names refer to systemd credentials while running under `LoadCredential=`.
+fn resolve(reference: &str, credentials: Option<&Path>) -> PathBuf {
+    match credentials {
+        Some(directory) if !reference.contains(['/', '\\']) => directory.join(reference),
+        _ => PathBuf::from(reference),
+    }
+}
+
+fn read(path: &Path) -> Result<Secret, SecretError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = fs::metadata(path).map_err(|error| SecretError::Read(path.into(), error))?;
+        if metadata.permissions().mode() & 0o007 != 0 {
+            return Err(SecretError::Permissions(path.into()));
+        }
+    }
+
+    let content =
+        fs::read_to_string(path).map_err(|error| SecretError::Read(path.into(), error))?;
+    let value = content.trim_end_matches(['\r', '\n']);
+    if value.is_empty() {
+        return Err(SecretError::Empty(path.into()));
+    }
+
+    Ok(Secret(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, resolve, SecretError};
+    use crate::mock::TempPath;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    /// Writes a credential file readable only by its owner.
+    fn credential(name: &str, content: &str) -> TempPath {
+        let path = TempPath::file(&format!("secret-{}", name), content);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn bare_names_refer_to_systemd_credentials() {
+        let directory = Path::new("/run/credentials/fan-controller.service");
+
+        assert_eq!(
+            directory.join("mqtt-token"),
+            resolve("mqtt-token", Some(directory))
+        );
+        assert_eq!(
+            PathBuf::from("/etc/fan-controller/token"),
+            resolve("/etc/fan-controller/token", Some(directory))
+        );
+        assert_eq!(PathBuf::from("mqtt-token"), resolve("mqtt-token", None));
+    }
+
+    #[test]
+    fn strips_trailing_newline() {
+        let path = credential("newline", "hunter2\n");
+        assert_eq!("hunter2", read(&path).unwrap().expose());
+    }
+
+    #[test]
+    fn debug_output_is_redacted() {
+        let path = credential("redacted", "hunter2");
+        assert!(!format!("{:?}", read(&path).unwrap()).contains("hunter2"));
+    }
+
+    #[test]
+    fn rejects_empty_credential() {
+        let path = credential("empty", "\n");
+        assert!(matches!(read(&path), Err(SecretError::Empty(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_world_readable_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = credential("world", "hunter2");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(read(&path), Err(SecretError::Permissions(_))));
+    }
+}
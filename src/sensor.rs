@@ -0,0 +1,491 @@
+use crate::{
+    aggregate::Aggregator,
+    args::Args,
+    hwmon::HwmonSensor,
+    lhm::LhmSensor,
+    nvme::{self, NvmeSensor},
+    pipeline::{self, Pipeline, Processed},
+    smart::SmartSensor,
+    thermal::ThermalZoneSensor,
+    units::{Celsius, Duty},
+};
+use std::{fmt, fs, io};
+
+/// Source of temperature readings.
+pub trait Sensor {
+    fn read(&mut self) -> Result<Celsius, SensorError>;
+
+    /// Returns the value the last reading was converted from, if the sensor has one.
+    fn raw(&self) -> Option<Raw> {
+        None
+    }
+
+    /// Returns the duty the last reading calls for, for sources with fan curves of their own.
+    fn demand(&self) -> Option<Duty> {
+        None
+    }
+}
+
+/// Value read from a sensor before converting it to a temperature, for calibrating sensors and
+/// explaining odd readings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Raw {
+    /// Integer from a sensor file, before rounding to a tenth of a degree
+    Millidegrees(i32),
+    /// Counts of an ADC
+    Counts(u16),
+}
+
+impl Raw {
+    pub fn value(self) -> i32 {
+        match self {
+            Raw::Millidegrees(value) => value,
+            Raw::Counts(value) => i32::from(value),
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            Raw::Millidegrees(_) => "millidegrees",
+            Raw::Counts(_) => "counts",
+        }
+    }
+}
+
+impl fmt::Display for Raw {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value(), self.unit())
+    }
+}
+
+/// Temperature source named on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SensorSpec {
+    File(String),
+    Lhm(String),
+    Mcp3008(u8),
+    /// Thermal zone of a type
+    ThermalZone(String),
+    /// hwmon channel, by its input name or label, of a chip by name
+    Hwmon {
+        chip: String,
+        channel: String,
+    },
+    /// Composite temperature of an NVMe controller
+    Nvme(String),
+    /// Disk read through smartctl
+    Smart(String),
+}
+
+/// Parses a source given as `lhm:<identifier>`, `mcp3008:<channel>`, `thermal:<type>`,
+/// `hwmon:<chip>/<channel>`, `nvme:<controller>`, `smart:<device>` or a file path.
+pub fn parse_spec(value: &str) -> Result<SensorSpec, String> {
+    if let Some(kind) = value.strip_prefix("thermal:") {
+        return Ok(SensorSpec::ThermalZone(kind.to_string()));
+    }
+    if let Some(channel) = value.strip_prefix("hwmon:") {
+        return parse_hwmon(channel);
+    }
+    if let Some(controller) = value.strip_prefix("nvme:") {
+        return nvme::parse_controller(controller).map(SensorSpec::Nvme);
+    }
+    if let Some(device) = value.strip_prefix("smart:") {
+        if device.is_empty() {
+            return Err("missing disk of smart:, expected e.g. smart:/dev/sda".to_string());
+        }
+        return Ok(SensorSpec::Smart(device.to_string()));
+    }
+    if let Some(identifier) = value.strip_prefix("lhm:") {
+        return Ok(SensorSpec::Lhm(identifier.to_string()));
+    }
+    if let Some(channel) = value.strip_prefix("mcp3008:") {
+        return match channel.parse() {
+            Ok(channel @ 0..=7) => Ok(SensorSpec::Mcp3008(channel)),
+            _ => Err(format!(
+                "invalid MCP3008 channel {:?}, expected 0-7",
+                channel
+            )),
+        };
+    }
+    Ok(SensorSpec::File(value.to_string()))
+}
+
+/// Parses an hwmon channel given as `<chip>/<channel>`, e.g. `cpu_thermal/temp1` or
+/// `nvme/Composite`.
+pub fn parse_hwmon(value: &str) -> Result<SensorSpec, String> {
+    match value.split_once('/') {
+        Some((chip, channel)) if !chip.is_empty() && !channel.is_empty() => Ok(SensorSpec::Hwmon {
+            chip: chip.to_string(),
+            channel: channel.to_string(),
+        }),
+        _ => Err(format!(
+            "invalid hwmon channel {:?}, expected e.g. cpu_thermal/temp1",
+            value
+        )),
+    }
+}
+
+/// Sensor with the pipeline its readings are run through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorSource {
+    pub spec: SensorSpec,
+    pub pipeline: Pipeline,
+}
+
+/// Parses a source followed by an optional pipeline after `@`, e.g. `mcp3008:0@offset=-1.5`.
+pub fn parse_source(value: &str) -> Result<SensorSource, String> {
+    let (spec, pipeline) = match value.split_once('@') {
+        Some((spec, pipeline)) => (spec, pipeline::parse_pipeline(pipeline)?),
+        None => (value, Pipeline::default()),
+    };
+    Ok(SensorSource {
+        spec: parse_spec(spec)?,
+        pipeline,
+    })
+}
+
+/// Returns the sensor selected by the application options.
+///
+/// With fallbacks given, they are tried in order whenever the primary sensor can't be read.
+/// With extra sensors, they are read along with the primary one and combined.
+pub fn from_args(args: &Args) -> Box<dyn Sensor> {
+    let primary = primary(args);
+    let extra = extra_sources(args);
+    if extra.is_empty() {
+        return primary;
+    }
+
+    let sensors = std::iter::once(primary)
+        .chain(extra.iter().map(|source| build(source, args)))
+        .collect();
+    Box::new(Aggregator::new(
+        sensors,
+        args.aggregate,
+        args.sensor_weight.clone(),
+        args.sensor_curve.clone(),
+    ))
+}
+
+/// Returns the sources read along with the primary one: the temperature files after the first,
+/// then the extra sensors.
+pub fn extra_sources(args: &Args) -> Vec<SensorSource> {
+    args.temperature_file_path
+        .iter()
+        .skip(1)
+        .map(|path| SensorSource {
+            spec: SensorSpec::File(path.clone()),
+            pipeline: Pipeline::default(),
+        })
+        .chain(args.extra_sensor.iter().cloned())
+        .collect()
+}
+
+/// Returns the primary sensor with its fallbacks.
+fn primary(args: &Args) -> Box<dyn Sensor> {
+    let spec = if let Some(channel) = args.mcp3008_channel {
+        SensorSpec::Mcp3008(channel)
+    } else if let Some(identifier) = &args.lhm_sensor {
+        SensorSpec::Lhm(identifier.clone())
+    } else if let Some(kind) = &args.thermal_zone {
+        SensorSpec::ThermalZone(kind.clone())
+    } else if let Some(spec) = &args.hwmon_sensor {
+        spec.clone()
+    } else {
+        SensorSpec::File(args.temperature_file_path[0].clone())
+    };
+    let primary = SensorSource {
+        spec,
+        pipeline: args.sensor_pipeline.clone().unwrap_or_default(),
+    };
+
+    if args.fallback_sensor.is_empty() {
+        return build(&primary, args);
+    }
+
+    let sensors = std::iter::once(&primary)
+        .chain(&args.fallback_sensor)
+        .map(|source| build(source, args))
+        .collect();
+    Box::new(FailoverSensor::new(sensors))
+}
+
+/// Returns the sensor of a source with its pipeline.
+pub(crate) fn build(source: &SensorSource, args: &Args) -> Box<dyn Sensor> {
+    let sensor = build_spec(&source.spec, args);
+    if source.pipeline.is_empty() {
+        sensor
+    } else {
+        Box::new(Processed::new(sensor, &source.pipeline))
+    }
+}
+
+fn build_spec(spec: &SensorSpec, args: &Args) -> Box<dyn Sensor> {
+    match spec {
+        SensorSpec::File(path) => Box::new(FileSensor::new(path)),
+        SensorSpec::Lhm(identifier) => Box::new(LhmSensor::new(identifier)),
+        SensorSpec::ThermalZone(kind) => Box::new(ThermalZoneSensor::new(kind)),
+        SensorSpec::Hwmon { chip, channel } => Box::new(HwmonSensor::new(chip, channel)),
+        SensorSpec::Nvme(controller) => Box::new(NvmeSensor::new(controller)),
+        SensorSpec::Smart(device) => {
+            Box::new(SmartSensor::new(device, args.smart_standby_temperature))
+        }
+        SensorSpec::Mcp3008(channel) => Box::new(crate::mcp3008::Mcp3008Thermistor::new(
+            &args.spi_device,
+            *channel,
+            args.thermistor_series_resistance,
+            args.thermistor_coefficients,
+        )),
+    }
+}
+
+/// Sensors in priority order, reading the first one that works.
+///
+/// The primary sensor is tried again on every read, so the controller returns to it as soon as
+/// it comes back, e.g. after a hwmon device was re-enumerated.
+pub struct FailoverSensor {
+    sensors: Vec<Box<dyn Sensor>>,
+    /// Index of the sensor the last reading came from
+    active: usize,
+}
+
+impl FailoverSensor {
+    pub fn new(sensors: Vec<Box<dyn Sensor>>) -> Self {
+        Self { sensors, active: 0 }
+    }
+}
+
+impl Sensor for FailoverSensor {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        let mut last_error = None;
+
+        for (index, sensor) in self.sensors.iter_mut().enumerate() {
+            match sensor.read() {
+                Ok(temperature) => {
+                    if index < self.active {
+                        log::info!("Temperature sensor {} is readable again", index + 1);
+                    } else if index > self.active {
+                        log::warn!("Failing over to temperature sensor {}", index + 1);
+                    }
+                    self.active = index;
+                    return Ok(temperature);
+                }
+                Err(error) => {
+                    if index == self.active {
+                        log::warn!("{}", error);
+                    }
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        // Every sensor failed; start from the primary again once one comes back
+        self.active = 0;
+        Err(last_error.expect("failover needs at least one sensor"))
+    }
+
+    fn raw(&self) -> Option<Raw> {
+        self.sensors[self.active].raw()
+    }
+}
+
+/// File holding a temperature in millidegrees, like sysfs thermal zones and hwmon inputs.
+pub struct FileSensor {
+    path: String,
+    /// Millidegrees of the last successful reading
+    raw: Option<i32>,
+}
+
+impl FileSensor {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            raw: None,
+        }
+    }
+}
+
+impl Sensor for FileSensor {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        let content = fs::read_to_string(&self.path)
+            .map_err(|error| SensorError::Read(self.path.clone(), error))?;
+
+        let millidegrees = parse_integer(&content)?;
+        self.raw = Some(millidegrees);
+        Ok(Celsius::from_millidegrees(millidegrees))
+    }
+
+    fn raw(&self) -> Option<Raw> {
+        self.raw.map(Raw::Millidegrees)
+    }
+}
+
+/// Parses sensor file content holding an integer value in millidegrees.
+pub fn parse_millidegrees(content: &str) -> Result<Celsius, SensorError> {
+    parse_integer(content).map(Celsius::from_millidegrees)
+}
+
+fn parse_integer(content: &str) -> Result<i32, SensorError> {
+    content
+        .trim()
+        .parse()
+        .map_err(|_| SensorError::Parse(content.to_string()))
+}
+
+#[derive(Debug)]
+pub enum SensorError {
+    Read(String, io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for SensorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SensorError::Read(source, error) => {
+                write!(f, "Failed to read temperature from {:?}: {}", source, error)
+            }
+            SensorError::Parse(content) => {
+                write!(f, "Failed to parse temperature value: {:?}", content)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SensorError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        extra_sources, from_args, parse_millidegrees, parse_source, parse_spec, FailoverSensor,
+        Sensor, SensorError, SensorSpec,
+    };
+    use crate::{args::Args, mock::TempPath, units::Celsius};
+    use clap::Parser;
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    /// Sensor returning queued readings, `None` standing for a failed read.
+    struct Scripted(Rc<RefCell<VecDeque<Option<i32>>>>);
+
+    impl Sensor for Scripted {
+        fn read(&mut self) -> Result<Celsius, SensorError> {
+            match self.0.borrow_mut().pop_front().flatten() {
+                Some(degrees) => Ok(Celsius::new(degrees, 0)),
+                None => Err(SensorError::Parse(String::new())),
+            }
+        }
+    }
+
+    fn scripted(readings: &[Option<i32>]) -> Box<dyn Sensor> {
+        Box::new(Scripted(Rc::new(RefCell::new(
+            readings.iter().copied().collect(),
+        ))))
+    }
+
+    #[test]
+    fn parses_sysfs_content() {
+        assert_eq!(
+            Celsius::new(45, 678),
+            parse_millidegrees("45678\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_content() {
+        assert!(parse_millidegrees("").is_err());
+        assert!(parse_millidegrees("45.6").is_err());
+        assert!(parse_millidegrees("99999999999").is_err());
+    }
+
+    #[test]
+    fn parses_sensor_specs() {
+        assert_eq!(
+            Ok(SensorSpec::File(
+                "/sys/class/hwmon/hwmon1/temp1_input".to_string()
+            )),
+            parse_spec("/sys/class/hwmon/hwmon1/temp1_input")
+        );
+        assert_eq!(
+            Ok(SensorSpec::Lhm("/amdcpu/0/temperature/2".to_string())),
+            parse_spec("lhm:/amdcpu/0/temperature/2")
+        );
+        assert_eq!(Ok(SensorSpec::Mcp3008(3)), parse_spec("mcp3008:3"));
+        assert!(parse_spec("mcp3008:8").is_err());
+        assert_eq!(
+            Ok(SensorSpec::ThermalZone("cpu-thermal".to_string())),
+            parse_spec("thermal:cpu-thermal")
+        );
+        assert_eq!(
+            Ok(SensorSpec::Hwmon {
+                chip: "nvme".to_string(),
+                channel: "Composite".to_string()
+            }),
+            parse_spec("hwmon:nvme/Composite")
+        );
+        assert!(parse_spec("hwmon:cpu_thermal").is_err());
+        assert!(parse_spec("hwmon:/temp1").is_err());
+        assert_eq!(
+            Ok(SensorSpec::Nvme("nvme0".to_string())),
+            parse_spec("nvme:/dev/nvme0")
+        );
+        assert_eq!(
+            Ok(SensorSpec::Smart("/dev/sda".to_string())),
+            parse_spec("smart:/dev/sda")
+        );
+        assert!(parse_spec("smart:").is_err());
+
+        let source = parse_source("mcp3008:3@offset=-1.5").unwrap();
+        assert_eq!(SensorSpec::Mcp3008(3), source.spec);
+        assert_eq!("offset=-1.5", source.pipeline.to_string());
+        assert!(parse_source("mcp3008:3@median=3").is_err());
+    }
+
+    #[test]
+    fn fails_over_and_returns_to_primary() {
+        let mut sensor = FailoverSensor::new(vec![
+            scripted(&[Some(40), None, None, Some(42)]),
+            scripted(&[Some(50), Some(51)]),
+        ]);
+
+        assert_eq!(Celsius::new(40, 0), sensor.read().unwrap());
+        assert_eq!(Celsius::new(50, 0), sensor.read().unwrap());
+        assert_eq!(Celsius::new(51, 0), sensor.read().unwrap());
+        assert_eq!(Celsius::new(42, 0), sensor.read().unwrap());
+    }
+
+    #[test]
+    fn combines_repeated_temperature_files() {
+        let cpu = TempPath::file("zone-cpu", "45000");
+        let gpu = TempPath::file("zone-gpu", "52000");
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--temperature-file-path",
+            cpu.arg(),
+            "--temperature-file-path",
+            gpu.arg(),
+            "--extra-sensor",
+            "thermal:pmic",
+        ]);
+
+        let extra = extra_sources(&args);
+        assert_eq!(2, extra.len());
+        assert_eq!(SensorSpec::File(gpu.arg().into()), extra[0].spec);
+
+        let args = Args::parse_from([
+            "fan-controller",
+            "--gpio-pwm",
+            "0",
+            "--temperature-file-path",
+            cpu.arg(),
+            "--temperature-file-path",
+            gpu.arg(),
+        ]);
+        assert_eq!(Celsius::new(52, 0), from_args(&args).read().unwrap());
+    }
+
+    #[test]
+    fn fails_when_every_sensor_fails() {
+        let mut sensor = FailoverSensor::new(vec![scripted(&[None]), scripted(&[None])]);
+        assert!(sensor.read().is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Output to fan controllers attached over a serial port, such as an Arduino driving the fan.
+
+use crate::{pwm::Output, units::Duty};
+use clap::ValueEnum;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+};
+
+/// Encoding of duty commands for a serial fan controller.
+pub trait Protocol {
+    /// Returns the bytes commanding the given duty.
+    fn encode(&self, duty: Duty) -> Vec<u8>;
+}
+
+/// Built-in protocols selectable from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProtocolKind {
+    /// Duty in percent as a decimal line, e.g. "55\n"
+    Text,
+    /// Single byte with duty scaled to 0-255, ready for Arduino `analogWrite`
+    Byte,
+}
+
+impl ProtocolKind {
+    pub fn protocol(self) -> Box<dyn Protocol> {
+        match self {
+            ProtocolKind::Text => Box::new(TextProtocol),
+            ProtocolKind::Byte => Box::new(ByteProtocol),
+        }
+    }
+}
+
+pub struct TextProtocol;
+
+impl Protocol for TextProtocol {
+    fn encode(&self, duty: Duty) -> Vec<u8> {
+        format!("{}\n", duty).into_bytes()
+    }
+}
+
+pub struct ByteProtocol;
+
+impl Protocol for ByteProtocol {
+    fn encode(&self, duty: Duty) -> Vec<u8> {
+        let scaled = (u16::from(duty.percent()) * 255 + 50) / 100;
+        vec![scaled as u8]
+    }
+}
+
+/// Serial-attached fan controller.
+pub struct SerialOutput {
+    path: String,
+    baud: u32,
+    protocol: Box<dyn Protocol>,
+    port: Option<File>,
+    /// First failure to write since it was last taken
+    error: Option<io::Error>,
+}
+
+impl SerialOutput {
+    pub fn new(path: &str, baud: u32, protocol: Box<dyn Protocol>) -> Self {
+        Self {
+            path: path.to_string(),
+            baud,
+            protocol,
+            port: None,
+            error: None,
+        }
+    }
+
+    fn open(&self) -> io::Result<File> {
+        OpenOptions::new().read(true).write(true).open(&self.path)
+    }
+
+    /// Opens the port and puts it into raw mode at the configured speed.
+    fn connect(&self) -> io::Result<File> {
+        let port = self.open().map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("failed to open serial port {:?}: {}", self.path, error),
+            )
+        })?;
+        configure(&port, self.baud).map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!(
+                    "failed to configure serial port {:?} to {} baud: {}",
+                    self.path, self.baud, error
+                ),
+            )
+        })?;
+        Ok(port)
+    }
+}
+
+impl Output for SerialOutput {
+    /// Opens the port, leaving a failure to be taken like that of a write, so the port is opened
+    /// again with backoff instead of stopping the controller.
+    fn init(&mut self) {
+        match self.connect() {
+            Ok(port) => {
+                self.port = Some(port);
+                // Match the full duty other outputs start at
+                self.write(Duty::FULL);
+            }
+            Err(error) => {
+                self.error.get_or_insert(error);
+            }
+        }
+    }
+
+    fn write(&mut self, duty: Duty) {
+        let Some(port) = self.port.as_mut() else {
+            let error = io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("serial port {:?} is not open", self.path),
+            );
+            self.error.get_or_insert(error);
+            return;
+        };
+
+        let command = self.protocol.encode(duty);
+        if let Err(error) = port.write_all(&command).and_then(|_| port.flush()) {
+            let error = io::Error::new(
+                error.kind(),
+                format!("failed to write to serial port {:?}: {}", self.path, error),
+            );
+            self.error.get_or_insert(error);
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.port = None;
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Opens the port again, e.g. once a USB adapter that was unplugged enumerates again.
+    fn reinit(&mut self) -> io::Result<()> {
+        self.port = None;
+        self.port = Some(self.connect()?);
+        Ok(())
+    }
+}
+
+/// Puts the port into raw mode at the given speed.
+#[cfg(unix)]
+fn configure(port: &File, baud: u32) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let speed = match baud {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported baud rate",
+            ))
+        }
+    };
+
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(port.as_raw_fd(), &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        libc::cfsetspeed(&mut termios, speed);
+        if libc::tcsetattr(port.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Windows keeps the speed set with e.g. `mode COM3 BAUD=9600`.
+#[cfg(not(unix))]
+fn configure(_port: &File, _baud: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteProtocol, Protocol, SerialOutput, TextProtocol};
+    use crate::{
+        args::Args,
+        mock::MockClock,
+        pwm::{Health, Output, Pwm},
+        units::Duty,
+    };
+    use clap::Parser;
+
+    #[test]
+    fn text_protocol_sends_percent_lines() {
+        assert_eq!(
+            b"55\n".to_vec(),
+            TextProtocol.encode(Duty::new(55).unwrap())
+        );
+    }
+
+    #[test]
+    fn byte_protocol_scales_to_full_byte() {
+        assert_eq!(vec![255], ByteProtocol.encode(Duty::FULL));
+        assert_eq!(vec![128], ByteProtocol.encode(Duty::new(50).unwrap()));
+        assert_eq!(vec![0], ByteProtocol.encode(Duty::new(0).unwrap()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn port_that_cannot_be_configured_fails_without_panicking() {
+        let mut output = SerialOutput::new("/dev/null", 9600, Box::new(TextProtocol));
+        output.init();
+        let error = output.take_error().unwrap();
+        assert!(error
+            .to_string()
+            .contains("failed to configure serial port"));
+
+        // Through the controller's output, the failure starts recovery instead
+        let args = Args::parse_from(["fan-controller", "--serial-port", "/dev/null"]);
+        let output = SerialOutput::new("/dev/null", 9600, Box::new(TextProtocol));
+        let mut pwm = Pwm::new(&args, Box::new(output));
+        pwm.init();
+        assert!(matches!(
+            pwm.flush(&mut MockClock::new()),
+            Some(Health::Failed(_))
+        ));
+        assert!(pwm.failed());
+    }
+}
@@ -0,0 +1,58 @@
+//! Target temperature changes requested from outside the control loop, e.g. over Modbus.
+
+use crate::units::Celsius;
+use std::sync::{Arc, Mutex};
+
+/// Shared slot for the latest requested target.
+///
+/// Clones share the same slot, so a server thread can request what the controller applies on
+/// its next poll.
+#[derive(Debug, Clone, Default)]
+pub struct Setpoint {
+    requested: Arc<Mutex<Option<Request>>>,
+}
+
+/// Target requested through a setpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request {
+    pub target: Celsius,
+    /// Whether it came over the network, so it lapses unless refreshed within the override TTL
+    pub remote: bool,
+}
+
+impl Setpoint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests a new target, replacing any request not yet applied.
+    pub fn request(&self, target: Celsius) {
+        *self.requested.lock().unwrap() = Some(Request {
+            target,
+            remote: false,
+        });
+    }
+
+    /// Requests a new target on behalf of a remote system, replacing any request not yet applied.
+    pub fn request_remote(&self, target: Celsius) {
+        *self.requested.lock().unwrap() = Some(Request {
+            target,
+            remote: true,
+        });
+    }
+
+    /// Returns the target requested and not yet applied, if any, leaving it in place.
+    pub fn pending(&self) -> Option<Celsius> {
+        self.requested.lock().unwrap().map(|request| request.target)
+    }
+
+    /// Returns the target requested since the last call, if any.
+    pub fn take(&self) -> Option<Celsius> {
+        self.take_request().map(|request| request.target)
+    }
+
+    /// Returns the request made since the last call, if any.
+    pub fn take_request(&self) -> Option<Request> {
+        self.requested.lock().unwrap().take()
+    }
+}
@@ -0,0 +1,489 @@
+//! Interactive setup for new installs: picks a temperature sensor, asks how the fan is wired,
+//! spins the fan to find the lowest speed it keeps running at and writes a commented config file.
+
+use crate::{
+    clock::Clock,
+    i18n::{language, text, translate, Language},
+    pwm::Output,
+    sensor::{FileSensor, Sensor as _},
+    units::{Celsius, Duty},
+};
+use std::{
+    fs, io,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// How long the fan runs at full speed before each spin test step, to start it from a standstill
+const SPIN_UP: Duration = Duration::from_secs(3);
+/// How long each spin test duty is held before asking whether the fan still turns
+const SETTLE: Duration = Duration::from_secs(3);
+/// Percentage points the duty is raised by after the fan stalled
+const SPIN_STEP: u8 = 10;
+
+/// Temperature file found under sysfs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sensor {
+    pub path: PathBuf,
+    /// Thermal zone type or hwmon chip name, e.g. `cpu-thermal`
+    pub label: String,
+    /// Reading at discovery, if it was readable
+    pub temperature: Option<Celsius>,
+}
+
+/// Finds the thermal zone and hwmon temperature files under a sysfs class directory, usually
+/// `/sys/class`.
+pub fn find_sensors(class: &Path) -> Vec<Sensor> {
+    let mut sensors = Vec::new();
+
+    for zone in entries(&class.join("thermal"), "thermal_zone") {
+        let label = read_label(&zone.join("type"));
+        sensors.push(sensor(zone.join("temp"), label));
+    }
+    for chip in entries(&class.join("hwmon"), "hwmon") {
+        let name = read_label(&chip.join("name"));
+        for input in entries(&chip, "temp") {
+            let Some(file) = input.file_name().and_then(|file| file.to_str()) else {
+                continue;
+            };
+            let Some(channel) = file.strip_suffix("_input") else {
+                continue;
+            };
+            let label = match fs::read_to_string(chip.join(format!("{}_label", channel))) {
+                Ok(label) => format!("{} {}", name, label.trim()),
+                Err(_) => format!("{} {}", name, channel),
+            };
+            sensors.push(sensor(input, label));
+        }
+    }
+    sensors
+}
+
+/// Returns the paths in a directory whose names start with the prefix, sorted.
+fn entries(directory: &Path, prefix: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(prefix))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn read_label(path: &Path) -> String {
+    fs::read_to_string(path)
+        .map(|label| label.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn sensor(path: PathBuf, label: String) -> Sensor {
+    let temperature = FileSensor::new(path.to_str().unwrap_or_default())
+        .read()
+        .ok();
+    Sensor {
+        path,
+        label,
+        temperature,
+    }
+}
+
+/// How the fan is wired to the pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FanType {
+    /// 2-pin fan switched on its ground through a transistor
+    TwoPin,
+    /// 4-pin fan with its PWM wire on the pin, powered separately
+    FourPin,
+}
+
+impl FanType {
+    pub fn describe(self) -> &'static str {
+        match self {
+            FanType::TwoPin => "2-pin fan switched through a transistor",
+            FanType::FourPin => "4-pin PWM fan with its PWM wire on the pin",
+        }
+    }
+
+    /// Returns the speed the spin test starts from, below which such fans rarely keep turning.
+    pub fn pwm_min(self) -> Duty {
+        match self {
+            FanType::TwoPin => Duty::new(30).unwrap(),
+            FanType::FourPin => Duty::new(20).unwrap(),
+        }
+    }
+
+    /// Returns the kick-start such fans need to spin up from a standstill, 0 for none.
+    pub fn kick_start_ms(self) -> u64 {
+        match self {
+            FanType::TwoPin => 500,
+            FanType::FourPin => 0,
+        }
+    }
+}
+
+/// Answers given to the setup questions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answers {
+    pub sensor: Sensor,
+    /// wiringPi pin driving the fan
+    pub pin: i32,
+    pub fan: FanType,
+    pub target: Celsius,
+    pub pwm_min: Duty,
+    /// Whether `pwm_min` was found by the spin test rather than assumed for the fan type
+    pub spin_tested: bool,
+}
+
+/// Asks the setup questions on `prompt`, reading the answers from `input`.
+pub struct Wizard<'a> {
+    input: &'a mut dyn BufRead,
+    prompt: &'a mut dyn Write,
+}
+
+impl<'a> Wizard<'a> {
+    pub fn new(input: &'a mut dyn BufRead, prompt: &'a mut dyn Write) -> Self {
+        Self { input, prompt }
+    }
+
+    /// Asks for the sensor out of those found, the pin, the fan type and the target temperature.
+    ///
+    /// Invalid answers are asked again. Empty answers take the default shown in brackets.
+    pub fn ask(&mut self, sensors: &[Sensor], target: Celsius) -> io::Result<Answers> {
+        let sensor = if sensors.is_empty() {
+            writeln!(self.prompt, "{}", text("setup-no-sensors", &[]))?;
+            let path = self.question(&text("setup-temperature-path", &[]), None, |answer| {
+                Ok(PathBuf::from(answer))
+            })?;
+            sensor(path, text("setup-given-by-hand", &[]))
+        } else {
+            writeln!(self.prompt, "{}", text("setup-sensors", &[]))?;
+            for (number, sensor) in sensors.iter().enumerate() {
+                let reading = match sensor.temperature {
+                    Some(temperature) => format!("{}°C", temperature),
+                    None => text("setup-unreadable", &[]),
+                };
+                writeln!(
+                    self.prompt,
+                    "  {}) {} ({}, {})",
+                    number + 1,
+                    sensor.label,
+                    sensor.path.display(),
+                    reading
+                )?;
+            }
+            let index = self.question(&text("setup-sensor", &[]), Some("1"), |answer| {
+                answer
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|number| (1..=sensors.len()).contains(number))
+                    .ok_or_else(|| text("setup-expected-sensor", &[("count", &sensors.len())]))
+            })?;
+            sensors[index - 1].clone()
+        };
+
+        let pin = self.question(&text("setup-pin", &[]), None, |answer| {
+            answer
+                .parse::<i32>()
+                .map_err(|_| text("setup-expected-pin", &[]))
+        })?;
+
+        writeln!(self.prompt, "{}", text("setup-fan-types", &[]))?;
+        for (number, id) in ["setup-fan-two-pin", "setup-fan-four-pin"]
+            .iter()
+            .enumerate()
+        {
+            writeln!(self.prompt, "  {}) {}", number + 1, text(id, &[]))?;
+        }
+        let fan = self.question(
+            &text("setup-fan-type", &[]),
+            Some("1"),
+            |answer| match answer {
+                "1" => Ok(FanType::TwoPin),
+                "2" => Ok(FanType::FourPin),
+                _ => Err(text("setup-expected-fan-type", &[])),
+            },
+        )?;
+
+        let target = self.question(
+            &text("setup-target", &[]),
+            Some(&target.to_string()),
+            |answer| answer.parse::<Celsius>().map_err(|error| error.to_string()),
+        )?;
+
+        Ok(Answers {
+            sensor,
+            pin,
+            fan,
+            target,
+            pwm_min: fan.pwm_min(),
+            spin_tested: false,
+        })
+    }
+
+    /// Spins the fan up at full speed, then holds it at rising speeds from the fan type's minimum
+    /// until it keeps turning, which becomes the minimum speed.
+    ///
+    /// Returns `false` if the fan didn't spin at full speed, in which case the answers are left
+    /// alone. However it ends, the output is shut down.
+    pub fn spin_test(
+        &mut self,
+        answers: &mut Answers,
+        output: &mut dyn Output,
+        clock: &mut dyn Clock,
+    ) -> io::Result<bool> {
+        output.init();
+        let spinning = self.spin(answers, output, clock);
+        output.shutdown();
+        spinning
+    }
+
+    fn spin(
+        &mut self,
+        answers: &mut Answers,
+        output: &mut dyn Output,
+        clock: &mut dyn Clock,
+    ) -> io::Result<bool> {
+        output.write(Duty::FULL);
+        clock.sleep(SPIN_UP);
+        if !self.confirm(&text("setup-spinning-full", &[]))? {
+            return Ok(false);
+        }
+
+        let mut duty = answers.fan.pwm_min();
+        while duty < Duty::FULL {
+            output.write(Duty::FULL);
+            clock.sleep(SPIN_UP);
+            output.write(duty);
+            clock.sleep(SETTLE);
+            if self.confirm(&text("setup-spinning-at", &[("duty", &duty)]))? {
+                break;
+            }
+            duty = duty.raise(SPIN_STEP);
+        }
+        answers.pwm_min = duty;
+        answers.spin_tested = true;
+        Ok(true)
+    }
+
+    /// Asks until the answer parses, returning the default for an empty answer if there is one.
+    fn question<T>(
+        &mut self,
+        question: &str,
+        default: Option<&str>,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> io::Result<T> {
+        loop {
+            match default {
+                Some(default) => write!(self.prompt, "{} [{}]: ", question, default)?,
+                None => write!(self.prompt, "{}: ", question)?,
+            }
+            self.prompt.flush()?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    text("setup-ended", &[]),
+                ));
+            }
+            let answer = match (line.trim(), default) {
+                ("", Some(default)) => default,
+                (answer, _) => answer,
+            };
+            match parse(answer) {
+                Ok(value) => return Ok(value),
+                Err(error) => writeln!(
+                    self.prompt,
+                    "{}",
+                    text("setup-invalid-answer", &[("reason", &error)])
+                )?,
+            }
+        }
+    }
+
+    /// Asks a yes or no question, yes by default.
+    ///
+    /// English answers are taken whatever the language.
+    fn confirm(&mut self, question: &str) -> io::Result<bool> {
+        let words = |language, id| -> Vec<String> {
+            translate(language, id, &[])
+                .split(',')
+                .map(|word| word.trim().to_lowercase())
+                .collect()
+        };
+        let yes = [
+            words(language(), "setup-yes"),
+            words(Language::En, "setup-yes"),
+        ]
+        .concat();
+        let no = [
+            words(language(), "setup-no"),
+            words(Language::En, "setup-no"),
+        ]
+        .concat();
+        let hint = text("setup-yes-no", &[]);
+        self.question(&format!("{} {}", question, hint), None, |answer| {
+            let answer = answer.to_lowercase();
+            if answer.is_empty() || yes.contains(&answer) {
+                Ok(true)
+            } else if no.contains(&answer) {
+                Ok(false)
+            } else {
+                Err(text("setup-expected-yes-no", &[]))
+            }
+        })
+    }
+}
+
+/// Returns the config file for the answers, with comments explaining each setting.
+pub fn to_config(answers: &Answers) -> String {
+    let mut config = String::from("# Written by fan-controller setup\n\n");
+
+    config.push_str(&format!("# Temperature sensor: {}\n", answers.sensor.label));
+    config.push_str(&format!(
+        "temperature-file-path = \"{}\"\n",
+        answers.sensor.path.display()
+    ));
+    config.push_str("# wiringPi pin driving the fan\n");
+    config.push_str(&format!("gpio-pwm = {}\n", answers.pin));
+    config.push_str(&format!("# {}\n", answers.fan.describe()));
+    if answers.spin_tested {
+        config.push_str("# Lowest speed the fan kept spinning at in the spin test\n");
+    } else {
+        config.push_str("# Usual lowest speed for this fan type, the fan wasn't spin tested\n");
+    }
+    config.push_str(&format!("pwm-min = {}\n", answers.pwm_min));
+    if answers.fan.kick_start_ms() > 0 {
+        config.push_str("# Full speed for a moment when the fan starts from a standstill\n");
+        config.push_str(&format!(
+            "kick-start-ms = {}\n",
+            answers.fan.kick_start_ms()
+        ));
+    }
+    config.push_str("# Temperature the fan speed is adjusted to hold\n");
+    config.push_str(&format!(
+        "temperature-target-value = \"{}\"\n",
+        answers.target
+    ));
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_sensors, to_config, Answers, FanType, Sensor, Wizard};
+    use crate::{
+        mock::{MockClock, MockOutput, TempPath},
+        units::{Celsius, Duty},
+    };
+    use std::{fs, io::Cursor, path::PathBuf};
+
+    fn answers() -> Answers {
+        Answers {
+            sensor: Sensor {
+                path: PathBuf::from("/sys/class/thermal/thermal_zone0/temp"),
+                label: "cpu-thermal".to_string(),
+                temperature: None,
+            },
+            pin: 3,
+            fan: FanType::TwoPin,
+            target: Celsius::new(45, 0),
+            pwm_min: Duty::new(30).unwrap(),
+            spin_tested: false,
+        }
+    }
+
+    #[test]
+    fn finds_thermal_zones_and_hwmon_inputs() {
+        let class = TempPath::dir("setup");
+        let zone = class.join("thermal/thermal_zone0");
+        let chip = class.join("hwmon/hwmon0");
+        fs::create_dir_all(&zone).unwrap();
+        fs::create_dir_all(&chip).unwrap();
+        fs::write(zone.join("type"), "cpu-thermal\n").unwrap();
+        fs::write(zone.join("temp"), "42500\n").unwrap();
+        fs::write(chip.join("name"), "nvme\n").unwrap();
+        fs::write(chip.join("temp1_input"), "38000\n").unwrap();
+        fs::write(chip.join("temp1_label"), "Composite\n").unwrap();
+
+        let sensors = find_sensors(&class);
+
+        assert_eq!(2, sensors.len());
+        assert_eq!("cpu-thermal", sensors[0].label);
+        assert_eq!(Some(Celsius::new(42, 500)), sensors[0].temperature);
+        assert_eq!("nvme Composite", sensors[1].label);
+        assert_eq!(chip.join("temp1_input"), sensors[1].path);
+    }
+
+    #[test]
+    fn asks_again_after_invalid_answers() {
+        let sensors = vec![answers().sensor];
+        let mut input = Cursor::new("2\n\nthree\n3\n\n45\n");
+        let mut prompt = Vec::new();
+
+        let given = Wizard::new(&mut input, &mut prompt)
+            .ask(&sensors, Celsius::new(40, 0))
+            .unwrap();
+
+        assert_eq!(answers(), given);
+        let prompt = String::from_utf8(prompt).unwrap();
+        assert!(prompt.contains("Invalid answer, expected a number from 1 to 1"));
+        assert!(prompt.contains("Invalid answer, expected a pin number"));
+    }
+
+    #[test]
+    fn spin_test_raises_minimum_until_fan_keeps_turning() {
+        let clock = MockClock::new();
+        let output = MockOutput::with_clock(&clock);
+        let mut input = Cursor::new("y\nn\n\n");
+        let mut prompt = Vec::new();
+        let mut given = answers();
+
+        let spinning = Wizard::new(&mut input, &mut prompt)
+            .spin_test(&mut given, &mut output.clone(), &mut clock.clone())
+            .unwrap();
+
+        assert!(spinning);
+        assert_eq!(Duty::new(40).unwrap(), given.pwm_min);
+        assert!(given.spin_tested);
+        let full = Duty::FULL;
+        assert_eq!(
+            vec![
+                full,
+                full,
+                Duty::new(30).unwrap(),
+                full,
+                Duty::new(40).unwrap()
+            ],
+            output.writes()
+        );
+    }
+
+    #[test]
+    fn config_explains_settings() {
+        assert_eq!(
+            "\
+# Written by fan-controller setup
+
+# Temperature sensor: cpu-thermal
+temperature-file-path = \"/sys/class/thermal/thermal_zone0/temp\"
+# wiringPi pin driving the fan
+gpio-pwm = 3
+# 2-pin fan switched through a transistor
+# Usual lowest speed for this fan type, the fan wasn't spin tested
+pwm-min = 30
+# Full speed for a moment when the fan starts from a standstill
+kick-start-ms = 500
+# Temperature the fan speed is adjusted to hold
+temperature-target-value = \"45\"
+",
+            to_config(&answers())
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Temperature of SATA and USB disks from their SMART attributes, for a Pi NAS whose fan should
+//! follow the drives.
+//!
+//! Readings come from `smartctl -A` with `-n standby`, so a spun-down drive is left asleep
+//! instead of being woken every poll.
+
+use crate::{
+    sensor::{Raw, Sensor, SensorError},
+    units::Celsius,
+};
+use std::process::Command;
+
+/// SMART attributes holding the temperature in their raw value, preferred first
+const ATTRIBUTES: &[&str] = &["194", "190"];
+
+/// Returns the temperature in `smartctl -A` output: the ATA attribute table, or the
+/// temperature line SCSI and NVMe drives report.
+pub fn parse_temperature(output: &str) -> Option<Celsius> {
+    let attribute = |id: &str| {
+        output.lines().find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.first() != Some(&id) {
+                return None;
+            }
+            // The raw value may be followed by e.g. "(Min/Max 18/45)"
+            fields.get(9)?.parse::<i32>().ok()
+        })
+    };
+    let line = || {
+        output.lines().find_map(|line| {
+            let value = line
+                .strip_prefix("Current Drive Temperature:")
+                .or_else(|| line.strip_prefix("Temperature:"))?;
+            value.split_whitespace().next()?.parse::<i32>().ok()
+        })
+    };
+    ATTRIBUTES
+        .iter()
+        .find_map(|id| attribute(id))
+        .or_else(line)
+        .map(|degrees| Celsius::new(degrees, 0))
+}
+
+/// Whether smartctl skipped the drive for being in standby or asleep.
+pub fn in_standby(output: &str) -> bool {
+    output
+        .lines()
+        .any(|line| line.starts_with("Device is in ") && line.contains(" mode"))
+}
+
+/// Disk read through smartctl.
+///
+/// While the drive is in standby it reads as the standby temperature: it makes next to no heat
+/// then, and failing the reading instead would run the fan at full speed.
+pub struct SmartSensor {
+    device: String,
+    standby: Celsius,
+    /// Whether the last read found the drive in standby, so transitions are logged once
+    asleep: bool,
+    /// Last temperature the drive reported, in millidegrees
+    raw: Option<i32>,
+}
+
+impl SmartSensor {
+    pub fn new(device: &str, standby: Celsius) -> Self {
+        Self {
+            device: device.to_string(),
+            standby,
+            asleep: false,
+            raw: None,
+        }
+    }
+
+    /// Returns the reading of smartctl output.
+    fn interpret(&mut self, output: &str) -> Result<Celsius, SensorError> {
+        let asleep = in_standby(output);
+        if asleep != self.asleep {
+            if asleep {
+                log::info!(
+                    "{} is in standby, not reading it until it wakes",
+                    self.device
+                );
+            } else {
+                log::info!("{} woke up", self.device);
+            }
+            self.asleep = asleep;
+        }
+        if asleep {
+            self.raw = None;
+            return Ok(self.standby);
+        }
+
+        let temperature = parse_temperature(output).ok_or_else(|| {
+            SensorError::Parse(format!("no temperature for {} from smartctl", self.device))
+        })?;
+        self.raw = Some(temperature.millidegrees());
+        Ok(temperature)
+    }
+}
+
+impl Sensor for SmartSensor {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        // The exit status flags problems of the drive as well, so only the output tells
+        let output = Command::new("smartctl")
+            .args(["-n", "standby", "-A", &self.device])
+            .output()
+            .map_err(|error| SensorError::Read(format!("smartctl {}", self.device), error))?;
+        self.interpret(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    fn raw(&self) -> Option<Raw> {
+        self.raw.map(Raw::Millidegrees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{in_standby, parse_temperature, SmartSensor};
+    use crate::{sensor::Sensor, units::Celsius};
+
+    const ATA: &str = "\
+=== START OF READ SMART DATA SECTION ===
+SMART Attributes Data Structure revision number: 16
+ID# ATTRIBUTE_NAME          FLAG     VALUE WORST THRESH TYPE      UPDATED  WHEN_FAILED RAW_VALUE
+  9 Power_On_Hours          0x0032   091   091   000    Old_age   Always       -       6744
+190 Airflow_Temperature_Cel 0x0022   064   055   045    Old_age   Always       -       35
+194 Temperature_Celsius     0x0022   036   045   000    Old_age   Always       -       36 (Min/Max 18/45)
+";
+
+    const STANDBY: &str = "\
+smartctl 7.3 2022-02-28 r5338 [aarch64-linux-6.6.31+rpt-rpi-2712] (local build)
+Copyright (C) 2002-22, Bruce Allen, Christian Franke, www.smartmontools.org
+
+Device is in STANDBY mode, exit(2)
+";
+
+    #[test]
+    fn parses_ata_scsi_and_nvme_output() {
+        assert_eq!(Some(Celsius::new(36, 0)), parse_temperature(ATA));
+        let airflow = ATA.replace("194 Temperature_Celsius", "231 Life_Left");
+        assert_eq!(Some(Celsius::new(35, 0)), parse_temperature(&airflow));
+        assert_eq!(
+            Some(Celsius::new(33, 0)),
+            parse_temperature("Current Drive Temperature:     33 C\n")
+        );
+        assert_eq!(
+            Some(Celsius::new(41, 0)),
+            parse_temperature("Temperature:                        41 Celsius\n")
+        );
+        assert_eq!(None, parse_temperature(STANDBY));
+    }
+
+    #[test]
+    fn reads_standby_temperature_while_asleep() {
+        assert!(in_standby(STANDBY));
+        assert!(in_standby("Device is in SLEEP mode, exit(2)\n"));
+        assert!(!in_standby(ATA));
+
+        let mut sensor = SmartSensor::new("/dev/sda", Celsius::new(25, 0));
+        assert_eq!(Celsius::new(36, 0), sensor.interpret(ATA).unwrap());
+        assert_eq!(Celsius::new(25, 0), sensor.interpret(STANDBY).unwrap());
+        assert_eq!(None, sensor.raw());
+        assert_eq!(Celsius::new(36, 0), sensor.interpret(ATA).unwrap());
+        assert!(sensor
+            .interpret("Smartctl open device: /dev/sdb failed\n")
+            .is_err());
+    }
+}
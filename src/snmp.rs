@@ -0,0 +1,428 @@
+//! Minimal SNMP v1/v2c responder, so network monitoring systems can poll the controller.
+//!
+//! Answers `get` and `get-next` for a few read-only scalars under the Net-SNMP experimental
+//! arc `.1.3.6.1.4.1.8072.9999.9999`, which is enough for `snmpwalk` and NMS polling without
+//! running an SNMP daemon:
+//!
+//! | OID suffix | Value |
+//! |------------|-------|
+//! | `.1.0` | Temperature in millidegrees Celsius |
+//! | `.2.0` | Target temperature in millidegrees Celsius |
+//! | `.3.0` | Fan duty in percent |
+//! | `.4.0` | 1 while latched at maximum after an overtemperature |
+//! | `.5.0` | Latest sensor fault, empty when readings succeed |
+
+use crate::status::{Snapshot, Status};
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    thread::{self, JoinHandle},
+};
+
+/// Arc the values are published under.
+pub const BASE: [u32; 10] = [1, 3, 6, 1, 4, 1, 8072, 9999, 9999, 1];
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const GAUGE32: u8 = 0x42;
+const GET_REQUEST: u8 = 0xa0;
+const GET_NEXT_REQUEST: u8 = 0xa1;
+const GET_RESPONSE: u8 = 0xa2;
+/// v2c exceptions, sent in place of a value
+const NO_SUCH_OBJECT: u8 = 0x80;
+const END_OF_MIB_VIEW: u8 = 0x82;
+/// v1 error status for unknown OIDs
+const NO_SUCH_NAME: i64 = 2;
+
+const VERSION_1: i64 = 0;
+
+/// Value of a scalar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    Gauge(u32),
+    Text(String),
+}
+
+impl Value {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Value::Integer(value) => integer(INTEGER, *value),
+            Value::Gauge(value) => integer(GAUGE32, i64::from(*value)),
+            Value::Text(text) => tlv(OCTET_STRING, text.as_bytes()),
+        }
+    }
+}
+
+/// Returns the scalars known from the snapshot, in OID order.
+pub fn table(snapshot: &Snapshot) -> Vec<(Vec<u32>, Value)> {
+    let oid = |index: u32| {
+        let mut oid = BASE.to_vec();
+        oid.extend([index, 0]);
+        oid
+    };
+
+    let mut table = Vec::new();
+    if let Some(temperature) = snapshot.temperature {
+        table.push((oid(1), Value::Integer(temperature.millidegrees().into())));
+    }
+    if let Some(target) = snapshot.target {
+        table.push((oid(2), Value::Integer(target.millidegrees().into())));
+    }
+    if let Some(duty) = snapshot.duty {
+        table.push((oid(3), Value::Gauge(duty.percent().into())));
+    }
+    table.push((oid(4), Value::Integer(snapshot.latched.into())));
+    table.push((
+        oid(5),
+        Value::Text(snapshot.fault.clone().unwrap_or_default()),
+    ));
+    table
+}
+
+/// Answers a request datagram, or returns `None` for anything to be dropped silently, such as a
+/// wrong community or a malformed packet.
+pub fn respond(request: &[u8], community: &str, snapshot: &Snapshot) -> Option<Vec<u8>> {
+    let mut message = Reader::new(request).expect(SEQUENCE)?;
+    let version = message.integer()?;
+    if version > 1 || message.expect(OCTET_STRING)?.data != community.as_bytes() {
+        return None;
+    }
+
+    let (kind, pdu) = message.next()?;
+    if kind != GET_REQUEST && kind != GET_NEXT_REQUEST {
+        return None;
+    }
+    let mut pdu = Reader::new(pdu);
+    let request_id = pdu.integer()?;
+    pdu.integer()?;
+    pdu.integer()?;
+
+    let table = table(snapshot);
+    let mut bindings = Vec::new();
+    let mut error = None;
+    let mut list = pdu.expect(SEQUENCE)?;
+    let mut index = 0;
+    while !list.is_empty() {
+        index += 1;
+        let mut binding = list.expect(SEQUENCE)?;
+        let oid = decode_oid(binding.expect(OBJECT_IDENTIFIER)?.data)?;
+
+        let found = if kind == GET_REQUEST {
+            table
+                .iter()
+                .find(|(candidate, _)| *candidate == oid)
+                .map(|(oid, value)| (oid.clone(), value.encode()))
+                .ok_or(NO_SUCH_OBJECT)
+        } else {
+            table
+                .iter()
+                .find(|(candidate, _)| *candidate > oid)
+                .map(|(oid, value)| (oid.clone(), value.encode()))
+                .ok_or(END_OF_MIB_VIEW)
+        };
+
+        let (oid, value) = match found {
+            Ok(found) => found,
+            Err(_) if version == VERSION_1 => {
+                // v1 has no exceptions, the whole request fails instead
+                error.get_or_insert(index);
+                (oid, tlv(NULL, &[]))
+            }
+            Err(exception) => (oid, tlv(exception, &[])),
+        };
+        bindings.push(tlv(SEQUENCE, &[encode_oid(&oid), value].concat()));
+    }
+
+    let (status, error_index) = match error {
+        Some(index) => (NO_SUCH_NAME, index),
+        None => (0, 0),
+    };
+    let pdu = [
+        integer(INTEGER, request_id),
+        integer(INTEGER, status),
+        integer(INTEGER, error_index),
+        tlv(SEQUENCE, &bindings.concat()),
+    ]
+    .concat();
+    let message = [
+        integer(INTEGER, version),
+        tlv(OCTET_STRING, community.as_bytes()),
+        tlv(GET_RESPONSE, &pdu),
+    ]
+    .concat();
+    Some(tlv(SEQUENCE, &message))
+}
+
+/// Answers requests on the given address from a background thread.
+pub fn serve(address: SocketAddr, community: String, status: Status) -> io::Result<JoinHandle<()>> {
+    let socket = UdpSocket::bind(address)?;
+
+    Ok(thread::spawn(move || {
+        let mut buffer = [0u8; 1500];
+        loop {
+            let (length, peer) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) => {
+                    log::warn!("Failed to receive SNMP request: {}", error);
+                    continue;
+                }
+            };
+            let Some(response) = respond(&buffer[..length], &community, &status.snapshot()) else {
+                log::debug!("Ignored SNMP request from {}", peer);
+                continue;
+            };
+            if let Err(error) = socket.send_to(&response, peer) {
+                log::warn!("Failed to answer SNMP request from {}: {}", peer, error);
+            }
+        }
+    }))
+}
+
+fn length(length: usize) -> Vec<u8> {
+    match length {
+        0..=0x7f => vec![length as u8],
+        0x80..=0xff => vec![0x81, length as u8],
+        _ => vec![0x82, (length >> 8) as u8, length as u8],
+    }
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    [&[tag][..], &length(content.len()), content].concat()
+}
+
+/// Encodes an integer in the fewest two's complement bytes.
+fn integer(tag: u8, value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7 {
+        let redundant = (bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    tlv(tag, &bytes[start..])
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut content = vec![(oid[0] * 40 + oid[1]) as u8];
+    for &arc in &oid[2..] {
+        let mut groups = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        content.extend(groups.iter().rev());
+    }
+    tlv(OBJECT_IDENTIFIER, &content)
+}
+
+fn decode_oid(content: &[u8]) -> Option<Vec<u32>> {
+    let (&first, rest) = content.split_first()?;
+    let mut oid = vec![u32::from(first / 40), u32::from(first % 40)];
+    let mut arc: u32 = 0;
+    for &byte in rest {
+        arc = arc.checked_mul(128)? | u32::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            oid.push(arc);
+            arc = 0;
+        }
+    }
+    Some(oid)
+}
+
+/// Cursor over BER encoded elements.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the tag and content of the next element.
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.data.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (length, rest) = match first {
+            0..=0x7f => (usize::from(first), rest),
+            0x81 => (usize::from(*rest.first()?), &rest[1..]),
+            0x82 if rest.len() >= 2 => {
+                (usize::from(rest[0]) << 8 | usize::from(rest[1]), &rest[2..])
+            }
+            _ => return None,
+        };
+        if rest.len() < length {
+            return None;
+        }
+        self.data = &rest[length..];
+        Some((tag, &rest[..length]))
+    }
+
+    /// Returns a reader over the content of the next element, if it has the given tag.
+    fn expect(&mut self, tag: u8) -> Option<Reader<'a>> {
+        match self.next()? {
+            (found, content) if found == tag => Some(Reader::new(content)),
+            _ => None,
+        }
+    }
+
+    fn integer(&mut self) -> Option<i64> {
+        let content = self.expect(INTEGER)?.data;
+        if content.is_empty() || content.len() > 8 {
+            return None;
+        }
+        let sign = if content[0] & 0x80 != 0 { -1 } else { 0 };
+        Some(
+            content
+                .iter()
+                .fold(sign, |value, &byte| value << 8 | i64::from(byte)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        encode_oid, integer, respond, tlv, Reader, BASE, GET_NEXT_REQUEST, GET_REQUEST, INTEGER,
+        NULL, OBJECT_IDENTIFIER, OCTET_STRING, SEQUENCE,
+    };
+    use crate::{pwm::tests::duty, status::Snapshot, units::Celsius};
+
+    fn request(version: i64, community: &str, kind: u8, oids: &[Vec<u32>]) -> Vec<u8> {
+        let bindings: Vec<u8> = oids
+            .iter()
+            .flat_map(|oid| tlv(SEQUENCE, &[encode_oid(oid), tlv(NULL, &[])].concat()))
+            .collect();
+        let pdu = [
+            integer(INTEGER, 42),
+            integer(INTEGER, 0),
+            integer(INTEGER, 0),
+            tlv(SEQUENCE, &bindings),
+        ]
+        .concat();
+        tlv(
+            SEQUENCE,
+            &[
+                integer(INTEGER, version),
+                tlv(OCTET_STRING, community.as_bytes()),
+                tlv(kind, &pdu),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Encoded OID and value of a variable binding
+    type Binding = (Vec<u8>, Vec<u8>);
+
+    /// Returns the error status and the bindings of a response.
+    fn bindings(response: &[u8]) -> (i64, Vec<Binding>) {
+        let mut message = Reader::new(response).expect(SEQUENCE).unwrap();
+        message.integer().unwrap();
+        message.expect(OCTET_STRING).unwrap();
+        let mut pdu = Reader::new(message.next().unwrap().1);
+        assert_eq!(Some(42), pdu.integer());
+        let status = pdu.integer().unwrap();
+        pdu.integer().unwrap();
+
+        let mut list = pdu.expect(SEQUENCE).unwrap();
+        let mut bindings = Vec::new();
+        while !list.is_empty() {
+            let mut binding = list.expect(SEQUENCE).unwrap();
+            let oid = binding.expect(OBJECT_IDENTIFIER).unwrap().data.to_vec();
+            let (tag, value) = binding.next().unwrap();
+            bindings.push((oid, [&[tag][..], value].concat()));
+        }
+        (status, bindings)
+    }
+
+    fn oid(index: u32) -> Vec<u32> {
+        let mut oid = BASE.to_vec();
+        oid.extend([index, 0]);
+        oid
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            temperature: Some(Celsius::new(41, 500)),
+            duty: Some(duty(52)),
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn answers_get() {
+        let response = respond(
+            &request(1, "public", GET_REQUEST, &[oid(1), oid(3)]),
+            "public",
+            &snapshot(),
+        )
+        .unwrap();
+
+        let (status, bindings) = bindings(&response);
+        assert_eq!(0, status);
+        // 41500 and 52 as INTEGER and Gauge32
+        assert_eq!(vec![0x02, 0x00, 0xa2, 0x1c], bindings[0].1);
+        assert_eq!(vec![0x42, 0x34], bindings[1].1);
+    }
+
+    #[test]
+    fn walks_with_get_next() {
+        let snapshot = snapshot();
+        let mut oid = BASE.to_vec();
+        let mut walked = Vec::new();
+        loop {
+            let response = respond(
+                &request(1, "public", GET_NEXT_REQUEST, &[oid.clone()]),
+                "public",
+                &snapshot,
+            )
+            .unwrap();
+            let (_, bindings) = bindings(&response);
+            let (next, value) = bindings[0].clone();
+            if value[0] == 0x82 {
+                break;
+            }
+            oid = super::decode_oid(&next).unwrap();
+            walked.push(*oid.iter().rev().nth(1).unwrap());
+        }
+
+        // Target is unknown until the first decision
+        assert_eq!(vec![1, 3, 4, 5], walked);
+    }
+
+    #[test]
+    fn v1_reports_unknown_oid_as_error() {
+        let response = respond(
+            &request(0, "public", GET_REQUEST, &[oid(2)]),
+            "public",
+            &snapshot(),
+        )
+        .unwrap();
+
+        assert_eq!(2, bindings(&response).0);
+    }
+
+    #[test]
+    fn ignores_wrong_community() {
+        assert_eq!(
+            None,
+            respond(
+                &request(1, "private", GET_REQUEST, &[oid(1)]),
+                "public",
+                &snapshot()
+            )
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{args::Args, pwm::Output, units::Duty};
+use libc::c_int;
+
+#[link(name = "wiringPi")]
+extern "C" {
+    fn wiringPiSetup() -> c_int;
+    fn pinMode(pin: c_int, mode: c_int);
+    fn digitalWrite(pin: c_int, value: c_int);
+    fn softPwmCreate(pin: c_int, value: c_int, range: c_int) -> c_int;
+    fn softPwmWrite(pin: c_int, value: c_int);
+    fn softPwmStop(pin: c_int);
+}
+
+/// wiringPi software PWM on a single GPIO pin.
+pub struct SoftPwm {
+    gpio_pin: i32,
+    cpu: Option<usize>,
+    priority: Option<i32>,
+}
+
+impl SoftPwm {
+    pub fn new(args: &Args) -> Self {
+        Self::on_pin(
+            args.gpio_pwm
+                .expect("--gpio-pwm is required without another output"),
+            args,
+        )
+    }
+
+    /// Returns software PWM on the given pin, sharing the scheduling options of the main fan.
+    pub fn on_pin(gpio_pin: i32, args: &Args) -> Self {
+        Self {
+            gpio_pin,
+            cpu: args.pwm_cpu,
+            priority: args.pwm_priority,
+        }
+    }
+
+    /// Applies configured CPU affinity and realtime priority to the calling thread.
+    ///
+    /// Returns the previous settings so they can be restored afterwards.
+    fn apply_thread_scheduling(&self) -> ThreadScheduling {
+        let saved = ThreadScheduling::current();
+
+        unsafe {
+            if let Some(cpu) = self.cpu {
+                let mut pinned: libc::cpu_set_t = std::mem::zeroed();
+                libc::CPU_SET(cpu, &mut pinned);
+                if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &pinned) != 0
+                {
+                    log::warn!(
+                        "Failed to pin PWM thread to CPU {}: {}",
+                        cpu,
+                        std::io::Error::last_os_error()
+                    );
+                }
+            }
+
+            if let Some(priority) = self.priority {
+                let realtime = libc::sched_param {
+                    sched_priority: priority,
+                };
+                if libc::sched_setscheduler(0, libc::SCHED_FIFO, &realtime) != 0 {
+                    log::warn!(
+                        "Failed to set PWM thread priority {}: {}",
+                        priority,
+                        std::io::Error::last_os_error()
+                    );
+                }
+            }
+        }
+
+        saved
+    }
+}
+
+impl Output for SoftPwm {
+    /// Initializes GPIO pin for PWM use
+    fn init(&mut self) {
+        // The softPwm thread inherits affinity and scheduling from the thread creating it,
+        // so apply them to ourselves for the duration of softPwmCreate.
+        let saved = self.apply_thread_scheduling();
+
+        unsafe {
+            wiringPiSetup();
+            pinMode(self.gpio_pin, 1); // 1 = output
+            softPwmCreate(self.gpio_pin, 100, 100); // GPIO pin, initial value, range
+        }
+
+        saved.restore();
+    }
+
+    fn write(&mut self, duty: Duty) {
+        unsafe {
+            softPwmWrite(self.gpio_pin, duty.percent().into());
+        }
+    }
+
+    fn shutdown(&mut self) {
+        unsafe {
+            softPwmStop(self.gpio_pin);
+        }
+    }
+}
+
+/// Plain digital output switching a relay or transistor fan on at any duty above zero.
+pub struct GpioSwitch {
+    gpio_pin: i32,
+    active_low: bool,
+}
+
+impl GpioSwitch {
+    pub fn new(gpio_pin: i32, active_low: bool) -> Self {
+        Self {
+            gpio_pin,
+            active_low,
+        }
+    }
+
+    fn set(&self, on: bool) {
+        unsafe {
+            digitalWrite(self.gpio_pin, c_int::from(on != self.active_low));
+        }
+    }
+}
+
+impl Output for GpioSwitch {
+    fn init(&mut self) {
+        unsafe {
+            wiringPiSetup();
+        }
+        // Start switched on, like other outputs start at full duty, before making the pin an output
+        self.set(true);
+        unsafe {
+            pinMode(self.gpio_pin, 1); // 1 = output
+        }
+    }
+
+    fn write(&mut self, duty: Duty) {
+        self.set(duty > Duty::OFF);
+    }
+
+    /// Leaves the pin at its last level, like an expander relay keeps its latch.
+    fn shutdown(&mut self) {}
+}
+
+/// CPU affinity and scheduling policy of a thread.
+struct ThreadScheduling {
+    cpuset: libc::cpu_set_t,
+    policy: c_int,
+    param: libc::sched_param,
+}
+
+impl ThreadScheduling {
+    /// Reads settings of the calling thread.
+    fn current() -> Self {
+        unsafe {
+            let mut cpuset: libc::cpu_set_t = std::mem::zeroed();
+            libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut cpuset);
+            let mut param: libc::sched_param = std::mem::zeroed();
+            libc::sched_getparam(0, &mut param);
+
+            Self {
+                cpuset,
+                policy: libc::sched_getscheduler(0),
+                param,
+            }
+        }
+    }
+
+    /// Applies settings to the calling thread.
+    fn restore(&self) {
+        unsafe {
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &self.cpuset);
+            libc::sched_setscheduler(0, self.policy, &self.param);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Latest controller state, kept up to date from events for reporting over the control socket.
+
+use crate::{
+    alarm::AlarmLevel,
+    events::{Event, Progress, Sink},
+    forecast::{Forecast, Forecaster},
+    sensor::Raw,
+    units::{Celsius, Duty},
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// State as of the latest events.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub temperature: Option<Celsius>,
+    /// Sensor value the latest temperature was converted from
+    pub raw: Option<Raw>,
+    pub target: Option<Celsius>,
+    pub duty: Option<Duty>,
+    /// Latest sensor fault, cleared by the next successful reading
+    pub fault: Option<String>,
+    /// Long-running operation in progress, such as calibration
+    pub progress: Option<Progress>,
+    /// Why the latest configuration reload was rejected, cleared by the next successful one
+    pub reload_error: Option<String>,
+    /// Expected temperature at the current trend
+    pub forecast: Option<Forecast>,
+    /// Whether the fan is latched at maximum after an overtemperature
+    pub latched: bool,
+    /// Latest fan output failure, cleared once the output is initialized again
+    pub output_error: Option<String>,
+    /// Times the fan output was initialized again after failing
+    pub output_reinits: u64,
+    /// Level of each sensor watched for alarms that is above normal
+    pub alarms: BTreeMap<String, AlarmLevel>,
+    /// Latest notable events, oldest first, with the Unix time they happened at
+    pub history: VecDeque<(u64, Event)>,
+}
+
+impl Snapshot {
+    /// Renders the snapshot as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        fn number<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "null".to_string(), |value| value.to_string())
+        }
+
+        fn string(value: Option<&String>) -> String {
+            value.map_or_else(
+                || "null".to_string(),
+                |value| format!("\"{}\"", crate::event_log::escape(value)),
+            )
+        }
+
+        let progress = self.progress.as_ref().map_or_else(
+            || "null".to_string(),
+            |progress| {
+                format!(
+                    "{{\"operation\":\"{}\",\"done\":{},\"total\":{},\"duty\":{},\"remaining\":{}}}",
+                    progress.operation,
+                    progress.done,
+                    progress.total,
+                    progress.duty,
+                    progress.remaining.as_secs()
+                )
+            },
+        );
+
+        let forecast = self.forecast.as_ref().map_or_else(
+            || "null".to_string(),
+            |forecast| {
+                format!(
+                    "{{\"horizon\":{},\"temperature\":{},\"slope\":{},\"limit_in\":{}}}",
+                    forecast.horizon.as_secs(),
+                    forecast.temperature,
+                    forecast.slope,
+                    number(forecast.limit_in.map(|limit_in| limit_in.as_secs()))
+                )
+            },
+        );
+
+        let alarms: Vec<String> = self
+            .alarms
+            .iter()
+            .map(|(sensor, level)| {
+                format!("\"{}\":\"{}\"", crate::event_log::escape(sensor), level)
+            })
+            .collect();
+
+        let history: Vec<String> = self
+            .history
+            .iter()
+            .map(|(time, event)| crate::event_log::to_json(event, *time))
+            .collect();
+
+        format!(
+            "{{\"temperature\":{},\"raw\":{},\"target\":{},\"duty\":{},\"fault\":{},\"progress\":{},\"reload_error\":{},\"forecast\":{},\"latched\":{},\"output_error\":{},\"output_reinits\":{},\"alarms\":{{{}}},\"history\":[{}]}}",
+            number(self.temperature),
+            crate::event_log::raw_json(self.raw),
+            number(self.target),
+            number(self.duty),
+            string(self.fault.as_ref()),
+            progress,
+            string(self.reload_error.as_ref()),
+            forecast,
+            self.latched,
+            string(self.output_error.as_ref()),
+            self.output_reinits,
+            alarms.join(","),
+            history.join(",")
+        )
+    }
+}
+
+/// Shared handle to the latest state.
+///
+/// Clones share the same state, so the control socket can report what a sink records.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    snapshot: Arc<Mutex<Snapshot>>,
+    /// Horizon and limit of the temperature forecast, if one is made
+    forecast: Option<(Duration, Celsius)>,
+    /// Number of notable events kept
+    history: usize,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a forecast of the temperature `horizon` ahead, with the time left until `limit`.
+    pub fn with_forecast(mut self, horizon: Duration, limit: Celsius) -> Self {
+        self.forecast = Some((horizon, limit));
+        self
+    }
+
+    /// Keeps the given number of the latest notable events, such as faults and overrides.
+    pub fn with_history(mut self, capacity: usize) -> Self {
+        self.history = capacity;
+        self
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    /// Returns a sink keeping this status up to date.
+    pub fn sink(&self) -> StatusSink {
+        StatusSink {
+            status: self.clone(),
+            forecaster: self
+                .forecast
+                .map(|(horizon, limit)| Forecaster::new(horizon, limit)),
+        }
+    }
+}
+
+/// Records events into a shared status.
+pub struct StatusSink {
+    status: Status,
+    forecaster: Option<Forecaster>,
+}
+
+impl Sink for StatusSink {
+    fn handle(&mut self, event: &Event) {
+        let mut snapshot = self.status.snapshot.lock().unwrap();
+        let notable = match event {
+            // Only the start of an outage, not every poll it lasts
+            Event::Fault { .. } => snapshot.fault.is_none(),
+            Event::OutputFailed { .. } => snapshot.output_error.is_none(),
+            Event::Overtemperature { .. }
+            | Event::Override { .. }
+            | Event::OverrideExpired { .. }
+            | Event::SeasonChanged { .. }
+            | Event::EnergyBias { .. }
+            | Event::ModeChanged { .. }
+            | Event::AbTestArm { .. }
+            | Event::Latched { .. }
+            | Event::LatchReleased
+            | Event::OutputReinitialized { .. }
+            | Event::Alarm { .. }
+            | Event::Reloaded { .. }
+            | Event::ReloadRejected { .. } => true,
+            Event::Sample { .. }
+            | Event::Decision { .. }
+            | Event::ScheduledTarget { .. }
+            | Event::Progress(_)
+            | Event::Dropped { .. } => false,
+        };
+        if notable && self.status.history > 0 {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            snapshot.history.push_back((time, event.clone()));
+            while snapshot.history.len() > self.status.history {
+                snapshot.history.pop_front();
+            }
+        }
+
+        match event {
+            Event::Sample { temperature, raw } => {
+                snapshot.temperature = Some(*temperature);
+                snapshot.raw = *raw;
+                snapshot.fault = None;
+                if let Some(forecaster) = &mut self.forecaster {
+                    forecaster.record(Instant::now(), *temperature);
+                    snapshot.forecast = forecaster.forecast();
+                }
+            }
+            Event::Decision { target, to, .. } => {
+                snapshot.target = Some(*target);
+                snapshot.duty = Some(*to);
+            }
+            Event::Override { target }
+            | Event::OverrideExpired { target }
+            | Event::ScheduledTarget { target }
+            | Event::SeasonChanged { target, .. }
+            | Event::EnergyBias { target, .. } => snapshot.target = Some(*target),
+            Event::Fault { message } => snapshot.fault = Some(message.clone()),
+            Event::Progress(progress) => {
+                snapshot.duty = Some(progress.duty);
+                snapshot.progress = (progress.done < progress.total).then(|| progress.clone());
+            }
+            Event::Dropped { .. } | Event::ModeChanged { .. } | Event::AbTestArm { .. } => {}
+            Event::Reloaded { .. } => snapshot.reload_error = None,
+            Event::ReloadRejected { message } => snapshot.reload_error = Some(message.clone()),
+            Event::Overtemperature { .. } => {}
+            Event::Latched { .. } => snapshot.latched = true,
+            Event::LatchReleased => snapshot.latched = false,
+            Event::OutputFailed { message } => snapshot.output_error = Some(message.clone()),
+            Event::OutputReinitialized { reinits } => {
+                snapshot.output_error = None;
+                snapshot.output_reinits = *reinits;
+            }
+            Event::Alarm {
+                sensor,
+                level: AlarmLevel::Normal,
+                ..
+            } => {
+                snapshot.alarms.remove(sensor);
+            }
+            Event::Alarm { sensor, level, .. } => {
+                snapshot.alarms.insert(sensor.clone(), *level);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Status;
+    use crate::{
+        alarm::AlarmLevel,
+        events::{Event, Progress, Sink},
+        pwm::tests::duty,
+        sensor::Raw,
+        units::Celsius,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn reports_latest_state_as_json() {
+        let status = Status::new();
+        let mut sink = status.sink();
+
+        sink.handle(&Event::Fault {
+            message: "unreadable".to_string(),
+        });
+        sink.handle(&Event::Sample {
+            temperature: Celsius::new(41, 500),
+            raw: Some(Raw::Millidegrees(41537)),
+        });
+        sink.handle(&Event::Progress(Progress {
+            operation: "calibration",
+            done: 2,
+            total: 8,
+            duty: duty(80),
+            remaining: Duration::from_secs(720),
+        }));
+        sink.handle(&Event::OutputFailed {
+            message: "device gone".to_string(),
+        });
+        assert_eq!(
+            Some("device gone".to_string()),
+            status.snapshot().output_error
+        );
+        sink.handle(&Event::OutputReinitialized { reinits: 1 });
+        sink.handle(&Event::Alarm {
+            sensor: "thermal:nvme".to_string(),
+            level: AlarmLevel::Warning,
+            temperature: Celsius::new(61, 0),
+        });
+        sink.handle(&Event::Alarm {
+            sensor: "/sys/hdd".to_string(),
+            level: AlarmLevel::Critical,
+            temperature: Celsius::new(56, 0),
+        });
+        sink.handle(&Event::Alarm {
+            sensor: "/sys/hdd".to_string(),
+            level: AlarmLevel::Normal,
+            temperature: Celsius::new(40, 0),
+        });
+
+        assert_eq!(
+            "{\"temperature\":41.5,\"raw\":{\"value\":41537,\"unit\":\"millidegrees\"},\
+             \"target\":null,\"duty\":80,\"fault\":null,\
+             \"progress\":{\"operation\":\"calibration\",\"done\":2,\"total\":8,\"duty\":80,\"remaining\":720},\
+             \"reload_error\":null,\"forecast\":null,\"latched\":false,\
+             \"output_error\":null,\"output_reinits\":1,\
+             \"alarms\":{\"thermal:nvme\":\"warning\"},\"history\":[]}",
+            status.snapshot().to_json()
+        );
+    }
+
+    #[test]
+    fn forecasts_from_samples() {
+        let status = Status::new().with_forecast(Duration::from_secs(300), Celsius::new(70, 0));
+        let mut sink = status.sink();
+
+        sink.handle(&Event::Sample {
+            temperature: Celsius::new(41, 0),
+            raw: None,
+        });
+        assert_eq!(None, status.snapshot().forecast);
+
+        std::thread::sleep(Duration::from_millis(10));
+        sink.handle(&Event::Sample {
+            temperature: Celsius::new(41, 0),
+            raw: None,
+        });
+        let forecast = status.snapshot().forecast.unwrap();
+        assert_eq!(Celsius::new(41, 0), forecast.temperature);
+        assert_eq!(None, forecast.limit_in);
+    }
+
+    #[test]
+    fn keeps_latest_notable_events() {
+        let status = Status::new().with_history(2);
+        let mut sink = status.sink();
+        let fault = Event::Fault {
+            message: "unreadable".to_string(),
+        };
+
+        sink.handle(&Event::Override {
+            target: Celsius::new(45, 0),
+        });
+        sink.handle(&fault);
+        sink.handle(&fault);
+        sink.handle(&Event::Sample {
+            temperature: Celsius::new(71, 0),
+            raw: None,
+        });
+        sink.handle(&Event::Overtemperature {
+            temperature: Celsius::new(71, 0),
+            max: Celsius::new(70, 0),
+        });
+
+        let events: Vec<Event> = status
+            .snapshot()
+            .history
+            .into_iter()
+            .map(|(_, event)| event)
+            .collect();
+        assert_eq!(
+            vec![
+                fault,
+                Event::Overtemperature {
+                    temperature: Celsius::new(71, 0),
+                    max: Celsius::new(70, 0),
+                }
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn finished_operation_clears_progress() {
+        let status = Status::new();
+        let mut sink = status.sink();
+
+        sink.handle(&Event::Progress(Progress {
+            operation: "calibration",
+            done: 8,
+            total: 8,
+            duty: duty(30),
+            remaining: Duration::ZERO,
+        }));
+
+        assert_eq!(None, status.snapshot().progress);
+    }
+}
@@ -0,0 +1,133 @@
+//! Status kept in a file for dashboards that read files, such as RPi-Monitor and Cockpit.
+
+use crate::{
+    events::{Event, Sink},
+    status::{Snapshot, Status},
+};
+use clap::ValueEnum;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Layout of the status file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatusFormat {
+    /// Same JSON object as the `status` control command, e.g. for a Cockpit page
+    Json,
+    /// One `key=value` per line, for RPi-Monitor's regular expressions
+    KeyValue,
+}
+
+impl StatusFormat {
+    pub fn render(self, snapshot: &Snapshot) -> String {
+        match self {
+            StatusFormat::Json => snapshot.to_json() + "\n",
+            StatusFormat::KeyValue => key_values(snapshot),
+        }
+    }
+}
+
+/// Renders the values dashboards graph, empty when unknown.
+fn key_values(snapshot: &Snapshot) -> String {
+    fn value<T: ToString>(value: Option<T>) -> String {
+        value.map(|value| value.to_string()).unwrap_or_default()
+    }
+
+    format!(
+        "temperature={}\ntarget={}\nduty={}\nforecast={}\nlatched={}\nfault={}\n",
+        value(snapshot.temperature),
+        value(snapshot.target),
+        value(snapshot.duty),
+        value(snapshot.forecast.map(|forecast| forecast.temperature)),
+        u8::from(snapshot.latched),
+        value(
+            snapshot
+                .fault
+                .as_ref()
+                .map(|fault| fault.replace('\n', " "))
+        ),
+    )
+}
+
+/// Rewrites the file with the latest status after every sample and fault.
+///
+/// Subscribe it after the status's own sink, so the file reflects each event.
+pub struct StatusFileSink {
+    status: Status,
+    path: PathBuf,
+    format: StatusFormat,
+}
+
+impl StatusFileSink {
+    pub fn new(status: Status, path: &Path, format: StatusFormat) -> Self {
+        Self {
+            status,
+            path: path.to_path_buf(),
+            format,
+        }
+    }
+
+    /// Replaces the file in one step, so readers never see it half written.
+    fn write(&self) -> io::Result<()> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, self.format.render(&self.status.snapshot()))?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+impl Sink for StatusFileSink {
+    fn handle(&mut self, event: &Event) {
+        // Samples come every poll, other events are covered by the next one
+        if !matches!(event, Event::Sample { .. } | Event::Fault { .. }) {
+            return;
+        }
+        if let Err(error) = self.write() {
+            log::error!("Failed to write status file {:?}: {}", self.path, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatusFileSink, StatusFormat};
+    use crate::{
+        events::{Event, Sink},
+        mock::TempPath,
+        pwm::tests::duty,
+        status::Status,
+        units::Celsius,
+    };
+    use std::fs;
+
+    #[test]
+    fn writes_key_values_for_rpi_monitor() {
+        let path = TempPath::new("status");
+        let status = Status::new();
+        let mut status_sink = status.sink();
+        let mut file_sink = StatusFileSink::new(status, &path, StatusFormat::KeyValue);
+
+        let events = [
+            Event::Decision {
+                temperature: Celsius::new(41, 500),
+                target: Celsius::new(40, 0),
+                from: duty(50),
+                to: duty(52),
+            },
+            Event::Sample {
+                temperature: Celsius::new(41, 500),
+                raw: None,
+            },
+        ];
+        for event in &events {
+            status_sink.handle(event);
+            file_sink.handle(event);
+        }
+
+        assert_eq!(
+            "temperature=41.5\ntarget=40\nduty=52\nforecast=\nlatched=0\nfault=\n",
+            fs::read_to_string(&path).unwrap()
+        );
+    }
+}
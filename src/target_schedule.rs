@@ -0,0 +1,131 @@
+//! Target temperature following the time of day, ramped between given points rather than
+//! switched, e.g. cooler during the day and warmer and quieter at night.
+
+use crate::{
+    schedule::{self, TimeOfDay},
+    units::{Celsius, Precision},
+};
+use std::fmt;
+
+/// Minutes in a day
+const DAY: i64 = 24 * 60;
+
+/// Target reached at a time of day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    pub time: TimeOfDay,
+    pub target: Celsius,
+}
+
+/// Points through the day, with the target moving linearly from one to the next, from the last
+/// over midnight to the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSchedule {
+    points: Vec<Point>,
+}
+
+impl TargetSchedule {
+    /// Returns the target at a time, to a tenth of a degree.
+    pub fn target(&self, time: TimeOfDay) -> Celsius {
+        let minutes = |time: TimeOfDay| i64::from(time.hour) * 60 + i64::from(time.minute);
+        let now = minutes(time);
+
+        // The point last passed, from yesterday if none was today, and the one coming next
+        let next = self
+            .points
+            .iter()
+            .position(|point| minutes(point.time) > now)
+            .unwrap_or(0);
+        let last = (next + self.points.len() - 1) % self.points.len();
+        let (from, to) = (self.points[last], self.points[next]);
+
+        let span = (minutes(to.time) - minutes(from.time)).rem_euclid(DAY);
+        if span == 0 {
+            return from.target;
+        }
+        let elapsed = (now - minutes(from.time)).rem_euclid(DAY);
+        let (from, to) = (
+            i64::from(from.target.millidegrees()),
+            i64::from(to.target.millidegrees()),
+        );
+        let millidegrees = from + (to - from) * elapsed / span;
+        Precision::default().round(Celsius::from_millidegrees(millidegrees as i32))
+    }
+}
+
+impl fmt::Display for TargetSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|point| format!("{}={}", point.time, point.target))
+            .collect();
+        f.write_str(&points.join(","))
+    }
+}
+
+/// Parses time=target points such as `07:00=45,22:00=38`.
+pub fn parse_target_schedule(value: &str) -> Result<TargetSchedule, String> {
+    let invalid = || {
+        format!(
+            "invalid target schedule {:?}, expected e.g. 07:00=45,22:00=38",
+            value
+        )
+    };
+
+    let mut points: Vec<Point> = Vec::new();
+    for point in value.split(',') {
+        let (time, target) = point.split_once('=').ok_or_else(invalid)?;
+        let point = Point {
+            time: schedule::parse_time(time.trim()).ok_or_else(invalid)?,
+            target: target.trim().parse().map_err(|_| invalid())?,
+        };
+        if let Some(previous) = points.last() {
+            if point.time <= previous.time {
+                return Err(format!(
+                    "target schedule {:?} times must rise from point to point",
+                    value
+                ));
+            }
+        }
+        points.push(point);
+    }
+    Ok(TargetSchedule { points })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_target_schedule;
+    use crate::{schedule::TimeOfDay, units::Celsius};
+
+    #[test]
+    fn parses_points() {
+        let schedule = parse_target_schedule("07:00=45, 22:00=38.5").unwrap();
+        assert_eq!("07:00=45,22:00=38.5", schedule.to_string());
+
+        assert!(parse_target_schedule("22:00=38,07:00=45").is_err());
+        assert!(parse_target_schedule("24:00=38").is_err());
+        assert!(parse_target_schedule("07:00").is_err());
+        assert!(parse_target_schedule("07:00=warm").is_err());
+    }
+
+    #[test]
+    fn ramps_between_points_over_midnight() {
+        let schedule = parse_target_schedule("06:00=45,08:00=40,22:00=47").unwrap();
+        let target = |hour, minute| schedule.target(TimeOfDay::new(hour, minute).unwrap());
+
+        assert_eq!(Celsius::new(45, 0), target(6, 0));
+        assert_eq!(Celsius::new(42, 500), target(7, 0));
+        assert_eq!(Celsius::new(40, 0), target(8, 0));
+        assert_eq!(Celsius::new(43, 500), target(15, 0));
+        assert_eq!(Celsius::new(47, 0), target(22, 0));
+        assert_eq!(Celsius::new(46, 300), target(1, 0));
+        assert_eq!(Celsius::new(45, 100), target(5, 30));
+
+        let constant = parse_target_schedule("12:00=42").unwrap();
+        assert_eq!(
+            Celsius::new(42, 0),
+            constant.target(TimeOfDay::new(3, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Events posted to an HTTP collector, spooled to disk while it is unreachable.
+//!
+//! Deployments on flaky Wi-Fi would otherwise lose their thermal history every time the
+//! connection drops. Spooled events are replayed in order before any new one once the collector
+//! accepts requests again.
+
+use crate::{
+    event_log::{escape, to_json, Arm},
+    events::{Event, Sink},
+    secret::Secret,
+};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Time allowed for connecting and for each read or write
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Plain HTTP endpoint events are posted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+/// Parses an `http://host[:port][/path]` URL.
+pub fn parse_url(value: &str) -> Result<HttpUrl, String> {
+    let rest = value
+        .strip_prefix("http://")
+        .ok_or_else(|| "only http:// URLs are supported".to_string())?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port {:?}", port))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err("missing host".to_string());
+    }
+
+    Ok(HttpUrl {
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+/// Events waiting on disk for the collector to come back, one JSON line each.
+///
+/// Only the newest `limit` events are kept.
+pub struct Spool {
+    path: PathBuf,
+    limit: usize,
+}
+
+impl Spool {
+    pub fn new(path: &Path, limit: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            limit,
+        }
+    }
+
+    /// Returns the spooled lines, oldest first.
+    fn lines(&self) -> io::Result<Vec<String>> {
+        match fs::File::open(&self.path) {
+            Ok(file) => BufReader::new(file).lines().collect(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn store(&self, lines: &[String]) -> io::Result<()> {
+        if lines.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(()),
+            };
+        }
+
+        let skip = lines.len().saturating_sub(self.limit);
+        let mut content = lines[skip..].join("\n");
+        content.push('\n');
+        fs::write(&self.path, content)
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.path.exists()
+    }
+}
+
+/// Posts each event as JSON to an HTTP collector.
+pub struct HttpSink {
+    url: HttpUrl,
+    token: Option<Secret>,
+    spool: Option<Spool>,
+    instance: Option<String>,
+    arm: Arm,
+}
+
+impl HttpSink {
+    /// Returns a sink posting to the given URL, authenticating with a bearer token if given.
+    ///
+    /// Without a spool, events the collector doesn't accept are lost.
+    pub fn new(url: HttpUrl, token: Option<Secret>, spool: Option<Spool>) -> Self {
+        Self {
+            url,
+            token,
+            spool,
+            instance: None,
+            arm: Arm::default(),
+        }
+    }
+
+    /// Labels each event with the instance name, telling apart controllers on one host.
+    pub fn with_instance(mut self, instance: Option<String>) -> Self {
+        self.instance = instance;
+        self
+    }
+
+    fn post(&self, body: &str) -> io::Result<()> {
+        let address = (self.url.host.as_str(), self.url.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let authorization = match &self.token {
+            Some(token) => format!("Authorization: Bearer {}\r\n", token.expose()),
+            None => String::new(),
+        };
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+            self.url.path,
+            self.url.host,
+            body.len(),
+            authorization,
+            body
+        )?;
+
+        let mut response = String::new();
+        stream.take(1024).read_to_string(&mut response)?;
+        let status = response.split(' ').nth(1).unwrap_or_default();
+        if !status.starts_with('2') {
+            return Err(io::Error::other(format!(
+                "collector responded {:?}",
+                status
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Replays spooled events, returning those still pending if the collector fails midway.
+    fn replay(&self, spool: &Spool) -> io::Result<Vec<String>> {
+        let mut lines = spool.lines()?;
+        let mut sent = 0;
+        for line in &lines {
+            if self.post(line).is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        lines.drain(..sent);
+        Ok(lines)
+    }
+}
+
+impl Sink for HttpSink {
+    fn handle(&mut self, event: &Event) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.arm.follow(event);
+        let line = self.arm.tag(to_json(event, time));
+        let line = match &self.instance {
+            Some(instance) => format!("{{\"instance\":\"{}\",{}", escape(instance), &line[1..]),
+            None => line,
+        };
+
+        let spool = match &self.spool {
+            Some(spool) => spool,
+            None => {
+                if let Err(error) = self.post(&line) {
+                    log::warn!("Failed to send event to collector: {}", error);
+                }
+                return;
+            }
+        };
+
+        let result = if spool.is_empty() {
+            self.post(&line).or_else(|_| spool.store(&[line]))
+        } else {
+            // Keep events in order: anything spooled goes first
+            self.replay(spool).and_then(|mut pending| {
+                if !pending.is_empty() || self.post(&line).is_err() {
+                    pending.push(line);
+                }
+                spool.store(&pending)
+            })
+        };
+        if let Err(error) = result {
+            log::error!("Failed to spool event: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_url, HttpSink, HttpUrl, Spool};
+    use crate::{
+        events::{Event, Sink},
+        mock::TempPath,
+        units::Celsius,
+    };
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    fn sample(degrees: i32) -> Event {
+        Event::Sample {
+            temperature: Celsius::new(degrees, 0),
+            raw: None,
+        }
+    }
+
+    /// Answers one request per status with that status, returning the bodies received.
+    fn collector(statuses: Vec<u16>) -> (HttpUrl, thread::JoinHandle<Vec<(u16, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = HttpUrl {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            path: "/events".to_string(),
+        };
+
+        let server = thread::spawn(move || {
+            let mut received = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = header.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                received.push((status, String::from_utf8(body).unwrap()));
+
+                write!(reader.get_mut(), "HTTP/1.1 {} X\r\n\r\n", status).unwrap();
+            }
+            received
+        });
+
+        (url, server)
+    }
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(
+            Ok(HttpUrl {
+                host: "collector.local".to_string(),
+                port: 8080,
+                path: "/api/events".to_string()
+            }),
+            parse_url("http://collector.local:8080/api/events")
+        );
+        assert_eq!(
+            Ok(80),
+            parse_url("http://collector.local").map(|url| url.port)
+        );
+        assert!(parse_url("https://collector.local").is_err());
+        assert!(parse_url("http://:80/").is_err());
+    }
+
+    #[test]
+    fn spool_keeps_newest_lines() {
+        let path = TempPath::new("spool-bounded");
+        let spool = Spool::new(&path, 2);
+        let lines: Vec<String> = ["a", "b", "c"].map(String::from).to_vec();
+
+        spool.store(&lines).unwrap();
+        assert_eq!(vec!["b", "c"], spool.lines().unwrap());
+
+        spool.store(&[]).unwrap();
+        assert!(spool.is_empty());
+    }
+
+    #[test]
+    fn replays_spooled_events_in_order_on_reconnect() {
+        let (url, server) = collector(vec![503, 200, 200]);
+        let spool = TempPath::new("spool-replay");
+        let mut sink = HttpSink::new(url, None, Some(Spool::new(&spool, 100)));
+
+        sink.handle(&sample(40));
+        assert!(spool.exists());
+        sink.handle(&sample(41));
+
+        let received = server.join().unwrap();
+        let delivered: Vec<&str> = received
+            .iter()
+            .filter(|(status, _)| *status == 200)
+            .map(|(_, body)| body.as_str())
+            .collect();
+        assert_eq!(2, delivered.len());
+        assert!(delivered[0].contains("\"temperature\":40"));
+        assert!(delivered[1].contains("\"temperature\":41"));
+        assert!(!spool.exists());
+    }
+
+    #[test]
+    fn labels_events_with_instance() {
+        let (url, server) = collector(vec![200]);
+        let mut sink = HttpSink::new(url, None, None).with_instance(Some("rack-1".to_string()));
+
+        sink.handle(&sample(40));
+
+        let received = server.join().unwrap();
+        assert!(received[0]
+            .1
+            .starts_with("{\"instance\":\"rack-1\",\"time\":"));
+    }
+}
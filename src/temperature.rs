@@ -0,0 +1,489 @@
+use crate::{
+    args::Args,
+    sensor::{self, Sensor, SensorError},
+    units::{Celsius, Precision},
+};
+use std::{collections::VecDeque, time::Duration};
+
+pub struct Temperature {
+    /// Latest smoothed reading, which control decisions are based on
+    pub(crate) current: Celsius,
+    /// Smoothed reading before the latest one, or with the Kalman filter the estimate one poll
+    /// back along the estimated rate of change
+    pub(crate) previous: Celsius,
+    /// Latest reading as given by the sensor
+    pub(crate) raw: Celsius,
+    pub(crate) max: Celsius,
+    pub(crate) target: Celsius,
+    pub(crate) sensor: Box<dyn Sensor>,
+    pub(crate) smoothing: Smoothing,
+    /// Rounding applied to readings and their smoothed values
+    pub(crate) precision: Precision,
+}
+
+/// Filter readings pass through before control decisions are made on them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Smoothing {
+    Average(MovingAverage),
+    Exponential(ExponentialAverage),
+    Kalman(Kalman),
+}
+
+impl Smoothing {
+    /// Returns the filter selected by the options: Kalman if a measurement noise is given,
+    /// exponential if an alpha is.
+    pub fn new(args: &Args) -> Self {
+        if let Some(noise) = args.kalman_measurement_noise {
+            return Self::Kalman(Kalman::new(noise, args.kalman_process_noise));
+        }
+        match args.smoothing_alpha {
+            Some(alpha) => Self::Exponential(ExponentialAverage::new(alpha)),
+            None => Self::Average(MovingAverage::new(args.smoothing_window)),
+        }
+    }
+
+    /// Adds a reading and returns the filtered value.
+    pub fn push(&mut self, value: Celsius) -> Celsius {
+        match self {
+            Self::Average(average) => average.push(value),
+            Self::Exponential(average) => average.push(value),
+            Self::Kalman(kalman) => kalman.push(value),
+        }
+    }
+
+    /// Notes time passing before the next reading, which only the Kalman filter models.
+    pub fn elapse(&mut self, duration: Duration) {
+        if let Self::Kalman(kalman) = self {
+            kalman.elapse(duration);
+        }
+    }
+
+    /// Returns the estimated rate of change in °C per second, if the filter tracks one.
+    pub fn rate(&self) -> Option<f64> {
+        match self {
+            Self::Kalman(kalman) => kalman.rate(),
+            _ => None,
+        }
+    }
+
+    /// Returns the estimate one interval before the latest, if the filter tracks a rate, so
+    /// decisions follow the estimated trend rather than the noise between two readings.
+    pub fn previous(&self) -> Option<Celsius> {
+        match self {
+            Self::Kalman(kalman) => kalman.previous(),
+            _ => None,
+        }
+    }
+
+    /// Forgets the readings so far.
+    pub fn reset(&mut self) {
+        match self {
+            Self::Average(average) => average.reset(),
+            Self::Exponential(average) => average.reset(),
+            Self::Kalman(kalman) => kalman.reset(),
+        }
+    }
+
+    /// Whether both filter readings the same way, regardless of the readings so far.
+    pub fn same_filter(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Average(a), Self::Average(b)) => a.window == b.window,
+            (Self::Exponential(a), Self::Exponential(b)) => a.alpha == b.alpha,
+            (Self::Kalman(a), Self::Kalman(b)) => {
+                a.measurement == b.measurement && a.process == b.process
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for Smoothing {
+    /// Passes readings through as is.
+    fn default() -> Self {
+        Self::Average(MovingAverage::new(1))
+    }
+}
+
+/// Average of the latest readings, to keep noisy sensors from making the fan hunt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovingAverage {
+    window: usize,
+    readings: VecDeque<Celsius>,
+}
+
+impl MovingAverage {
+    /// Returns an average over `window` readings, a window of 1 passing readings through as is.
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            readings: VecDeque::new(),
+        }
+    }
+
+    /// Adds a reading and returns the average of those in the window.
+    pub fn push(&mut self, value: Celsius) -> Celsius {
+        self.readings.push_back(value);
+        while self.readings.len() > self.window {
+            self.readings.pop_front();
+        }
+
+        let count = self.readings.len() as i64;
+        let sum: i64 = self
+            .readings
+            .iter()
+            .map(|reading| i64::from(reading.millidegrees()))
+            .sum();
+        Celsius::from_millidegrees((sum + count / 2).div_euclid(count) as i32)
+    }
+
+    /// Forgets the readings so far.
+    pub fn reset(&mut self) {
+        self.readings.clear();
+    }
+}
+
+/// Exponentially weighted average, smoothing with less lag than a window and no stored readings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExponentialAverage {
+    /// Weight of each new reading, 1 passing readings through as is
+    alpha: f64,
+    /// Average in millidegrees, unrounded
+    value: Option<f64>,
+}
+
+impl ExponentialAverage {
+    pub fn new(alpha: f64) -> Self {
+        Self { alpha, value: None }
+    }
+
+    /// Adds a reading and returns the new average, the first reading as is.
+    pub fn push(&mut self, value: Celsius) -> Celsius {
+        let reading = f64::from(value.millidegrees());
+        let average = match self.value {
+            Some(average) => self.alpha * reading + (1.0 - self.alpha) * average,
+            None => reading,
+        };
+        self.value = Some(average);
+        Celsius::from_millidegrees(average.round() as i32)
+    }
+
+    /// Forgets the readings so far.
+    pub fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+/// Kalman filter tracking the temperature and its rate of change, assuming the rate itself only
+/// drifts slowly, e.g. as load comes and goes.
+///
+/// Unlike the averages it doesn't lag behind a steady rise, and it gives the control loop an
+/// estimate of the rate that is far less noisy than the difference of two readings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kalman {
+    /// Variance of the sensor noise, in millidegrees squared
+    measurement: f64,
+    /// Variance of the change in rate, in (millidegrees per second squared) squared
+    process: f64,
+    /// Temperature in millidegrees and rate in millidegrees per second, with their covariance
+    state: Option<([f64; 2], [[f64; 2]; 2])>,
+    /// Time since the last reading
+    pending: Duration,
+    /// Time between the last two readings
+    interval: Duration,
+}
+
+/// Variance of the rate before the second reading, about a degree per second either way
+const INITIAL_RATE_VARIANCE: f64 = 1_000_000.0;
+
+impl Kalman {
+    /// Returns a filter for a sensor with the standard deviation of noise given, whose rate of
+    /// change changes by about `process` °C/s².
+    pub fn new(noise: Celsius, process: f64) -> Self {
+        let noise = f64::from(noise.millidegrees().max(1));
+        let process = process * 1000.0;
+        Self {
+            measurement: noise * noise,
+            process: process * process,
+            state: None,
+            pending: Duration::ZERO,
+            interval: Duration::ZERO,
+        }
+    }
+
+    pub fn elapse(&mut self, duration: Duration) {
+        self.pending += duration;
+    }
+
+    /// Adds a reading taken after the time elapsed since the last one, returning the estimated
+    /// temperature.
+    pub fn push(&mut self, value: Celsius) -> Celsius {
+        let reading = f64::from(value.millidegrees());
+        self.interval = std::mem::take(&mut self.pending);
+        let dt = self.interval.as_secs_f64();
+        let Some(([temperature, rate], p)) = self.state else {
+            self.interval = Duration::ZERO;
+            self.state = Some((
+                [reading, 0.0],
+                [[self.measurement, 0.0], [0.0, INITIAL_RATE_VARIANCE]],
+            ));
+            return value;
+        };
+
+        // Predict along the rate, with the uncertainty a drifting rate adds over the interval
+        let (temperature, q) = (temperature + rate * dt, self.process);
+        let p = [
+            [
+                p[0][0] + dt * (p[1][0] + p[0][1]) + dt * dt * p[1][1] + q * dt.powi(4) / 4.0,
+                p[0][1] + dt * p[1][1] + q * dt.powi(3) / 2.0,
+            ],
+            [
+                p[1][0] + dt * p[1][1] + q * dt.powi(3) / 2.0,
+                p[1][1] + q * dt * dt,
+            ],
+        ];
+
+        // Correct by the reading, weighted by how uncertain the prediction is against the noise
+        let innovation = reading - temperature;
+        let s = p[0][0] + self.measurement;
+        let gain = [p[0][0] / s, p[1][0] / s];
+        let state = [
+            temperature + gain[0] * innovation,
+            rate + gain[1] * innovation,
+        ];
+        let p = [
+            [(1.0 - gain[0]) * p[0][0], (1.0 - gain[0]) * p[0][1]],
+            [p[1][0] - gain[1] * p[0][0], p[1][1] - gain[1] * p[0][1]],
+        ];
+        self.state = Some((state, p));
+        Celsius::from_millidegrees(state[0].round() as i32)
+    }
+
+    /// Returns the estimated rate of change in °C per second, once there was a reading.
+    pub fn rate(&self) -> Option<f64> {
+        self.state.map(|([_, rate], _)| rate / 1000.0)
+    }
+
+    /// Returns the estimate along the rate one interval before the latest, once there were two
+    /// readings with time between them.
+    pub fn previous(&self) -> Option<Celsius> {
+        let ([temperature, rate], _) = self.state?;
+        let dt = self.interval.as_secs_f64();
+        (dt > 0.0).then(|| Celsius::from_millidegrees((temperature - rate * dt).round() as i32))
+    }
+
+    /// Forgets the readings so far.
+    pub fn reset(&mut self) {
+        self.state = None;
+        self.pending = Duration::ZERO;
+        self.interval = Duration::ZERO;
+    }
+}
+
+/// Parses how quickly the Kalman filter lets the rate of change change, in °C/s², above 0.
+pub fn parse_process_noise(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|noise| *noise > 0.0 && noise.is_finite())
+        .ok_or_else(|| format!("invalid process noise {:?}, expected above 0", value))
+}
+
+/// Parses a smoothing factor, above 0 and at most 1.
+pub fn parse_alpha(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|alpha| *alpha > 0.0 && *alpha <= 1.0)
+        .ok_or_else(|| format!("invalid alpha {:?}, expected above 0 and at most 1", value))
+}
+
+/// Returns the precision readings are rounded to by the options.
+pub fn precision(args: &Args) -> Precision {
+    Precision {
+        decimals: args.temperature_precision,
+        rounding: args.temperature_rounding,
+    }
+}
+
+impl Temperature {
+    pub fn new(args: &Args) -> Self {
+        Self {
+            current: Celsius::default(),
+            previous: Celsius::default(),
+            raw: Celsius::default(),
+            max: args.temperature_max_value,
+            target: args.temperature_target_value,
+            sensor: sensor::from_args(args),
+            smoothing: Smoothing::new(args),
+            precision: precision(args),
+        }
+    }
+
+    /// Read temperature from the sensor.
+    pub fn read(&mut self) -> Result<(), SensorError> {
+        let value = self.sensor.read()?;
+        self.update(value);
+        Ok(())
+    }
+
+    /// Records a new temperature reading.
+    ///
+    /// Readings at or above the maximum bypass the average, so smoothing never delays the fan
+    /// reaching full speed. Readings and smoothed values are rounded to the precision first, so
+    /// thresholds compare against the same values that are logged.
+    pub fn update(&mut self, value: Celsius) {
+        let value = self.precision.round(value);
+        if value >= self.max {
+            self.smoothing.reset();
+        }
+        let previous = self.current;
+        self.raw = value;
+        self.current = self.precision.round(self.smoothing.push(value));
+        self.previous = self
+            .smoothing
+            .previous()
+            .map_or(previous, |smoothed| self.precision.round(smoothed));
+    }
+
+    /// Notes time passing before the next reading.
+    pub fn elapse(&mut self, duration: Duration) {
+        self.smoothing.elapse(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_alpha, ExponentialAverage, Kalman, MovingAverage, Smoothing, Temperature};
+    use crate::{
+        sensor::FileSensor,
+        units::{Celsius, Precision, Rounding},
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn readings_and_averages_are_rounded_to_the_precision() {
+        let mut temperature = Temperature {
+            current: Celsius::default(),
+            previous: Celsius::default(),
+            raw: Celsius::default(),
+            max: Celsius::new(70, 0),
+            target: Celsius::new(40, 0),
+            sensor: Box::new(FileSensor::new("")),
+            smoothing: Smoothing::Average(MovingAverage::new(2)),
+            precision: Precision {
+                decimals: 0,
+                rounding: Rounding::Floor,
+            },
+        };
+
+        temperature.update(Celsius::new(69, 999));
+        assert_eq!(Celsius::new(69, 0), temperature.raw);
+        temperature.update(Celsius::new(70, 0));
+        assert_eq!(Celsius::new(70, 0), temperature.raw);
+        assert_eq!(Celsius::new(70, 0), temperature.current);
+        temperature.update(Celsius::new(40, 500));
+        assert_eq!(Celsius::new(55, 0), temperature.current);
+    }
+
+    #[test]
+    fn maximum_bypasses_smoothing() {
+        let mut temperature = Temperature {
+            current: Celsius::default(),
+            previous: Celsius::default(),
+            raw: Celsius::default(),
+            max: Celsius::new(70, 0),
+            target: Celsius::new(40, 0),
+            sensor: Box::new(FileSensor::new("")),
+            smoothing: Smoothing::Average(MovingAverage::new(5)),
+            precision: Precision::default(),
+        };
+
+        temperature.update(Celsius::new(40, 0));
+        temperature.update(Celsius::new(50, 0));
+        assert_eq!(Celsius::new(45, 0), temperature.current);
+        assert_eq!(Celsius::new(50, 0), temperature.raw);
+
+        temperature.update(Celsius::new(72, 0));
+        assert_eq!(Celsius::new(72, 0), temperature.current);
+        temperature.update(Celsius::new(68, 0));
+        assert_eq!(Celsius::new(70, 0), temperature.current);
+    }
+
+    #[test]
+    fn averages_latest_readings() {
+        let mut average = MovingAverage::new(3);
+        let mut push = |millidegrees| {
+            average
+                .push(Celsius::from_millidegrees(millidegrees))
+                .millidegrees()
+        };
+
+        assert_eq!(40_000, push(40_000));
+        assert_eq!(41_000, push(42_000));
+        assert_eq!(42_000, push(44_000));
+        // The first reading has left the window
+        assert_eq!(43_667, push(45_000));
+    }
+
+    #[test]
+    fn window_of_one_passes_readings_through() {
+        let mut average = MovingAverage::new(1);
+        average.push(Celsius::new(40, 0));
+        assert_eq!(Celsius::new(52, 100), average.push(Celsius::new(52, 100)));
+    }
+
+    #[test]
+    fn exponential_average_weighs_new_readings_by_alpha() {
+        let mut average = ExponentialAverage::new(0.25);
+        assert_eq!(Celsius::new(40, 0), average.push(Celsius::new(40, 0)));
+        assert_eq!(Celsius::new(41, 0), average.push(Celsius::new(44, 0)));
+        assert_eq!(Celsius::new(41, 750), average.push(Celsius::new(44, 0)));
+
+        average.reset();
+        assert_eq!(Celsius::new(30, 0), average.push(Celsius::new(30, 0)));
+    }
+
+    #[test]
+    fn kalman_follows_steady_rise_through_noise() {
+        let mut kalman = Kalman::new(Celsius::new(0, 500), 0.01);
+        let mut estimate = Celsius::default();
+        // Rising 0.1°C/s, read every 5 seconds with up to half a degree of noise either way
+        for step in 0..40 {
+            let noise = (step * 7 % 11 - 5) * 100;
+            kalman.elapse(Duration::from_secs(5));
+            estimate = kalman.push(Celsius::from_millidegrees(40_000 + step * 500 + noise));
+        }
+
+        let truth = 40_000 + 39 * 500;
+        assert!(
+            (estimate.millidegrees() - truth).abs() < 400,
+            "{}",
+            estimate
+        );
+        let rate = kalman.rate().unwrap();
+        assert!((rate - 0.1).abs() < 0.02, "{}", rate);
+        // One poll back along the estimated rate
+        let previous = kalman.previous().unwrap().millidegrees();
+        let expected = f64::from(estimate.millidegrees()) - rate * 5000.0;
+        assert!(
+            // Both are rounded to whole millidegrees, the tenth of a degree precision is only
+            // applied by Temperature::update
+            (f64::from(previous) - expected).abs() <= 1.0,
+            "{}",
+            previous
+        );
+
+        kalman.reset();
+        assert_eq!(None, kalman.previous());
+        assert_eq!(Celsius::new(30, 0), kalman.push(Celsius::new(30, 0)));
+    }
+
+    #[test]
+    fn parses_alpha() {
+        assert_eq!(Ok(0.3), parse_alpha("0.3"));
+        assert_eq!(Ok(1.0), parse_alpha("1"));
+        assert!(parse_alpha("0").is_err());
+        assert!(parse_alpha("1.5").is_err());
+        assert!(parse_alpha("NaN").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+//! Templates for the log line of fan speed changes, so their wording can be fixed or translated
+//! independently of the built-in one.
+//!
+//! Placeholders in braces are replaced by values, e.g. `{temp}°C -> {pwm}%`, and `{{` and `}}`
+//! stand for literal braces.
+
+use std::{fmt, str::FromStr};
+
+/// Value a placeholder stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Temperature,
+    Target,
+    /// Duty before the change
+    From,
+    /// Duty after the change
+    Pwm,
+    /// `rising` or `lowering`
+    Direction,
+}
+
+/// Placeholders by name, in the order listed in errors
+const FIELDS: &[(&str, Field)] = &[
+    ("temp", Field::Temperature),
+    ("target", Field::Target),
+    ("from", Field::From),
+    ("pwm", Field::Pwm),
+    ("direction", Field::Direction),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Field(Field),
+}
+
+/// Values of a fan speed change, already formatted for people.
+#[derive(Debug, Clone, Copy)]
+pub struct Values<'a> {
+    pub temperature: &'a str,
+    pub target: &'a str,
+    pub from: &'a str,
+    pub pwm: &'a str,
+    pub direction: &'a str,
+}
+
+/// Parsed log line template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn render(&self, values: &Values) -> String {
+        let mut line = String::new();
+        for segment in &self.segments {
+            line.push_str(match segment {
+                Segment::Text(text) => text,
+                Segment::Field(Field::Temperature) => values.temperature,
+                Segment::Field(Field::Target) => values.target,
+                Segment::Field(Field::From) => values.from,
+                Segment::Field(Field::Pwm) => values.pwm,
+                Segment::Field(Field::Direction) => values.direction,
+            });
+        }
+        line
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let (name, rest) = chars
+                        .as_str()
+                        .split_once('}')
+                        .ok_or_else(|| format!("unclosed {{ in log template {:?}", s))?;
+                    let field = FIELDS
+                        .iter()
+                        .find(|(known, _)| *known == name)
+                        .map(|(_, field)| *field)
+                        .ok_or_else(|| {
+                            let known: Vec<_> = FIELDS
+                                .iter()
+                                .map(|(name, _)| format!("{{{}}}", name))
+                                .collect();
+                            format!(
+                                "unknown placeholder {{{}}} in log template, expected one of {}",
+                                name,
+                                known.join(", ")
+                            )
+                        })?;
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Field(field));
+                    chars = rest.chars();
+                }
+                '}' => return Err(format!("unmatched }} in log template {:?}", s)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(Self {
+            source: s.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Template, Values};
+
+    const VALUES: Values = Values {
+        temperature: "41.5",
+        target: "40",
+        from: "50",
+        pwm: "52",
+        direction: "rising",
+    };
+
+    #[test]
+    fn replaces_placeholders() {
+        let template: Template = "{temp}°C -> {pwm}% ({direction} from {from}%, target {target}°C)"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            "41.5°C -> 52% (rising from 50%, target 40°C)",
+            template.render(&VALUES)
+        );
+        assert_eq!(
+            "{pwm} = 52",
+            "{{pwm}} = {pwm}"
+                .parse::<Template>()
+                .unwrap()
+                .render(&VALUES)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        let error = "{temperature}°C".parse::<Template>().unwrap_err();
+        assert!(error.contains("{temp}, {target}"), "{}", error);
+        assert!("{temp".parse::<Template>().is_err());
+        assert!("temp}".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Thermal zones found by their type, e.g. `cpu-thermal`, rather than by their number.
+//!
+//! Zone numbering follows probe order, which kernel and firmware updates change, so a path like
+//! `thermal_zone0` can end up pointing at another sensor or nowhere.
+
+use crate::{
+    sensor::{FileSensor, Raw, Sensor, SensorError},
+    units::Celsius,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Directory the kernel lists thermal zones in
+pub const THERMAL_ROOT: &str = "/sys/class/thermal";
+
+/// Returns the temperature file of the first zone of a type under `root`.
+pub fn find_zone(root: &Path, kind: &str) -> Option<PathBuf> {
+    let mut zones: Vec<PathBuf> = fs::read_dir(root)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("thermal_zone"))
+        })
+        .collect();
+    // thermal_zone10 sorts before thermal_zone2 as text, so compare the numbers
+    zones.sort_by_key(|path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.trim_start_matches("thermal_zone").parse::<u32>().ok())
+    });
+    zones
+        .into_iter()
+        .find(|zone| {
+            fs::read_to_string(zone.join("type")).is_ok_and(|content| content.trim() == kind)
+        })
+        .map(|zone| zone.join("temp"))
+}
+
+/// Thermal zone of a type, looked up when first read and again after it couldn't be read.
+pub struct ThermalZoneSensor {
+    kind: String,
+    root: PathBuf,
+    /// Temperature file of the zone found, until reading it fails
+    file: Option<FileSensor>,
+    /// Zone last read from, to tell when the lookup lands somewhere new
+    last: Option<PathBuf>,
+}
+
+impl ThermalZoneSensor {
+    pub fn new(kind: &str) -> Self {
+        Self::with_root(kind, Path::new(THERMAL_ROOT))
+    }
+
+    /// Returns a sensor looking for zones under another directory, e.g. in tests.
+    pub fn with_root(kind: &str, root: &Path) -> Self {
+        Self {
+            kind: kind.to_string(),
+            root: root.to_path_buf(),
+            file: None,
+            last: None,
+        }
+    }
+
+    fn resolve(&mut self) -> Result<&mut FileSensor, SensorError> {
+        if self.file.is_none() {
+            let path = find_zone(&self.root, &self.kind).ok_or_else(|| {
+                SensorError::Read(
+                    format!("thermal zone of type {:?}", self.kind),
+                    io::Error::new(io::ErrorKind::NotFound, "no such zone"),
+                )
+            })?;
+            if self.last.as_ref() != Some(&path) {
+                log::info!(
+                    "Reading thermal zone {:?} from {}",
+                    self.kind,
+                    path.display()
+                );
+                self.last = Some(path.clone());
+            }
+            self.file = Some(FileSensor::new(&path.to_string_lossy()));
+        }
+        Ok(self.file.as_mut().expect("zone was just resolved"))
+    }
+}
+
+impl Sensor for ThermalZoneSensor {
+    fn read(&mut self) -> Result<Celsius, SensorError> {
+        let reading = self.resolve()?.read();
+        if reading.is_err() {
+            // Look the zone up again next time, in case it was renumbered
+            self.file = None;
+        }
+        reading
+    }
+
+    fn raw(&self) -> Option<Raw> {
+        self.file.as_ref().and_then(FileSensor::raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_zone, ThermalZoneSensor};
+    use crate::{mock::TempPath, sensor::Sensor, units::Celsius};
+    use std::{fs, path::Path};
+
+    fn zone(root: &Path, number: u32, kind: &str, millidegrees: i32) {
+        let zone = root.join(format!("thermal_zone{}", number));
+        fs::create_dir_all(&zone).unwrap();
+        fs::write(zone.join("type"), format!("{}\n", kind)).unwrap();
+        fs::write(zone.join("temp"), format!("{}\n", millidegrees)).unwrap();
+    }
+
+    #[test]
+    fn follows_a_zone_across_renumbering() {
+        let root = TempPath::dir("thermal");
+        zone(&root, 0, "gpu-thermal", 60000);
+        zone(&root, 2, "cpu-thermal", 45000);
+        zone(&root, 10, "cpu-thermal", 50000);
+        fs::create_dir_all(root.join("cooling_device0")).unwrap();
+
+        assert_eq!(
+            Some(root.join("thermal_zone2").join("temp")),
+            find_zone(&root, "cpu-thermal")
+        );
+        assert_eq!(None, find_zone(&root, "soc-thermal"));
+
+        let mut sensor = ThermalZoneSensor::with_root("cpu-thermal", &root);
+        assert_eq!(Celsius::new(45, 0), sensor.read().unwrap());
+
+        fs::remove_dir_all(root.join("thermal_zone2")).unwrap();
+        assert!(sensor.read().is_err());
+        assert_eq!(Celsius::new(50, 0), sensor.read().unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+        assert!(sensor.read().is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Throttling the Raspberry Pi firmware reports, fed forward so the fan speeds up as soon as the
+//! SoC is held back instead of once the temperature reading catches up.
+//!
+//! The firmware's flags are read from sysfs where the kernel exposes them, and from
+//! `vcgencmd get_throttled` otherwise.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// File recent kernels expose the firmware's throttling flags in
+pub const GET_THROTTLED: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+
+/// Throttling flags as the firmware reports them, current states in the low bits and whether
+/// they occurred since boot in the high ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags(u32);
+
+impl Flags {
+    const UNDER_VOLTAGE: u32 = 1 << 0;
+    const THROTTLED: u32 = 1 << 2;
+    const SOFT_LIMIT: u32 = 1 << 3;
+
+    pub fn new(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub fn under_voltage(self) -> bool {
+        self.0 & Self::UNDER_VOLTAGE != 0
+    }
+
+    pub fn throttled(self) -> bool {
+        self.0 & Self::THROTTLED != 0
+    }
+
+    /// Whether the soft temperature limit lowers the clock
+    pub fn soft_limit(self) -> bool {
+        self.0 & Self::SOFT_LIMIT != 0
+    }
+
+    /// Whether the SoC is held back in a way more cooling helps with: the soft temperature
+    /// limit, or throttling along with under-voltage, which lowers the temperature it throttles at.
+    pub fn calls_for_cooling(self) -> bool {
+        self.soft_limit() || (self.under_voltage() && self.throttled())
+    }
+}
+
+/// Returns the flags of `vcgencmd get_throttled` output or the sysfs file, both in hexadecimal.
+fn parse(output: &str) -> Option<Flags> {
+    let value = output.trim();
+    let value = value.strip_prefix("throttled=").unwrap_or(value);
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    u32::from_str_radix(value, 16).ok().map(Flags)
+}
+
+/// Source of the firmware's throttling flags.
+pub struct Throttle {
+    path: PathBuf,
+    /// Whether to ask `vcgencmd` when the file can't be read
+    vcgencmd: bool,
+    warned: bool,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self {
+            path: PathBuf::from(GET_THROTTLED),
+            vcgencmd: true,
+            warned: false,
+        }
+    }
+
+    /// Returns a source reading another file alone, e.g. in tests.
+    pub fn with_path(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            vcgencmd: false,
+            warned: false,
+        }
+    }
+
+    fn vcgencmd(&self) -> Option<Flags> {
+        if !self.vcgencmd {
+            return None;
+        }
+        let output = Command::new("vcgencmd")
+            .arg("get_throttled")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        parse(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Returns the current flags, or `None` if neither the file nor `vcgencmd` gives them.
+    pub fn sample(&mut self) -> Option<Flags> {
+        let flags = fs::read_to_string(&self.path)
+            .ok()
+            .as_deref()
+            .and_then(parse)
+            .or_else(|| self.vcgencmd());
+        if flags.is_none() && !self.warned {
+            log::warn!(
+                "Failed to read throttling flags from {} or vcgencmd",
+                self.path.display()
+            );
+            self.warned = true;
+        }
+        flags
+    }
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Flags, Throttle};
+    use crate::mock::TempPath;
+    use std::fs;
+
+    #[test]
+    fn parses_vcgencmd_and_sysfs() {
+        assert_eq!(Some(Flags::new(0x50005)), parse("throttled=0x50005\n"));
+        assert_eq!(Some(Flags::new(0x8)), parse("8\n"));
+        assert_eq!(Some(Flags::new(0)), parse("throttled=0x0"));
+        assert_eq!(None, parse("error=1"));
+    }
+
+    #[test]
+    fn calls_for_cooling_on_soft_limit_or_under_voltage_throttling() {
+        assert!(Flags::new(0x8).calls_for_cooling());
+        assert!(Flags::new(0x50005).calls_for_cooling());
+        // Under-voltage alone, or only in the past
+        assert!(!Flags::new(0x1).calls_for_cooling());
+        assert!(!Flags::new(0x80000).calls_for_cooling());
+    }
+
+    #[test]
+    fn samples_file() {
+        let path = TempPath::new("throttled");
+        let mut throttle = Throttle::with_path(&path);
+        path.write("8\n");
+        assert_eq!(Some(Flags::new(0x8)), throttle.sample());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(None, throttle.sample());
+    }
+}
@@ -0,0 +1,263 @@
+//! Trip points of the selected thermal zone, choosing the temperature limits from what the
+//! kernel was told about the SoC instead of fixed defaults.
+//!
+//! Like the board defaults, the limits are inserted ahead of all other options, so the config
+//! file and the command line both take precedence over them.
+
+use crate::{
+    thermal::{self, THERMAL_ROOT},
+    units::Celsius,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Option turning trip point defaults on
+const ENABLE: &str = "--trip-point-defaults";
+
+/// How far below a trip point the limit derived from it lies, so the fan is at full speed
+/// before the kernel steps in
+const MARGIN: Celsius = Celsius::new(5, 0);
+
+/// Trip point of a thermal zone, named by its type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TripPoint {
+    /// `active`, `passive`, `hot` or `critical`
+    pub kind: String,
+    pub temperature: Celsius,
+}
+
+/// Trip points of a thermal zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TripPoints {
+    pub zone: PathBuf,
+    pub points: Vec<TripPoint>,
+}
+
+impl TripPoints {
+    /// Reads the trip points of a zone directory, none if it has none.
+    pub fn read(zone: &Path) -> Self {
+        let mut points = Vec::new();
+        for number in 0.. {
+            let file = |name: &str| {
+                fs::read_to_string(zone.join(format!("trip_point_{}_{}", number, name)))
+                    .map(|content| content.trim().to_string())
+            };
+            let (Ok(kind), Ok(temperature)) = (file("type"), file("temp")) else {
+                break;
+            };
+            if let Ok(millidegrees) = temperature.parse() {
+                points.push(TripPoint {
+                    kind,
+                    temperature: Celsius::from_millidegrees(millidegrees),
+                });
+            }
+        }
+        Self {
+            zone: zone.to_path_buf(),
+            points,
+        }
+    }
+
+    /// Returns the lowest trip point of the given types.
+    fn lowest(&self, kinds: &[&str]) -> Option<Celsius> {
+        self.points
+            .iter()
+            .filter(|point| kinds.contains(&point.kind.as_str()))
+            .map(|point| point.temperature)
+            .min()
+    }
+
+    /// Returns the options for the limits the trip points give.
+    ///
+    /// `--temperature-max-value` lies below where the kernel starts throttling, the lowest
+    /// passive or hot trip point, and `--temperature-hard-max` below where it shuts down, the
+    /// critical one. Active trip points only drive the kernel's own fan control.
+    pub fn options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if let Some(throttling) = self.lowest(&["passive", "hot"]) {
+            options.push("--temperature-max-value".to_string());
+            options.push((throttling - MARGIN).to_string());
+        }
+        if let Some(critical) = self.lowest(&["critical"]) {
+            options.push("--temperature-hard-max".to_string());
+            options.push((critical - MARGIN).to_string());
+        }
+        options
+    }
+
+    /// Describes the trip points found and which defaults they selected, for the log.
+    pub fn describe(&self) -> String {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|point| format!("{} {}", point.kind, point.temperature))
+            .collect();
+        match self.options() {
+            options if options.is_empty() => format!(
+                "No passive, hot or critical trip points in {}",
+                self.zone.display()
+            ),
+            options => format!(
+                "Trip points of {} are {}, defaulting to {}",
+                self.zone.display(),
+                points.join(", "),
+                options.join(" ")
+            ),
+        }
+    }
+}
+
+/// Returns the value of an option in a command line, given as `--option value` or
+/// `--option=value`.
+fn value_of<'a>(argv: &'a [String], option: &str) -> Option<&'a str> {
+    let prefix = format!("{}=", option);
+    argv.iter()
+        .enumerate()
+        .skip(1)
+        .find_map(|(index, argument)| {
+            if argument == option {
+                argv.get(index + 1).map(String::as_str)
+            } else {
+                argument.strip_prefix(&prefix)
+            }
+        })
+}
+
+/// Returns the directory of the zone a command line reads from: that of `--thermal-zone`, or
+/// that of the first `--temperature-file-path` if it is a zone's `temp` file.
+fn zone(argv: &[String], root: &Path) -> Option<PathBuf> {
+    let temp = match value_of(argv, "--thermal-zone") {
+        Some(kind) => thermal::find_zone(root, kind)?,
+        None => PathBuf::from(
+            value_of(argv, "--temperature-file-path")
+                .unwrap_or("/sys/class/thermal/thermal_zone0/temp"),
+        ),
+    };
+    if temp.file_name()? != "temp" {
+        return None;
+    }
+    temp.parent().map(Path::to_path_buf)
+}
+
+/// Reads the trip points of the zone a command line reads from, if it gives
+/// `--trip-point-defaults`.
+pub fn detect(argv: &[String]) -> Option<TripPoints> {
+    detect_under(argv, Path::new(THERMAL_ROOT))
+}
+
+fn detect_under(argv: &[String], root: &Path) -> Option<TripPoints> {
+    if !argv.iter().skip(1).any(|argument| argument == ENABLE) {
+        return None;
+    }
+    zone(argv, root).map(|zone| TripPoints::read(&zone))
+}
+
+/// Returns the command line with the limits of the trip points inserted before all other
+/// options, leaving out those it already gives.
+pub fn args_with_trip_points(mut argv: Vec<String>, trips: Option<&TripPoints>) -> Vec<String> {
+    let Some(trips) = trips else {
+        return argv;
+    };
+
+    let mut options = Vec::new();
+    for pair in trips.options().chunks(2) {
+        if value_of(&argv, &pair[0]).is_none() {
+            options.extend_from_slice(pair);
+        }
+    }
+
+    let position = argv.len().min(1);
+    argv.splice(position..position, options);
+    argv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{args_with_trip_points, detect_under, TripPoints};
+    use crate::{args::Args, board, mock::TempPath, units::Celsius};
+    use clap::Parser;
+    use std::{fs, path::Path};
+
+    fn zone(root: &Path, number: u32, kind: &str, trips: &[(&str, i32)]) {
+        let zone = root.join(format!("thermal_zone{}", number));
+        fs::create_dir_all(&zone).unwrap();
+        fs::write(zone.join("type"), format!("{}\n", kind)).unwrap();
+        fs::write(zone.join("temp"), "45000\n").unwrap();
+        for (index, (kind, millidegrees)) in trips.iter().enumerate() {
+            fs::write(zone.join(format!("trip_point_{}_type", index)), kind).unwrap();
+            let temp = format!("{}\n", millidegrees);
+            fs::write(zone.join(format!("trip_point_{}_temp", index)), temp).unwrap();
+        }
+    }
+
+    fn command_line(options: &[&str]) -> Vec<String> {
+        ["fan-controller", "--gpio-pwm", "3"]
+            .iter()
+            .chain(options)
+            .map(|option| option.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn derives_limits_from_the_selected_zone() {
+        let root = TempPath::dir("trip");
+        zone(&root, 0, "cpu-thermal", &[("critical", 110000)]);
+        zone(
+            &root,
+            1,
+            "soc-thermal",
+            &[("active", 60000), ("passive", 85000), ("critical", 105000)],
+        );
+
+        let trips = detect_under(
+            &command_line(&["--trip-point-defaults", "--thermal-zone", "soc-thermal"]),
+            &root,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "--temperature-max-value",
+                "80",
+                "--temperature-hard-max",
+                "100"
+            ],
+            trips.options()
+        );
+
+        // Without a throttling trip point only the hard limit is derived
+        let path = root.join("thermal_zone0").join("temp");
+        let path = format!("--temperature-file-path={}", path.display());
+        let trips = detect_under(&command_line(&["--trip-point-defaults", &path]), &root).unwrap();
+        assert_eq!(vec!["--temperature-hard-max", "105"], trips.options());
+
+        assert_eq!(
+            None,
+            detect_under(&command_line(&["--thermal-zone", "soc-thermal"]), &root)
+        );
+    }
+
+    #[test]
+    fn given_options_and_board_defaults_come_after() {
+        let trips = TripPoints {
+            zone: "/sys/class/thermal/thermal_zone0".into(),
+            points: vec![super::TripPoint {
+                kind: "passive".to_string(),
+                temperature: Celsius::new(85, 0),
+            }],
+        };
+        let pi = board::Board::from_model("Raspberry Pi 5 Model B Rev 1.0");
+
+        let argv = args_with_trip_points(command_line(&["--trip-point-defaults"]), Some(&trips));
+        let args = Args::parse_from(board::args_with_board(argv, Some(&pi)));
+        assert_eq!(Celsius::new(80, 0), args.temperature_max_value);
+
+        let argv = args_with_trip_points(
+            command_line(&["--trip-point-defaults", "--temperature-max-value", "65"]),
+            Some(&trips),
+        );
+        let args = Args::parse_from(board::args_with_board(argv, Some(&pi)));
+        assert_eq!(Celsius::new(65, 0), args.temperature_max_value);
+    }
+}
@@ -0,0 +1,347 @@
+//! Relay auto-tuning, measuring how the enclosure oscillates around the target when the fan is
+//! switched between two duties, and suggesting PID gains from it.
+//!
+//! This is the Åström–Hägglund relay method: the oscillation period and amplitude give the
+//! ultimate gain and period, which Ziegler–Nichols rules turn into gains.
+
+use crate::{
+    clock::Clock,
+    events::{Event, EventBus, Progress},
+    pwm::Output,
+    sensor::Sensor,
+    units::{Celsius, Duty},
+};
+use std::{
+    f64::consts::PI,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Name of the operation in progress reports
+const OPERATION: &str = "tune";
+
+/// Rule turning the ultimate gain and period into PID gains.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// Classic Ziegler–Nichols, reacting quickly at the cost of some overshoot
+    Classic,
+    /// Ziegler–Nichols without overshoot, gentler on the fan
+    NoOvershoot,
+}
+
+/// Suggested PID gains, with the duty in percent and the error in °C.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gains {
+    /// Percent per °C of error
+    pub kp: f64,
+    /// Percent per °C of error per second
+    pub ki: f64,
+    /// Percent per °C/s of change in error
+    pub kd: f64,
+}
+
+/// Oscillation measured by the relay test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Response {
+    /// Mean time between the fan switching to high
+    pub period: Duration,
+    /// Mean half of the distance between peaks and troughs
+    pub amplitude: f64,
+    /// Percent per °C at which a proportional controller would keep oscillating
+    pub ultimate_gain: f64,
+}
+
+impl Response {
+    pub fn gains(&self, rule: Rule) -> Gains {
+        let (ku, tu) = (self.ultimate_gain, self.period.as_secs_f64());
+        let (kp, ti, td) = match rule {
+            Rule::Classic => (0.6 * ku, tu / 2.0, tu / 8.0),
+            Rule::NoOvershoot => (0.2 * ku, tu / 2.0, tu / 3.0),
+        };
+        Gains {
+            kp,
+            ki: kp / ti,
+            kd: kp * td,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TuneError {
+    Aborted,
+    /// The maximum temperature was reached at the given reading
+    Overheated(Celsius),
+    /// Fewer than the cycles asked for were measured in time
+    Timeout {
+        cycles: usize,
+    },
+}
+
+impl fmt::Display for TuneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TuneError::Aborted => write!(f, "tuning aborted"),
+            TuneError::Overheated(temperature) => write!(
+                f,
+                "tuning stopped, temperature reached {}°C; raise --low",
+                temperature
+            ),
+            TuneError::Timeout { cycles } => write!(
+                f,
+                "only {} cycles measured before the timeout; the duties may not move the \
+                 temperature across the target, or the enclosure reacts slower than the timeout allows",
+                cycles
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TuneError {}
+
+/// Settings of the relay test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relay {
+    /// Duty while the temperature is below the target, letting it rise
+    pub low: Duty,
+    /// Duty while the temperature is above the target, cooling it down
+    pub high: Duty,
+    pub target: Celsius,
+    /// Distance past the target before switching, keeping sensor noise from causing switches
+    pub hysteresis: Celsius,
+    /// Full oscillations to average over, after the first one settles in
+    pub cycles: usize,
+    /// Time between readings
+    pub interval: Duration,
+    /// Time after which tuning gives up
+    pub timeout: Duration,
+    /// Temperature at which tuning is abandoned and the fan returned to full speed
+    pub temperature_max: Celsius,
+}
+
+impl Relay {
+    /// Runs the relay test, reporting progress as events, and returns the oscillation measured.
+    ///
+    /// However it ends, the fan is left at full speed.
+    pub fn run(
+        &self,
+        output: &mut dyn Output,
+        sensor: &mut dyn Sensor,
+        clock: &mut dyn Clock,
+        events: &mut EventBus,
+        abort: &AtomicBool,
+    ) -> Result<Response, TuneError> {
+        let response = self.oscillate(output, sensor, clock, events, abort);
+        output.write(Duty::FULL);
+        response
+    }
+
+    fn oscillate(
+        &self,
+        output: &mut dyn Output,
+        sensor: &mut dyn Sensor,
+        clock: &mut dyn Clock,
+        events: &mut EventBus,
+        abort: &AtomicBool,
+    ) -> Result<Response, TuneError> {
+        let upper =
+            Celsius::from_millidegrees(self.target.millidegrees() + self.hysteresis.millidegrees());
+        let lower =
+            Celsius::from_millidegrees(self.target.millidegrees() - self.hysteresis.millidegrees());
+
+        let mut cooling = false;
+        output.write(self.low);
+        let mut elapsed = Duration::ZERO;
+        // Highest reading since the fan last switched to low and lowest since it last switched to
+        // high, so that overshoot past either switch is included in the extremes
+        let (mut highest, mut lowest): (Option<Celsius>, Option<Celsius>) = (None, None);
+        let (mut peaks, mut troughs) = (Vec::new(), Vec::new());
+        let mut switched_high: Vec<Duration> = Vec::new();
+
+        while switched_high.len() <= self.cycles {
+            let done = switched_high.len().saturating_sub(1);
+            events.publish(Event::Progress(Progress {
+                operation: OPERATION,
+                done,
+                total: self.cycles,
+                duty: if cooling { self.high } else { self.low },
+                remaining: self.timeout.saturating_sub(elapsed),
+            }));
+            if elapsed >= self.timeout {
+                return Err(TuneError::Timeout { cycles: done });
+            }
+
+            clock.sleep(self.interval);
+            elapsed += self.interval;
+            if abort.load(Ordering::SeqCst) {
+                return Err(TuneError::Aborted);
+            }
+
+            let temperature = match sensor.read() {
+                Ok(temperature) => temperature,
+                Err(error) => {
+                    events.publish(Event::Fault {
+                        message: error.to_string(),
+                    });
+                    continue;
+                }
+            };
+            events.publish(Event::Sample {
+                temperature,
+                raw: sensor.raw(),
+            });
+            if temperature >= self.temperature_max {
+                return Err(TuneError::Overheated(temperature));
+            }
+
+            highest = Some(highest.map_or(temperature, |peak| peak.max(temperature)));
+            lowest = Some(lowest.map_or(temperature, |trough| trough.min(temperature)));
+            if cooling && temperature < lower {
+                peaks.extend(highest.take());
+                cooling = false;
+                output.write(self.low);
+            } else if !cooling && temperature > upper {
+                // Before the first switch the fan may not have run at high yet
+                let trough = lowest.take();
+                if !switched_high.is_empty() {
+                    troughs.extend(trough);
+                }
+                cooling = true;
+                output.write(self.high);
+                switched_high.push(elapsed);
+            }
+        }
+
+        let mean = |values: &[Celsius]| {
+            values
+                .iter()
+                .map(|value| f64::from(value.millidegrees()) / 1000.0)
+                .sum::<f64>()
+                / values.len() as f64
+        };
+        let amplitude = (mean(&peaks) - mean(&troughs)) / 2.0;
+        let hysteresis = f64::from(self.hysteresis.millidegrees()) / 1000.0;
+        let relay = f64::from(self.high.percent().saturating_sub(self.low.percent())) / 2.0;
+        let period = (switched_high[self.cycles] - switched_high[0]) / self.cycles as u32;
+
+        Ok(Response {
+            period,
+            amplitude,
+            ultimate_gain: 4.0 * relay
+                / (PI
+                    * (amplitude * amplitude - hysteresis * hysteresis)
+                        .max(f64::EPSILON)
+                        .sqrt()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Gains, Relay, Response, Rule, TuneError};
+    use crate::{
+        events::EventBus,
+        mock::{MockClock, MockOutput},
+        pwm::tests::duty,
+        sensor::{Sensor, SensorError},
+        units::{Celsius, Duty},
+    };
+    use std::{sync::atomic::AtomicBool, time::Duration};
+
+    /// Enclosure closing a fifth of the distance to 130°C less a degree per percent of fan speed
+    /// at every reading.
+    struct Enclosure {
+        output: MockOutput,
+        millidegrees: i32,
+    }
+
+    impl Sensor for Enclosure {
+        fn read(&mut self) -> Result<Celsius, SensorError> {
+            let duty = self.output.writes().last().copied().unwrap_or(Duty::FULL);
+            let settled = (130 - i32::from(duty.percent())) * 1000;
+            self.millidegrees += (settled - self.millidegrees) / 5;
+            Ok(Celsius::new(0, self.millidegrees))
+        }
+    }
+
+    fn relay() -> Relay {
+        Relay {
+            low: duty(40),
+            high: duty(100),
+            target: Celsius::new(50, 0),
+            hysteresis: Celsius::new(0, 500),
+            cycles: 3,
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(3600),
+            temperature_max: Celsius::new(85, 0),
+        }
+    }
+
+    #[test]
+    fn measures_oscillation_around_target() {
+        let output = MockOutput::new();
+        let mut enclosure = Enclosure {
+            output: output.clone(),
+            millidegrees: 40_000,
+        };
+
+        let response = relay()
+            .run(
+                &mut output.clone(),
+                &mut enclosure,
+                &mut MockClock::new(),
+                &mut EventBus::new(),
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+
+        assert!(response.period >= Duration::from_secs(20));
+        assert!(response.amplitude > 0.5);
+        assert!(response.ultimate_gain > 0.0);
+        assert_eq!(Some(&Duty::FULL), output.writes().last());
+        assert!(output.writes().contains(&duty(40)));
+    }
+
+    #[test]
+    fn stops_at_maximum_temperature() {
+        let output = MockOutput::new();
+        let mut enclosure = Enclosure {
+            output: output.clone(),
+            millidegrees: 40_000,
+        };
+        let relay = Relay {
+            low: duty(0),
+            target: Celsius::new(100, 0),
+            ..relay()
+        };
+
+        let result = relay.run(
+            &mut output.clone(),
+            &mut enclosure,
+            &mut MockClock::new(),
+            &mut EventBus::new(),
+            &AtomicBool::new(false),
+        );
+
+        assert!(matches!(result, Err(TuneError::Overheated(_))));
+        assert_eq!(Some(&Duty::FULL), output.writes().last());
+    }
+
+    #[test]
+    fn ziegler_nichols_gains() {
+        let response = Response {
+            period: Duration::from_secs(100),
+            amplitude: 1.0,
+            ultimate_gain: 10.0,
+        };
+
+        assert_eq!(
+            Gains {
+                kp: 6.0,
+                ki: 0.12,
+                kd: 75.0
+            },
+            response.gains(Rule::Classic)
+        );
+    }
+}